@@ -52,8 +52,12 @@ pub struct Cli {
 pub enum Commands {
     /// Attach to a running box's agent PTY
     Attach {
-        /// Box name or workspace path
+        /// Box name or workspace path, optionally as NAME:AGENT
         name: Option<String>,
+
+        /// Attach to this agent from `[[agents]]` instead of the first
+        #[arg(short, long)]
+        agent: Option<String>,
     },
 
     /// Open or manage shell sessions inside a box
@@ -101,6 +105,12 @@ pub enum Commands {
         no_cache: bool,
     },
 
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
     /// Show status
     Status,
 
@@ -118,13 +128,120 @@ pub enum Commands {
         #[arg(long)]
         token: Option<String>,
 
+        /// Serve HTTPS (self-signed unless --cert and --key are given)
+        #[arg(long)]
+        tls: bool,
+
+        /// PEM certificate chain for --tls
+        #[arg(long, requires_all = ["tls", "key"])]
+        cert: Option<std::path::PathBuf>,
+
+        /// PEM private key for --tls
+        #[arg(long, requires_all = ["tls", "cert"])]
+        key: Option<std::path::PathBuf>,
+
+        /// Also serve Prometheus metrics, without auth, on this port
+        #[arg(long)]
+        metrics_port: Option<u16>,
+
+        /// Serve box ports on this port, a separate origin from the web UI
+        #[arg(long)]
+        proxy_port: Option<u16>,
+
         /// Stop the running web server
         #[arg(long)]
         stop: bool,
     },
 
+    /// Print box lifecycle events as they happen
+    Events {
+        /// Only events about this box
+        name: Option<String>,
+
+        /// One JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the conversation of a box's `stream-json` agents: turns, tool
+    /// calls and results
+    Messages {
+        /// Box name or workspace path (default: current workspace)
+        name: Option<String>,
+
+        /// Only messages after this sequence number
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+
+        /// Keep printing new messages
+        #[arg(short, long)]
+        follow: bool,
+
+        /// One JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show who sent input to a box, resized, restarted or killed it
+    Audit {
+        /// Box name
+        name: String,
+
+        /// Only this kind of client: local, web or tunnel
+        #[arg(long)]
+        client: Option<crate::ipc::ClientKind>,
+
+        /// Only this web token (or paired tunnel device)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Only this action
+        #[arg(long, value_parser = ["input", "resize", "blocked", "restart", "kill"])]
+        action: Option<String>,
+
+        /// Only this PTY
+        #[arg(long)]
+        pty: Option<u32>,
+
+        /// Only entries newer than this (e.g. 30m, 2h, 7d)
+        #[arg(long, value_parser = crate::config::parse_duration)]
+        since: Option<std::time::Duration>,
+
+        /// Show last N entries (0 = all)
+        #[arg(short, default_value_t = 0)]
+        n: usize,
+
+        /// One JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Create a read-only web link for watching a box
+    Share {
+        /// Box name
+        name: String,
+
+        /// Only share this PTY (default: any PTY in the box)
+        #[arg(long)]
+        pty: Option<u32>,
+
+        /// Link lifetime (e.g. 30m, 1h, 7d)
+        #[arg(long, default_value = "1h", value_parser = crate::config::parse_duration)]
+        expires: std::time::Duration,
+    },
+
+    /// Manage web API tokens
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+
     /// Create a P2P WebRTC tunnel
+    #[command(args_conflicts_with_subcommands = true)]
     Tunnel {
+        #[command(subcommand)]
+        action: Option<TunnelAction>,
+
         /// Custom STUN server
         #[arg(long)]
         stun: Option<String>,
@@ -133,11 +250,38 @@ pub enum Commands {
         #[arg(long)]
         no_stun: bool,
 
+        /// Only offer a 127.0.0.1 candidate (browser on this machine)
+        #[arg(long)]
+        loopback: bool,
+
+        /// Exchange offer/answer through a rendezvous server instead of pasting
+        #[arg(long, value_name = "URL")]
+        signal: Option<String>,
+
+        /// Only give access to this box (repeatable; also applies to devices paired now)
+        #[arg(long = "box", value_name = "NAME")]
+        boxes: Vec<String>,
+
+        /// Watch only: no typing, shells, kills or restarts
+        #[arg(long)]
+        read_only: bool,
+
         /// Don't display QR code
         #[arg(long)]
         no_qr: bool,
     },
 
+    /// Run a tunnel rendezvous server (for `coop tunnel --signal`)
+    SignalServer {
+        /// Port number
+        #[arg(short, long, default_value_t = 8787)]
+        port: u16,
+
+        /// Bind address
+        #[arg(short = 'H', long, default_value = "0.0.0.0")]
+        host: String,
+    },
+
     /// Manage boxes
     Box {
         #[command(subcommand)]
@@ -165,17 +309,100 @@ pub enum Commands {
         /// Show last N lines (0 = all)
         #[arg(short, default_value_t = 0)]
         n: usize,
+
+        /// Show a `[[services]]` entry's logs instead of the agent's
+        #[arg(long)]
+        service: Option<String>,
     },
 
     /// Restart the agent process (PTY 0)
     Restart,
 
+    /// Type into a box's agent (or another PTY) without attaching
+    Send {
+        /// Text to send; `\n`, `\r`, `\t`, `\e`, `\\` and `\xNN` escapes are decoded
+        text: Option<String>,
+
+        /// Box name or workspace path (default: current workspace)
+        #[arg(long = "box", value_name = "NAME")]
+        name: Option<String>,
+
+        /// Keys to send after the text, by tmux name (e.g. `Enter`, `C-c`, `Up`)
+        #[arg(long, num_args = 1..)]
+        keys: Vec<String>,
+
+        /// PTY to write to
+        #[arg(long, default_value_t = 0)]
+        pty: u32,
+    },
+
+    /// Block until a PTY's output matches a pattern. Exits 0 on a match
+    /// (printing it), 2 if the PTY exits first, 124 on timeout.
+    Wait {
+        /// Box name or workspace path (default: current workspace)
+        name: Option<String>,
+
+        /// Regex matched against the output, with escape sequences stripped
+        #[arg(long)]
+        pattern: String,
+
+        /// Give up after this long (e.g. 90s, 10m, 2h)
+        #[arg(long, value_parser = crate::config::parse_duration)]
+        timeout: Option<std::time::Duration>,
+
+        /// PTY to watch
+        #[arg(long, default_value_t = 0)]
+        pty: u32,
+
+        /// Match the scrollback too, not just output from now on
+        #[arg(long)]
+        scrollback: bool,
+    },
+
+    /// Queue headless tasks and run them in parallel boxes
+    Queue {
+        #[command(subcommand)]
+        action: QueueAction,
+    },
+
+    /// Run the agent headless on a task, collecting the transcript and
+    /// workspace diff, then tear the box down. Exits with the agent's code.
+    Run {
+        /// Task prompt for the agent (`-` reads it from stdin)
+        #[arg(short, long)]
+        task: String,
+
+        /// Give up after this long (e.g. 90s, 30m, 2h)
+        #[arg(long, value_parser = crate::config::parse_duration)]
+        timeout: Option<std::time::Duration>,
+
+        /// Output directory (default: ./coop-run-<timestamp>)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Type the task into the agent's terminal instead of passing it as
+        /// the last argument
+        #[arg(long)]
+        stdin: bool,
+
+        /// Extra argument for the agent, placed before the task (repeatable)
+        #[arg(long = "arg", allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
     /// Update coop to the latest release
     Update {
         /// Check for updates without installing
         #[arg(long)]
         check: bool,
     },
+
+    /// git credential helper inside a box (`[sandbox] forward_git_credentials`)
+    #[command(hide = true)]
+    GitCredential {
+        /// get, store or erase
+        operation: String,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -188,8 +415,12 @@ pub enum BoxAction {
     },
     /// Attach to a box's agent PTY
     Attach {
-        /// Box name
+        /// Box name, optionally as NAME:AGENT
         name: Option<String>,
+
+        /// Attach to this agent from `[[agents]]` instead of the first
+        #[arg(short, long)]
+        agent: Option<String>,
     },
     /// Open a shell in a box
     Shell {
@@ -215,6 +446,16 @@ pub enum BoxAction {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Print the merged config for the current directory, secrets masked
+    Show {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ShellAction {
     /// List shell sessions in the current box
@@ -247,6 +488,90 @@ pub enum ShellAction {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum QueueAction {
+    /// Add the tasks from a tasks.toml file
+    Add {
+        /// Path to the tasks file
+        file: String,
+        /// Max boxes running at once (applies to the whole queue)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// List queued, running and finished tasks
+    Ls {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Cancel a pending task or stop a running one
+    Cancel {
+        /// Task ID
+        id: u64,
+    },
+    /// Run a finished, failed or cancelled task again
+    Retry {
+        /// Task ID
+        id: u64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TokenAction {
+    /// Create a token and print its secret (shown only once)
+    Create {
+        /// Token name
+        name: String,
+
+        /// Access level: read, attach, write or admin
+        #[arg(long, default_value = "read")]
+        scope: crate::ipc::TokenScope,
+
+        /// Only allow this box (repeatable)
+        #[arg(long = "box", value_name = "NAME")]
+        boxes: Vec<String>,
+
+        /// Expire after this long (e.g. 12h, 30d)
+        #[arg(long, value_parser = crate::config::parse_duration)]
+        expires: Option<std::time::Duration>,
+    },
+    /// List tokens
+    Ls {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a token
+    Revoke {
+        /// Token name
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TunnelAction {
+    /// Manage browsers paired over the tunnel
+    Devices {
+        #[command(subcommand)]
+        action: DeviceAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DeviceAction {
+    /// List paired devices
+    Ls {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Forget a device and drop its connection
+    Revoke {
+        /// Device ID
+        id: String,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum SessionAction {
     /// List PTY sessions in a box
@@ -302,6 +627,11 @@ pub enum SystemAction {
 }
 
 pub async fn run(cli: Cli) -> Result<()> {
+    // Runs inside a box, where there is no daemon or ~/.coop
+    if let Some(Commands::GitCredential { operation }) = &cli.command {
+        return crate::daemon::forward::git_credential_client(operation);
+    }
+
     crate::config::ensure_dirs()?;
 
     match cli.command {
@@ -338,7 +668,7 @@ pub async fn run(cli: Cli) -> Result<()> {
                 }
             }
         }
-        Some(Commands::Attach { name }) => cmd_attach(name).await?,
+        Some(Commands::Attach { name, agent }) => cmd_attach(name, agent).await?,
         Some(Commands::Shell {
             action,
             command,
@@ -367,7 +697,7 @@ pub async fn run(cli: Cli) -> Result<()> {
         Some(Commands::Kill { name, all, force }) => cmd_kill(name, all, force).await?,
         Some(Commands::Box { action }) => match action {
             BoxAction::Ls { json } => cmd_ls(json).await?,
-            BoxAction::Attach { name } => cmd_attach(name).await?,
+            BoxAction::Attach { name, agent } => cmd_attach(name, agent).await?,
             BoxAction::Shell { name, command, new } => {
                 cmd_shell(name, command.as_deref(), new).await?
             }
@@ -379,6 +709,9 @@ pub async fn run(cli: Cli) -> Result<()> {
         Some(Commands::Build { no_cache }) => {
             crate::sandbox::init::build_rootfs("./coop.toml", no_cache).await?;
         }
+        Some(Commands::Config { action }) => match action {
+            ConfigAction::Show { json } => cmd_config_show(json)?,
+        },
         Some(Commands::Status) => {
             // Keep as a convenience alias for `coop system status`
             let client = crate::daemon::client::DaemonClient::connect().await?;
@@ -388,6 +721,11 @@ pub async fn run(cli: Cli) -> Result<()> {
             port,
             host,
             token,
+            tls,
+            cert,
+            key,
+            metrics_port,
+            proxy_port,
             stop,
         }) => {
             if stop {
@@ -395,16 +733,109 @@ pub async fn run(cli: Cli) -> Result<()> {
                 client.stop_serve().await?;
             } else {
                 let client = crate::daemon::client::DaemonClient::connect().await?;
-                client.serve(port, &host, token.as_deref()).await?;
+                client
+                    .serve(
+                        port,
+                        &host,
+                        token.as_deref(),
+                        tls,
+                        cert.as_deref(),
+                        key.as_deref(),
+                        metrics_port,
+                        proxy_port,
+                    )
+                    .await?;
+            }
+        }
+        Some(Commands::Events { name, json }) => {
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            client.events(name.as_deref(), json).await?;
+        }
+        Some(Commands::Messages {
+            name,
+            since,
+            follow,
+            json,
+        }) => {
+            let box_name = name.unwrap_or_else(default_box_name);
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            client.messages(&box_name, since, follow, json).await?;
+        }
+        Some(Commands::Audit {
+            name,
+            client,
+            token,
+            action,
+            pty,
+            since,
+            n,
+            json,
+        }) => {
+            let filter = crate::daemon::audit::AuditFilter {
+                client,
+                token,
+                action,
+                pty,
+                since: since
+                    .map(|d| crate::daemon::audit::now_ms().saturating_sub(d.as_millis() as u64)),
+            };
+            cmd_audit(&name, &filter, n, json)?;
+        }
+        Some(Commands::Share { name, pty, expires }) => {
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            client.share(&name, pty, expires).await?;
+        }
+        Some(Commands::Token { action }) => {
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            match action {
+                TokenAction::Create {
+                    name,
+                    scope,
+                    boxes,
+                    expires,
+                } => client.token_create(&name, scope, boxes, expires).await?,
+                TokenAction::Ls { json } => client.token_ls(json).await?,
+                TokenAction::Revoke { name } => client.token_revoke(&name).await?,
             }
         }
         Some(Commands::Tunnel {
+            action: Some(TunnelAction::Devices { action }),
+            ..
+        }) => {
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            match action {
+                DeviceAction::Ls { json } => client.device_ls(json).await?,
+                DeviceAction::Revoke { id } => client.device_revoke(&id).await?,
+            }
+        }
+        Some(Commands::Tunnel {
+            action: None,
             stun,
             no_stun,
+            loopback,
+            signal,
+            boxes,
+            read_only,
             no_qr,
         }) => {
+            let scope = crate::ipc::DeviceScope {
+                sessions: boxes,
+                read_only,
+            };
             let client = crate::daemon::client::DaemonClient::connect().await?;
-            client.tunnel(stun.as_deref(), no_stun, no_qr).await?;
+            client
+                .tunnel(
+                    stun.as_deref(),
+                    no_stun,
+                    loopback,
+                    signal.as_deref(),
+                    scope,
+                    no_qr,
+                )
+                .await?;
+        }
+        Some(Commands::SignalServer { port, host }) => {
+            crate::tunnel::signal_server::run(&host, port).await?;
         }
         Some(Commands::Session { action }) => match action {
             SessionAction::Ls { name } => {
@@ -422,20 +853,81 @@ pub async fn run(cli: Cli) -> Result<()> {
         Some(Commands::System { action }) => {
             cmd_system(action).await?;
         }
-        Some(Commands::Logs { follow, n }) => {
+        Some(Commands::Logs { follow, n, service }) => {
             let box_name = default_box_name();
             let tail = if n > 0 { Some(n) } else { None };
             let client = crate::daemon::client::DaemonClient::connect().await?;
-            client.logs(&box_name, 0, follow, tail).await?;
+            match service {
+                Some(service) => {
+                    client
+                        .service_logs(&box_name, &service, follow, tail)
+                        .await?
+                }
+                None => client.logs(&box_name, 0, follow, tail).await?,
+            }
         }
         Some(Commands::Restart) => {
             let box_name = default_box_name();
             let client = crate::daemon::client::DaemonClient::connect().await?;
             client.restart(&box_name, 0).await?;
         }
+        Some(Commands::Send {
+            text,
+            name,
+            keys,
+            pty,
+        }) => {
+            let box_name = name.unwrap_or_else(default_box_name);
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            client
+                .send_input(&box_name, pty, text.unwrap_or_default(), keys)
+                .await?;
+        }
+        Some(Commands::Wait {
+            name,
+            pattern,
+            timeout,
+            pty,
+            scrollback,
+        }) => {
+            let box_name = name.unwrap_or_else(default_box_name);
+            let client = crate::daemon::client::DaemonClient::connect().await?;
+            let code = client
+                .wait(&box_name, pty, pattern, timeout, scrollback)
+                .await?;
+            std::process::exit(code);
+        }
+        Some(Commands::Queue { action }) => cmd_queue(action).await?,
+        Some(Commands::Run {
+            task,
+            timeout,
+            output,
+            stdin,
+            args,
+        }) => {
+            let workspace = cli.workspace.unwrap_or_else(|| {
+                std::env::current_dir()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            });
+            crate::sandbox::init::ensure_rootfs(cli.build, cli.no_cache).await?;
+            let code = cmd_run(
+                cli.name.as_deref(),
+                &workspace,
+                task,
+                timeout,
+                output,
+                stdin,
+                args,
+            )
+            .await?;
+            std::process::exit(code);
+        }
         Some(Commands::Update { check }) => {
             cmd_update(check)?;
         }
+        Some(Commands::GitCredential { .. }) => unreachable!("handled above"),
     }
 
     Ok(())
@@ -464,6 +956,67 @@ mounts = [
 ]
 "#;
 
+async fn cmd_run(
+    name: Option<&str>,
+    workspace: &str,
+    task: String,
+    timeout: Option<std::time::Duration>,
+    output: Option<String>,
+    stdin: bool,
+    args: Vec<String>,
+) -> Result<i32> {
+    let task = if task == "-" {
+        let mut buf = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut buf)?;
+        buf.trim_end().to_string()
+    } else {
+        task
+    };
+
+    let output = output.unwrap_or_else(|| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("coop-run-{}", now)
+    });
+    // The daemon writes the results, so it needs an absolute path
+    let output = std::env::current_dir()?.join(output);
+
+    let client = crate::daemon::client::DaemonClient::connect().await?;
+    client
+        .run_task(
+            name,
+            workspace,
+            &task,
+            args,
+            stdin,
+            timeout.map(|t| t.as_secs().max(1)),
+            &output.to_string_lossy(),
+        )
+        .await
+}
+
+async fn cmd_queue(action: QueueAction) -> Result<()> {
+    use crate::daemon::client::DaemonClient;
+
+    match action {
+        QueueAction::Add { file, jobs } => {
+            let (concurrency, tasks) = crate::config::TaskFile::load(std::path::Path::new(&file))?;
+            if tasks.is_empty() {
+                anyhow::bail!("No [[tasks]] in {}", file);
+            }
+            crate::sandbox::init::ensure_rootfs(false, false).await?;
+            let client = DaemonClient::connect().await?;
+            client.queue_add(tasks, jobs.or(concurrency)).await?;
+        }
+        QueueAction::Ls { json } => DaemonClient::connect().await?.queue_ls(json).await?,
+        QueueAction::Cancel { id } => DaemonClient::connect().await?.queue_cancel(id).await?,
+        QueueAction::Retry { id } => DaemonClient::connect().await?.queue_retry(id).await?,
+    }
+    Ok(())
+}
+
 async fn cmd_init() -> Result<()> {
     let path = std::path::Path::new("coop.toml");
     if path.exists() {
@@ -474,10 +1027,67 @@ async fn cmd_init() -> Result<()> {
     Ok(())
 }
 
-async fn cmd_attach(name: Option<String>) -> Result<()> {
+fn cmd_config_show(json: bool) -> Result<()> {
+    use crate::config::{Coopfile, Secrets};
+
+    let mut config = Coopfile::resolve(&std::env::current_dir()?, None)?;
+    config.expand_env();
+
+    // `[env]` may copy a secret from the same host variable or file; command
+    // secrets are not run here, so values they print stay visible
+    let secrets = Secrets::resolve_quietly(&config.secrets);
+    let mask = |env: &mut std::collections::HashMap<String, String>| {
+        for value in env.values_mut() {
+            *value = secrets.mask(value);
+        }
+    };
+    mask(&mut config.env);
+    for agent in &mut config.agents {
+        mask(&mut agent.env);
+    }
+    for service in &mut config.services {
+        mask(&mut service.env);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+    } else {
+        print!("{}", toml::to_string_pretty(&config)?);
+    }
+    Ok(())
+}
+
+async fn cmd_attach(name: Option<String>, agent: Option<String>) -> Result<()> {
     let client = crate::daemon::client::DaemonClient::connect().await?;
     let name = name.unwrap_or_else(default_box_name);
-    client.attach(&name, 0).await
+    let (name, agent) = match agent {
+        Some(agent) => (name.as_str(), Some(agent)),
+        None => {
+            let (name, agent) = split_agent(&name);
+            (name, agent.map(str::to_string))
+        }
+    };
+    match agent {
+        Some(agent) => client.attach_agent(name, &agent).await,
+        None => client.attach(name, 0).await,
+    }
+}
+
+/// Split `NAME:AGENT` at its last colon. Agent names can't contain `:` or
+/// `/`, so a path with a `/` after its last colon is taken whole, as is any
+/// path that exists.
+fn split_agent(name: &str) -> (&str, Option<&str>) {
+    if std::path::Path::new(name).exists() {
+        return (name, None);
+    }
+    match name.rsplit_once(':') {
+        Some((box_name, agent))
+            if !box_name.is_empty() && !agent.is_empty() && !agent.contains('/') =>
+        {
+            (box_name, Some(agent))
+        }
+        _ => (name, None),
+    }
 }
 
 async fn cmd_shell(name: Option<String>, command: Option<&str>, force_new: bool) -> Result<()> {
@@ -680,6 +1290,87 @@ async fn cmd_system(action: SystemAction) -> Result<()> {
     Ok(())
 }
 
+fn cmd_audit(
+    name: &str,
+    filter: &crate::daemon::audit::AuditFilter,
+    n: usize,
+    json: bool,
+) -> Result<()> {
+    use crate::daemon::audit::{self, AuditAction};
+
+    // The log outlives the box, so read it directly rather than asking the daemon
+    let path = audit::path(name)?;
+    if !path.exists() {
+        anyhow::bail!("No audit log for box '{}'", name);
+    }
+    let entries: Vec<_> = audit::read(&path)?
+        .into_iter()
+        .filter(|e| filter.matches(e))
+        .collect();
+    let start = if n > 0 {
+        entries.len().saturating_sub(n)
+    } else {
+        0
+    };
+
+    for entry in &entries[start..] {
+        if json {
+            println!("{}", serde_json::to_string(entry)?);
+            continue;
+        }
+        let who = match (&entry.actor.client, &entry.actor.token) {
+            (Some(client), Some(token)) => format!("{} {}", client, token),
+            (Some(client), None) => client.to_string(),
+            (None, _) => "daemon".to_string(),
+        };
+        let pty = entry.pty.map(|p| format!("pty {}", p)).unwrap_or_default();
+        let detail = match &entry.action {
+            AuditAction::Input {
+                data: Some(data), ..
+            } => format!("\"{}\"", data),
+            AuditAction::Input { bytes, data: None } => format!("{} bytes", bytes),
+            AuditAction::Resize { cols, rows } => format!("{}x{}", cols, rows),
+            AuditAction::Blocked {
+                sequence: Some(sequence),
+                ..
+            } => sequence.clone(),
+            AuditAction::Blocked {
+                bytes,
+                sequence: None,
+            } => format!("{} bytes", bytes),
+            AuditAction::Restart | AuditAction::Kill => String::new(),
+        };
+        let line = format!(
+            "{}  {:<20} {:<7} {:<8} {}",
+            format_timestamp(entry.ts),
+            who,
+            pty,
+            entry.action.name(),
+            detail
+        );
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in local time, from Unix milliseconds
+fn format_timestamp(ms: u64) -> String {
+    let secs = (ms / 1000) as nix::libc::time_t;
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { nix::libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return ms.to_string();
+    }
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
 fn cmd_update(check: bool) -> Result<()> {
     let current = env!("CARGO_PKG_VERSION");
     let updater = self_update::backends::github::Update::configure()
@@ -750,3 +1441,20 @@ fn format_size(bytes: u64) -> String {
         format!("{} B", bytes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_agent() {
+        assert_eq!(split_agent("myproj:reviewer"), ("myproj", Some("reviewer")));
+        assert_eq!(split_agent("myproj"), ("myproj", None));
+        assert_eq!(split_agent("/work/a:b/proj"), ("/work/a:b/proj", None));
+        let dir = std::env::temp_dir().join(format!("coop-attach:{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_string_lossy().to_string();
+        assert_eq!(split_agent(&path), (path.as_str(), None));
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
+use super::SecretConfig;
+
 /// Top-level Coopfile structure (coop.toml)
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(deny_unknown_fields)]
@@ -14,12 +17,26 @@ pub struct Coopfile {
     pub workspace: WorkspaceConfig,
     #[serde(default)]
     pub env: HashMap<String, String>,
+    /// Secrets read on the host and delivered as files or agent env vars
+    #[serde(default)]
+    pub secrets: BTreeMap<String, SecretConfig>,
     #[serde(default)]
     pub network: NetworkConfig,
     #[serde(default)]
     pub session: SessionConfig,
     #[serde(default)]
     pub input_filter: InputFilterConfig,
+    #[serde(default)]
+    pub output_filter: OutputFilterConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Agents run in the box. When empty, a single agent named "agent" is
+    /// derived from `sandbox.agent`.
+    #[serde(default)]
+    pub agents: Vec<AgentConfig>,
+    /// Background services (databases, caches, ...) supervised by the daemon
+    #[serde(default)]
+    pub services: Vec<ServiceConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,6 +72,9 @@ pub struct SandboxConfig {
     pub command: Option<String>,
     /// The agent process command (the main long-running process in the box)
     pub agent: Option<String>,
+    /// Output protocol of the `agent` command (see `AgentConfig::protocol`)
+    #[serde(default)]
+    pub agent_protocol: AgentProtocol,
     /// The shell command for `coop shell` sessions (default: /bin/bash)
     pub shell: Option<String>,
     #[serde(default)]
@@ -63,6 +83,26 @@ pub struct SandboxConfig {
     pub setup: Vec<String>,
     #[serde(default = "default_user")]
     pub user: String,
+    /// UID (and GID) of `user` inside the box; the host user is mapped to
+    /// it. 0 runs the agent as root.
+    #[serde(default = "default_uid")]
+    pub uid: u32,
+    /// Proxy the daemon's `SSH_AUTH_SOCK` into the box
+    #[serde(default)]
+    pub forward_ssh_agent: bool,
+    /// Keys the box may list and use, by fingerprint (`SHA256:...`) or
+    /// comment; all keys when empty
+    #[serde(default)]
+    pub ssh_agent_keys: Vec<String>,
+    /// Ask through `SSH_ASKPASS` before each signature
+    #[serde(default)]
+    pub ssh_agent_confirm: bool,
+    /// Answer git credential requests from the box with the host's helpers
+    #[serde(default)]
+    pub forward_git_credentials: bool,
+    /// Hosts git credentials are handed out for; all hosts when empty
+    #[serde(default)]
+    pub git_credential_hosts: Vec<String>,
     /// Mounts into the sandbox. Path-based entries (e.g. "~/.bashrc:~/.bashrc")
     /// are bind-mounted directly. Named entries (e.g. "claude-config:~/.claude")
     /// use managed persistent storage (see `coop volume ls/rm/prune`).
@@ -76,6 +116,11 @@ impl SandboxConfig {
         self.agent.as_deref().or(self.command.as_deref())
     }
 
+    /// Whether the box gets any socket from the daemon in `/run/coop`
+    pub fn forwards(&self) -> bool {
+        self.forward_ssh_agent || self.forward_git_credentials
+    }
+
     /// Resolve the shell command: `shell` > "/bin/bash"
     pub fn shell_command(&self) -> &str {
         self.shell.as_deref().unwrap_or("/bin/bash")
@@ -88,10 +133,17 @@ impl Default for SandboxConfig {
             image: None,
             command: None,
             agent: None,
+            agent_protocol: AgentProtocol::default(),
             shell: None,
             args: Vec::new(),
             setup: Vec::new(),
             user: default_user(),
+            uid: default_uid(),
+            forward_ssh_agent: false,
+            ssh_agent_keys: Vec::new(),
+            ssh_agent_confirm: false,
+            forward_git_credentials: false,
+            git_credential_hosts: Vec::new(),
             mounts: Vec::new(),
         }
     }
@@ -182,6 +234,124 @@ impl MountConfig {
     }
 }
 
+/// An agent process, from a `[[agents]]` table.
+/// The first agent runs as PTY 0; the rest get the following PTY ids.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    /// Name used to address the agent (`coop attach <box>:<name>`)
+    pub name: String,
+    pub command: String,
+    /// Extra env vars for this agent only (layered over `[env]`)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Overrides `session.auto_restart` for this agent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_restart: Option<bool>,
+    /// Overrides `session.restart_delay_ms` for this agent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_delay_ms: Option<u64>,
+    /// What the agent writes to its terminal
+    #[serde(default)]
+    pub protocol: AgentProtocol,
+}
+
+/// What an agent writes to its terminal. With `stream-json` (e.g. Claude's
+/// `--output-format stream-json`) the daemon also parses the output into
+/// messages, served by `coop messages`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AgentProtocol {
+    /// Terminal output only
+    #[default]
+    Pty,
+    /// One JSON event per line
+    StreamJson,
+}
+
+/// An agent with its restart policy resolved against `[session]` defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedAgent {
+    pub name: String,
+    pub command: String,
+    pub env: Vec<(String, String)>,
+    pub auto_restart: bool,
+    pub restart_delay_ms: u64,
+    pub protocol: AgentProtocol,
+}
+
+/// Name of the agent derived from `sandbox.agent` when no `[[agents]]` are set
+pub const DEFAULT_AGENT_NAME: &str = "agent";
+
+/// A background service, from a `[[services]]` table. Services get their own
+/// PTY after the agents and are restarted according to `restart`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// Name used to address the service (`coop logs --service <name>`)
+    pub name: String,
+    pub command: String,
+    /// Extra env vars for this service only (layered over `[env]`)
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// How to tell the service is up. Without a check it counts as ready
+    /// as soon as it has started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<ReadyCheck>,
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default = "default_service_restart_delay")]
+    pub restart_delay_ms: u64,
+}
+
+/// Readiness check for a service: either a TCP port accepting connections
+/// or a command exiting with status 0, polled until `timeout_ms`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReadyCheck {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default = "default_ready_timeout")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_ready_interval")]
+    pub interval_ms: u64,
+}
+
+/// When a supervised process is restarted after it exits
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Always,
+    #[default]
+    OnFailure,
+    Never,
+}
+
+impl RestartPolicy {
+    /// Map an agent's `auto_restart` flag onto a policy
+    pub fn from_auto_restart(auto_restart: bool) -> Self {
+        if auto_restart {
+            RestartPolicy::Always
+        } else {
+            RestartPolicy::Never
+        }
+    }
+}
+
+fn default_service_restart_delay() -> u64 {
+    1000
+}
+
+fn default_ready_timeout() -> u64 {
+    30_000
+}
+
+fn default_ready_interval() -> u64 {
+    500
+}
+
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum NetworkMode {
@@ -195,6 +365,39 @@ pub enum NetworkMode {
 pub struct NetworkConfig {
     #[serde(default)]
     pub mode: NetworkMode,
+    /// Nameservers for the box; the host's resolvers when empty
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Search domains for the box; the host's when empty
+    #[serde(default)]
+    pub dns_search: Vec<String>,
+    /// Extra `/etc/hosts` entries as `"name:address"`
+    #[serde(default)]
+    pub extra_hosts: Vec<String>,
+}
+
+impl NetworkConfig {
+    /// Parse `extra_hosts` into (name, address) pairs
+    pub fn extra_hosts(&self) -> Result<Vec<(String, IpAddr)>> {
+        self.extra_hosts
+            .iter()
+            .map(|entry| {
+                let (name, addr) = entry
+                    .split_once(':')
+                    .filter(|(name, _)| !name.is_empty() && !name.contains(char::is_whitespace))
+                    .with_context(|| {
+                        format!(
+                            "Invalid extra_hosts entry '{}': expected name:address",
+                            entry
+                        )
+                    })?;
+                let addr = addr
+                    .parse()
+                    .with_context(|| format!("Invalid address in extra_hosts entry '{}'", entry))?;
+                Ok((name.to_string(), addr))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -211,6 +414,10 @@ fn default_user() -> String {
     "coop".to_string()
 }
 
+fn default_uid() -> u32 {
+    1000
+}
+
 fn default_persist() -> Vec<String> {
     vec![".claude".to_string()]
 }
@@ -233,12 +440,39 @@ impl Default for SessionConfig {
     }
 }
 
+/// `[input_filter]`: rules for input from clients. The top-level fields are
+/// the base policy; `[input_filter.<client>]` tables adjust it per client.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InputFilterConfig {
     #[serde(default = "default_debounce")]
     pub ctrl_c_debounce_ms: u64,
     #[serde(default)]
     pub block_sequences: Vec<String>,
+    /// Regexes checked against a line when Enter is pressed
+    #[serde(default)]
+    pub block_patterns: Vec<String>,
+    /// When set, only these keys pass, written like `block_sequences`
+    /// (`\x0d`, `\x1b[A`, `y`, ...)
+    #[serde(default)]
+    pub allow_keys: Vec<String>,
+    /// Filter shell and service PTYs too, not just agents
+    #[serde(default)]
+    pub shells: bool,
+    /// Hold remote input until a local `coop attach` approves it
+    #[serde(default)]
+    pub require_approval: bool,
+    /// `coop attach` (off unless enabled)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local: Option<InputPolicyConfig>,
+    /// Web UI tokens with `write` or `admin` scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web: Option<InputPolicyConfig>,
+    /// `coop tunnel` peers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<InputPolicyConfig>,
+    /// Web UI tokens with `attach` scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spectator: Option<InputPolicyConfig>,
 }
 
 fn default_debounce() -> u64 {
@@ -250,10 +484,133 @@ impl Default for InputFilterConfig {
         Self {
             ctrl_c_debounce_ms: 500,
             block_sequences: Vec::new(),
+            block_patterns: Vec::new(),
+            allow_keys: Vec::new(),
+            shells: false,
+            require_approval: false,
+            local: None,
+            web: None,
+            tunnel: None,
+            spectator: None,
         }
     }
 }
 
+/// `[input_filter.<client>]`: unset fields inherit the base policy; block
+/// lists are added to it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputPolicyConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ctrl_c_debounce_ms: Option<u64>,
+    #[serde(default)]
+    pub block_sequences: Vec<String>,
+    #[serde(default)]
+    pub block_patterns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_keys: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shells: Option<bool>,
+    /// Ignored for `local`: it is the one approving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_approval: Option<bool>,
+}
+
+/// Which `[input_filter.<client>]` policy a connection gets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputClient {
+    Local,
+    Web,
+    Tunnel,
+    Spectator,
+}
+
+/// An input filter policy with the per-client table applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputPolicy {
+    pub enabled: bool,
+    pub shells: bool,
+    pub ctrl_c_debounce_ms: u64,
+    pub block_sequences: Vec<String>,
+    pub block_patterns: Vec<String>,
+    /// Empty allows every key
+    pub allow_keys: Vec<String>,
+    /// Input waits for a local terminal's approval. Applies to the same
+    /// PTYs as the filter, whether or not the filter is enabled.
+    pub require_approval: bool,
+}
+
+impl Default for InputPolicy {
+    fn default() -> Self {
+        InputFilterConfig::default().policy(InputClient::Web)
+    }
+}
+
+impl InputFilterConfig {
+    /// The policy for `client`
+    pub fn policy(&self, client: InputClient) -> InputPolicy {
+        let (table, enabled) = match client {
+            InputClient::Local => (&self.local, false),
+            InputClient::Web => (&self.web, true),
+            InputClient::Tunnel => (&self.tunnel, true),
+            InputClient::Spectator => (&self.spectator, true),
+        };
+        let table = table.clone().unwrap_or_default();
+        InputPolicy {
+            enabled: table.enabled.unwrap_or(enabled),
+            shells: table.shells.unwrap_or(self.shells),
+            ctrl_c_debounce_ms: table.ctrl_c_debounce_ms.unwrap_or(self.ctrl_c_debounce_ms),
+            block_sequences: [self.block_sequences.clone(), table.block_sequences].concat(),
+            block_patterns: [self.block_patterns.clone(), table.block_patterns].concat(),
+            allow_keys: table.allow_keys.unwrap_or_else(|| self.allow_keys.clone()),
+            require_approval: client != InputClient::Local
+                && table.require_approval.unwrap_or(self.require_approval),
+        }
+    }
+
+    fn policies(&self) -> impl Iterator<Item = InputPolicy> + '_ {
+        [
+            InputClient::Local,
+            InputClient::Web,
+            InputClient::Tunnel,
+            InputClient::Spectator,
+        ]
+        .into_iter()
+        .map(|client| self.policy(client))
+    }
+}
+
+/// Masking of PTY output sent to web and tunnel clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputFilterConfig {
+    /// Mask AWS keys, GitHub tokens and API keys in well-known formats
+    #[serde(default = "default_true")]
+    pub builtin_patterns: bool,
+    /// Extra regexes to mask. With a capture group, only the first group is
+    /// masked (e.g. `password=(\S+)`).
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl Default for OutputFilterConfig {
+    fn default() -> Self {
+        Self {
+            builtin_patterns: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Log only the length of client input, not the keystrokes
+    #[serde(default)]
+    pub redact_input: bool,
+}
+
 impl Coopfile {
     /// Parse a Coopfile from a TOML string
     pub fn parse(content: &str) -> Result<Self> {
@@ -280,6 +637,9 @@ impl Coopfile {
         if other.sandbox.agent.is_some() {
             self.sandbox.agent = other.sandbox.agent.clone();
         }
+        if other.sandbox.agent_protocol != AgentProtocol::default() {
+            self.sandbox.agent_protocol = other.sandbox.agent_protocol;
+        }
         if other.sandbox.shell.is_some() {
             self.sandbox.shell = other.sandbox.shell.clone();
         }
@@ -294,6 +654,18 @@ impl Coopfile {
         if other.sandbox.user != default_user() {
             self.sandbox.user = other.sandbox.user.clone();
         }
+        if other.sandbox.uid != default_uid() {
+            self.sandbox.uid = other.sandbox.uid;
+        }
+        self.sandbox.forward_ssh_agent |= other.sandbox.forward_ssh_agent;
+        if !other.sandbox.ssh_agent_keys.is_empty() {
+            self.sandbox.ssh_agent_keys = other.sandbox.ssh_agent_keys.clone();
+        }
+        self.sandbox.ssh_agent_confirm |= other.sandbox.ssh_agent_confirm;
+        self.sandbox.forward_git_credentials |= other.sandbox.forward_git_credentials;
+        if !other.sandbox.git_credential_hosts.is_empty() {
+            self.sandbox.git_credential_hosts = other.sandbox.git_credential_hosts.clone();
+        }
         if !other.sandbox.mounts.is_empty() {
             self.sandbox
                 .mounts
@@ -305,8 +677,22 @@ impl Coopfile {
             self.env.insert(k.clone(), v.clone());
         }
 
+        // Secrets: additive merge by name
+        for (k, v) in &other.secrets {
+            self.secrets.insert(k.clone(), v.clone());
+        }
+
         // Network: override
         self.network.mode = other.network.mode;
+        if !other.network.dns.is_empty() {
+            self.network.dns = other.network.dns.clone();
+        }
+        if !other.network.dns_search.is_empty() {
+            self.network.dns_search = other.network.dns_search.clone();
+        }
+        self.network
+            .extra_hosts
+            .extend(other.network.extra_hosts.iter().cloned());
 
         // Session: override
         if other.session.persist != default_persist() {
@@ -322,6 +708,51 @@ impl Coopfile {
                 .block_sequences
                 .extend(other.input_filter.block_sequences.iter().cloned());
         }
+        self.input_filter
+            .block_patterns
+            .extend(other.input_filter.block_patterns.iter().cloned());
+        if !other.input_filter.allow_keys.is_empty() {
+            self.input_filter.allow_keys = other.input_filter.allow_keys.clone();
+        }
+        self.input_filter.shells |= other.input_filter.shells;
+        self.input_filter.require_approval |= other.input_filter.require_approval;
+        // A per-client table is replaced as a whole
+        for (ours, theirs) in [
+            (&mut self.input_filter.local, &other.input_filter.local),
+            (&mut self.input_filter.web, &other.input_filter.web),
+            (&mut self.input_filter.tunnel, &other.input_filter.tunnel),
+            (
+                &mut self.input_filter.spectator,
+                &other.input_filter.spectator,
+            ),
+        ] {
+            if theirs.is_some() {
+                ours.clone_from(theirs);
+            }
+        }
+
+        // Output filter: patterns are additive, and a layer that turns the
+        // built-in patterns off cannot be undone by a later one
+        self.output_filter.builtin_patterns &= other.output_filter.builtin_patterns;
+        self.output_filter
+            .patterns
+            .extend(other.output_filter.patterns.iter().cloned());
+
+        // Audit: a layer that redacts cannot be undone by a later one
+        self.audit.redact_input |= other.audit.redact_input;
+
+        // Agents: override (the agent set is defined by a single layer)
+        if !other.agents.is_empty() {
+            self.agents = other.agents.clone();
+        }
+
+        // Services: additive, a later layer replaces a service of the same name
+        for service in &other.services {
+            match self.services.iter_mut().find(|s| s.name == service.name) {
+                Some(existing) => *existing = service.clone(),
+                None => self.services.push(service.clone()),
+            }
+        }
     }
 
     /// Resolve the full Coopfile by merging layers: defaults -> global -> project -> CLI
@@ -352,25 +783,134 @@ impl Coopfile {
 
     /// Expand $VARIABLE references in env values from host environment
     pub fn expand_env(&mut self) {
-        let expanded: HashMap<String, String> = self
-            .env
+        self.env = expand_env_map(&self.env);
+        for agent in &mut self.agents {
+            agent.env = expand_env_map(&agent.env);
+        }
+        for service in &mut self.services {
+            service.env = expand_env_map(&service.env);
+        }
+    }
+
+    /// Resolve the agents to run, in PTY order. `[[agents]]` takes precedence;
+    /// otherwise `sandbox.agent` becomes a single agent named "agent".
+    pub fn agents(&self) -> Vec<ResolvedAgent> {
+        if self.agents.is_empty() {
+            return vec![ResolvedAgent {
+                name: DEFAULT_AGENT_NAME.to_string(),
+                command: self.sandbox.agent_command().unwrap_or("claude").to_string(),
+                env: Vec::new(),
+                auto_restart: self.session.auto_restart,
+                restart_delay_ms: self.session.restart_delay_ms,
+                protocol: self.sandbox.agent_protocol,
+            }];
+        }
+
+        self.agents
             .iter()
-            .map(|(k, v)| {
-                let value = if let Some(var_name) = v.strip_prefix('$') {
-                    std::env::var(var_name).unwrap_or_default()
-                } else {
-                    v.clone()
-                };
-                (k.clone(), value)
+            .map(|a| ResolvedAgent {
+                name: a.name.clone(),
+                command: a.command.clone(),
+                env: a.env.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+                auto_restart: a.auto_restart.unwrap_or(self.session.auto_restart),
+                restart_delay_ms: a.restart_delay_ms.unwrap_or(self.session.restart_delay_ms),
+                protocol: a.protocol,
             })
-            .collect();
-        self.env = expanded;
+            .collect()
     }
 
     /// Validate the Coopfile, returning errors for invalid configuration
     pub fn validate(&self) -> Result<()> {
-        if self.sandbox.agent_command().is_none() {
-            bail!("sandbox.agent or sandbox.command is required (set it in coop.toml or ~/.config/coop/default.toml)");
+        if self.agents.is_empty() && self.sandbox.agent_command().is_none() {
+            bail!("sandbox.agent, sandbox.command or [[agents]] is required (set it in coop.toml or ~/.config/coop/default.toml)");
+        }
+        self.validate_agents()?;
+        self.validate_services()?;
+        self.validate_network()?;
+        self.validate_input_filter()?;
+        self.validate_output_filter()?;
+        super::validate_secrets(&self.secrets)
+    }
+
+    /// Validate `[input_filter]` patterns, for every client
+    pub fn validate_input_filter(&self) -> Result<()> {
+        for policy in self.input_filter.policies() {
+            for pattern in &policy.block_patterns {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    bail!(
+                        "Invalid pattern '{}' in input_filter.block_patterns: {}",
+                        pattern,
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate `[output_filter]` patterns
+    pub fn validate_output_filter(&self) -> Result<()> {
+        for pattern in &self.output_filter.patterns {
+            if let Err(e) = regex::bytes::Regex::new(pattern) {
+                bail!(
+                    "Invalid pattern '{}' in output_filter.patterns: {}",
+                    pattern,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate `[network]` nameservers and extra hosts
+    pub fn validate_network(&self) -> Result<()> {
+        for server in &self.network.dns {
+            if server.parse::<IpAddr>().is_err() {
+                bail!("Invalid nameserver '{}' in network.dns", server);
+            }
+        }
+        self.network.extra_hosts()?;
+        Ok(())
+    }
+
+    /// Validate `[[agents]]` names (unique, no `:` or `/`)
+    pub fn validate_agents(&self) -> Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for agent in &self.agents {
+            if agent.name.is_empty() || agent.name.contains(':') || agent.name.contains('/') {
+                bail!(
+                    "Invalid agent name '{}': must be non-empty and contain no ':' or '/'",
+                    agent.name
+                );
+            }
+            if !seen.insert(agent.name.as_str()) {
+                bail!("Duplicate agent name '{}'", agent.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate `[[services]]` names and readiness checks
+    pub fn validate_services(&self) -> Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for service in &self.services {
+            if service.name.is_empty() || service.name.contains('/') {
+                bail!(
+                    "Invalid service name '{}': must be non-empty and contain no '/'",
+                    service.name
+                );
+            }
+            if !seen.insert(service.name.as_str()) {
+                bail!("Duplicate service name '{}'", service.name);
+            }
+            if let Some(ready) = &service.ready {
+                if ready.port.is_some() == ready.command.is_some() {
+                    bail!(
+                        "Service '{}': ready check needs exactly one of `port` or `command`",
+                        service.name
+                    );
+                }
+            }
         }
         Ok(())
     }
@@ -386,6 +926,20 @@ impl Coopfile {
     }
 }
 
+/// Expand `$VAR` values in an env table from the host environment
+fn expand_env_map(env: &HashMap<String, String>) -> HashMap<String, String> {
+    env.iter()
+        .map(|(k, v)| {
+            let value = if let Some(var_name) = v.strip_prefix('$') {
+                std::env::var(var_name).unwrap_or_default()
+            } else {
+                v.clone()
+            };
+            (k.clone(), value)
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -434,7 +988,199 @@ command = "claude"
         assert_eq!(cf.session.auto_restart, true);
         assert_eq!(cf.session.restart_delay_ms, 100);
         assert_eq!(cf.input_filter.ctrl_c_debounce_ms, 500);
+        assert!(cf.output_filter.builtin_patterns);
         assert_eq!(cf.session.persist, vec![".claude"]);
+        assert_eq!(cf.sandbox.uid, 1000);
         assert_eq!(cf.network.mode, NetworkMode::Host);
     }
+
+    #[test]
+    fn test_input_filter_policies() {
+        let toml = r#"
+[input_filter]
+block_sequences = ["\\x1b:q!"]
+block_patterns = ["rm -rf"]
+
+[input_filter.spectator]
+allow_keys = ["\\x1b[A", "\\x1b[B", "\\x0d", "y", "n"]
+ctrl_c_debounce_ms = 2000
+
+[input_filter.local]
+enabled = true
+block_patterns = ["git push"]
+require_approval = true
+
+[input_filter.tunnel]
+require_approval = true
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        cf.validate_input_filter().unwrap();
+
+        let web = cf.input_filter.policy(InputClient::Web);
+        assert!(web.enabled && !web.shells);
+        assert_eq!(web.ctrl_c_debounce_ms, 500);
+        assert!(web.allow_keys.is_empty());
+        assert!(!web.require_approval);
+        assert!(cf.input_filter.policy(InputClient::Tunnel).require_approval);
+
+        let spectator = cf.input_filter.policy(InputClient::Spectator);
+        assert_eq!(spectator.ctrl_c_debounce_ms, 2000);
+        assert_eq!(spectator.allow_keys.len(), 5);
+        assert_eq!(spectator.block_sequences, web.block_sequences);
+
+        let local = cf.input_filter.policy(InputClient::Local);
+        assert!(local.enabled);
+        assert_eq!(local.block_patterns, vec!["rm -rf", "git push"]);
+        assert!(!local.require_approval);
+        assert!(
+            !InputFilterConfig::default()
+                .policy(InputClient::Local)
+                .enabled
+        );
+
+        let mut bad = cf.clone();
+        bad.input_filter.web = Some(InputPolicyConfig {
+            block_patterns: vec!["(".into()],
+            ..Default::default()
+        });
+        assert!(bad.validate_input_filter().is_err());
+    }
+
+    #[test]
+    fn test_agents_default_from_sandbox() {
+        let toml = r#"
+[sandbox]
+agent = "claude"
+
+[session]
+auto_restart = false
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        let agents = cf.agents();
+        assert_eq!(agents.len(), 1);
+        assert_eq!(agents[0].name, DEFAULT_AGENT_NAME);
+        assert_eq!(agents[0].command, "claude");
+        assert!(!agents[0].auto_restart);
+    }
+
+    #[test]
+    fn test_parse_agents() {
+        let toml = r#"
+[[agents]]
+name = "coder"
+command = "claude"
+
+[[agents]]
+name = "reviewer"
+command = "codex"
+auto_restart = false
+restart_delay_ms = 2000
+protocol = "stream-json"
+
+[agents.env]
+ROLE = "review"
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        cf.validate().unwrap();
+        let agents = cf.agents();
+        assert_eq!(agents.len(), 2);
+        assert_eq!(agents[0].name, "coder");
+        assert!(agents[0].auto_restart);
+        assert_eq!(agents[1].name, "reviewer");
+        assert!(!agents[1].auto_restart);
+        assert_eq!(agents[1].restart_delay_ms, 2000);
+        assert_eq!(agents[0].protocol, AgentProtocol::Pty);
+        assert_eq!(agents[1].protocol, AgentProtocol::StreamJson);
+        assert_eq!(
+            agents[1].env,
+            vec![("ROLE".to_string(), "review".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_agent_names_rejected() {
+        let toml = r#"
+[[agents]]
+name = "a"
+command = "claude"
+
+[[agents]]
+name = "a"
+command = "codex"
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        assert!(cf.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_services() {
+        let toml = r#"
+[sandbox]
+agent = "claude"
+
+[[services]]
+name = "db"
+command = "postgres -D /var/lib/postgres"
+ready = { port = 5432 }
+
+[[services]]
+name = "cache"
+command = "redis-server"
+restart = "always"
+ready = { command = "redis-cli ping", timeout_ms = 5000 }
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        assert!(cf.validate().is_ok());
+        assert_eq!(cf.services.len(), 2);
+        assert_eq!(cf.services[0].restart, RestartPolicy::OnFailure);
+        assert_eq!(cf.services[0].ready.as_ref().unwrap().port, Some(5432));
+        assert_eq!(cf.services[1].restart, RestartPolicy::Always);
+        let ready = cf.services[1].ready.as_ref().unwrap();
+        assert_eq!(ready.command.as_deref(), Some("redis-cli ping"));
+        assert_eq!(ready.timeout_ms, 5000);
+        assert_eq!(ready.interval_ms, 500);
+    }
+
+    #[test]
+    fn test_service_ready_check_needs_one_probe() {
+        let toml = r#"
+[sandbox]
+agent = "claude"
+
+[[services]]
+name = "db"
+command = "postgres"
+ready = { port = 5432, command = "pg_isready" }
+"#;
+        let cf = Coopfile::parse(toml).unwrap();
+        assert!(cf.validate().is_err());
+    }
+
+    #[test]
+    fn test_merge_services_by_name() {
+        let mut base = Coopfile::parse(
+            r#"
+[[services]]
+name = "db"
+command = "postgres"
+"#,
+        )
+        .unwrap();
+        let overlay = Coopfile::parse(
+            r#"
+[[services]]
+name = "db"
+command = "postgres -p 5433"
+
+[[services]]
+name = "cache"
+command = "redis-server"
+"#,
+        )
+        .unwrap();
+        base.merge(&overlay);
+        assert_eq!(base.services.len(), 2);
+        assert_eq!(base.services[0].command, "postgres -p 5433");
+        assert_eq!(base.services[1].name, "cache");
+    }
 }
@@ -1,5 +1,9 @@
 mod coopfile;
 mod paths;
+mod secrets;
+mod taskfile;
 
 pub use coopfile::*;
 pub use paths::*;
+pub use secrets::*;
+pub use taskfile::*;
@@ -44,6 +44,35 @@ pub fn session_dir(name: &str) -> Result<PathBuf> {
     Ok(sessions_dir()?.join(name))
 }
 
+/// Returns a session's forwarded sockets directory: ~/.coop/sessions/<name>/run,
+/// mounted at [`BOX_RUN_DIR`] inside the box
+pub fn session_run_dir(name: &str) -> Result<PathBuf> {
+    Ok(session_dir(name)?.join("run"))
+}
+
+/// Where the daemon's sockets (and the `coop` helper binary) appear in a box
+pub const BOX_RUN_DIR: &str = "/run/coop";
+
+/// Returns the task queue directory: ~/.coop/queue
+pub fn queue_dir() -> Result<PathBuf> {
+    Ok(coop_dir()?.join("queue"))
+}
+
+/// Returns the paired tunnel devices file: ~/.coop/devices.json
+pub fn devices_path() -> Result<PathBuf> {
+    Ok(coop_dir()?.join("devices.json"))
+}
+
+/// Returns the web API tokens file: ~/.coop/tokens.json
+pub fn tokens_path() -> Result<PathBuf> {
+    Ok(coop_dir()?.join("tokens.json"))
+}
+
+/// Returns the self-signed web certificate directory: ~/.coop/tls
+pub fn tls_dir() -> Result<PathBuf> {
+    Ok(coop_dir()?.join("tls"))
+}
+
 /// Returns the OCI cache directory: ~/.coop/cache/oci
 pub fn oci_cache_dir() -> Result<PathBuf> {
     Ok(coop_dir()?.join("cache").join("oci"))
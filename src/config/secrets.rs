@@ -0,0 +1,300 @@
+//! `[secrets]`: values read on the host when a box is created and handed to
+//! it as files on a private tmpfs (`/run/secrets/<name>`) or as env vars of
+//! the agent processes only. Resolved values never appear in `Debug` output,
+//! and are masked in the audit log and `coop config show`.
+
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Where secret files appear inside a box
+pub const BOX_SECRETS_DIR: &str = "/run/secrets";
+
+/// Shown in place of a secret value
+pub const MASK: &str = "********";
+
+/// One `[secrets]` entry: exactly one of `env`, `file` or `command`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecretConfig {
+    /// Host environment variable holding the value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// Host file holding the value (`~` is expanded)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Host command printing the value (e.g. `pass show github/token`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub deliver: SecretDelivery,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretDelivery {
+    /// `/run/secrets/<name>`, readable only by the sandbox user
+    #[default]
+    File,
+    /// Env var `<name>` of agent processes (not shells or services)
+    Env,
+}
+
+/// Check names and sources of a `[secrets]` table
+pub fn validate_secrets(secrets: &BTreeMap<String, SecretConfig>) -> Result<()> {
+    for (name, secret) in secrets {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!(
+                "Invalid secret name '{}': use letters, digits and '_', not starting with a digit",
+                name
+            );
+        }
+        let sources = [&secret.env, &secret.file, &secret.command]
+            .iter()
+            .filter(|s| s.is_some())
+            .count();
+        if sources != 1 {
+            bail!(
+                "Secret '{}' needs exactly one of `env`, `file` or `command`",
+                name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// A resolved secret
+#[derive(Clone)]
+pub struct Secret {
+    pub name: String,
+    pub value: String,
+    pub deliver: SecretDelivery,
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secret")
+            .field("name", &self.name)
+            .field("value", &MASK)
+            .field("deliver", &self.deliver)
+            .finish()
+    }
+}
+
+/// The resolved secrets of a box
+#[derive(Debug, Clone, Default)]
+pub struct Secrets(Vec<Secret>);
+
+impl Secrets {
+    /// Read every secret from the host. Errors name the secret, never its
+    /// value.
+    pub fn resolve(config: &BTreeMap<String, SecretConfig>) -> Result<Self> {
+        config
+            .iter()
+            .map(|(name, secret)| {
+                let value =
+                    read_value(secret).with_context(|| format!("Cannot read secret '{}'", name))?;
+                Ok(Secret {
+                    name: name.clone(),
+                    value,
+                    deliver: secret.deliver,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    /// Read the secrets that come from host env vars and files, skipping
+    /// commands (which may prompt) and anything that cannot be read. Enough
+    /// to mask what `[env]` copied from the same places.
+    pub fn resolve_quietly(config: &BTreeMap<String, SecretConfig>) -> Self {
+        Self(
+            config
+                .iter()
+                .filter(|(_, secret)| secret.command.is_none())
+                .filter_map(|(name, secret)| {
+                    Some(Secret {
+                        name: name.clone(),
+                        value: read_value(secret).ok()?,
+                        deliver: secret.deliver,
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Secrets delivered as files under [`BOX_SECRETS_DIR`]
+    pub fn files(&self) -> impl Iterator<Item = &Secret> {
+        self.0.iter().filter(|s| s.deliver == SecretDelivery::File)
+    }
+
+    /// Env vars for agent processes
+    pub fn agent_env(&self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .filter(|s| s.deliver == SecretDelivery::Env)
+            .map(|s| (s.name.clone(), s.value.clone()))
+            .collect()
+    }
+
+    /// Values to hide wherever they show up
+    pub fn values(&self) -> Vec<Vec<u8>> {
+        self.0
+            .iter()
+            .filter(|s| !s.value.is_empty())
+            .map(|s| s.value.as_bytes().to_vec())
+            .collect()
+    }
+
+    /// `text` with every secret value replaced by [`MASK`]
+    pub fn mask(&self, text: &str) -> String {
+        String::from_utf8_lossy(&mask_bytes(text.as_bytes(), &self.values())).into_owned()
+    }
+}
+
+fn read_value(secret: &SecretConfig) -> Result<String> {
+    if let Some(var) = &secret.env {
+        return std::env::var(var).with_context(|| format!("${} is not set", var));
+    }
+    if let Some(file) = &secret.file {
+        let path = shellexpand::tilde(file).to_string();
+        let value =
+            std::fs::read_to_string(&path).with_context(|| format!("Cannot read {}", path))?;
+        return Ok(value.trim_end_matches(['\r', '\n']).to_string());
+    }
+    if let Some(command) = &secret.command {
+        // stderr is dropped: tools may echo the value there, and it would
+        // end up in the daemon log
+        let output = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .with_context(|| format!("Cannot run `{}`", command))?;
+        if !output.status.success() {
+            bail!("`{}` failed with {}", command, output.status);
+        }
+        let value = String::from_utf8(output.stdout).context("Command output is not UTF-8")?;
+        return Ok(value.trim_end_matches(['\r', '\n']).to_string());
+    }
+    bail!("No source given")
+}
+
+/// `data` with every occurrence of each of `values` replaced by [`MASK`]
+pub fn mask_bytes(data: &[u8], values: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    for value in values.iter().filter(|v| !v.is_empty()) {
+        let mut masked = Vec::with_capacity(out.len());
+        let mut rest = out.as_slice();
+        while let Some(pos) = rest
+            .windows(value.len())
+            .position(|w| w == value.as_slice())
+        {
+            masked.extend_from_slice(&rest[..pos]);
+            masked.extend_from_slice(MASK.as_bytes());
+            rest = &rest[pos + value.len()..];
+        }
+        masked.extend_from_slice(rest);
+        out = masked;
+    }
+    out
+}
+
+/// Length of the longest tail of `data` that starts a value in `values`
+/// without finishing it: a secret that may go on in the next chunk
+pub fn partial_secret_len(data: &[u8], values: &[Vec<u8>]) -> usize {
+    values
+        .iter()
+        .filter_map(|value| {
+            (1..value.len().min(data.len() + 1))
+                .rev()
+                .find(|&n| data.ends_with(&value[..n]))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_resolve_and_mask() {
+        let path = std::env::temp_dir().join(format!("coop-secret-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let mut config = BTreeMap::new();
+        config.insert(
+            "TOKEN".to_string(),
+            SecretConfig {
+                command: Some("echo s3cret-token".to_string()),
+                deliver: SecretDelivery::Env,
+                ..Default::default()
+            },
+        );
+        config.insert(
+            "key".to_string(),
+            SecretConfig {
+                file: Some(path.to_string_lossy().into_owned()),
+                ..Default::default()
+            },
+        );
+        validate_secrets(&config).unwrap();
+
+        let secrets = Secrets::resolve(&config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            secrets.agent_env(),
+            vec![("TOKEN".to_string(), "s3cret-token".to_string())]
+        );
+        assert_eq!(
+            secrets
+                .files()
+                .map(|s| s.value.as_str())
+                .collect::<Vec<_>>(),
+            vec!["from-file"]
+        );
+        assert_eq!(
+            secrets.mask("curl -H 'token: s3cret-token' from-file"),
+            "curl -H 'token: ********' ********"
+        );
+        assert!(!format!("{:?}", secrets).contains("s3cret"));
+
+        let failing = BTreeMap::from([(
+            "X".to_string(),
+            SecretConfig {
+                command: Some("printf 'hun%s' ter2; exit 3".to_string()),
+                ..Default::default()
+            },
+        )]);
+        let err = format!("{:#}", Secrets::resolve(&failing).unwrap_err());
+        assert!(err.contains("'X'") && !err.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_validate_secrets() {
+        let two_sources = BTreeMap::from([(
+            "A".to_string(),
+            SecretConfig {
+                env: Some("A".to_string()),
+                file: Some("/a".to_string()),
+                ..Default::default()
+            },
+        )]);
+        assert!(validate_secrets(&two_sources).is_err());
+        let bad_name = BTreeMap::from([(
+            "../a".to_string(),
+            SecretConfig {
+                env: Some("A".to_string()),
+                ..Default::default()
+            },
+        )]);
+        assert!(validate_secrets(&bad_name).is_err());
+    }
+}
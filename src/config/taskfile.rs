@@ -0,0 +1,141 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::ipc::TaskSpec;
+
+/// A batch of headless tasks for `coop queue add` (tasks.toml)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskFile {
+    /// Max boxes the queue runs at once (applies to the whole queue)
+    pub concurrency: Option<usize>,
+    #[serde(default)]
+    pub tasks: Vec<TaskEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaskEntry {
+    pub name: Option<String>,
+    pub task: String,
+    /// Source workspace, relative to the tasks file (default: its directory)
+    pub workspace: Option<String>,
+    /// e.g. "30m"; see `parse_duration`
+    pub timeout: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub stdin: bool,
+}
+
+impl TaskFile {
+    /// Load a tasks file, resolving workspaces to absolute paths
+    pub fn load(path: &Path) -> Result<(Option<usize>, Vec<TaskSpec>)> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let file: TaskFile = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        let path = std::env::current_dir()?.join(path);
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let specs = file
+            .tasks
+            .into_iter()
+            .map(|t| {
+                let workspace = match &t.workspace {
+                    Some(w) => base.join(shellexpand::tilde(w).as_ref()),
+                    None => base.clone(),
+                };
+                let timeout_secs = match &t.timeout {
+                    Some(s) => Some(parse_duration(s).map_err(anyhow::Error::msg)?.as_secs()),
+                    None => None,
+                };
+                Ok(TaskSpec {
+                    name: t.name,
+                    task: t.task,
+                    workspace: workspace.to_string_lossy().to_string(),
+                    args: t.args,
+                    stdin: t.stdin,
+                    timeout_secs,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((file.concurrency, specs))
+    }
+}
+
+/// Parse a duration like `90`, `90s`, `30m`, `2h` or `1d` (bare numbers are seconds)
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let secs = match unit {
+        "" | "s" => num,
+        "m" => num * 60,
+        "h" => num * 3600,
+        "d" => num * 86400,
+        _ => {
+            return Err(format!(
+                "invalid duration unit '{}' (use s, m, h or d)",
+                unit
+            ))
+        }
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap().as_secs(), 90);
+        assert_eq!(parse_duration("30m").unwrap().as_secs(), 1800);
+        assert_eq!(parse_duration("2h").unwrap().as_secs(), 7200);
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn test_load_task_file() {
+        let dir = std::env::temp_dir().join(format!("coop-taskfile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tasks.toml");
+        std::fs::write(
+            &path,
+            r#"
+concurrency = 3
+
+[[tasks]]
+task = "fix the tests"
+timeout = "30m"
+
+[[tasks]]
+name = "docs"
+task = "update the README"
+workspace = "sub"
+args = ["-p"]
+"#,
+        )
+        .unwrap();
+
+        let (concurrency, specs) = TaskFile::load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(concurrency, Some(3));
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].timeout_secs, Some(1800));
+        assert_eq!(specs[0].workspace, dir.to_string_lossy());
+        assert_eq!(specs[1].workspace, dir.join("sub").to_string_lossy());
+        assert_eq!(specs[1].args, vec!["-p"]);
+    }
+}
@@ -0,0 +1,356 @@
+//! Agent protocol adapters. An agent with `protocol = "stream-json"` still
+//! runs in its PTY, but each JSON event it writes there (one per line, as
+//! with Claude's `--output-format stream-json`) is also parsed into
+//! conversation turns and tool calls. A box keeps its recent messages in a
+//! `Transcript` for `coop messages` and `GET /api/sessions/{name}/messages`.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+use crate::ipc::{AgentMessage, MessageKind};
+use crate::pty::RedactRules;
+
+/// Lines longer than this (e.g. a huge tool result) are skipped
+const MAX_LINE: usize = 4 * 1024 * 1024;
+
+/// Longest string kept in a message; the rest is cut
+const MAX_TEXT: usize = 64 * 1024;
+
+/// Messages kept per box, oldest dropped first
+const MAX_MESSAGES: usize = 2000;
+
+/// Parses one agent's `stream-json` output into messages
+pub struct StreamJson {
+    pty: u32,
+    /// The unfinished line
+    line: Vec<u8>,
+    /// Dropping the rest of a line that is too long or was cut short
+    skipping: bool,
+}
+
+impl StreamJson {
+    pub fn new(pty: u32) -> Self {
+        Self {
+            pty,
+            line: Vec::new(),
+            skipping: false,
+        }
+    }
+
+    /// Feed output. Returns the messages of every complete line; lines
+    /// that are not JSON events (other output, notices) are ignored.
+    pub fn push(&mut self, data: &[u8]) -> Vec<AgentMessage> {
+        let mut messages = Vec::new();
+        for chunk in data.split_inclusive(|&b| b == b'\n') {
+            let complete = chunk.ends_with(b"\n");
+            if self.skipping {
+                self.skipping = !complete;
+                continue;
+            }
+            self.line.extend_from_slice(chunk);
+            if self.line.len() > MAX_LINE {
+                self.line.clear();
+                self.skipping = !complete;
+            } else if complete {
+                let line = std::mem::take(&mut self.line);
+                messages.extend(self.parse_line(&line));
+            }
+        }
+        messages
+    }
+
+    /// Output was missed: drop everything up to the next line
+    pub fn reset(&mut self) {
+        self.line.clear();
+        self.skipping = true;
+    }
+
+    fn parse_line(&self, line: &[u8]) -> Vec<AgentMessage> {
+        // The terminal ends lines with \r\n
+        let end = line
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(0, |i| i + 1);
+        let line = &line[..end];
+        if !line.starts_with(b"{") {
+            return Vec::new();
+        }
+        match serde_json::from_slice::<Value>(line) {
+            Ok(event) => parse_event(&event, self.pty),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// The messages in one event. `seq` and `time` are set by the transcript.
+fn parse_event(event: &Value, pty: u32) -> Vec<AgentMessage> {
+    let message = |kind| AgentMessage {
+        seq: 0,
+        pty,
+        time: 0,
+        kind,
+        text: None,
+        tool: None,
+        tool_use_id: None,
+        input: None,
+        is_error: false,
+    };
+
+    match event["type"].as_str() {
+        Some("system") => vec![AgentMessage {
+            text: string(event, "subtype"),
+            ..message(MessageKind::System)
+        }],
+        Some("result") => vec![AgentMessage {
+            text: string(event, "result"),
+            is_error: event["is_error"].as_bool().unwrap_or(false),
+            ..message(MessageKind::Result)
+        }],
+        Some(role @ ("user" | "assistant")) => {
+            let kind = if role == "user" {
+                MessageKind::User
+            } else {
+                MessageKind::Assistant
+            };
+            let blocks = match &event["message"]["content"] {
+                Value::String(text) => {
+                    return vec![AgentMessage {
+                        text: Some(truncate(text)),
+                        ..message(kind)
+                    }]
+                }
+                Value::Array(blocks) => blocks,
+                _ => return Vec::new(),
+            };
+            blocks
+                .iter()
+                .filter_map(|block| match block["type"].as_str()? {
+                    "text" => Some(AgentMessage {
+                        text: string(block, "text"),
+                        ..message(kind)
+                    }),
+                    "tool_use" => {
+                        let mut input = block.get("input").cloned();
+                        if let Some(input) = &mut input {
+                            map_strings(input, &truncate);
+                        }
+                        Some(AgentMessage {
+                            tool: string(block, "name"),
+                            tool_use_id: string(block, "id"),
+                            input,
+                            ..message(MessageKind::ToolUse)
+                        })
+                    }
+                    "tool_result" => Some(AgentMessage {
+                        text: content_text(&block["content"]),
+                        tool_use_id: string(block, "tool_use_id"),
+                        is_error: block["is_error"].as_bool().unwrap_or(false),
+                        ..message(MessageKind::ToolResult)
+                    }),
+                    // Thinking and other blocks are not kept
+                    _ => None,
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn string(value: &Value, key: &str) -> Option<String> {
+    value.get(key).and_then(Value::as_str).map(truncate)
+}
+
+/// A tool result's content: a string, or text blocks
+fn content_text(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(truncate(text)),
+        Value::Array(blocks) => {
+            let texts: Vec<&str> = blocks.iter().filter_map(|b| b["text"].as_str()).collect();
+            Some(truncate(&texts.join("\n")))
+        }
+        _ => None,
+    }
+}
+
+fn truncate(s: &str) -> String {
+    if s.len() <= MAX_TEXT {
+        return s.to_string();
+    }
+    let mut end = MAX_TEXT;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &s[..end])
+}
+
+/// Apply `f` to every string in a JSON value
+fn map_strings(value: &mut Value, f: &impl Fn(&str) -> String) {
+    match value {
+        Value::String(s) => *s = f(s),
+        Value::Array(items) => items.iter_mut().for_each(|v| map_strings(v, f)),
+        Value::Object(map) => map.values_mut().for_each(|v| map_strings(v, f)),
+        _ => {}
+    }
+}
+
+/// Mask a message for web and tunnel clients, like the box's output
+pub fn redact(message: &mut AgentMessage, rules: &RedactRules) {
+    let mask = |s: &str| String::from_utf8_lossy(&rules.redact(s.as_bytes())).into_owned();
+    for text in [&mut message.text, &mut message.tool].into_iter().flatten() {
+        *text = mask(text);
+    }
+    if let Some(input) = &mut message.input {
+        map_strings(input, &mask);
+    }
+}
+
+/// The messages of a box's `stream-json` agents
+#[derive(Debug)]
+pub struct Transcript {
+    messages: Mutex<VecDeque<AgentMessage>>,
+    /// Each message as it is added
+    added: broadcast::Sender<AgentMessage>,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            added: broadcast::channel(256).0,
+        }
+    }
+}
+
+impl Transcript {
+    /// Add a message, numbering and timestamping it
+    pub fn push(&self, mut message: AgentMessage) {
+        let mut messages = self.messages.lock().unwrap();
+        message.seq = messages.back().map_or(1, |m| m.seq + 1);
+        message.time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if messages.len() == MAX_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(message.clone());
+        let _ = self.added.send(message);
+    }
+
+    /// Messages after `seq`, oldest first
+    pub fn since(&self, seq: u64) -> Vec<AgentMessage> {
+        let messages = self.messages.lock().unwrap();
+        messages.iter().filter(|m| m.seq > seq).cloned().collect()
+    }
+
+    /// Follow the transcript: the messages after `seq`, then each new one
+    pub fn subscribe(&self, seq: u64) -> (Vec<AgentMessage>, broadcast::Receiver<AgentMessage>) {
+        let messages = self.messages.lock().unwrap();
+        let backlog = messages.iter().filter(|m| m.seq > seq).cloned().collect();
+        (backlog, self.added.subscribe())
+    }
+}
+
+/// Parse a `stream-json` agent's output into `transcript` until its PTY is
+/// gone. `output` must be subscribed before the PTY reader starts.
+pub fn spawn_parser(mut output: broadcast::Receiver<Bytes>, pty: u32, transcript: Arc<Transcript>) {
+    tokio::spawn(async move {
+        let mut parser = StreamJson::new(pty);
+        loop {
+            match output.recv().await {
+                Ok(data) => {
+                    for message in parser.push(&data) {
+                        transcript.push(message);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(pty, missed, "Agent message parser fell behind");
+                    parser.reset();
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream_json() {
+        let mut parser = StreamJson::new(0);
+        let output = concat!(
+            "{\"type\":\"system\",\"subtype\":\"init\",\"model\":\"m\"}\r\n",
+            "\x1b[?25lnot json\r\n",
+            "{\"type\":\"assistant\",\"message\":{\"content\":[",
+            "{\"type\":\"thinking\",\"thinking\":\"hmm\"},",
+            "{\"type\":\"text\",\"text\":\"Running the tests\"},",
+            "{\"type\":\"tool_use\",\"id\":\"t1\",\"name\":\"Bash\",\"input\":{\"command\":\"cargo test\"}}]}}\r\n",
+            "{\"type\":\"user\",\"message\":{\"content\":[{\"type\":\"tool_result\",",
+            "\"tool_use_id\":\"t1\",\"is_error\":true,\"content\":[{\"type\":\"text\",\"text\":\"FAILED\"}]}]}}\r\n",
+            "{\"type\":\"result\",\"subtype\":\"success\",\"result\":\"Done\"}\r\n",
+        );
+        // Split mid-line
+        let (first, rest) = output.as_bytes().split_at(90);
+        let mut messages = parser.push(first);
+        messages.extend(parser.push(rest));
+
+        let kinds: Vec<MessageKind> = messages.iter().map(|m| m.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MessageKind::System,
+                MessageKind::Assistant,
+                MessageKind::ToolUse,
+                MessageKind::ToolResult,
+                MessageKind::Result,
+            ]
+        );
+        assert_eq!(messages[0].text.as_deref(), Some("init"));
+        assert_eq!(messages[1].text.as_deref(), Some("Running the tests"));
+        assert_eq!(messages[2].tool.as_deref(), Some("Bash"));
+        assert_eq!(
+            messages[2].input,
+            Some(serde_json::json!({"command": "cargo test"}))
+        );
+        assert_eq!(messages[3].tool_use_id.as_deref(), Some("t1"));
+        assert_eq!(messages[3].text.as_deref(), Some("FAILED"));
+        assert!(messages[3].is_error);
+        assert_eq!(messages[4].text.as_deref(), Some("Done"));
+    }
+
+    #[test]
+    fn test_transcript() {
+        let transcript = Transcript::default();
+        let mut parser = StreamJson::new(1);
+        for text in ["one", "two", "three"] {
+            let line = format!(
+                "{{\"type\":\"user\",\"message\":{{\"content\":\"{}\"}}}}\n",
+                text
+            );
+            for message in parser.push(line.as_bytes()) {
+                transcript.push(message);
+            }
+        }
+
+        let (backlog, mut added) = transcript.subscribe(1);
+        let seqs: Vec<u64> = backlog.iter().map(|m| m.seq).collect();
+        assert_eq!(seqs, vec![2, 3]);
+        assert_eq!(backlog[1].text.as_deref(), Some("three"));
+        assert_eq!(backlog[1].pty, 1);
+
+        transcript.push(
+            parser
+                .push(b"{\"type\":\"result\",\"result\":\"ok\"}\n")
+                .remove(0),
+        );
+        assert_eq!(added.try_recv().unwrap().seq, 4);
+        assert!(transcript.since(4).is_empty());
+    }
+}
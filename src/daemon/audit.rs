@@ -0,0 +1,416 @@
+//! Per-box audit log: `~/.coop/sessions/<name>/audit.log`, one JSON object
+//! per line recording which client (and token) sent input, resized, had
+//! input blocked, restarted or killed something. Only ever appended to, and
+//! kept when the box is killed. Read back by `coop audit`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, mask_bytes, partial_secret_len};
+use crate::ipc::ClientKind;
+
+/// Who did something to a box
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Actor {
+    /// `None` for the daemon itself (auto-restarts, task timeouts)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<ClientKind>,
+    /// Web token name, or the paired device of a tunnel client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Actor {
+    pub fn local() -> Self {
+        Self {
+            client: Some(ClientKind::Local),
+            token: None,
+        }
+    }
+
+    pub fn web(token: &str) -> Self {
+        Self {
+            client: Some(ClientKind::Web),
+            token: Some(token.to_string()),
+        }
+    }
+
+    pub fn tunnel(device: Option<&str>) -> Self {
+        Self {
+            client: Some(ClientKind::Tunnel),
+            token: device.map(str::to_string),
+        }
+    }
+}
+
+/// `web:alice`, `tunnel`, `local`; `daemon` for the daemon itself
+impl std::fmt::Display for Actor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.client, &self.token) {
+            (Some(client), Some(token)) => write!(f, "{}:{}", client, token),
+            (Some(client), None) => write!(f, "{}", client),
+            (None, _) => f.write_str("daemon"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Input written to the PTY, escaped; `data` is left out with
+    /// `[audit] redact_input`. Input that may start a secret is held back
+    /// and logged with the client's next input, so `data` can lag `bytes`.
+    Input {
+        bytes: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<String>,
+    },
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+    /// Input dropped by the input filter, escaped and masked like `Input`;
+    /// `sequence` is left out with `[audit] redact_input`
+    Blocked {
+        #[serde(default)]
+        bytes: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sequence: Option<String>,
+    },
+    Restart,
+    /// A PTY, or the whole box when `pty` is absent
+    Kill,
+}
+
+impl AuditAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Input { .. } => "input",
+            AuditAction::Resize { .. } => "resize",
+            AuditAction::Blocked { .. } => "blocked",
+            AuditAction::Restart => "restart",
+            AuditAction::Kill => "kill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Unix time in milliseconds
+    pub ts: u64,
+    #[serde(flatten)]
+    pub actor: Actor,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pty: Option<u32>,
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+/// The audit log of one box
+#[derive(Debug)]
+pub struct AuditLog {
+    /// `None` if the log could not be opened; auditing is then skipped
+    file: Mutex<Option<File>>,
+    redact_input: bool,
+    /// Secret values replaced in logged input
+    masks: Vec<Vec<u8>>,
+    /// Per client and PTY, input held back because a secret may continue
+    /// in the next write
+    held: Mutex<HashMap<(Actor, u32), Vec<u8>>>,
+}
+
+impl AuditLog {
+    /// Open (or create) the log of box `session`. Occurrences of `masks` in
+    /// input are logged as `********`.
+    pub fn open(session: &str, redact_input: bool, masks: Vec<Vec<u8>>) -> Self {
+        let file = path(session).and_then(|p| open_append(&p));
+        if let Err(e) = &file {
+            tracing::warn!(session = %session, error = %e, "Cannot open audit log");
+        }
+        Self {
+            file: Mutex::new(file.ok()),
+            redact_input,
+            masks,
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, actor: &Actor, pty: Option<u32>, action: AuditAction) {
+        let entry = AuditEntry {
+            ts: now_ms(),
+            actor: actor.clone(),
+            pty,
+            action,
+        };
+        let Ok(mut line) = serde_json::to_vec(&entry) else {
+            return;
+        };
+        line.push(b'\n');
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            if let Err(e) = file.write_all(&line) {
+                tracing::warn!(error = %e, "Failed to write audit log");
+            }
+        }
+    }
+
+    pub fn input(&self, actor: &Actor, pty: u32, data: &[u8]) {
+        let logged = (!self.redact_input).then(|| {
+            let key = (actor.clone(), pty);
+            let mut held = self.held.lock().unwrap();
+            let mut input = held.remove(&key).unwrap_or_default();
+            input.extend_from_slice(data);
+            let mut masked = mask_bytes(&input, &self.masks);
+            let partial = partial_secret_len(&masked, &self.masks);
+            if partial > 0 {
+                held.insert(key, masked.split_off(masked.len() - partial));
+            }
+            escape(&masked)
+        });
+        self.record(
+            actor,
+            Some(pty),
+            AuditAction::Input {
+                bytes: data.len(),
+                data: logged,
+            },
+        );
+    }
+
+    /// Record input that was dropped, masked and redacted as `input` is
+    pub fn blocked(&self, actor: &Actor, pty: u32, data: &[u8]) {
+        let sequence = (!self.redact_input).then(|| escape(&mask_bytes(data, &self.masks)));
+        self.record(
+            actor,
+            Some(pty),
+            AuditAction::Blocked {
+                bytes: data.len(),
+                sequence,
+            },
+        );
+    }
+}
+
+impl Drop for AuditLog {
+    /// Log input still held back; it never turned into a secret
+    fn drop(&mut self) {
+        let held = std::mem::take(self.held.get_mut().unwrap());
+        for ((actor, pty), data) in held {
+            self.record(
+                &actor,
+                Some(pty),
+                AuditAction::Input {
+                    bytes: 0,
+                    data: Some(escape(&data)),
+                },
+            );
+        }
+    }
+}
+
+/// `~/.coop/sessions/<name>/audit.log`
+pub fn path(session: &str) -> Result<PathBuf> {
+    Ok(config::session_dir(session)?.join("audit.log"))
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)?)
+}
+
+/// Read a log, skipping lines that do not parse (e.g. a torn last line)
+pub fn read(path: &Path) -> Result<Vec<AuditEntry>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+/// Which entries `coop audit` shows
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub client: Option<ClientKind>,
+    pub token: Option<String>,
+    pub action: Option<String>,
+    pub pty: Option<u32>,
+    /// Unix time in milliseconds
+    pub since: Option<u64>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.client.map_or(true, |c| entry.actor.client == Some(c))
+            && self
+                .token
+                .as_ref()
+                .map_or(true, |t| entry.actor.token.as_ref() == Some(t))
+            && self
+                .action
+                .as_ref()
+                .map_or(true, |a| a == entry.action.name())
+            && self.pty.map_or(true, |p| entry.pty == Some(p))
+            && self.since.map_or(true, |s| entry.ts >= s)
+    }
+}
+
+/// Printable form of raw input: ASCII as is, everything else escaped
+/// (`exit\r` becomes `exit\\r`)
+pub fn escape(data: &[u8]) -> String {
+    data.iter()
+        .flat_map(|b| std::ascii::escape_default(*b))
+        .map(char::from)
+        .collect()
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_round_trip() {
+        let path = std::env::temp_dir().join(format!("coop-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog {
+            file: Mutex::new(Some(open_append(&path).unwrap())),
+            redact_input: false,
+            masks: vec![b"hunter2".to_vec()],
+            held: Mutex::new(HashMap::new()),
+        };
+        let web = Actor::web("ci");
+        log.input(&web, 0, b"ls\r");
+        log.input(&web, 0, b"pw=hunter2\r");
+        log.blocked(&web, 0, b"\x04");
+        log.record(&Actor::default(), None, AuditAction::Kill);
+        // A line the input filter blocked
+        log.blocked(&web, 0, b"curl -u me:hunter2 x\r");
+
+        let entries = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(
+            entries[0].action,
+            AuditAction::Input {
+                bytes: 3,
+                data: Some("ls\\r".to_string()),
+            }
+        );
+        assert_eq!(entries[0].actor, web);
+        assert_eq!(
+            entries[1].action,
+            AuditAction::Input {
+                bytes: 11,
+                data: Some("pw=********\\r".to_string()),
+            }
+        );
+        assert_eq!(entries[3].actor.client, None);
+
+        let filter = AuditFilter {
+            client: Some(ClientKind::Web),
+            action: Some("blocked".to_string()),
+            ..Default::default()
+        };
+        let shown: Vec<_> = entries.iter().filter(|e| filter.matches(e)).collect();
+        assert_eq!(shown.len(), 2);
+        assert_eq!(
+            shown[0].action,
+            AuditAction::Blocked {
+                bytes: 1,
+                sequence: Some("\\x04".to_string()),
+            }
+        );
+        assert_eq!(
+            shown[1].action,
+            AuditAction::Blocked {
+                bytes: 21,
+                sequence: Some("curl -u me:******** x\\r".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit_redacts_blocked_input() {
+        let path =
+            std::env::temp_dir().join(format!("coop-audit-redact-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog {
+            file: Mutex::new(Some(open_append(&path).unwrap())),
+            redact_input: true,
+            masks: vec![b"hunter2".to_vec()],
+            held: Mutex::new(HashMap::new()),
+        };
+        // A batch held for approval and rejected by the local terminal
+        log.blocked(&Actor::web("ci"), 0, b"su -c 'hunter2'\r");
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let entries = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!text.contains("hunter2") && !text.contains("su -c"));
+        assert_eq!(
+            entries[0].action,
+            AuditAction::Blocked {
+                bytes: 16,
+                sequence: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit_masks_input_across_writes() {
+        let path =
+            std::env::temp_dir().join(format!("coop-audit-bytes-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog {
+            file: Mutex::new(Some(open_append(&path).unwrap())),
+            redact_input: false,
+            masks: vec![b"hunter2".to_vec()],
+            held: Mutex::new(HashMap::new()),
+        };
+        let web = Actor::web("ci");
+        // Typed a byte at a time, interleaved with another client
+        for byte in b"pw=hunter2\r" {
+            log.input(&web, 0, std::slice::from_ref(byte));
+            log.input(&Actor::local(), 0, b"h");
+        }
+        // A prefix that never becomes a secret is logged in the end
+        log.input(&web, 1, b"hunt");
+        drop(log);
+
+        let entries = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let typed = |actor: &Actor, pty: u32| -> String {
+            entries
+                .iter()
+                .filter(|e| &e.actor == actor && e.pty == Some(pty))
+                .filter_map(|e| match &e.action {
+                    AuditAction::Input { data, .. } => data.clone(),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(typed(&web, 0), "pw=********\\r");
+        assert_eq!(typed(&web, 1), "hunt");
+        assert_eq!(typed(&Actor::local(), 0), "h".repeat(11));
+    }
+}
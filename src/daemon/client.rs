@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use anyhow::{bail, Context, Result};
 use bytes::Bytes;
 use futures_util::{SinkExt, StreamExt};
@@ -6,8 +8,9 @@ use tokio::net::UnixStream;
 use tokio_util::codec::Framed;
 
 use crate::ipc::{
-    Command, DaemonEvent, MessageCodec, Response, StreamCodec, StreamFrame, VersionHandshake,
-    VersionResponse, FRAME_CONTROL, FRAME_PTY_DATA, PROTOCOL_VERSION,
+    AgentMessage, Command, DaemonEvent, DeviceScope, MessageCodec, PtyRole, Response, SessionEvent,
+    StreamCodec, StreamFrame, TokenScope, VersionHandshake, VersionResponse, FRAME_CONTROL,
+    FRAME_PTY_DATA, PROTOCOL_VERSION,
 };
 use base64::Engine;
 
@@ -62,7 +65,10 @@ impl DaemonClient {
     async fn send_command(&mut self, cmd: &Command) -> Result<Response> {
         let json = serde_json::to_vec(cmd)?;
         self.framed.send(Bytes::from(json)).await?;
+        self.recv_response().await
+    }
 
+    async fn recv_response(&mut self) -> Result<Response> {
         let resp = self
             .framed
             .next()
@@ -135,11 +141,21 @@ impl DaemonClient {
         Ok(())
     }
 
-    pub async fn attach(mut self, session: &str, pty: u32) -> Result<()> {
+    pub async fn attach(self, session: &str, pty: u32) -> Result<()> {
+        self.attach_target(session, pty, None).await
+    }
+
+    /// Attach to an agent by name (`coop attach --agent <agent>`)
+    pub async fn attach_agent(self, session: &str, agent: &str) -> Result<()> {
+        self.attach_target(session, 0, Some(agent)).await
+    }
+
+    async fn attach_target(mut self, session: &str, pty: u32, agent: Option<&str>) -> Result<()> {
         let (cols, rows) = terminal_size();
         let cmd = Command::Attach {
             session: session.to_string(),
             pty,
+            agent: agent.map(|s| s.to_string()),
             cols,
             rows,
         };
@@ -148,6 +164,7 @@ impl DaemonClient {
             bail!("Failed to attach: {}", resp.message.unwrap_or_default());
         }
 
+        let pty = resp.data.pty.unwrap_or(pty);
         self.enter_stream_mode(session, pty).await
     }
 
@@ -240,18 +257,33 @@ impl DaemonClient {
                 println!("No running boxes.");
             } else {
                 println!(
-                    "{:<12} {:<30} {:<10} {:<6} {:<15} AGE",
-                    "BOX", "WORKSPACE", "STATE", "PTYS", "CLIENTS"
+                    "{:<12} {:<30} {:<10} {:<6} {:<15} {:<8} SERVICES",
+                    "BOX", "WORKSPACE", "STATE", "PTYS", "CLIENTS", "AGE"
                 );
                 for s in sessions {
+                    let services: Vec<String> = s
+                        .ptys
+                        .iter()
+                        .filter_map(|p| match (&p.role, p.status) {
+                            (PtyRole::Service(name), Some(status)) => {
+                                Some(format!("{} ({})", name, status))
+                            }
+                            _ => None,
+                        })
+                        .collect();
                     println!(
-                        "{:<12} {:<30} {:<10} {:<6} {:<15} {}",
+                        "{:<12} {:<30} {:<10} {:<6} {:<15} {:<8} {}",
                         s.name,
                         truncate(&s.workspace, 28),
                         "running",
                         s.ptys.len(),
                         format!("{} local, {} web", s.local_clients, s.web_clients),
                         format_age(s.created),
+                        if services.is_empty() {
+                            "-".to_string()
+                        } else {
+                            services.join(", ")
+                        },
                     );
                 }
             }
@@ -307,7 +339,7 @@ impl DaemonClient {
                 println!("No running boxes.");
             } else {
                 println!(
-                    "{:<12} {:<6} {:<8} {:<20} PID",
+                    "{:<12} {:<6} {:<16} {:<20} PID",
                     "BOX", "ID", "ROLE", "COMMAND"
                 );
                 for s in sessions {
@@ -316,13 +348,13 @@ impl DaemonClient {
                             .pid
                             .map(|p| p.to_string())
                             .unwrap_or_else(|| "-".to_string());
-                        let role = match p.role {
-                            crate::ipc::PtyRole::Agent => "agent",
-                            crate::ipc::PtyRole::Shell => "shell",
-                        };
                         println!(
-                            "{:<12} {:<6} {:<8} {:<20} {}",
-                            s.name, p.id, role, p.command, pid_str
+                            "{:<12} {:<6} {:<16} {:<20} {}",
+                            s.name,
+                            p.id,
+                            p.role.to_string(),
+                            p.command,
+                            pid_str
                         );
                     }
                 }
@@ -347,17 +379,19 @@ impl DaemonClient {
             if ptys.is_empty() {
                 println!("No sessions in box '{}'.", box_name);
             } else {
-                println!("{:<6} {:<8} {:<20} PID", "ID", "ROLE", "COMMAND");
+                println!("{:<6} {:<16} {:<20} PID", "ID", "ROLE", "COMMAND");
                 for p in ptys {
                     let pid_str = p
                         .pid
                         .map(|p| p.to_string())
                         .unwrap_or_else(|| "-".to_string());
-                    let role = match p.role {
-                        crate::ipc::PtyRole::Agent => "agent",
-                        crate::ipc::PtyRole::Shell => "shell",
-                    };
-                    println!("{:<6} {:<8} {:<20} {}", p.id, role, p.command, pid_str);
+                    println!(
+                        "{:<6} {:<16} {:<20} {}",
+                        p.id,
+                        p.role.to_string(),
+                        p.command,
+                        pid_str
+                    );
                 }
             }
         }
@@ -380,11 +414,36 @@ impl DaemonClient {
         Ok(())
     }
 
-    pub async fn serve(mut self, port: u16, host: &str, token: Option<&str>) -> Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn serve(
+        mut self,
+        port: u16,
+        host: &str,
+        token: Option<&str>,
+        tls: bool,
+        cert: Option<&Path>,
+        key: Option<&Path>,
+        metrics_port: Option<u16>,
+        proxy_port: Option<u16>,
+    ) -> Result<()> {
+        // The daemon runs elsewhere, so hand it absolute paths
+        let absolute = |path: Option<&Path>| -> Result<Option<String>> {
+            path.map(|p| {
+                std::fs::canonicalize(p)
+                    .with_context(|| format!("Cannot find {}", p.display()))
+                    .map(|p| p.to_string_lossy().into_owned())
+            })
+            .transpose()
+        };
         let cmd = Command::Serve {
             port,
             host: host.to_string(),
             token: token.map(|s| s.to_string()),
+            tls,
+            cert: absolute(cert)?,
+            key: absolute(key)?,
+            metrics_port,
+            proxy_port,
         };
         let resp = self.send_command(&cmd).await?;
         if !resp.ok {
@@ -397,12 +456,60 @@ impl DaemonClient {
         let token = resp.data.token.unwrap_or_default();
         let host = resp.data.host.unwrap_or_else(|| host.to_string());
         let port = resp.data.port.unwrap_or(port);
+        let scheme = if tls { "https" } else { "http" };
+
+        // Bound to all interfaces: show loopback plus the LAN address
+        let wildcard = host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_unspecified());
+        let local_host = if wildcard { "127.0.0.1" } else { host.as_str() };
+        let network = if wildcard {
+            crate::web::server::lan_address()
+        } else {
+            None
+        };
 
         println!();
         println!("  \u{1f414} Coop web UI");
         println!();
-        println!("  Local:   http://{}:{}?token={}", host, port, token);
-        // TODO: detect network address for LAN URL
+        println!(
+            "  Local:   {}://{}:{}?token={}",
+            scheme, local_host, port, token
+        );
+        if let Some(ip) = network {
+            println!("  Network: {}://{}:{}?token={}", scheme, ip, port, token);
+        }
+        if let Some(metrics_port) = metrics_port {
+            println!("  Metrics: http://{}:{}/metrics", local_host, metrics_port);
+        }
+        if let Some(proxy_port) = proxy_port {
+            println!(
+                "  Proxy:   {}://{}:{}/box/NAME/port/PORT/",
+                scheme, local_host, proxy_port
+            );
+        }
+        if let Some(fingerprint) = &resp.data.fingerprint {
+            let origin = if resp.data.self_signed == Some(true) {
+                " (self-signed)"
+            } else {
+                ""
+            };
+            println!("  TLS:     SHA-256 {}{}", fingerprint, origin);
+            println!();
+
+            // The fragment never reaches the server; it lets the scanning
+            // device compare the certificate it is shown
+            let url = format!(
+                "https://{}:{}?token={}#fp={}",
+                network.map_or(local_host.to_string(), |ip| ip.to_string()),
+                port,
+                token,
+                fingerprint.replace(':', "")
+            );
+            for line in crate::tunnel::signaling::generate_qr_terminal(&url).lines() {
+                println!("  {}", line);
+            }
+        }
         println!();
 
         // Block until Ctrl+C
@@ -410,9 +517,357 @@ impl DaemonClient {
         Ok(())
     }
 
-    pub async fn tunnel(self, _stun: Option<&str>, _no_stun: bool, _no_qr: bool) -> Result<()> {
-        // TODO: implement WebRTC tunnel
-        bail!("Tunnel support not yet implemented");
+    pub async fn tunnel(
+        mut self,
+        stun: Option<&str>,
+        no_stun: bool,
+        loopback: bool,
+        signal: Option<&str>,
+        scope: DeviceScope,
+        no_qr: bool,
+    ) -> Result<()> {
+        let resp = self
+            .send_command(&Command::Tunnel {
+                stun: stun.map(str::to_string),
+                no_stun,
+                loopback,
+                signal: signal.map(str::to_string),
+                scope,
+            })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to create tunnel: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        let url = resp.data.qr_data.unwrap_or_default();
+        let short_code = resp.data.short_code;
+
+        println!();
+        println!("  \u{1f414} Coop tunnel ready");
+        println!();
+        if !no_qr {
+            println!("  Scan to connect:");
+            println!();
+            for line in crate::tunnel::signaling::generate_qr_terminal(&url).lines() {
+                println!("  {}", line);
+            }
+            println!();
+        }
+        println!("  Or open: {}", url);
+        println!();
+
+        // The tunnel is torn down when this connection closes, so Ctrl+C
+        // (which exits the process) is all it takes to stop it
+        let resp = if let Some(code) = short_code {
+            // The daemon picks up the answer from the signal server itself
+            println!("  Short code: {}", code);
+            println!("  Waiting for the browser to connect...");
+            self.recv_response().await?
+        } else {
+            println!("  Paste the answer from the connect page, then press Enter:");
+            let answer = tokio::task::spawn_blocking(read_answer).await??;
+            self.send_command(&Command::TunnelAnswer { answer }).await?
+        };
+        if !resp.ok {
+            bail!(
+                "Tunnel failed: {}\n\nIf the browser can't reach this machine directly, try \
+                 `coop serve` on the LAN or configure port forwarding.",
+                resp.message.unwrap_or_default()
+            );
+        }
+
+        println!();
+        println!("  Connected. Press Ctrl+C to close the tunnel.");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = self.framed.next() => println!("  Peer disconnected"),
+        }
+        Ok(())
+    }
+
+    pub async fn device_ls(mut self, json: bool) -> Result<()> {
+        let resp = self.send_command(&Command::DeviceLs).await?;
+        if !resp.ok {
+            bail!(
+                "Failed to list devices: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+
+        let devices = resp.data.devices.unwrap_or_default();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&devices)?);
+        } else if devices.is_empty() {
+            println!("No paired devices.");
+        } else {
+            println!(
+                "{:<10} {:<20} {:<24} {:<8} {:<10} FINGERPRINT",
+                "ID", "NAME", "ACCESS", "PAIRED", "LAST SEEN"
+            );
+            for d in &devices {
+                let mut access = if d.scope.sessions.is_empty() {
+                    "all boxes".to_string()
+                } else {
+                    d.scope.sessions.join(",")
+                };
+                if d.scope.read_only {
+                    access.push_str(" (read-only)");
+                }
+                let last_seen = match (d.connected, d.last_seen) {
+                    (true, _) => "connected".to_string(),
+                    (false, Some(t)) => format_age(t),
+                    (false, None) => "-".to_string(),
+                };
+                println!(
+                    "{:<10} {:<20} {:<24} {:<8} {:<10} {}",
+                    d.id,
+                    truncate(&d.name, 20),
+                    truncate(&access, 24),
+                    format_age(d.paired),
+                    last_seen,
+                    truncate(&d.fingerprint, 23),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn device_revoke(mut self, id: &str) -> Result<()> {
+        let resp = self
+            .send_command(&Command::DeviceRevoke { id: id.to_string() })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to revoke device: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        println!("Device {} revoked", id);
+        Ok(())
+    }
+
+    pub async fn token_create(
+        mut self,
+        name: &str,
+        scope: TokenScope,
+        sessions: Vec<String>,
+        expires: Option<std::time::Duration>,
+    ) -> Result<()> {
+        let resp = self
+            .send_command(&Command::TokenCreate {
+                name: name.to_string(),
+                scope,
+                sessions,
+                expires_secs: expires.map(|d| d.as_secs()),
+            })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to create token: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        println!("{}", resp.data.token.unwrap_or_default());
+        eprintln!(
+            "Token '{}' created ({}). It will not be shown again.",
+            name, scope
+        );
+        Ok(())
+    }
+
+    pub async fn share(
+        mut self,
+        session: &str,
+        pty: Option<u32>,
+        expires: std::time::Duration,
+    ) -> Result<()> {
+        let resp = self
+            .send_command(&Command::Share {
+                session: session.to_string(),
+                pty,
+                expires_secs: Some(expires.as_secs()),
+            })
+            .await?;
+        if !resp.ok {
+            bail!("Failed to share: {}", resp.message.unwrap_or_default());
+        }
+
+        let token = resp.data.token.unwrap_or_default();
+        let name = resp
+            .data
+            .tokens
+            .and_then(|t| t.into_iter().next())
+            .map(|t| t.name)
+            .unwrap_or_default();
+        let session = resp.data.session.unwrap_or_else(|| session.to_string());
+        let what = match pty {
+            Some(pty) => format!("PTY {} in '{}'", pty, session),
+            None => format!("'{}'", session),
+        };
+
+        println!();
+        println!(
+            "  Read-only link for {} (expires in {}):",
+            what,
+            format_duration(expires.as_secs())
+        );
+        println!();
+        match resp.data.url {
+            Some(url) => println!("  {}", url),
+            None => {
+                println!("  Token: {}", token);
+                println!();
+                println!("  No web server is running. Start one with `coop serve`, then open");
+                println!("  /?token=<token>&session={} on it.", session);
+            }
+        }
+        println!();
+        println!("  Revoke with: coop token revoke {}", name);
+        println!();
+        Ok(())
+    }
+
+    pub async fn token_ls(mut self, json: bool) -> Result<()> {
+        let resp = self.send_command(&Command::TokenLs).await?;
+        if !resp.ok {
+            bail!(
+                "Failed to list tokens: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+
+        let tokens = resp.data.tokens.unwrap_or_default();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&tokens)?);
+        } else if tokens.is_empty() {
+            println!("No tokens.");
+        } else {
+            println!(
+                "{:<20} {:<8} {:<24} {:<8} EXPIRES",
+                "NAME", "SCOPE", "BOXES", "CREATED"
+            );
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            for t in &tokens {
+                let mut boxes = if t.sessions.is_empty() {
+                    "all".to_string()
+                } else {
+                    t.sessions.join(",")
+                };
+                if let Some(pty) = t.pty {
+                    boxes.push_str(&format!(" (pty {})", pty));
+                }
+                let expires = match t.expires {
+                    None => "never".to_string(),
+                    Some(e) if e <= now => "expired".to_string(),
+                    Some(e) => format!("in {}", format_duration(e - now)),
+                };
+                println!(
+                    "{:<20} {:<8} {:<24} {:<8} {}",
+                    truncate(&t.name, 20),
+                    t.scope,
+                    truncate(&boxes, 24),
+                    format_age(t.created),
+                    expires,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn token_revoke(mut self, name: &str) -> Result<()> {
+        let resp = self
+            .send_command(&Command::TokenRevoke {
+                name: name.to_string(),
+            })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to revoke token: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        println!("Token {} revoked", name);
+        Ok(())
+    }
+
+    /// Print session events as they happen, until interrupted
+    pub async fn events(mut self, session: Option<&str>, json: bool) -> Result<()> {
+        let resp = self
+            .send_command(&Command::Subscribe {
+                session: session.map(str::to_string),
+            })
+            .await?;
+        if !resp.ok {
+            bail!("Failed to subscribe: {}", resp.message.unwrap_or_default());
+        }
+
+        while let Some(msg) = self.framed.next().await {
+            let event: SessionEvent = serde_json::from_slice(&msg.context("Read error")?)?;
+            if json {
+                println!("{}", serde_json::to_string(&event)?);
+            } else {
+                println!(
+                    "{:<16} {:<16} {}",
+                    event.name(),
+                    event.session().unwrap_or("-"),
+                    describe_event(&event)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Print a box's agent messages after `since`, then with `follow` each
+    /// new one until interrupted
+    pub async fn messages(
+        mut self,
+        session: &str,
+        since: u64,
+        follow: bool,
+        json: bool,
+    ) -> Result<()> {
+        let resp = self
+            .send_command(&Command::Messages {
+                session: session.to_string(),
+                since,
+                follow,
+            })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to get messages: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+
+        let print = |message: &AgentMessage| -> Result<()> {
+            if json {
+                println!("{}", serde_json::to_string(message)?);
+            } else {
+                println!(
+                    "{:<6} {:<12} {}",
+                    message.seq,
+                    message.kind,
+                    describe_message(message)
+                );
+            }
+            Ok(())
+        };
+        for message in resp.data.messages.unwrap_or_default() {
+            print(&message)?;
+        }
+        if follow {
+            while let Some(msg) = self.framed.next().await {
+                print(&serde_json::from_slice(&msg.context("Read error")?)?)?;
+            }
+        }
+        Ok(())
     }
 
     pub async fn shutdown(mut self) -> Result<()> {
@@ -442,15 +897,40 @@ impl DaemonClient {
     }
 
     pub async fn logs(
+        self,
+        session: &str,
+        pty: u32,
+        follow: bool,
+        tail_lines: Option<usize>,
+    ) -> Result<()> {
+        self.logs_target(session, pty, None, follow, tail_lines)
+            .await
+    }
+
+    /// View a service's logs by name (`coop logs --service <name>`)
+    pub async fn service_logs(
+        self,
+        session: &str,
+        service: &str,
+        follow: bool,
+        tail_lines: Option<usize>,
+    ) -> Result<()> {
+        self.logs_target(session, 0, Some(service), follow, tail_lines)
+            .await
+    }
+
+    async fn logs_target(
         mut self,
         session: &str,
         pty: u32,
+        service: Option<&str>,
         follow: bool,
         tail_lines: Option<usize>,
     ) -> Result<()> {
         let cmd = Command::Logs {
             session: session.to_string(),
             pty,
+            service: service.map(|s| s.to_string()),
             follow,
             tail_lines,
         };
@@ -471,12 +951,134 @@ impl DaemonClient {
 
         if follow {
             // Enter stream mode to follow live output
+            let pty = resp.data.pty.unwrap_or(pty);
             self.enter_stream_mode(session, pty).await?;
         }
 
         Ok(())
     }
 
+    /// Run a headless task to completion. Returns the agent's exit code, or
+    /// 124 on timeout (like `timeout(1)`).
+    #[allow(clippy::too_many_arguments)]
+    pub async fn run_task(
+        mut self,
+        name: Option<&str>,
+        workspace: &str,
+        task: &str,
+        args: Vec<String>,
+        stdin: bool,
+        timeout_secs: Option<u64>,
+        output: &str,
+    ) -> Result<i32> {
+        let cmd = Command::Run {
+            name: name.map(|s| s.to_string()),
+            workspace: workspace.to_string(),
+            task: task.to_string(),
+            args,
+            stdin,
+            timeout_secs,
+            output: output.to_string(),
+        };
+        let resp = self.send_command(&cmd).await?;
+        if !resp.ok {
+            bail!("Failed to run task: {}", resp.message.unwrap_or_default());
+        }
+
+        let session = resp.data.session.unwrap_or_default();
+        let code = if resp.data.timed_out == Some(true) {
+            eprintln!("Task in box '{}' timed out", session);
+            124
+        } else {
+            let code = resp.data.exit_code.unwrap_or(1);
+            eprintln!("Task in box '{}' exited with code {}", session, code);
+            code
+        };
+        eprintln!("Results written to {}", output);
+        Ok(code)
+    }
+
+    pub async fn queue_add(
+        mut self,
+        tasks: Vec<crate::ipc::TaskSpec>,
+        concurrency: Option<usize>,
+    ) -> Result<()> {
+        let resp = self
+            .send_command(&Command::QueueAdd { tasks, concurrency })
+            .await?;
+        if !resp.ok {
+            bail!(
+                "Failed to queue tasks: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        let added = resp.data.tasks.unwrap_or_default();
+        for t in &added {
+            println!("Queued task {} ({})", t.id, truncate(&t.spec.task, 50));
+        }
+        if let Some(concurrency) = resp.data.concurrency {
+            println!("Running up to {} at a time", concurrency);
+        }
+        Ok(())
+    }
+
+    pub async fn queue_ls(mut self, json: bool) -> Result<()> {
+        let resp = self.send_command(&Command::QueueLs).await?;
+        if !resp.ok {
+            bail!("Failed to list queue: {}", resp.message.unwrap_or_default());
+        }
+
+        let tasks = resp.data.tasks.unwrap_or_default();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&tasks)?);
+        } else if tasks.is_empty() {
+            println!("Queue is empty.");
+        } else {
+            println!(
+                "{:<6} {:<10} {:<16} {:<36} {:<8} RESULT",
+                "ID", "STATE", "NAME", "TASK", "AGE"
+            );
+            for t in &tasks {
+                let result = match (&t.error, t.exit_code) {
+                    (Some(e), _) => e.clone(),
+                    (None, Some(code)) => format!("exit {}", code),
+                    (None, None) => "-".to_string(),
+                };
+                println!(
+                    "{:<6} {:<10} {:<16} {:<36} {:<8} {}",
+                    t.id,
+                    t.state.to_string(),
+                    truncate(t.spec.name.as_deref().unwrap_or("-"), 16),
+                    truncate(&t.spec.task, 34),
+                    format_age(t.created),
+                    result,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn queue_cancel(mut self, id: u64) -> Result<()> {
+        let resp = self.send_command(&Command::QueueCancel { id }).await?;
+        if !resp.ok {
+            bail!(
+                "Failed to cancel task: {}",
+                resp.message.unwrap_or_default()
+            );
+        }
+        println!("Task {} cancelled", id);
+        Ok(())
+    }
+
+    pub async fn queue_retry(mut self, id: u64) -> Result<()> {
+        let resp = self.send_command(&Command::QueueRetry { id }).await?;
+        if !resp.ok {
+            bail!("Failed to retry task: {}", resp.message.unwrap_or_default());
+        }
+        println!("Task {} queued again", id);
+        Ok(())
+    }
+
     pub async fn restart(mut self, session: &str, pty: u32) -> Result<()> {
         let cmd = Command::Restart {
             session: session.to_string(),
@@ -491,6 +1093,64 @@ impl DaemonClient {
         Ok(())
     }
 
+    pub async fn send_input(
+        mut self,
+        session: &str,
+        pty: u32,
+        text: String,
+        keys: Vec<String>,
+    ) -> Result<()> {
+        let cmd = Command::SendInput {
+            session: session.to_string(),
+            pty,
+            text,
+            keys,
+        };
+        let resp = self.send_command(&cmd).await?;
+        if !resp.ok {
+            bail!("Failed to send: {}", resp.message.unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    /// Wait for a PTY's output to match `pattern`, printing the match. Returns
+    /// the exit code for `coop wait`: 0 on a match, 2 if the PTY exits
+    /// first, 124 on timeout.
+    pub async fn wait(
+        mut self,
+        session: &str,
+        pty: u32,
+        pattern: String,
+        timeout: Option<std::time::Duration>,
+        scrollback: bool,
+    ) -> Result<i32> {
+        let cmd = Command::Wait {
+            session: session.to_string(),
+            pty,
+            pattern,
+            timeout_ms: timeout.map(|t| t.as_millis() as u64),
+            scrollback,
+        };
+        let resp = self.send_command(&cmd).await?;
+        if !resp.ok {
+            bail!("Failed to wait: {}", resp.message.unwrap_or_default());
+        }
+
+        if let Some(matched) = resp.data.matched {
+            println!("{}", matched);
+            Ok(0)
+        } else if resp.data.timed_out == Some(true) {
+            eprintln!("Timed out waiting for output to match");
+            Ok(124)
+        } else {
+            match resp.data.exit_code {
+                Some(code) => eprintln!("PTY {} exited with code {} before a match", pty, code),
+                None => eprintln!("PTY {} exited before a match", pty),
+            }
+            Ok(2)
+        }
+    }
+
     /// Enter stream mode for an attached PTY session.
     ///
     /// This upgrades the connection from MessageCodec to StreamCodec and bridges
@@ -549,6 +1209,11 @@ async fn run_stream_bridge(
     let mut stdout = tokio::io::stdout();
     let mut stdin_buf = [0u8; 4096];
 
+    // Remote input waiting for approval, oldest first, and whether Ctrl+]
+    // has started an approval chord
+    let mut approvals = std::collections::VecDeque::new();
+    let mut chord = false;
+
     // Set up SIGWINCH handler for terminal resize
     let mut sigwinch =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())?;
@@ -565,23 +1230,57 @@ async fn run_stream_bridge(
                     break;
                 }
 
-                // Check for escape character (Ctrl+])
-                if let Some(pos) = stdin_buf[..n].iter().position(|&b| b == ESCAPE_CHAR) {
+                // Ctrl+] detaches, or with remote input waiting for approval,
+                // starts a chord: y approves, n rejects, d or Ctrl+] detaches
+                let mut data = &stdin_buf[..n];
+                let mut detach = false;
+                while !data.is_empty() {
+                    if chord {
+                        chord = false;
+                        match data[0] {
+                            key @ (b'y' | b'n') => {
+                                if let Some(id) = approvals.pop_front() {
+                                    let answer = serde_json::to_vec(&Command::ApproveInput {
+                                        id,
+                                        approve: key == b'y',
+                                    })?;
+                                    sink.send(StreamFrame::control(Bytes::from(answer))).await?;
+                                    let msg = if key == b'y' { "approved" } else { "rejected" };
+                                    stdout.write_all(format!("\r\n\x1b[2m[input {}]\x1b[0m\r\n", msg).as_bytes()).await?;
+                                    stdout.flush().await?;
+                                }
+                            }
+                            b'd' | ESCAPE_CHAR => {
+                                detach = true;
+                                break;
+                            }
+                            _ => {}
+                        }
+                        data = &data[1..];
+                        continue;
+                    }
+                    let Some(pos) = data.iter().position(|&b| b == ESCAPE_CHAR) else {
+                        sink.send(StreamFrame::pty_data(Bytes::copy_from_slice(data))).await?;
+                        break;
+                    };
                     // Send any bytes before the escape char
                     if pos > 0 {
                         sink.send(StreamFrame::pty_data(
-                            Bytes::copy_from_slice(&stdin_buf[..pos]),
+                            Bytes::copy_from_slice(&data[..pos]),
                         )).await?;
                     }
-                    // Send detach control
+                    if approvals.is_empty() {
+                        detach = true;
+                        break;
+                    }
+                    chord = true;
+                    data = &data[pos + 1..];
+                }
+                if detach {
                     let detach = serde_json::to_vec(&Command::Detach)?;
                     sink.send(StreamFrame::control(Bytes::from(detach))).await?;
                     break;
                 }
-
-                sink.send(StreamFrame::pty_data(
-                    Bytes::copy_from_slice(&stdin_buf[..n]),
-                )).await?;
             }
 
             // daemon -> stdout (PTY data or control frames)
@@ -604,17 +1303,32 @@ async fn run_stream_bridge(
                                         stdout.flush().await?;
                                         break;
                                     }
-                                    Ok(DaemonEvent::PtyRestarting { delay_ms }) => {
+                                    Ok(DaemonEvent::PtyRestarting { .. }) => {
+                                        // The daemon already notes the restart in
+                                        // the PTY output. Stay connected -- new PTY
+                                        // output will follow
+                                    }
+                                    Ok(DaemonEvent::Detached) => {
+                                        break;
+                                    }
+                                    Ok(DaemonEvent::InputApproval { id, pty, client, input }) => {
+                                        approvals.push_back(id);
+                                        let mut preview: String = input.chars().take(200).collect();
+                                        if preview.len() < input.len() {
+                                            preview.push_str("...");
+                                        }
                                         let msg = format!(
-                                            "\x1b[2m[restarting in {}ms...]\x1b[0m\r\n",
-                                            delay_ms
+                                            "\r\n\x1b[1;33m[{} wants to send to pty {}: \"{}\"]\x1b[0m\r\n\x1b[2m[Ctrl+] y to approve, Ctrl+] n to reject]\x1b[0m\r\n",
+                                            client,
+                                            pty,
+                                            preview
                                         );
                                         stdout.write_all(msg.as_bytes()).await?;
                                         stdout.flush().await?;
-                                        // Stay connected -- new PTY output will follow
                                     }
-                                    Ok(DaemonEvent::Detached) => {
-                                        break;
+                                    Ok(DaemonEvent::InputApprovalDone { id }) => {
+                                        // Answered elsewhere, expired or withdrawn
+                                        approvals.retain(|&open| open != id);
                                     }
                                     Err(_) => {
                                         // Unknown control frame, ignore
@@ -648,6 +1362,58 @@ async fn run_stream_bridge(
     Ok(())
 }
 
+/// Details of an event for `coop events`
+fn describe_event(event: &SessionEvent) -> String {
+    match event {
+        SessionEvent::PtySpawned { pty, role, pid, .. } => {
+            let role = match role {
+                PtyRole::Agent(name) => format!("agent {}", name),
+                PtyRole::Service(name) => format!("service {}", name),
+                PtyRole::Shell => "shell".to_string(),
+            };
+            format!("pty {} ({}, pid {})", pty, role, pid)
+        }
+        SessionEvent::PtyExited { pty, code, .. } => match code {
+            Some(code) => format!("pty {} (exit {})", pty, code),
+            None => format!("pty {} (killed)", pty),
+        },
+        SessionEvent::PtyRestarting { pty, delay_ms, .. } => {
+            format!("pty {} (in {}ms)", pty, delay_ms)
+        }
+        SessionEvent::CrashLoop { pty, failures, .. } => {
+            format!(
+                "pty {} ({} fast failures, auto-restart stopped)",
+                pty, failures
+            )
+        }
+        SessionEvent::ClientAttached { client, .. }
+        | SessionEvent::ClientDetached { client, .. } => client.to_string(),
+        SessionEvent::Lagged { missed } => format!("{} events missed", missed),
+        SessionEvent::SessionCreated { .. } | SessionEvent::SessionKilled { .. } => String::new(),
+    }
+}
+
+/// One line for a message: the tool call, or the first line of its text
+fn describe_message(message: &AgentMessage) -> String {
+    let mut line = match (&message.tool, &message.input) {
+        (Some(tool), Some(input)) => format!("{} {}", tool, input),
+        (Some(tool), None) => tool.clone(),
+        _ => message
+            .text
+            .as_deref()
+            .and_then(|text| text.lines().find(|l| !l.trim().is_empty()))
+            .unwrap_or_default()
+            .to_string(),
+    };
+    if line.chars().count() > 120 {
+        line = line.chars().take(120).collect::<String>() + "...";
+    }
+    if message.is_error {
+        line.insert_str(0, "(error) ");
+    }
+    line
+}
+
 fn terminal_size() -> (u16, u16) {
     let mut ws = nix::libc::winsize {
         ws_row: 40,
@@ -661,9 +1427,12 @@ fn terminal_size() -> (u16, u16) {
     (ws.ws_col, ws.ws_row)
 }
 
+/// Shorten `s` to at most `max` characters, ending in `...` if cut. Counts
+/// characters, not bytes, so names and task text in any script are safe.
 fn truncate(s: &str, max: usize) -> String {
-    if s.len() > max {
-        format!("{}...", &s[..max - 3])
+    if s.chars().count() > max {
+        let kept: String = s.chars().take(max.saturating_sub(3)).collect();
+        format!("{}...", kept)
     } else {
         s.to_string()
     }
@@ -674,15 +1443,57 @@ fn format_age(created: u64) -> String {
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
         .as_secs();
-    let elapsed = now.saturating_sub(created);
-
-    if elapsed < 60 {
-        format!("{}s", elapsed)
-    } else if elapsed < 3600 {
-        format!("{}m", elapsed / 60)
-    } else if elapsed < 86400 {
-        format!("{}h {}m", elapsed / 3600, (elapsed % 3600) / 60)
+    format_duration(now.saturating_sub(created))
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else if secs < 86400 {
+        format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
     } else {
-        format!("{}d {}h", elapsed / 86400, (elapsed % 86400) / 3600)
+        format!("{}d {}h", secs / 86400, (secs % 86400) / 3600)
+    }
+}
+
+/// Read a pasted tunnel answer from stdin: a single encoded line, or raw SDP
+/// terminated by an empty line
+fn read_answer() -> Result<String> {
+    let stdin = std::io::stdin();
+    let mut answer = String::new();
+    for line in stdin.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            if answer.is_empty() {
+                continue;
+            }
+            break;
+        }
+        answer.push_str(line);
+        if !answer.starts_with("v=0") {
+            break;
+        }
+        answer.push_str("\r\n");
+    }
+    if answer.is_empty() {
+        bail!("No answer provided");
+    }
+    Ok(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("abcdefghijkl", 10), "abcdefg...");
+        assert_eq!(truncate("修复测试并提交结果", 6), "修复测...");
+        assert_eq!(truncate("fix — then test", 8), "fix —...");
+        assert_eq!(truncate("Pixel 8 — Chrome", 16), "Pixel 8 — Chrome");
     }
 }
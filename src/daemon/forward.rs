@@ -0,0 +1,576 @@
+//! Credential forwarding into a box without exposing key material: an SSH
+//! agent proxy that only lists and signs with allowed keys, and a git
+//! credential bridge that answers `get` requests with the host's helpers.
+//! Both listen on sockets in `~/.coop/sessions/<name>/run`, which the box
+//! sees as `/run/coop`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+use crate::config::{self, SandboxConfig};
+
+const SSH_AGENT_SOCK: &str = "ssh-agent.sock";
+const GIT_CREDENTIAL_SOCK: &str = "git-credential.sock";
+
+// SSH agent protocol message numbers
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+
+/// Largest agent message accepted from either side
+const MAX_AGENT_MESSAGE: usize = 256 * 1024;
+/// Largest git credential request accepted from the box
+const MAX_CREDENTIAL_REQUEST: u64 = 64 * 1024;
+/// How long the host's `git credential fill` may take
+const CREDENTIAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The forwarding listeners of one box; dropping it stops them
+#[derive(Debug, Default)]
+pub struct Forwards {
+    /// Env vars pointing ssh inside the box at the agent socket
+    env: Vec<(String, String)>,
+    /// `credential.helper` for git inside the box
+    credential_helper: Option<String>,
+    dir: Option<PathBuf>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Forwards {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        if let Some(dir) = &self.dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Start the listeners box `session` asks for in `[sandbox]`. Must run
+/// before the box is created, which mounts the socket directory.
+pub fn start(session: &str, sandbox: &SandboxConfig) -> Result<Forwards> {
+    let mut forwards = Forwards::default();
+    if !sandbox.forwards() {
+        return Ok(forwards);
+    }
+
+    let dir = config::session_run_dir(session)?;
+    std::fs::create_dir_all(&dir)?;
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    forwards.dir = Some(dir.clone());
+
+    if sandbox.forward_ssh_agent {
+        match std::env::var_os("SSH_AUTH_SOCK") {
+            Some(upstream) => {
+                let policy = Arc::new(AgentPolicy {
+                    session: session.to_string(),
+                    upstream: PathBuf::from(upstream),
+                    keys: sandbox.ssh_agent_keys.clone(),
+                    confirm: sandbox.ssh_agent_confirm,
+                });
+                let listener = bind(&dir.join(SSH_AGENT_SOCK))?;
+                forwards
+                    .tasks
+                    .push(tokio::spawn(serve_ssh_agent(listener, policy)));
+                forwards.env.push((
+                    "SSH_AUTH_SOCK".to_string(),
+                    format!("{}/{}", config::BOX_RUN_DIR, SSH_AGENT_SOCK),
+                ));
+            }
+            None => tracing::warn!(
+                session = %session,
+                "forward_ssh_agent is set but the daemon has no SSH_AUTH_SOCK"
+            ),
+        }
+    }
+
+    if sandbox.forward_git_credentials {
+        let listener = bind(&dir.join(GIT_CREDENTIAL_SOCK))?;
+        let bridge = Arc::new(CredentialBridge {
+            session: session.to_string(),
+            hosts: sandbox.git_credential_hosts.clone(),
+        });
+        forwards
+            .tasks
+            .push(tokio::spawn(serve_git_credentials(listener, bridge)));
+        forwards.credential_helper = Some(format!("{}/coop git-credential", config::BOX_RUN_DIR));
+    }
+
+    Ok(forwards)
+}
+
+impl Forwards {
+    /// Add the env vars pointing ssh and git inside the box at the sockets.
+    /// Config from the environment comes after the image's gitconfig, so its
+    /// own helpers are still tried first. The helper takes the next free
+    /// `GIT_CONFIG_KEY_<n>`, after any git config `env` already passes.
+    pub fn apply_env(&self, env: &mut HashMap<String, String>) {
+        env.extend(self.env.iter().cloned());
+        if let Some(helper) = &self.credential_helper {
+            let n = env
+                .get("GIT_CONFIG_COUNT")
+                .and_then(|count| count.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            env.insert(
+                format!("GIT_CONFIG_KEY_{}", n),
+                "credential.helper".to_string(),
+            );
+            env.insert(format!("GIT_CONFIG_VALUE_{}", n), helper.clone());
+            env.insert("GIT_CONFIG_COUNT".to_string(), (n + 1).to_string());
+        }
+    }
+}
+
+fn bind(path: &Path) -> Result<UnixListener> {
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path).with_context(|| format!("Cannot listen on {}", path.display()))
+}
+
+// ── SSH agent ──────────────────────────────────────────────
+
+#[derive(Debug)]
+struct AgentPolicy {
+    session: String,
+    upstream: PathBuf,
+    keys: Vec<String>,
+    confirm: bool,
+}
+
+impl AgentPolicy {
+    fn allows(&self, identity: &Identity) -> bool {
+        self.keys.is_empty()
+            || self
+                .keys
+                .iter()
+                .any(|k| *k == identity.fingerprint() || *k == identity.comment)
+    }
+
+    /// Ask through `SSH_ASKPASS`, like `ssh-add -c` keys do
+    async fn approve(&self, identity: &Identity) -> bool {
+        if !self.confirm {
+            return true;
+        }
+        let askpass = std::env::var_os("SSH_ASKPASS").unwrap_or_else(|| "ssh-askpass".into());
+        let prompt = format!(
+            "Allow box '{}' to sign with {} ({})?",
+            self.session,
+            identity.comment,
+            identity.fingerprint()
+        );
+        match tokio::process::Command::new(&askpass)
+            .arg(prompt)
+            .env("SSH_ASKPASS_PROMPT", "confirm")
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .await
+        {
+            Ok(status) => status.success(),
+            Err(e) => {
+                tracing::warn!(
+                    session = %self.session,
+                    askpass = ?askpass,
+                    error = %e,
+                    "Cannot ask for SSH signature confirmation, refusing"
+                );
+                false
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Identity {
+    blob: Vec<u8>,
+    comment: String,
+}
+
+impl Identity {
+    /// As `ssh-add -l` prints it
+    fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(&self.blob);
+        format!(
+            "SHA256:{}",
+            base64::engine::general_purpose::STANDARD_NO_PAD.encode(digest)
+        )
+    }
+}
+
+async fn serve_ssh_agent(listener: UnixListener, policy: Arc<AgentPolicy>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(session = %policy.session, error = %e, "SSH agent proxy stopped");
+                return;
+            }
+        };
+        let policy = policy.clone();
+        tokio::spawn(async move {
+            if let Err(e) = proxy_ssh_agent(stream, &policy).await {
+                tracing::debug!(session = %policy.session, error = %e, "SSH agent connection ended");
+            }
+        });
+    }
+}
+
+/// Relay one client connection, answering everything but listing and
+/// signing with allowed keys with a failure
+async fn proxy_ssh_agent(mut client: UnixStream, policy: &AgentPolicy) -> Result<()> {
+    let mut upstream = UnixStream::connect(&policy.upstream)
+        .await
+        .with_context(|| format!("Cannot connect to {}", policy.upstream.display()))?;
+
+    while let Some(request) = read_agent_message(&mut client).await? {
+        let reply = match request.first() {
+            Some(&SSH_AGENTC_REQUEST_IDENTITIES) => {
+                let identities = list_identities(&mut upstream).await?;
+                identities_answer(identities.iter().filter(|id| policy.allows(id)))
+            }
+            Some(&SSH_AGENTC_SIGN_REQUEST) => {
+                let blob = take_string(&mut &request[1..])?.to_vec();
+                let identity = list_identities(&mut upstream)
+                    .await?
+                    .into_iter()
+                    .find(|id| id.blob == blob && policy.allows(id));
+                match identity {
+                    Some(id) if policy.approve(&id).await => {
+                        tracing::info!(
+                            session = %policy.session,
+                            key = %id.fingerprint(),
+                            "Signing for box"
+                        );
+                        agent_request(&mut upstream, &request).await?
+                    }
+                    _ => {
+                        tracing::info!(session = %policy.session, "Refused SSH signature for box");
+                        vec![SSH_AGENT_FAILURE]
+                    }
+                }
+            }
+            // Adding, removing and locking keys, and extensions, stay with
+            // the host
+            _ => vec![SSH_AGENT_FAILURE],
+        };
+        write_agent_message(&mut client, &reply).await?;
+    }
+    Ok(())
+}
+
+async fn list_identities(upstream: &mut UnixStream) -> Result<Vec<Identity>> {
+    let answer = agent_request(upstream, &[SSH_AGENTC_REQUEST_IDENTITIES]).await?;
+    parse_identities(&answer)
+}
+
+async fn agent_request(upstream: &mut UnixStream, request: &[u8]) -> Result<Vec<u8>> {
+    write_agent_message(upstream, request).await?;
+    read_agent_message(upstream)
+        .await?
+        .context("SSH agent closed the connection")
+}
+
+/// Read one length-prefixed message; `None` on a clean end of stream
+async fn read_agent_message(stream: &mut UnixStream) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len == 0 || len > MAX_AGENT_MESSAGE {
+        bail!("Bad SSH agent message length {}", len);
+    }
+    let mut message = vec![0u8; len];
+    stream.read_exact(&mut message).await?;
+    Ok(Some(message))
+}
+
+async fn write_agent_message(stream: &mut UnixStream, message: &[u8]) -> Result<()> {
+    let mut frame = Vec::with_capacity(4 + message.len());
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    stream.write_all(&frame).await?;
+    Ok(())
+}
+
+fn parse_identities(answer: &[u8]) -> Result<Vec<Identity>> {
+    let Some((&SSH_AGENT_IDENTITIES_ANSWER, mut rest)) = answer.split_first() else {
+        bail!("Unexpected SSH agent answer");
+    };
+    let count = take_u32(&mut rest)?;
+    (0..count)
+        .map(|_| {
+            let blob = take_string(&mut rest)?.to_vec();
+            let comment = String::from_utf8_lossy(take_string(&mut rest)?).into_owned();
+            Ok(Identity { blob, comment })
+        })
+        .collect()
+}
+
+fn identities_answer<'a>(identities: impl Iterator<Item = &'a Identity>) -> Vec<u8> {
+    let identities: Vec<_> = identities.collect();
+    let mut answer = vec![SSH_AGENT_IDENTITIES_ANSWER];
+    answer.extend_from_slice(&(identities.len() as u32).to_be_bytes());
+    for id in identities {
+        for field in [id.blob.as_slice(), id.comment.as_bytes()] {
+            answer.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            answer.extend_from_slice(field);
+        }
+    }
+    answer
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    if buf.len() < 4 {
+        bail!("Truncated SSH agent message");
+    }
+    let (n, rest) = buf.split_at(4);
+    *buf = rest;
+    Ok(u32::from_be_bytes(n.try_into().unwrap()))
+}
+
+fn take_string<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = take_u32(buf)? as usize;
+    if buf.len() < len {
+        bail!("Truncated SSH agent message");
+    }
+    let (s, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(s)
+}
+
+// ── git credentials ────────────────────────────────────────
+
+#[derive(Debug)]
+struct CredentialBridge {
+    session: String,
+    hosts: Vec<String>,
+}
+
+impl CredentialBridge {
+    /// The attributes to ask the host's helpers for, if the box may have a
+    /// credential for this request. Only `get` is answered: the box cannot
+    /// store or erase the host's credentials.
+    fn query(&self, request: &str) -> Option<String> {
+        let mut lines = request.lines();
+        if lines.next()? != "get" {
+            return None;
+        }
+        let mut query = String::new();
+        let mut host = None;
+        for line in lines {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            if key == "host" {
+                host = Some(value);
+            }
+            if matches!(key, "protocol" | "host" | "path" | "username") {
+                query.push_str(line);
+                query.push('\n');
+            }
+        }
+        let host = host?;
+        let allowed =
+            self.hosts.is_empty() || self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host));
+        if !allowed {
+            tracing::info!(session = %self.session, host = %host, "Refused git credential for box");
+            return None;
+        }
+        Some(query)
+    }
+
+    async fn fill(&self, query: &str) -> Result<String> {
+        let mut child = tokio::process::Command::new("git")
+            .args(["credential", "fill"])
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Cannot run git credential fill")?;
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(query.as_bytes()).await?;
+        drop(stdin);
+
+        let output = tokio::time::timeout(CREDENTIAL_TIMEOUT, child.wait_with_output())
+            .await
+            .context("git credential fill timed out")??;
+        if !output.status.success() {
+            bail!("git credential fill failed");
+        }
+        Ok(credential_answer(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// The parts of `git credential fill` output handed to the box
+fn credential_answer(output: &str) -> String {
+    output
+        .lines()
+        .filter(|line| {
+            line.split_once('=').is_some_and(|(key, _)| {
+                matches!(key, "username" | "password" | "password_expiry_utc")
+            })
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect()
+}
+
+async fn serve_git_credentials(listener: UnixListener, bridge: Arc<CredentialBridge>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(session = %bridge.session, error = %e, "Git credential bridge stopped");
+                return;
+            }
+        };
+        let bridge = bridge.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_git_credential(stream, &bridge).await {
+                tracing::debug!(session = %bridge.session, error = %e, "Git credential request failed");
+            }
+        });
+    }
+}
+
+async fn answer_git_credential(mut stream: UnixStream, bridge: &CredentialBridge) -> Result<()> {
+    let mut request = Vec::new();
+    (&mut stream)
+        .take(MAX_CREDENTIAL_REQUEST)
+        .read_to_end(&mut request)
+        .await?;
+    let answer = match bridge.query(&String::from_utf8_lossy(&request)) {
+        Some(query) => bridge.fill(&query).await.unwrap_or_else(|e| {
+            tracing::debug!(session = %bridge.session, error = %e, "No git credential for box");
+            String::new()
+        }),
+        None => String::new(),
+    };
+    stream.write_all(answer.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// `coop git-credential <operation>`, git's credential helper inside a box:
+/// relays `get` requests to the daemon. `store` and `erase` are ignored.
+pub fn git_credential_client(operation: &str) -> Result<()> {
+    use std::io::{Read, Write};
+
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    if operation != "get" {
+        return Ok(());
+    }
+
+    let path = format!("{}/{}", config::BOX_RUN_DIR, GIT_CREDENTIAL_SOCK);
+    let mut stream = std::os::unix::net::UnixStream::connect(&path)
+        .with_context(|| format!("Cannot connect to {}", path))?;
+    stream.write_all(format!("{}\n{}", operation, input).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer)?;
+    std::io::stdout().write_all(&answer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_identities_filtered() {
+        let work = Identity {
+            blob: b"\0\0\0\x0bssh-ed25519work".to_vec(),
+            comment: "work@laptop".to_string(),
+        };
+        let personal = Identity {
+            blob: b"\0\0\0\x0bssh-ed25519home".to_vec(),
+            comment: "me@home".to_string(),
+        };
+        let answer = identities_answer([&work, &personal].into_iter());
+        assert_eq!(
+            parse_identities(&answer).unwrap(),
+            vec![work.clone(), personal.clone()]
+        );
+
+        let fingerprint = personal.fingerprint();
+        assert!(fingerprint.starts_with("SHA256:") && !fingerprint.ends_with('='));
+        let policy = AgentPolicy {
+            session: "b".to_string(),
+            upstream: PathBuf::new(),
+            keys: vec!["work@laptop".to_string(), fingerprint],
+            confirm: false,
+        };
+        assert!(policy.allows(&work) && policy.allows(&personal));
+        let policy = AgentPolicy {
+            keys: vec!["work@laptop".to_string()],
+            ..policy
+        };
+        let allowed = identities_answer(
+            [&work, &personal]
+                .into_iter()
+                .filter(|id| policy.allows(id)),
+        );
+        assert_eq!(parse_identities(&allowed).unwrap(), vec![work]);
+
+        assert!(parse_identities(&answer[..answer.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_git_credential_query() {
+        let bridge = CredentialBridge {
+            session: "b".to_string(),
+            hosts: vec!["github.com".to_string()],
+        };
+        assert_eq!(
+            bridge
+                .query("get\nprotocol=https\nhost=GitHub.com\npassword=x\ncapability[]=authtype\n")
+                .unwrap(),
+            "protocol=https\nhost=GitHub.com\n"
+        );
+        assert_eq!(bridge.query("get\nprotocol=https\nhost=gitlab.com\n"), None);
+        assert_eq!(bridge.query("store\nhost=github.com\npassword=x\n"), None);
+
+        assert_eq!(
+            credential_answer("protocol=https\nhost=github.com\nusername=me\npassword=tok\n"),
+            "username=me\npassword=tok\n"
+        );
+    }
+
+    #[test]
+    fn test_credential_helper_env() {
+        let mut forwards = Forwards::default();
+        forwards.credential_helper = Some("/run/coop/coop git-credential".to_string());
+        let mut env = HashMap::new();
+        forwards.apply_env(&mut env);
+        assert_eq!(env["GIT_CONFIG_COUNT"], "1");
+        assert_eq!(env["GIT_CONFIG_KEY_0"], "credential.helper");
+
+        // The user's own git config from `[env]` is kept
+        let mut env = HashMap::from([
+            ("GIT_CONFIG_COUNT".to_string(), "1".to_string()),
+            ("GIT_CONFIG_KEY_0".to_string(), "user.name".to_string()),
+            ("GIT_CONFIG_VALUE_0".to_string(), "Agent".to_string()),
+        ]);
+        forwards.apply_env(&mut env);
+        assert_eq!(env["GIT_CONFIG_COUNT"], "2");
+        assert_eq!(env["GIT_CONFIG_KEY_0"], "user.name");
+        assert_eq!(env["GIT_CONFIG_VALUE_1"], "/run/coop/coop git-credential");
+    }
+}
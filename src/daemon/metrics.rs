@@ -0,0 +1,296 @@
+//! Prometheus metrics for `GET /metrics`.
+//!
+//! Counters live next to what they count (`PtyStats` on each PTY, `Metrics`
+//! on the session manager); `render` turns a snapshot of them into the text
+//! exposition format. Per-box CPU and memory are summed over each box's
+//! process trees from procfs at scrape time.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Traffic and restart counters of one PTY. Kept across restarts, since the
+/// PTY keeps its id and clients.
+#[derive(Debug, Default)]
+pub struct PtyStats {
+    /// Input written to the PTY by attached clients
+    pub bytes_in: AtomicU64,
+    /// Output read from the PTY
+    pub bytes_out: AtomicU64,
+    pub restarts: AtomicU64,
+}
+
+impl PtyStats {
+    pub fn add_in(&self, n: usize) {
+        self.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_out(&self, n: usize) {
+        self.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Daemon-wide counters
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub crash_loops: AtomicU64,
+    /// PTY output frames dropped for clients that fell behind
+    pub lagged_frames: AtomicU64,
+}
+
+pub struct BoxSample {
+    pub session: String,
+    /// Top-level processes of the box (init and each PTY's process)
+    pub pids: Vec<u32>,
+    pub local_clients: u32,
+    pub web_clients: u32,
+}
+
+pub struct PtySample {
+    pub session: String,
+    pub pty: u32,
+    pub role: &'static str,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub restarts: u64,
+}
+
+type PtyValue = fn(&PtySample) -> u64;
+
+/// Render a scrape, with `usage` from `box_usage`
+pub fn render(
+    boxes: &[BoxSample],
+    ptys: &[PtySample],
+    metrics: &Metrics,
+    usage: &[(String, Usage)],
+) -> String {
+    let mut out = Exposition::default();
+
+    out.family("coop_sessions", "gauge", "Running boxes");
+    out.sample("coop_sessions", &[], boxes.len());
+
+    out.family("coop_ptys", "gauge", "PTYs by role");
+    for role in ["agent", "service", "shell"] {
+        let count = ptys.iter().filter(|p| p.role == role).count();
+        out.sample("coop_ptys", &[("role", role)], count);
+    }
+
+    out.family("coop_clients", "gauge", "Attached clients per box");
+    for b in boxes {
+        out.sample(
+            "coop_clients",
+            &[("session", &b.session), ("kind", "local")],
+            b.local_clients,
+        );
+        out.sample(
+            "coop_clients",
+            &[("session", &b.session), ("kind", "web")],
+            b.web_clients,
+        );
+    }
+
+    let pty_counters: [(&str, &str, PtyValue); 3] = [
+        (
+            "coop_pty_input_bytes_total",
+            "Bytes written to the PTY by clients",
+            |p| p.bytes_in,
+        ),
+        (
+            "coop_pty_output_bytes_total",
+            "Bytes read from the PTY",
+            |p| p.bytes_out,
+        ),
+        (
+            "coop_pty_restarts_total",
+            "Restarts of the PTY's process",
+            |p| p.restarts,
+        ),
+    ];
+    for (name, help, value) in pty_counters {
+        out.family(name, "counter", help);
+        for p in ptys {
+            let pty = p.pty.to_string();
+            out.sample(
+                name,
+                &[("session", &p.session), ("pty", &pty), ("role", p.role)],
+                value(p),
+            );
+        }
+    }
+
+    out.family(
+        "coop_crash_loops_total",
+        "counter",
+        "PTYs whose auto-restart was stopped after repeated fast failures",
+    );
+    out.sample(
+        "coop_crash_loops_total",
+        &[],
+        metrics.crash_loops.load(Ordering::Relaxed),
+    );
+
+    out.family(
+        "coop_lagged_frames_total",
+        "counter",
+        "PTY output frames dropped for clients that fell behind",
+    );
+    out.sample(
+        "coop_lagged_frames_total",
+        &[],
+        metrics.lagged_frames.load(Ordering::Relaxed),
+    );
+
+    // Not a counter: the time of a process leaves the sum when it exits
+    out.family(
+        "coop_box_cpu_seconds",
+        "gauge",
+        "CPU time used so far by the box's running processes",
+    );
+    for (session, u) in usage {
+        out.sample(
+            "coop_box_cpu_seconds",
+            &[("session", session)],
+            u.cpu_seconds,
+        );
+    }
+    out.family(
+        "coop_box_memory_bytes",
+        "gauge",
+        "Resident memory of the box's processes",
+    );
+    for (session, u) in usage {
+        out.sample(
+            "coop_box_memory_bytes",
+            &[("session", session)],
+            u.rss_bytes,
+        );
+    }
+    out.family(
+        "coop_box_processes",
+        "gauge",
+        "Processes running in the box",
+    );
+    for (session, u) in usage {
+        out.sample("coop_box_processes", &[("session", session)], u.processes);
+    }
+
+    out.text
+}
+
+#[derive(Default)]
+pub struct Usage {
+    cpu_seconds: f64,
+    rss_bytes: u64,
+    processes: usize,
+}
+
+/// CPU and memory of each box, summed over its process trees. There is no
+/// PID namespace, so a box is its top-level processes and their descendants.
+/// Reads procfs, so call it off the async runtime.
+pub fn box_usage(boxes: &[BoxSample]) -> Vec<(String, Usage)> {
+    let Ok(procs) = procfs::process::all_processes() else {
+        return Vec::new();
+    };
+    let stats: Vec<procfs::process::Stat> = procs.flatten().filter_map(|p| p.stat().ok()).collect();
+    let mut children: HashMap<i32, Vec<usize>> = HashMap::new();
+    let mut by_pid: HashMap<i32, usize> = HashMap::new();
+    for (i, stat) in stats.iter().enumerate() {
+        children.entry(stat.ppid).or_default().push(i);
+        by_pid.insert(stat.pid, i);
+    }
+
+    let ticks = procfs::ticks_per_second().max(1) as f64;
+    let page_size = procfs::page_size();
+    boxes
+        .iter()
+        .map(|b| {
+            let mut usage = Usage::default();
+            let mut seen = std::collections::HashSet::new();
+            let mut queue: Vec<usize> = b
+                .pids
+                .iter()
+                .filter_map(|pid| by_pid.get(&(*pid as i32)).copied())
+                .collect();
+            while let Some(i) = queue.pop() {
+                if !seen.insert(i) {
+                    continue;
+                }
+                let stat = &stats[i];
+                usage.cpu_seconds += (stat.utime + stat.stime) as f64 / ticks;
+                usage.rss_bytes += stat.rss * page_size;
+                usage.processes += 1;
+                if let Some(kids) = children.get(&stat.pid) {
+                    queue.extend(kids);
+                }
+            }
+            (b.session.clone(), usage)
+        })
+        .collect()
+}
+
+/// Prometheus text format writer
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exposition() {
+        let boxes = [BoxSample {
+            session: "we\"b".to_string(),
+            pids: vec![std::process::id()],
+            local_clients: 1,
+            web_clients: 2,
+        }];
+        let ptys = [PtySample {
+            session: "we\"b".to_string(),
+            pty: 0,
+            role: "agent",
+            bytes_in: 5,
+            bytes_out: 1024,
+            restarts: 1,
+        }];
+        let usage = box_usage(&boxes);
+        let text = render(&boxes, &ptys, &Metrics::default(), &usage);
+
+        assert!(text.contains("# TYPE coop_sessions gauge\ncoop_sessions 1\n"));
+        assert!(text.contains("coop_ptys{role=\"agent\"} 1\n"));
+        assert!(text.contains("coop_clients{session=\"we\\\"b\",kind=\"web\"} 2\n"));
+        assert!(text.contains(
+            "coop_pty_output_bytes_total{session=\"we\\\"b\",pty=\"0\",role=\"agent\"} 1024\n"
+        ));
+        // The test process itself stands in for the box
+        assert!(text.contains("coop_box_processes{session=\"we\\\"b\"} "));
+        assert!(!text.contains("coop_box_memory_bytes{session=\"we\\\"b\"} 0\n"));
+    }
+}
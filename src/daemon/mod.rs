@@ -1,5 +1,11 @@
+pub mod adapter;
+pub mod audit;
 pub mod client;
+pub mod forward;
 pub mod logs;
+pub mod metrics;
+pub mod queue;
 pub mod server;
 pub mod session;
 pub mod spawn;
+pub mod task;
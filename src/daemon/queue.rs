@@ -0,0 +1,410 @@
+//! Task queue: runs queued headless tasks (see `task.rs`) in their own boxes,
+//! at most `concurrency` at a time. State is persisted under ~/.coop/queue so
+//! the queue survives daemon restarts.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, Notify};
+
+use super::audit::Actor;
+use super::session::SessionManager;
+use super::task::{run_task, TaskRun};
+use crate::config;
+use crate::ipc::{QueuedTask, Response, ResponseData, TaskSpec, TaskState, ERR_TASK_NOT_FOUND};
+
+/// Default number of queued tasks running at once
+const DEFAULT_CONCURRENCY: usize = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct QueueState {
+    concurrency: usize,
+    next_id: u64,
+    tasks: Vec<QueuedTask>,
+    /// Tasks whose run has not returned yet. A cancelled task stays here
+    /// while its box is torn down.
+    #[serde(skip)]
+    in_flight: HashSet<u64>,
+}
+
+impl Default for QueueState {
+    fn default() -> Self {
+        Self {
+            concurrency: DEFAULT_CONCURRENCY,
+            next_id: 1,
+            tasks: Vec::new(),
+            in_flight: HashSet::new(),
+        }
+    }
+}
+
+pub struct TaskQueue {
+    state: Mutex<QueueState>,
+    session_manager: Arc<SessionManager>,
+    /// Wakes the scheduler when tasks are added or finish
+    wake: Notify,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(config::queue_dir()?.join("state.json"))
+}
+
+/// Per-task directory holding the workspace copy and the results
+fn task_dir(id: u64) -> Result<PathBuf> {
+    Ok(config::queue_dir()?.join(id.to_string()))
+}
+
+/// Delete a task's workspace copy, keeping its results
+fn remove_workspace(id: u64) {
+    let Ok(dir) = task_dir(id) else {
+        return;
+    };
+    let workspace = dir.join("workspace");
+    if workspace.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&workspace) {
+            tracing::warn!(task = id, error = %e, "Failed to remove task workspace");
+        }
+    }
+}
+
+impl TaskQueue {
+    /// Load the persisted queue. Tasks that were running when the daemon
+    /// stopped lost their box, so they are marked failed (and can be retried).
+    pub fn load(session_manager: Arc<SessionManager>) -> Self {
+        let mut state: QueueState = state_path()
+            .ok()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        for task in &mut state.tasks {
+            if task.state == TaskState::Running {
+                task.state = TaskState::Failed;
+                task.finished = Some(now());
+                task.error = Some("interrupted by daemon restart".to_string());
+                remove_workspace(task.id);
+            }
+        }
+
+        Self {
+            state: Mutex::new(state),
+            session_manager,
+            wake: Notify::new(),
+        }
+    }
+
+    /// Start the scheduler loop
+    pub fn start(self: &Arc<Self>) {
+        let queue = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                queue.schedule().await;
+                queue.wake.notified().await;
+            }
+        });
+    }
+
+    /// Whether any task is pending or running (keeps the daemon alive)
+    pub async fn is_busy(&self) -> bool {
+        self.state
+            .lock()
+            .await
+            .tasks
+            .iter()
+            .any(|t| matches!(t.state, TaskState::Pending | TaskState::Running))
+    }
+
+    pub async fn add(&self, specs: Vec<TaskSpec>, concurrency: Option<usize>) -> Result<Response> {
+        let mut state = self.state.lock().await;
+        if let Some(concurrency) = concurrency {
+            state.concurrency = concurrency.max(1);
+        }
+
+        let mut added = Vec::new();
+        for spec in specs {
+            let id = state.next_id;
+            state.next_id += 1;
+            let task = QueuedTask {
+                id,
+                spec,
+                state: TaskState::Pending,
+                created: now(),
+                started: None,
+                finished: None,
+                exit_code: None,
+                error: None,
+                box_name: format!("queue-{}", id),
+                output: task_dir(id)?.join("output").to_string_lossy().to_string(),
+                attempt: 0,
+            };
+            added.push(task.clone());
+            state.tasks.push(task);
+        }
+        save(&state)?;
+        let concurrency = state.concurrency;
+        drop(state);
+
+        tracing::info!(count = added.len(), "Queued tasks");
+        self.wake.notify_one();
+
+        Ok(Response::ok_with(ResponseData {
+            tasks: Some(added),
+            concurrency: Some(concurrency),
+            ..Default::default()
+        }))
+    }
+
+    pub async fn list(&self) -> Result<Response> {
+        let state = self.state.lock().await;
+        Ok(Response::ok_with(ResponseData {
+            tasks: Some(state.tasks.clone()),
+            concurrency: Some(state.concurrency),
+            ..Default::default()
+        }))
+    }
+
+    /// Cancel a pending task, or kill the box of a running one
+    pub async fn cancel(&self, id: u64) -> Result<Response> {
+        let mut state = self.state.lock().await;
+        let Some(task) = state.tasks.iter_mut().find(|t| t.id == id) else {
+            return Ok(task_not_found(id));
+        };
+
+        let was_running = match task.state {
+            TaskState::Pending => false,
+            TaskState::Running => true,
+            other => {
+                return Ok(Response::err(
+                    "INVALID_STATE",
+                    format!("Task {} is already {}", id, other),
+                ))
+            }
+        };
+        task.state = TaskState::Cancelled;
+        task.finished = Some(now());
+        let box_name = task.box_name.clone();
+        save(&state)?;
+        drop(state);
+
+        if was_running {
+            // The run notices its agent is gone and returns; its result is
+            // discarded since the task is no longer marked running
+            let _ = self
+                .session_manager
+                .kill_session(&box_name, true, &Actor::default())
+                .await;
+        }
+        tracing::info!(task = id, "Cancelled task");
+        self.wake.notify_one();
+        Ok(Response::ok())
+    }
+
+    /// Put a finished task back in the queue with a fresh workspace copy.
+    /// A cancelled task must first be rid of its old box.
+    pub async fn retry(&self, id: u64) -> Result<Response> {
+        let mut state = self.state.lock().await;
+        let in_flight = state.in_flight.contains(&id);
+        let Some(task) = state.tasks.iter_mut().find(|t| t.id == id) else {
+            return Ok(task_not_found(id));
+        };
+        if matches!(task.state, TaskState::Pending | TaskState::Running) {
+            return Ok(Response::err(
+                "INVALID_STATE",
+                format!("Task {} is still {}", id, task.state),
+            ));
+        }
+        if in_flight
+            || self
+                .session_manager
+                .session_name(&task.box_name)
+                .await
+                .is_some()
+        {
+            return Ok(Response::err(
+                "INVALID_STATE",
+                format!(
+                    "Task {} is still shutting down box '{}'; retry once it is gone",
+                    id, task.box_name
+                ),
+            ));
+        }
+
+        task.state = TaskState::Pending;
+        task.started = None;
+        task.finished = None;
+        task.exit_code = None;
+        task.error = None;
+        save(&state)?;
+        drop(state);
+
+        tracing::info!(task = id, "Retrying task");
+        self.wake.notify_one();
+        Ok(Response::ok())
+    }
+
+    /// Start pending tasks (oldest first) until `concurrency` are running
+    async fn schedule(self: &Arc<Self>) {
+        let mut state = self.state.lock().await;
+        let running = state
+            .tasks
+            .iter()
+            .filter(|t| t.state == TaskState::Running)
+            .count();
+        let slots = state.concurrency.saturating_sub(running);
+
+        let mut started = Vec::new();
+        for task in state
+            .tasks
+            .iter_mut()
+            .filter(|t| t.state == TaskState::Pending)
+            .take(slots)
+        {
+            task.state = TaskState::Running;
+            task.started = Some(now());
+            task.attempt += 1;
+            started.push(task.clone());
+        }
+        if started.is_empty() {
+            return;
+        }
+        state.in_flight.extend(started.iter().map(|t| t.id));
+        if let Err(e) = save(&state) {
+            tracing::error!(error = %e, "Failed to save queue state");
+        }
+        drop(state);
+
+        for task in started {
+            let queue = Arc::clone(self);
+            tokio::spawn(async move {
+                let (id, attempt) = (task.id, task.attempt);
+                tracing::info!(task = id, attempt, "Starting queued task");
+                let outcome = queue.run(task).await;
+                remove_workspace(id);
+                queue.finish(id, attempt, outcome).await;
+            });
+        }
+    }
+
+    /// Copy the workspace and run the task. Returns the exit code, or an
+    /// error describing why the task failed.
+    async fn run(&self, task: QueuedTask) -> Result<i32, String> {
+        let dir = task_dir(task.id).map_err(|e| e.to_string())?;
+        let workspace = dir.join("workspace");
+        let source = PathBuf::from(&task.spec.workspace);
+        let copy_to = workspace.clone();
+        tokio::task::spawn_blocking(move || copy_workspace(&source, &copy_to))
+            .await
+            .map_err(|e| e.to_string())?
+            .map_err(|e| format!("{:#}", e))?;
+
+        let run = TaskRun {
+            name: Some(task.box_name.clone()),
+            workspace: workspace.to_string_lossy().to_string(),
+            task: task.spec.task.clone(),
+            args: task.spec.args.clone(),
+            stdin: task.spec.stdin,
+            timeout: task.spec.timeout_secs.map(Duration::from_secs),
+            output: PathBuf::from(&task.output),
+        };
+        let resp = run_task(&self.session_manager, run)
+            .await
+            .map_err(|e| format!("{:#}", e))?;
+
+        if !resp.ok {
+            return Err(resp.message.unwrap_or_else(|| "task failed".to_string()));
+        }
+        if resp.data.timed_out == Some(true) {
+            return Err("timed out".to_string());
+        }
+        resp.data
+            .exit_code
+            .ok_or_else(|| "agent exit code unavailable".to_string())
+    }
+
+    /// Record a task's outcome, unless it was cancelled meanwhile or the
+    /// outcome is from an earlier attempt
+    async fn finish(&self, id: u64, attempt: u32, outcome: Result<i32, String>) {
+        let mut state = self.state.lock().await;
+        state.in_flight.remove(&id);
+        if let Some(task) = state.tasks.iter_mut().find(|t| t.id == id) {
+            if task.state == TaskState::Running && task.attempt == attempt {
+                task.finished = Some(now());
+                match outcome {
+                    Ok(0) => {
+                        task.state = TaskState::Done;
+                        task.exit_code = Some(0);
+                    }
+                    Ok(code) => {
+                        task.state = TaskState::Failed;
+                        task.exit_code = Some(code);
+                        task.error = Some(format!("exited with code {}", code));
+                    }
+                    Err(e) => {
+                        task.state = TaskState::Failed;
+                        task.error = Some(e);
+                    }
+                }
+                tracing::info!(task = id, state = %task.state, "Queued task finished");
+            }
+        }
+        if let Err(e) = save(&state) {
+            tracing::error!(error = %e, "Failed to save queue state");
+        }
+        drop(state);
+        self.wake.notify_one();
+    }
+}
+
+fn task_not_found(id: u64) -> Response {
+    Response::err(ERR_TASK_NOT_FOUND, format!("Task {} not found", id))
+}
+
+/// Persist the queue atomically (write + rename)
+fn save(state: &QueueState) -> Result<()> {
+    let path = state_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Give the task its own copy of the source workspace (replacing any copy
+/// left by a previous attempt). Uses reflinks where the filesystem supports them.
+fn copy_workspace(source: &std::path::Path, dest: &std::path::Path) -> Result<()> {
+    if !source.is_dir() {
+        anyhow::bail!("Workspace {} is not a directory", source.display());
+    }
+    if dest.exists() {
+        std::fs::remove_dir_all(dest)
+            .with_context(|| format!("Failed to remove {}", dest.display()))?;
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let status = std::process::Command::new("cp")
+        .arg("-a")
+        .arg("--reflink=auto")
+        .arg(source)
+        .arg(dest)
+        .status()
+        .context("Failed to run cp")?;
+    if !status.success() {
+        anyhow::bail!("Failed to copy workspace {}", source.display());
+    }
+    Ok(())
+}
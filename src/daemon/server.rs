@@ -1,4 +1,3 @@
-use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,25 +8,42 @@ use tokio::net::UnixListener;
 use tokio::sync::broadcast;
 use tokio_util::codec::{Framed, FramedParts};
 
-use crate::config;
+use crate::config::{self, InputClient};
 use crate::ipc::{
-    Command, DaemonEvent, MessageCodec, Response, ResponseData, StreamCodec, StreamFrame,
-    VersionHandshake, VersionResponse, FRAME_CONTROL, FRAME_PTY_DATA, PROTOCOL_VERSION,
+    AgentMessage, Command, DaemonEvent, MessageCodec, Response, ResponseData, SessionEvent,
+    StreamCodec, StreamFrame, VersionHandshake, VersionResponse, ERR_INVALID_COMMAND,
+    ERR_PTY_NOT_FOUND, ERR_SESSION_NOT_FOUND, ERR_TUNNEL_FAILED, FRAME_CONTROL, FRAME_PTY_DATA,
+    PROTOCOL_VERSION,
 };
+use crate::pty::Approvals;
+use crate::tunnel::signaling::{self, ConnectionEnvelope};
+use crate::tunnel::{Access, DeviceRegistry, Rendezvous, Tunnel, TunnelConfig, CONNECT_TIMEOUT};
+use crate::web::tokens::{generate_token, TokenStore};
 
-use super::session::SessionManager;
+use super::audit::Actor;
+use super::queue::TaskQueue;
+use super::session::{PtyHandle, SessionManager};
 
 /// The daemon server that listens on the unix socket and manages sessions.
 pub struct DaemonServer {
     session_manager: Arc<SessionManager>,
+    task_queue: Arc<TaskQueue>,
+    devices: Arc<DeviceRegistry>,
+    tokens: Arc<TokenStore>,
     shutdown_tx: broadcast::Sender<()>,
 }
 
 impl DaemonServer {
     pub fn new() -> Self {
         let (shutdown_tx, _) = broadcast::channel(1);
+        let session_manager = Arc::new(SessionManager::new());
+        let task_queue = Arc::new(TaskQueue::load(session_manager.clone()));
+        let devices = Arc::new(DeviceRegistry::load(session_manager.clone()));
         Self {
-            session_manager: Arc::new(SessionManager::new()),
+            session_manager,
+            task_queue,
+            devices,
+            tokens: Arc::new(TokenStore::load()),
             shutdown_tx,
         }
     }
@@ -67,6 +83,9 @@ impl DaemonServer {
 
         tracing::info!(socket = %sock_path.display(), "Daemon listening");
 
+        self.task_queue.start();
+        self.devices.start().await;
+
         let mut shutdown_rx = self.shutdown_tx.subscribe();
         let idle_timeout = Duration::from_secs(30);
         let mut idle_since = tokio::time::Instant::now();
@@ -78,9 +97,12 @@ impl DaemonServer {
                         Ok((stream, _)) => {
                             idle_since = tokio::time::Instant::now();
                             let session_manager = self.session_manager.clone();
+                            let task_queue = self.task_queue.clone();
+                            let devices = self.devices.clone();
+                            let tokens = self.tokens.clone();
                             let shutdown_tx = self.shutdown_tx.clone();
                             tokio::spawn(async move {
-                                if let Err(e) = handle_client(stream, session_manager, shutdown_tx).await {
+                                if let Err(e) = handle_client(stream, session_manager, task_queue, devices, tokens, shutdown_tx).await {
                                     tracing::error!(error = %e, "Client handler error");
                                 }
                             });
@@ -96,6 +118,8 @@ impl DaemonServer {
                 }
                 _ = tokio::time::sleep(idle_timeout) => {
                     if self.session_manager.session_count().await == 0
+                        && !self.task_queue.is_busy().await
+                        && !self.devices.is_busy().await
                         && idle_since.elapsed() >= idle_timeout
                     {
                         tracing::info!("Idle timeout reached, shutting down");
@@ -129,6 +153,9 @@ struct StreamTarget {
 async fn handle_client(
     stream: tokio::net::UnixStream,
     session_manager: Arc<SessionManager>,
+    task_queue: Arc<TaskQueue>,
+    devices: Arc<DeviceRegistry>,
+    tokens: Arc<TokenStore>,
     shutdown_tx: broadcast::Sender<()>,
 ) -> Result<()> {
     // Verify peer credentials
@@ -176,6 +203,9 @@ async fn handle_client(
 
     // Command loop
     let mut stream_target: Option<StreamTarget> = None;
+    let mut tunnel: Option<(Tunnel, Option<Rendezvous>)> = None;
+    let mut subscription: Option<(broadcast::Receiver<SessionEvent>, Option<String>)> = None;
+    let mut following: Option<broadcast::Receiver<AgentMessage>> = None;
 
     while let Some(msg) = framed.next().await {
         let msg = msg.context("Read error")?;
@@ -198,6 +228,7 @@ async fn handle_client(
                 pty,
                 cols,
                 rows,
+                ..
             } => Some(StreamTarget {
                 session: session.clone(),
                 pty: *pty,
@@ -232,7 +263,12 @@ async fn handle_client(
             _ => None,
         };
 
-        let is_shell = matches!(cmd, Command::Shell { .. });
+        // Shell, attach and logs responses carry the resolved PTY id (a new
+        // shell, or the PTY of an agent or service addressed by name)
+        let takes_pty_from_resp = matches!(
+            cmd,
+            Command::Shell { .. } | Command::Attach { .. } | Command::Logs { .. }
+        );
 
         let resp = match cmd {
             Command::Create {
@@ -248,9 +284,14 @@ async fn handle_client(
             Command::Attach {
                 session,
                 pty,
+                agent,
                 cols,
                 rows,
-            } => session_manager.attach(&session, pty, cols, rows).await,
+            } => {
+                session_manager
+                    .attach(&session, pty, agent.as_deref(), cols, rows)
+                    .await
+            }
             Command::Shell {
                 session,
                 command,
@@ -269,40 +310,125 @@ async fn handle_client(
                 force,
             } => {
                 if all {
-                    session_manager.kill_all(force).await
+                    session_manager.kill_all(force, &Actor::local()).await
                 } else {
-                    session_manager.kill_session(&session, force).await
+                    session_manager
+                        .kill_session(&session, force, &Actor::local())
+                        .await
                 }
             }
-            Command::Serve { port, host, token } => {
-                let token = token.unwrap_or_else(generate_token);
-                let sm = session_manager.clone();
-                let token_clone = token.clone();
-                let host_clone = host.clone();
-                tokio::spawn(async move {
-                    if let Err(e) =
-                        crate::web::server::start_web_server(&host_clone, port, sm, token_clone)
-                            .await
-                    {
-                        tracing::error!(error = %e, "Web server error");
-                    }
-                });
-                Ok(Response::ok_with(ResponseData {
-                    port: Some(port),
-                    host: Some(host),
-                    token: Some(token),
-                    ..Default::default()
-                }))
+            Command::Serve {
+                port,
+                host,
+                token,
+                tls,
+                cert,
+                key,
+                metrics_port,
+                proxy_port,
+            } => {
+                let tls = tls.then_some((cert, key));
+                serve_web(
+                    &session_manager,
+                    &task_queue,
+                    &tokens,
+                    port,
+                    host,
+                    token,
+                    tls,
+                    metrics_port,
+                    proxy_port,
+                )
+                .await
             }
             Command::SessionLs { session } => session_manager.session_ls(&session).await,
-            Command::SessionKill { session, pty } => session_manager.kill_pty(&session, pty).await,
+            Command::SessionKill { session, pty } => {
+                session_manager
+                    .kill_pty(&session, pty, &Actor::local())
+                    .await
+            }
             Command::Logs {
                 session,
                 pty,
+                service,
                 tail_lines,
                 ..
-            } => session_manager.get_logs(&session, pty, tail_lines).await,
-            Command::Restart { session, pty } => session_manager.restart_pty(&session, pty).await,
+            } => {
+                session_manager
+                    .get_logs(&session, pty, service.as_deref(), tail_lines, false)
+                    .await
+            }
+            Command::Restart { session, pty } => {
+                session_manager
+                    .restart_pty(&session, pty, &Actor::local())
+                    .await
+            }
+            Command::Wait {
+                session,
+                pty,
+                pattern,
+                timeout_ms,
+                scrollback,
+            } => {
+                let wait = session_manager.wait_output(
+                    &session,
+                    pty,
+                    &pattern,
+                    timeout_ms.map(Duration::from_millis),
+                    scrollback,
+                    false,
+                );
+                // Stop waiting if the client goes away
+                tokio::select! {
+                    resp = wait => resp,
+                    _ = framed.next() => break,
+                }
+            }
+            Command::SendInput {
+                session,
+                pty,
+                text,
+                keys,
+            } => {
+                session_manager
+                    .send_input(
+                        &session,
+                        pty,
+                        Actor::local(),
+                        InputClient::Local,
+                        &text,
+                        &keys,
+                    )
+                    .await
+            }
+            Command::Run {
+                name,
+                workspace,
+                task,
+                args,
+                stdin,
+                timeout_secs,
+                output,
+            } => {
+                let run = super::task::TaskRun {
+                    name,
+                    workspace,
+                    task,
+                    args,
+                    stdin,
+                    timeout: timeout_secs.map(std::time::Duration::from_secs),
+                    output: output.into(),
+                };
+                // Abandon the run, and with it the box, if the client goes away
+                tokio::select! {
+                    resp = super::task::run_task(&session_manager, run) => resp,
+                    _ = framed.next() => break,
+                }
+            }
+            Command::QueueAdd { tasks, concurrency } => task_queue.add(tasks, concurrency).await,
+            Command::QueueLs => task_queue.list().await,
+            Command::QueueCancel { id } => task_queue.cancel(id).await,
+            Command::QueueRetry { id } => task_queue.retry(id).await,
             Command::Shutdown => {
                 let _ = shutdown_tx.send(());
                 Ok(Response::ok())
@@ -311,20 +437,88 @@ async fn handle_client(
                 "INVALID_COMMAND",
                 "Resize is only valid in stream mode",
             )),
-            Command::Detach => Ok(Response::ok()),
-            Command::Tunnel { .. } => Ok(Response::err(
+            Command::ApproveInput { .. } => Ok(Response::err(
                 "INVALID_COMMAND",
-                "Tunnel not yet implemented",
+                "ApproveInput is only valid in stream mode",
             )),
+            Command::Detach => Ok(Response::ok()),
+            Command::Tunnel {
+                stun,
+                no_stun,
+                loopback,
+                signal,
+                scope,
+            } => {
+                let config = TunnelConfig::new(stun, no_stun, loopback);
+                let access = Access {
+                    scope,
+                    devices: devices.clone(),
+                    device: None,
+                    signal: signal.clone(),
+                };
+                match open_tunnel(session_manager.clone(), &config, access).await {
+                    Ok((new_tunnel, rendezvous, resp)) => {
+                        tunnel = Some((new_tunnel, rendezvous));
+                        Ok(resp)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Command::TunnelAnswer { .. } => Ok(Response::err(
+                ERR_INVALID_COMMAND,
+                "No tunnel is waiting for an answer",
+            )),
+            Command::Pair { .. } => Ok(Response::err(
+                ERR_INVALID_COMMAND,
+                "Pairing is only valid on a tunnel control channel",
+            )),
+            Command::DeviceLs => devices.list().await,
+            Command::DeviceRevoke { id } => devices.revoke(&id).await,
+            Command::TokenCreate {
+                name,
+                scope,
+                sessions,
+                expires_secs,
+            } => tokens.create(&name, scope, sessions, expires_secs).await,
+            Command::Share {
+                session,
+                pty,
+                expires_secs,
+            } => share(&session_manager, &tokens, &session, pty, expires_secs).await,
+            Command::TokenLs => tokens.list().await,
+            Command::TokenRevoke { name } => tokens.revoke(&name).await,
+            Command::Messages {
+                session,
+                since,
+                follow: true,
+            } => session_manager
+                .transcript(&session)
+                .await
+                .map(|transcript| {
+                    // Subscribe before replying so no message is missed
+                    let (messages, added) = transcript.subscribe(since);
+                    following = Some(added);
+                    Response::ok_with(ResponseData {
+                        messages: Some(messages),
+                        ..Default::default()
+                    })
+                }),
+            Command::Messages { session, since, .. } => {
+                session_manager.messages(&session, since, false).await
+            }
+            Command::Subscribe { session } => {
+                // Subscribe before replying so no event is missed
+                subscription = Some((session_manager.subscribe(), session));
+                Ok(Response::ok())
+            }
         };
 
-        let resp = resp.unwrap_or_else(|e| Response::err("INTERNAL_ERROR", e.to_string()));
+        let resp = Response::from_result(resp);
 
         // If attach/shell succeeded, prepare for stream mode upgrade
         if resp.ok {
             if let Some(mut target) = pending_stream {
-                // For shell commands, the assigned PTY id is in the response
-                if is_shell {
+                if takes_pty_from_resp {
                     if let Some(pty_id) = resp.data.pty {
                         target.pty = pty_id;
                     }
@@ -347,11 +541,297 @@ async fn handle_client(
             handle_stream_mode(parts, &target, session_manager).await?;
             return Ok(());
         }
+
+        // A tunnel lives as long as the connection that opened it
+        if let Some((tunnel, rendezvous)) = tunnel.take() {
+            let result = handle_tunnel(&mut framed, tunnel, rendezvous).await;
+            return result;
+        }
+
+        if let Some((events, session)) = subscription.take() {
+            return handle_subscription(&mut framed, events, session).await;
+        }
+
+        if let Some(added) = following.take() {
+            return follow_messages(&mut framed, added).await;
+        }
     }
 
     Ok(())
 }
 
+/// Send each session event to a subscribed client, one message per event,
+/// until it disconnects
+async fn handle_subscription(
+    framed: &mut Framed<tokio::net::UnixStream, MessageCodec>,
+    mut events: broadcast::Receiver<SessionEvent>,
+    session: Option<String>,
+) -> Result<()> {
+    loop {
+        let event = tokio::select! {
+            // The client sends nothing more; a message or EOF ends it
+            _ = framed.next() => return Ok(()),
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => SessionEvent::Lagged { missed },
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        };
+        if session.as_deref().is_some_and(|s| !event.concerns(s)) {
+            continue;
+        }
+        framed
+            .send(Bytes::from(serde_json::to_vec(&event)?))
+            .await?;
+    }
+}
+
+/// Send each new agent message to a following client, one message each,
+/// until it disconnects or the box is gone. Messages missed by a slow
+/// client show as a gap in `seq`.
+async fn follow_messages(
+    framed: &mut Framed<tokio::net::UnixStream, MessageCodec>,
+    mut added: broadcast::Receiver<AgentMessage>,
+) -> Result<()> {
+    loop {
+        let message = tokio::select! {
+            _ = framed.next() => return Ok(()),
+            message = added.recv() => match message {
+                Ok(message) => message,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        };
+        framed
+            .send(Bytes::from(serde_json::to_vec(&message)?))
+            .await?;
+    }
+}
+
+/// Start the web UI server in the background, and the metrics and proxy
+/// servers if `metrics_port` or `proxy_port` is given
+#[allow(clippy::too_many_arguments)]
+async fn serve_web(
+    session_manager: &Arc<SessionManager>,
+    task_queue: &Arc<TaskQueue>,
+    tokens: &Arc<TokenStore>,
+    port: u16,
+    host: String,
+    token: Option<String>,
+    tls: Option<(Option<String>, Option<String>)>,
+    metrics_port: Option<u16>,
+    proxy_port: Option<u16>,
+) -> Result<Response> {
+    // Load certificates up front so a bad path fails the command
+    let tls = tls
+        .map(|(cert, key)| {
+            crate::web::tls::load(
+                cert.as_deref().map(std::path::Path::new),
+                key.as_deref().map(std::path::Path::new),
+            )
+        })
+        .transpose()?;
+    // Bind the metrics and proxy ports up front, so one that is taken fails
+    // the command
+    let metrics_listener = bind_extra_port(&host, metrics_port).await?;
+    let proxy_listener = bind_extra_port(&host, proxy_port).await?;
+    let token = token.unwrap_or_else(generate_token);
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    let wildcard = host
+        .parse::<std::net::IpAddr>()
+        .is_ok_and(|ip| ip.is_unspecified());
+    let reachable = match crate::web::server::lan_address() {
+        Some(ip) if wildcard => ip.to_string(),
+        _ if wildcard => "127.0.0.1".to_string(),
+        _ => host.clone(),
+    };
+    tokens
+        .set_serve_token(&token, format!("{}://{}:{}", scheme, reachable, port))
+        .await;
+
+    let fingerprint = tls.as_ref().map(|t| t.fingerprint.clone());
+    let self_signed = tls.as_ref().map(|t| t.self_signed);
+    if let Some(listener) = metrics_listener {
+        let sm = session_manager.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::web::metrics::start_metrics_server(listener, sm).await {
+                tracing::error!(error = %e, "Metrics server error");
+            }
+        });
+    }
+
+    let sm = session_manager.clone();
+    let queue = task_queue.clone();
+    let web_tokens = tokens.clone();
+    let host_clone = host.clone();
+    tokio::spawn(async move {
+        if let Err(e) = crate::web::server::start_web_server(
+            &host_clone,
+            port,
+            sm,
+            queue,
+            web_tokens,
+            tls.map(|t| t.acceptor),
+            proxy_listener,
+        )
+        .await
+        {
+            tracing::error!(error = %e, "Web server error");
+        }
+    });
+    Ok(Response::ok_with(ResponseData {
+        port: Some(port),
+        host: Some(host),
+        token: Some(token),
+        fingerprint,
+        self_signed,
+        ..Default::default()
+    }))
+}
+
+/// Listen on an optional extra port of `host`
+async fn bind_extra_port(host: &str, port: Option<u16>) -> Result<Option<tokio::net::TcpListener>> {
+    let Some(port) = port else {
+        return Ok(None);
+    };
+    tokio::net::TcpListener::bind((host, port))
+        .await
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {}:{}: {}", host, port, e))
+}
+
+/// Mint a spectator token for a box, checking the box and PTY exist
+async fn share(
+    session_manager: &SessionManager,
+    tokens: &TokenStore,
+    session: &str,
+    pty: Option<u32>,
+    expires_secs: Option<u64>,
+) -> Result<Response> {
+    let Ok(ls) = session_manager.session_ls(session).await else {
+        return Ok(Response::err(
+            ERR_SESSION_NOT_FOUND,
+            format!("Session '{}' not found", session),
+        ));
+    };
+    let name = ls.data.session.unwrap_or_else(|| session.to_string());
+    if let Some(pty) = pty {
+        let ptys = ls.data.ptys.unwrap_or_default();
+        if !ptys.iter().any(|p| p.id == pty) {
+            return Ok(Response::err(
+                ERR_PTY_NOT_FOUND,
+                format!("PTY {} not found in session '{}'", pty, name),
+            ));
+        }
+    }
+    tokens.share(&name, pty, expires_secs).await
+}
+
+/// Create a tunnel and its offer, publishing the offer to the rendezvous
+/// server if one is given
+async fn open_tunnel(
+    session_manager: Arc<SessionManager>,
+    config: &TunnelConfig,
+    access: Access,
+) -> Result<(Tunnel, Option<Rendezvous>, Response)> {
+    let signal = access.signal.clone();
+    let tunnel = Tunnel::new(session_manager, config, access).await?;
+    let envelope = ConnectionEnvelope::new(tunnel.offer().await?)?;
+    let mut data = ResponseData {
+        offer_sdp: Some(signaling::encode(&envelope)?),
+        qr_data: Some(envelope.url()?),
+        ..Default::default()
+    };
+
+    let rendezvous = match signal.as_deref() {
+        Some(server) => {
+            let rendezvous = Rendezvous::new(server)?;
+            rendezvous.publish_offer(&envelope).await?;
+            data.short_code = Some(rendezvous.code().to_string());
+            data.qr_data = Some(rendezvous.url());
+            Some(rendezvous)
+        }
+        None => None,
+    };
+    Ok((tunnel, rendezvous, Response::ok_with(data)))
+}
+
+/// Wait for the browser's answer, relayed by the client or picked up from
+/// the rendezvous server, then keep the tunnel up until the peer disconnects
+/// or the client goes away (Ctrl+C)
+async fn handle_tunnel(
+    framed: &mut Framed<tokio::net::UnixStream, MessageCodec>,
+    mut tunnel: Tunnel,
+    mut rendezvous: Option<Rendezvous>,
+) -> Result<()> {
+    let result = async {
+        loop {
+            let resp = tokio::select! {
+                msg = framed.next() => {
+                    let Some(msg) = msg else {
+                        return Ok(());
+                    };
+                    match serde_json::from_slice::<Command>(&msg?) {
+                        Ok(Command::TunnelAnswer { answer }) => {
+                            connect_response(connect_tunnel(&mut tunnel, &answer).await)
+                        }
+                        Ok(_) => Response::err(
+                            ERR_INVALID_COMMAND,
+                            "Only tunnelanswer is valid while a tunnel is open",
+                        ),
+                        Err(e) => Response::err(ERR_INVALID_COMMAND, format!("Parse error: {}", e)),
+                    }
+                }
+                answer = rendezvous_answer(rendezvous.as_ref()) => {
+                    // The signal server hands each answer out once
+                    rendezvous = None;
+                    let result = match answer {
+                        Ok(sdp) => tunnel.connect(&sdp, CONNECT_TIMEOUT).await,
+                        Err(e) => Err(e),
+                    };
+                    connect_response(result)
+                }
+            };
+            let connected = resp.ok;
+            framed.send(Bytes::from(serde_json::to_vec(&resp)?)).await?;
+            if connected {
+                break;
+            }
+        }
+
+        tracing::info!("Tunnel connected");
+        tokio::select! {
+            _ = tunnel.closed() => tracing::info!("Tunnel peer disconnected"),
+            _ = framed.next() => tracing::info!("Tunnel closed by client"),
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    tunnel.close().await;
+    result
+}
+
+/// Answer from the rendezvous server; never resolves without one
+async fn rendezvous_answer(rendezvous: Option<&Rendezvous>) -> Result<String> {
+    match rendezvous {
+        Some(rendezvous) => rendezvous.wait_answer().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn connect_response(result: Result<()>) -> Response {
+    match result {
+        Ok(()) => Response::ok(),
+        Err(e) => Response::err(ERR_TUNNEL_FAILED, format!("{:#}", e)),
+    }
+}
+
+async fn connect_tunnel(tunnel: &mut Tunnel, answer: &str) -> Result<()> {
+    let sdp = signaling::parse_answer(answer)?;
+    tunnel.connect(&sdp, CONNECT_TIMEOUT).await
+}
+
 /// Handle a client in stream mode: bridge between the client's framed stream
 /// and the PTY master fd, using the broadcast channel for fan-out.
 async fn handle_stream_mode(
@@ -381,22 +861,35 @@ async fn handle_stream_mode_inner(
     let stream_framed = Framed::from_parts(new_parts);
     let (mut sink, mut client_stream) = stream_framed.split();
 
-    // Get the PTY broadcast channel, master fd, and scrollback buffer
-    let (master_fd, output_tx, scrollback) = session_manager
-        .get_pty_handle(&target.session, target.pty)
+    // Subscribed to the PTY's output before the scrollback is replayed, so
+    // we don't miss anything
+    let PtyHandle {
+        input,
+        output: mut output_rx,
+        scrollback,
+        filter: mut input_filter,
+        ..
+    } = session_manager
+        .get_pty_handle(
+            &target.session,
+            target.pty,
+            Actor::local(),
+            InputClient::Local,
+        )
         .await?;
 
-    // Subscribe BEFORE replaying scrollback so we don't miss anything
-    let mut output_rx = output_tx.subscribe();
-    // Drop our sender clone so the channel properly closes when the PTY exits
-    drop(output_tx);
+    // Restarts and exit codes arrive on the event bus
+    let mut events = session_manager.subscribe();
+    let session_name = session_manager
+        .session_name(&target.session)
+        .await
+        .unwrap_or_else(|| target.session.clone());
+    let is_target = |session: &str, pty: u32| session == session_name && pty == target.pty;
+    let mut exit_code = None;
 
-    // If we have a real master fd and not readonly, set initial window size
+    // If not readonly, set initial window size
     if !target.readonly {
-        let fd = master_fd.load(Ordering::SeqCst);
-        if fd >= 0 {
-            set_pty_size(fd, target.cols, target.rows);
-        }
+        input.resize(target.cols, target.rows);
     }
 
     // Replay scrollback buffer so the client sees previous terminal state
@@ -408,6 +901,20 @@ async fn handle_stream_mode_inner(
         }
     }
 
+    // Writable local clients approve remote input held for the box
+    // (`require_approval`), starting with requests already open
+    let mut approvals = if target.readonly {
+        None
+    } else {
+        let approvals = session_manager.approvals(&target.session).await?;
+        let (open, rx) = approvals.subscribe();
+        for event in open {
+            let event = serde_json::to_vec(&event)?;
+            sink.send(StreamFrame::control(Bytes::from(event))).await?;
+        }
+        Some((approvals, rx))
+    };
+
     // The persistent PTY reader (spawned in create_session) handles reading
     // from the master fd and broadcasting. We just bridge broadcast -> client.
 
@@ -419,33 +926,18 @@ async fn handle_stream_mode_inner(
                 match frame {
                     Some(Ok(frame)) => {
                         match frame.frame_type {
-                            FRAME_PTY_DATA => {
-                                // Write input to PTY master (skip if readonly).
-                                // Read fd atomically so we always use the current
-                                // fd even after a PTY restart.
-                                if !target.readonly {
-                                    let fd = master_fd.load(Ordering::SeqCst);
-                                    if fd >= 0 {
-                                        let data = &frame.payload;
-                                        unsafe {
-                                            nix::libc::write(
-                                                fd,
-                                                data.as_ptr() as *const _,
-                                                data.len(),
-                                            );
-                                        }
-                                    }
+                            // Write input to PTY master (skip if readonly).
+                            // Read fd atomically so we always use the current
+                            // fd even after a PTY restart.
+                            FRAME_PTY_DATA if !target.readonly => {
+                                if let Some(warning) = input.write_filtered(input_filter.as_mut(), None, &frame.payload) {
+                                    sink.send(StreamFrame::pty_data(Bytes::from_static(warning))).await?;
                                 }
                             }
                             FRAME_CONTROL => {
                                 match serde_json::from_slice::<Command>(&frame.payload) {
-                                    Ok(Command::Resize { cols, rows }) => {
-                                        if !target.readonly {
-                                            let fd = master_fd.load(Ordering::SeqCst);
-                                            if fd >= 0 {
-                                                set_pty_size(fd, cols, rows);
-                                            }
-                                        }
+                                    Ok(Command::Resize { cols, rows }) if !target.readonly => {
+                                        input.resize(cols, rows);
                                     }
                                     Ok(Command::Detach) => {
                                         // Send detached event and close
@@ -453,6 +945,11 @@ async fn handle_stream_mode_inner(
                                         let _ = sink.send(StreamFrame::control(Bytes::from(event))).await;
                                         break;
                                     }
+                                    Ok(Command::ApproveInput { id, approve }) => {
+                                        if let Some((approvals, _)) = &approvals {
+                                            approvals.answer(id, approve);
+                                        }
+                                    }
                                     _ => {
                                         // Unknown control command in stream mode, ignore
                                     }
@@ -472,6 +969,38 @@ async fn handle_stream_mode_inner(
                 }
             }
 
+            // PTY lifecycle -> client
+            event = events.recv() => {
+                match event {
+                    Ok(SessionEvent::PtyExited { session, pty, code }) if is_target(&session, pty) => {
+                        exit_code = code;
+                    }
+                    Ok(SessionEvent::PtyRestarting { session, pty, delay_ms }) if is_target(&session, pty) => {
+                        let event = serde_json::to_vec(&DaemonEvent::PtyRestarting { delay_ms })?;
+                        if sink.send(StreamFrame::control(Bytes::from(event))).await.is_err() {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // Approval requests -> client
+            event = recv_approval(approvals.as_mut()) => {
+                match event {
+                    Ok(event) => {
+                        let event = serde_json::to_vec(&event)?;
+                        if sink.send(StreamFrame::control(Bytes::from(event))).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // Missed requests expire on their own
+                        tracing::debug!(error = %e, "Approval events lagged");
+                    }
+                }
+            }
+
             // PTY output -> client
             data = output_rx.recv() => {
                 match data {
@@ -483,11 +1012,22 @@ async fn handle_stream_mode_inner(
                     }
                     Err(broadcast::error::RecvError::Lagged(n)) => {
                         tracing::warn!(dropped = n, "Client lagging, dropped frames");
+                        session_manager.record_lag(n);
                         // Continue — client will see a gap in output
                     }
                     Err(broadcast::error::RecvError::Closed) => {
-                        // PTY output channel closed (PTY exited)
-                        let event = serde_json::to_vec(&DaemonEvent::PtyExited { code: 0 })?;
+                        // PTY output channel closed (PTY exited). Its exit
+                        // event is published first, but may still be queued.
+                        while let Ok(event) = events.try_recv() {
+                            if let SessionEvent::PtyExited { session, pty, code } = event {
+                                if is_target(&session, pty) {
+                                    exit_code = code;
+                                }
+                            }
+                        }
+                        let event = serde_json::to_vec(&DaemonEvent::PtyExited {
+                            code: exit_code.unwrap_or(0),
+                        })?;
                         let _ = sink.send(StreamFrame::control(Bytes::from(event))).await;
                         break;
                     }
@@ -499,8 +1039,18 @@ async fn handle_stream_mode_inner(
     Ok(())
 }
 
+/// The next approval event for an approving client; never resolves for others
+async fn recv_approval(
+    approvals: Option<&mut (Arc<Approvals>, broadcast::Receiver<DaemonEvent>)>,
+) -> Result<DaemonEvent, broadcast::error::RecvError> {
+    match approvals {
+        Some((_, rx)) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Set the window size on a PTY master fd
-fn set_pty_size(fd: std::os::unix::io::RawFd, cols: u16, rows: u16) {
+pub(crate) fn set_pty_size(fd: std::os::unix::io::RawFd, cols: u16, rows: u16) {
     let ws = nix::libc::winsize {
         ws_row: rows,
         ws_col: cols,
@@ -511,11 +1061,3 @@ fn set_pty_size(fd: std::os::unix::io::RawFd, cols: u16, rows: u16) {
         nix::libc::ioctl(fd, nix::libc::TIOCSWINSZ, &ws);
     }
 }
-
-fn generate_token() -> String {
-    use base64::Engine;
-    use rand::Rng;
-    let mut rng = rand::rng();
-    let bytes: [u8; 16] = rng.random();
-    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
-}
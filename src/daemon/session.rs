@@ -1,25 +1,40 @@
 use std::collections::HashMap;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
 use std::sync::Arc;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use bytes::Bytes;
 use tokio::sync::{broadcast, oneshot, Mutex, RwLock};
 
-use crate::config::{self, Coopfile};
+use super::adapter::{self, Transcript};
+use super::audit::{Actor, AuditAction, AuditLog};
+use super::forward::{self, Forwards};
+use super::metrics::{self, BoxSample, Metrics, PtySample, PtyStats};
+use crate::config::{
+    self, AgentProtocol, Coopfile, InputClient, InputFilterConfig, ReadyCheck, ResolvedAgent,
+    RestartPolicy, Secrets, ServiceConfig,
+};
 use crate::ipc::{
-    PtyInfo, PtyRole, Response, ResponseData, SessionInfo, ERR_SESSION_EXISTS,
-    ERR_SESSION_NOT_FOUND,
+    ClientKind, CodedError, PtyInfo, PtyRole, Response, ResponseData, ServiceStatus, SessionEvent,
+    SessionInfo, ERR_INPUT_BLOCKED, ERR_INTERNAL_ERROR, ERR_INVALID_COMMAND, ERR_PTY_NOT_FOUND,
+    ERR_SESSION_EXISTS, ERR_SESSION_NOT_FOUND,
 };
+use crate::pty::approval::Approval;
+use crate::pty::expect::OutputMatcher;
+use crate::pty::keys::input_bytes;
+use crate::pty::{ApprovalGate, Approvals, InputFilter, RedactRules};
 use crate::sandbox::namespace;
 use base64::Engine;
 
 /// Max scrollback buffer size (256KB)
 const SCROLLBACK_MAX: usize = 256 * 1024;
 
+/// Events buffered per subscriber before it starts missing them
+const EVENT_BUFFER: usize = 256;
+
 /// State of a single PTY
 #[derive(Debug, Clone)]
 pub struct PtyState {
@@ -34,24 +49,38 @@ pub struct PtyState {
     pub output_tx: Option<broadcast::Sender<Bytes>>,
     /// Shared scrollback buffer for replay on re-attach.
     pub scrollback: Option<Arc<Mutex<Vec<u8>>>>,
-    /// Whether this PTY auto-restarts on exit
-    pub auto_restart: bool,
+    /// Whether this PTY is restarted when it exits
+    pub restart: RestartPolicy,
     /// Consecutive fast failures counter (for crash loop detection)
     pub fast_failures: Arc<AtomicU32>,
+    /// Supervision state, tracked for services only
+    pub status: Option<ServiceStatus>,
+    pub stats: Arc<PtyStats>,
 }
 
 impl PtyState {
+    /// With a `transcript`, the output is also parsed as `stream-json` into it
     fn new(
         id: u32,
         role: PtyRole,
         command: String,
         pid: u32,
         master_fd: RawFd,
-        auto_restart: bool,
+        restart: RestartPolicy,
+        transcript: Option<Arc<Transcript>>,
     ) -> (Self, oneshot::Receiver<()>) {
         let (output_tx, _) = broadcast::channel(256);
+        if let Some(transcript) = transcript {
+            adapter::spawn_parser(output_tx.subscribe(), id, transcript);
+        }
         let scrollback = Arc::new(Mutex::new(Vec::new()));
-        let exit_rx = spawn_pty_reader(master_fd, output_tx.clone(), scrollback.clone());
+        let stats = Arc::new(PtyStats::default());
+        let exit_rx = spawn_pty_reader(
+            master_fd,
+            output_tx.clone(),
+            scrollback.clone(),
+            stats.clone(),
+        );
         let state = Self {
             id,
             role,
@@ -60,13 +89,120 @@ impl PtyState {
             master_fd: Arc::new(AtomicI32::new(master_fd)),
             output_tx: Some(output_tx),
             scrollback: Some(scrollback),
-            auto_restart,
+            restart,
             fast_failures: Arc::new(AtomicU32::new(0)),
+            status: None,
+            stats,
         };
         (state, exit_rx)
     }
 }
 
+/// A client bridge's (socket, WebSocket, tunnel) connection to a PTY
+pub struct PtyHandle {
+    pub input: PtyInput,
+    /// Subscribed before `scrollback` is handed out, so replaying the
+    /// scrollback and then this misses nothing. Closes when the PTY is gone.
+    pub output: broadcast::Receiver<Bytes>,
+    pub scrollback: Option<Arc<Mutex<Vec<u8>>>>,
+    /// What remote bridges mask in output and scrollback
+    pub redact: Arc<RedactRules>,
+    /// The input filter for this client and PTY, if its policy has one
+    pub filter: Option<InputFilter>,
+    /// Holds input for a local terminal's approval, if the policy asks
+    pub approval: Option<ApprovalGate>,
+}
+
+/// The writing side of a PTY, auditing what `actor` does with it
+pub struct PtyInput {
+    /// Shared atomically so bridges always use the current fd after
+    /// restarts. -1 = closed.
+    master_fd: Arc<AtomicI32>,
+    stats: Arc<PtyStats>,
+    pty: u32,
+    actor: Actor,
+    audit: Arc<AuditLog>,
+}
+
+impl PtyInput {
+    /// Whether the PTY's process is still running
+    pub fn is_open(&self) -> bool {
+        self.master_fd.load(Ordering::SeqCst) >= 0
+    }
+
+    /// Write client input to the PTY, if it is still open
+    pub fn write(&self, data: &[u8]) {
+        let fd = self.master_fd.load(Ordering::SeqCst);
+        if fd >= 0 && !data.is_empty() {
+            let n = unsafe { nix::libc::write(fd, data.as_ptr() as *const _, data.len()) };
+            if n > 0 {
+                self.stats.add_in(n as usize);
+                self.audit.input(&self.actor, self.pty, &data[..n as usize]);
+            }
+        }
+    }
+
+    /// Set the PTY's window size, if it is still open
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let fd = self.master_fd.load(Ordering::SeqCst);
+        if fd >= 0 {
+            super::server::set_pty_size(fd, cols, rows);
+            self.audit.record(
+                &self.actor,
+                Some(self.pty),
+                AuditAction::Resize { cols, rows },
+            );
+        }
+    }
+
+    /// Note input the client's input filter dropped
+    pub fn blocked(&self, sequence: &[u8]) {
+        self.audit.blocked(&self.actor, self.pty, sequence);
+    }
+
+    /// Write client input through its filter, auditing what the filter
+    /// drops. With an approval gate, what passes is held there instead.
+    /// Returns the warning to show the client, if any.
+    pub fn write_filtered(
+        &self,
+        filter: Option<&mut InputFilter>,
+        gate: Option<&mut ApprovalGate>,
+        data: &[u8],
+    ) -> Option<&'static [u8]> {
+        let (to_forward, warning) = match filter {
+            Some(filter) => {
+                let (to_forward, warning) = filter.filter(data);
+                if warning.is_some() {
+                    if let Some(sequence) = filter.take_blocked() {
+                        self.blocked(&sequence);
+                    }
+                }
+                (to_forward, warning)
+            }
+            None => (data.to_vec(), None),
+        };
+        match gate {
+            Some(gate) => gate.push(&to_forward),
+            None => self.write(&to_forward),
+        }
+        warning
+    }
+
+    /// Act on news from the client's approval gate: write approved input,
+    /// audit the rest as blocked. Returns the notice to show the client.
+    pub fn settle(&self, approval: Approval) -> Option<&'static [u8]> {
+        let notice = approval.notice();
+        match approval {
+            Approval::Waiting => {}
+            Approval::Approved(data) => self.write(&data),
+            Approval::Rejected(data) | Approval::Expired(data) | Approval::NoApprover(data) => {
+                self.blocked(&data)
+            }
+        }
+        notice
+    }
+}
+
 /// State of a running session
 #[derive(Debug)]
 pub struct Session {
@@ -89,6 +225,22 @@ pub struct Session {
     pub sandbox_workspace: String,
     /// Delay before restarting PTYs with auto_restart (ms)
     pub restart_delay_ms: u64,
+    pub audit: Arc<AuditLog>,
+    /// SSH agent and git credential listeners for the box
+    pub forwards: Forwards,
+    /// `[secrets]` as read when the box was created
+    pub secrets: Secrets,
+    /// Output masked for web and tunnel clients
+    pub redact: Arc<RedactRules>,
+    /// `[input_filter]` policies for client connections
+    pub input_filter: InputFilterConfig,
+    /// Remote input waiting for a local terminal (`require_approval`)
+    pub approvals: Arc<Approvals>,
+    /// Input filters for one-shot input (`coop send`), one per client and
+    /// PTY, so line tracking and Ctrl+C debounce carry across sends
+    pub send_filters: HashMap<(Actor, u32), Arc<Mutex<InputFilter>>>,
+    /// Messages parsed from `stream-json` agents
+    pub transcript: Arc<Transcript>,
     /// Pinned namespace fds — keep the namespace alive for restart support.
     /// -1 means not set (e.g. rediscovered sessions without namespace fds).
     pub ns_user_fd: RawFd,
@@ -124,6 +276,30 @@ impl Drop for Session {
 }
 
 impl Session {
+    /// Spawn a new process with its own PTY inside this session's namespaces,
+    /// with `extra_env` layered over the session's user env.
+    fn nsenter(
+        &self,
+        command: &str,
+        extra_env: &[(String, String)],
+    ) -> Result<namespace::ShellNamespace> {
+        let mut env_vars = self.user_env.clone();
+        env_vars.extend(extra_env.iter().cloned());
+
+        namespace::nsenter_shell(
+            self.ns_user_fd,
+            self.ns_mnt_fd,
+            self.ns_uts_fd,
+            self.ns_net_fd,
+            self.ns_root_fd,
+            command,
+            &env_vars,
+            &self.sandbox_user,
+            &self.sandbox_home,
+            &self.sandbox_workspace,
+        )
+    }
+
     /// Remove dead PTY processes (both agent and shell roles).
     fn prune_dead_ptys(&mut self) {
         self.ptys.retain(|p| match p.pid {
@@ -146,6 +322,7 @@ impl Session {
                     role: p.role.clone(),
                     command: p.command.clone(),
                     pid: p.pid,
+                    status: p.status,
                 })
                 .collect(),
             web_clients: self.web_clients,
@@ -157,6 +334,42 @@ impl Session {
 /// Manages all active sessions.
 pub struct SessionManager {
     sessions: RwLock<HashMap<String, Session>>,
+    /// Event bus for `subscribe` and `GET /api/events`
+    events: broadcast::Sender<SessionEvent>,
+    metrics: Metrics,
+}
+
+/// Read and discard a PTY's output until every slave end is closed. The
+/// master is closed when this returns or is dropped.
+async fn drain_pty(master: OwnedFd) {
+    unsafe {
+        let fd = master.as_raw_fd();
+        let flags = nix::libc::fcntl(fd, nix::libc::F_GETFL);
+        nix::libc::fcntl(fd, nix::libc::F_SETFL, flags | nix::libc::O_NONBLOCK);
+    }
+    let Ok(async_fd) = tokio::io::unix::AsyncFd::new(master) else {
+        return;
+    };
+    let mut buf = [0u8; 4096];
+    loop {
+        let Ok(mut guard) = async_fd.readable().await else {
+            return;
+        };
+        match guard.try_io(|inner| {
+            let n = unsafe {
+                nix::libc::read(inner.as_raw_fd(), buf.as_mut_ptr() as *mut _, buf.len())
+            };
+            if n < 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(n)
+            }
+        }) {
+            Ok(Ok(n)) if n > 0 => {}
+            Ok(_) => return, // EOF, or EIO once the slave is closed
+            Err(_would_block) => {}
+        }
+    }
 }
 
 /// Spawn a persistent PTY reader task that reads from master_fd, broadcasts
@@ -166,6 +379,7 @@ fn spawn_pty_reader(
     master_fd: RawFd,
     output_tx: broadcast::Sender<Bytes>,
     scrollback: Arc<Mutex<Vec<u8>>>,
+    stats: Arc<PtyStats>,
 ) -> oneshot::Receiver<()> {
     let (exit_tx, exit_rx) = oneshot::channel();
 
@@ -207,6 +421,7 @@ fn spawn_pty_reader(
                 }
             }) {
                 Ok(Ok(n)) => {
+                    stats.add_out(n);
                     let data = Bytes::copy_from_slice(&buf[..n]);
 
                     // Append to scrollback buffer
@@ -239,13 +454,123 @@ fn is_pid_alive(pid: u32) -> bool {
     nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), None).is_ok()
 }
 
+/// Exit code of a child process that has exited, without reaping it (the
+/// zombie keeps the pid from being reused while the PTY is still tracked).
+/// Signals map to 128+N like a shell would report. None if still running.
+pub(crate) fn try_exit_code(pid: u32) -> Option<i32> {
+    use nix::sys::wait::{waitid, Id, WaitPidFlag, WaitStatus};
+
+    let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+    match waitid(Id::Pid(nix::unistd::Pid::from_raw(pid as i32)), flags) {
+        Ok(WaitStatus::Exited(_, code)) => Some(code),
+        Ok(WaitStatus::Signaled(_, sig, _)) => Some(128 + sig as i32),
+        _ => None,
+    }
+}
+
+/// Like `try_exit_code`, but the PTY can hit EOF just before the process is
+/// gone, so poll briefly before giving up.
+pub(crate) async fn exit_code(pid: u32) -> Option<i32> {
+    for _ in 0..20 {
+        if let Some(code) = try_exit_code(pid) {
+            return Some(code);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    None
+}
+
+/// A terminal warning (`BLOCKED_WARNING` and the like) as plain text, for
+/// responses
+fn plain_text(warning: &[u8]) -> String {
+    let warning = String::from_utf8_lossy(warning);
+    let mut text = String::new();
+    let mut chars = warning.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the escape sequence up to its final letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            text.push(c);
+        }
+    }
+    text.trim()
+        .trim_start_matches('\u{26a0}')
+        .trim()
+        .to_string()
+}
+
 impl SessionManager {
     pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
         Self {
             sessions: RwLock::new(HashMap::new()),
+            events,
+            metrics: Metrics::default(),
         }
     }
 
+    /// Count PTY output frames dropped for a client that fell behind
+    pub fn record_lag(&self, frames: u64) {
+        self.metrics
+            .lagged_frames
+            .fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Prometheus text exposition of the daemon's metrics
+    pub async fn render_metrics(&self) -> Result<String> {
+        let (boxes, ptys) = {
+            let sessions = self.sessions.read().await;
+            let boxes: Vec<BoxSample> = sessions
+                .values()
+                .map(|s| BoxSample {
+                    session: s.name.clone(),
+                    pids: std::iter::once(s.namespace_pid)
+                        .chain(s.ptys.iter().filter_map(|p| p.pid))
+                        .collect(),
+                    local_clients: s.local_clients,
+                    web_clients: s.web_clients,
+                })
+                .collect();
+            let ptys: Vec<PtySample> = sessions
+                .values()
+                .flat_map(|s| {
+                    s.ptys.iter().map(|p| PtySample {
+                        session: s.name.clone(),
+                        pty: p.id,
+                        role: match p.role {
+                            PtyRole::Agent(_) => "agent",
+                            PtyRole::Service(_) => "service",
+                            PtyRole::Shell => "shell",
+                        },
+                        bytes_in: p.stats.bytes_in.load(Ordering::Relaxed),
+                        bytes_out: p.stats.bytes_out.load(Ordering::Relaxed),
+                        restarts: p.stats.restarts.load(Ordering::Relaxed),
+                    })
+                })
+                .collect();
+            (boxes, ptys)
+        };
+        // Scanning procfs blocks
+        let (boxes, usage) = tokio::task::spawn_blocking(move || {
+            let usage = metrics::box_usage(&boxes);
+            (boxes, usage)
+        })
+        .await?;
+        Ok(metrics::render(&boxes, &ptys, &self.metrics, &usage))
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: SessionEvent) {
+        tracing::trace!(event = ?event, "Session event");
+        // No subscribers is fine
+        let _ = self.events.send(event);
+    }
+
     pub async fn session_count(&self) -> usize {
         self.sessions.read().await.len()
     }
@@ -268,6 +593,7 @@ impl SessionManager {
                 "Rediscovered orphaned session"
             );
 
+            let audit = Arc::new(AuditLog::open(&ds.name, false, vec![]));
             sessions.insert(
                 ds.name.clone(),
                 Session {
@@ -284,6 +610,14 @@ impl SessionManager {
                     user_env: vec![],
                     sandbox_workspace: "/workspace".to_string(),
                     restart_delay_ms: 1000,
+                    audit,
+                    forwards: Forwards::default(),
+                    secrets: Secrets::default(),
+                    redact: Arc::new(RedactRules::default()),
+                    input_filter: InputFilterConfig::default(),
+                    approvals: Arc::default(),
+                    send_filters: HashMap::new(),
+                    transcript: Arc::default(),
                     // Rediscovered sessions don't have pinned fds — restart won't work
                     ns_user_fd: -1,
                     ns_mnt_fd: -1,
@@ -301,6 +635,17 @@ impl SessionManager {
         workspace: String,
         _coopfile: Option<String>,
         _detach: bool,
+    ) -> Result<Response> {
+        self.create_session_with(name, workspace, |_| {}).await
+    }
+
+    /// Create a session, letting the caller adjust the resolved config before
+    /// the namespace is created (used by headless task runs).
+    pub async fn create_session_with(
+        self: &Arc<Self>,
+        name: Option<String>,
+        workspace: String,
+        customize: impl FnOnce(&mut Coopfile) + Send,
     ) -> Result<Response> {
         let name = name.unwrap_or_else(|| {
             std::path::Path::new(&workspace)
@@ -343,6 +688,25 @@ impl SessionManager {
         let workspace_path = PathBuf::from(&workspace);
         let mut config = Coopfile::resolve(&workspace_path, None).unwrap_or_default();
         config.expand_env();
+        customize(&mut config);
+        if let Err(e) = config
+            .validate_agents()
+            .and_then(|_| config.validate_services())
+            .and_then(|_| config.validate_network())
+            .and_then(|_| config.validate_input_filter())
+            .and_then(|_| config.validate_output_filter())
+            .and_then(|_| config::validate_secrets(&config.secrets))
+        {
+            return Ok(Response::err("INVALID_CONFIG", e.to_string()));
+        }
+        let secrets = match Secrets::resolve(&config.secrets) {
+            Ok(secrets) => secrets,
+            Err(e) => return Ok(Response::err("INVALID_CONFIG", format!("{:#}", e))),
+        };
+        let redact = match RedactRules::new(&config.output_filter, &secrets.values()) {
+            Ok(rules) => Arc::new(rules),
+            Err(e) => return Ok(Response::err("INVALID_CONFIG", e.to_string())),
+        };
 
         // Verify base rootfs exists
         let base_path = config::rootfs_base_path()?;
@@ -353,8 +717,20 @@ impl SessionManager {
             ));
         }
 
+        // The sockets must exist before the box mounts their directory
+        let forwards = match forward::start(&name, &config.sandbox) {
+            Ok(forwards) => forwards,
+            Err(e) => {
+                return Ok(Response::err(
+                    ERR_INTERNAL_ERROR,
+                    format!("Failed to set up forwarding: {}", e),
+                ));
+            }
+        };
+        forwards.apply_env(&mut config.env);
+
         // Create the namespace
-        let ns_result = match namespace::create_session(&name, &config, &workspace_path) {
+        let ns_result = match namespace::create_session(&name, &config, &workspace_path, &secrets) {
             Ok(ns) => ns,
             Err(e) => {
                 return Ok(Response::err(
@@ -364,11 +740,10 @@ impl SessionManager {
             }
         };
 
-        let agent_cmd = config
-            .sandbox
-            .agent_command()
-            .unwrap_or("claude")
-            .to_string();
+        // The primary agent runs as the namespace init process (PTY 0);
+        // additional agents are nsenter'd once the session is registered
+        let mut agents = config.agents().into_iter();
+        let primary = agents.next().expect("config.agents() is never empty");
 
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -385,16 +760,18 @@ impl SessionManager {
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
 
-        let auto_restart = config.session.auto_restart;
+        let restart = RestartPolicy::from_auto_restart(primary.auto_restart);
         let restart_delay_ms = config.session.restart_delay_ms;
+        let transcript = Arc::new(Transcript::default());
 
         let (agent_pty, exit_rx) = PtyState::new(
             0,
-            PtyRole::Agent,
-            agent_cmd,
+            PtyRole::Agent(primary.name.clone()),
+            primary.command,
             ns_result.child_pid,
             ns_result.pty_master_fd,
-            auto_restart,
+            restart,
+            (primary.protocol == AgentProtocol::StreamJson).then(|| transcript.clone()),
         );
         let output_tx = agent_pty.output_tx.clone().unwrap();
         let fast_failures = agent_pty.fast_failures.clone();
@@ -413,6 +790,18 @@ impl SessionManager {
             user_env,
             sandbox_workspace,
             restart_delay_ms,
+            audit: Arc::new(AuditLog::open(
+                &name,
+                config.audit.redact_input,
+                secrets.values(),
+            )),
+            forwards,
+            secrets,
+            redact,
+            input_filter: config.input_filter.clone(),
+            approvals: Arc::default(),
+            send_filters: HashMap::new(),
+            transcript,
             ns_user_fd: ns_result.ns_user_fd,
             ns_mnt_fd: ns_result.ns_mnt_fd,
             ns_uts_fd: ns_result.ns_uts_fd,
@@ -430,6 +819,15 @@ impl SessionManager {
         let mut sessions = self.sessions.write().await;
         sessions.insert(name.clone(), session);
         drop(sessions);
+        self.publish(SessionEvent::SessionCreated {
+            session: name.clone(),
+        });
+        self.publish(SessionEvent::PtySpawned {
+            session: name.clone(),
+            pty: 0,
+            role: PtyRole::Agent(primary.name),
+            pid: ns_result.child_pid,
+        });
 
         self.spawn_exit_watcher(
             exit_rx,
@@ -437,12 +835,24 @@ impl SessionManager {
             0,
             ns_result.child_pid,
             output_tx,
-            auto_restart,
-            restart_delay_ms,
+            restart,
+            primary.restart_delay_ms,
             fast_failures,
             Instant::now(),
         );
 
+        let mut pty_ids = 1..;
+        for (pty_id, agent) in pty_ids.by_ref().zip(agents) {
+            if let Err(e) = self.spawn_agent(&name, pty_id, agent).await {
+                tracing::error!(session = %name, pty = pty_id, error = %e, "Failed to start agent");
+            }
+        }
+        for (pty_id, service) in pty_ids.zip(config.services) {
+            if let Err(e) = self.spawn_service(&name, pty_id, service).await {
+                tracing::error!(session = %name, pty = pty_id, error = %e, "Failed to start service");
+            }
+        }
+
         Ok(Response::ok_with(ResponseData {
             session: Some(name),
             pid: Some(ns_result.child_pid),
@@ -450,25 +860,296 @@ impl SessionManager {
         }))
     }
 
+    /// Start an additional agent in an existing session's namespaces.
+    async fn spawn_agent(
+        self: &Arc<Self>,
+        session_name: &str,
+        pty_id: u32,
+        agent: ResolvedAgent,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(session_name).ok_or_else(|| {
+            CodedError::new(
+                ERR_SESSION_NOT_FOUND,
+                format!("Session '{}' not found", session_name),
+            )
+        })?;
+
+        let mut env = agent.env;
+        env.extend(session.secrets.agent_env());
+        let agent_ns = session.nsenter(&agent.command, &env)?;
+
+        let (agent_pty, exit_rx) = PtyState::new(
+            pty_id,
+            PtyRole::Agent(agent.name.clone()),
+            agent.command,
+            agent_ns.shell_pid,
+            agent_ns.pty_master_fd,
+            RestartPolicy::from_auto_restart(agent.auto_restart),
+            (agent.protocol == AgentProtocol::StreamJson).then(|| session.transcript.clone()),
+        );
+        let output_tx = agent_pty.output_tx.clone().unwrap();
+        let fast_failures = agent_pty.fast_failures.clone();
+        session.ptys.push(agent_pty);
+        drop(sessions);
+
+        self.spawn_exit_watcher(
+            exit_rx,
+            session_name.to_string(),
+            pty_id,
+            agent_ns.shell_pid,
+            output_tx,
+            RestartPolicy::from_auto_restart(agent.auto_restart),
+            agent.restart_delay_ms,
+            fast_failures,
+            Instant::now(),
+        );
+
+        tracing::info!(session = %session_name, pty = pty_id, agent = %agent.name, "Started agent");
+        self.publish(SessionEvent::PtySpawned {
+            session: session_name.to_string(),
+            pty: pty_id,
+            role: PtyRole::Agent(agent.name),
+            pid: agent_ns.shell_pid,
+        });
+        Ok(())
+    }
+
+    /// Start a `[[services]]` entry in an existing session's namespaces and
+    /// begin polling its readiness check.
+    async fn spawn_service(
+        self: &Arc<Self>,
+        session_name: &str,
+        pty_id: u32,
+        service: ServiceConfig,
+    ) -> Result<()> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions.get_mut(session_name).ok_or_else(|| {
+            CodedError::new(
+                ERR_SESSION_NOT_FOUND,
+                format!("Session '{}' not found", session_name),
+            )
+        })?;
+
+        let env: Vec<(String, String)> = service
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let service_ns = session.nsenter(&service.command, &env)?;
+
+        let (mut service_pty, exit_rx) = PtyState::new(
+            pty_id,
+            PtyRole::Service(service.name.clone()),
+            service.command,
+            service_ns.shell_pid,
+            service_ns.pty_master_fd,
+            service.restart,
+            None,
+        );
+        service_pty.status = Some(ServiceStatus::Starting);
+        let output_tx = service_pty.output_tx.clone().unwrap();
+        let fast_failures = service_pty.fast_failures.clone();
+        session.ptys.push(service_pty);
+        drop(sessions);
+
+        self.spawn_exit_watcher(
+            exit_rx,
+            session_name.to_string(),
+            pty_id,
+            service_ns.shell_pid,
+            output_tx,
+            service.restart,
+            service.restart_delay_ms,
+            fast_failures,
+            Instant::now(),
+        );
+        self.spawn_readiness_check(
+            session_name.to_string(),
+            pty_id,
+            service_ns.shell_pid,
+            service.ready,
+        );
+
+        tracing::info!(session = %session_name, pty = pty_id, service = %service.name, "Started service");
+        self.publish(SessionEvent::PtySpawned {
+            session: session_name.to_string(),
+            pty: pty_id,
+            role: PtyRole::Service(service.name),
+            pid: service_ns.shell_pid,
+        });
+        Ok(())
+    }
+
+    /// Poll a service's readiness check in the background and record the
+    /// outcome. Gives up quietly if the service is restarted or killed meanwhile.
+    fn spawn_readiness_check(
+        self: &Arc<Self>,
+        session_name: String,
+        pty_id: u32,
+        pid: u32,
+        check: Option<ReadyCheck>,
+    ) {
+        let sm = Arc::clone(self);
+        tokio::spawn(async move {
+            let status = match check {
+                None => ServiceStatus::Ready,
+                Some(check) => {
+                    let deadline =
+                        Instant::now() + std::time::Duration::from_millis(check.timeout_ms);
+                    let interval = std::time::Duration::from_millis(check.interval_ms.max(10));
+                    loop {
+                        if sm.get_pty_pid(&session_name, pty_id).await != Some(pid) {
+                            return;
+                        }
+                        if sm.probe_ready(&session_name, &check, interval).await {
+                            break ServiceStatus::Ready;
+                        }
+                        if Instant::now() >= deadline {
+                            break ServiceStatus::NotReady;
+                        }
+                        tokio::time::sleep(interval).await;
+                    }
+                }
+            };
+
+            if status == ServiceStatus::NotReady {
+                tracing::warn!(session = %session_name, pty = pty_id, "Service readiness check timed out");
+            }
+            sm.set_service_status(&session_name, pty_id, Some(pid), status)
+                .await;
+        });
+    }
+
+    /// Run one readiness probe: a TCP connect to the port, or the check
+    /// command (killed after `timeout`) exiting with status 0.
+    async fn probe_ready(
+        &self,
+        session_name: &str,
+        check: &ReadyCheck,
+        timeout: std::time::Duration,
+    ) -> bool {
+        if let Some(port) = check.port {
+            return matches!(
+                tokio::time::timeout(timeout, self.connect_box_port(session_name, port)).await,
+                Ok(Ok(_))
+            );
+        }
+        let Some(command) = &check.command else {
+            return true;
+        };
+
+        let check_ns = {
+            let sessions = self.sessions.read().await;
+            let Ok(session) = self.resolve_session(&sessions, session_name) else {
+                return false;
+            };
+            match session.nsenter(command, &[]) {
+                Ok(ns) => ns,
+                Err(e) => {
+                    tracing::debug!(session = %session_name, error = %e, "Failed to run readiness check");
+                    return false;
+                }
+            }
+        };
+
+        let pid = nix::unistd::Pid::from_raw(check_ns.shell_pid as i32);
+        let master = unsafe { OwnedFd::from_raw_fd(check_ns.pty_master_fd) };
+        let waiter = async move {
+            // Drain output so a chatty check can't block on a full PTY. The
+            // timeout covers the drain too: a child the check left running
+            // can hold the PTY open after the check itself exits.
+            drain_pty(master).await;
+            tokio::task::spawn_blocking(move || nix::sys::wait::waitpid(pid, None)).await
+        };
+
+        match tokio::time::timeout(timeout, waiter).await {
+            Ok(status) => matches!(status, Ok(Ok(nix::sys::wait::WaitStatus::Exited(_, 0)))),
+            Err(_) => {
+                let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+                tokio::task::spawn_blocking(move || nix::sys::wait::waitpid(pid, None));
+                false
+            }
+        }
+    }
+
+    /// Record a service's supervision state. With `expected_pid`, the update is
+    /// dropped if the service has since been restarted.
+    async fn set_service_status(
+        &self,
+        session_name: &str,
+        pty_id: u32,
+        expected_pid: Option<u32>,
+        status: ServiceStatus,
+    ) {
+        let mut sessions = self.sessions.write().await;
+        let Some(pty) = sessions
+            .get_mut(session_name)
+            .and_then(|s| s.ptys.iter_mut().find(|p| p.id == pty_id))
+        else {
+            return;
+        };
+        if expected_pid.is_some() && pty.pid != expected_pid {
+            return;
+        }
+        if pty.status.is_some() {
+            pty.status = Some(status);
+        }
+    }
+
+    /// Mark a service as no longer running, keeping its PTY (and scrollback)
+    /// around so `coop logs --service` still works.
+    async fn stop_service(&self, session_name: &str, pty_id: u32, status: ServiceStatus) {
+        let mut sessions = self.sessions.write().await;
+        let Some(pty) = sessions
+            .get_mut(session_name)
+            .and_then(|s| s.ptys.iter_mut().find(|p| p.id == pty_id))
+        else {
+            return;
+        };
+        pty.pid = None;
+        pty.status = Some(status);
+        let fd = pty.master_fd.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            unsafe { nix::libc::close(fd) };
+        }
+    }
+
+    /// Check that a PTY exists, resolving an agent name to its PTY id if given.
     pub async fn attach(
         &self,
         session: &str,
         pty: u32,
+        agent: Option<&str>,
         _cols: u16,
         _rows: u16,
     ) -> Result<Response> {
         let sessions = self.sessions.read().await;
         let session = self.resolve_session(&sessions, session)?;
 
-        if pty as usize >= session.ptys.len() {
-            return Ok(Response::err(
-                "PTY_NOT_FOUND",
-                format!("PTY {} not found in session '{}'", pty, session.name),
-            ));
-        }
+        let found = match agent {
+            Some(agent) => session
+                .ptys
+                .iter()
+                .find(|p| matches!(&p.role, PtyRole::Agent(n) if n == agent)),
+            None => session.ptys.iter().find(|p| p.id == pty),
+        };
 
-        // TODO: upgrade to stream mode
-        Ok(Response::ok())
+        match found {
+            Some(p) => Ok(Response::ok_with(ResponseData {
+                pty: Some(p.id),
+                ..Default::default()
+            })),
+            None => Ok(Response::err(
+                ERR_PTY_NOT_FOUND,
+                match agent {
+                    Some(agent) => {
+                        format!("Agent '{}' not found in session '{}'", agent, session.name)
+                    }
+                    None => format!("PTY {} not found in session '{}'", pty, session.name),
+                },
+            )),
+        }
     }
 
     pub async fn spawn_shell(
@@ -503,28 +1184,7 @@ impl SessionManager {
 
         let pty_id = session.ptys.iter().map(|p| p.id).max().map_or(1, |m| m + 1);
 
-        let env_vars = session.user_env.clone();
-        let ns_user_fd = session.ns_user_fd;
-        let ns_mnt_fd = session.ns_mnt_fd;
-        let ns_uts_fd = session.ns_uts_fd;
-        let ns_net_fd = session.ns_net_fd;
-        let ns_root_fd = session.ns_root_fd;
-        let sandbox_user = session.sandbox_user.clone();
-        let sandbox_home = session.sandbox_home.clone();
-        let sandbox_workspace = session.sandbox_workspace.clone();
-
-        let shell_ns = namespace::nsenter_shell(
-            ns_user_fd,
-            ns_mnt_fd,
-            ns_uts_fd,
-            ns_net_fd,
-            ns_root_fd,
-            &cmd,
-            &env_vars,
-            &sandbox_user,
-            &sandbox_home,
-            &sandbox_workspace,
-        )?;
+        let shell_ns = session.nsenter(&cmd, &[])?;
 
         let (shell_pty, exit_rx) = PtyState::new(
             pty_id,
@@ -532,7 +1192,8 @@ impl SessionManager {
             cmd,
             shell_ns.shell_pid,
             shell_ns.pty_master_fd,
-            false,
+            RestartPolicy::Never,
+            None,
         );
         let output_tx = shell_pty.output_tx.clone().unwrap();
         let fast_failures = shell_pty.fast_failures.clone();
@@ -541,15 +1202,21 @@ impl SessionManager {
 
         self.spawn_exit_watcher(
             exit_rx,
-            name,
+            name.clone(),
             pty_id,
             shell_ns.shell_pid,
             output_tx,
-            false,
+            RestartPolicy::Never,
             0,
             fast_failures,
             Instant::now(),
         );
+        self.publish(SessionEvent::PtySpawned {
+            session: name,
+            pty: pty_id,
+            role: PtyRole::Shell,
+            pid: shell_ns.shell_pid,
+        });
 
         Ok(Response::ok_with(ResponseData {
             pty: Some(pty_id),
@@ -558,7 +1225,26 @@ impl SessionManager {
     }
 
     /// Kill a specific PTY session within a box
-    pub async fn kill_pty(&self, session_name: &str, pty_id: u32) -> Result<Response> {
+    pub async fn kill_pty(
+        &self,
+        session_name: &str,
+        pty_id: u32,
+        actor: &Actor,
+    ) -> Result<Response> {
+        let name = self.remove_pty(session_name, pty_id).await?;
+        if let Some(session) = self.sessions.read().await.get(&name) {
+            session.audit.record(actor, Some(pty_id), AuditAction::Kill);
+        }
+        self.publish(SessionEvent::PtyExited {
+            session: name,
+            pty: pty_id,
+            code: None,
+        });
+        Ok(Response::ok())
+    }
+
+    /// Stop a PTY's process and forget it, returning the resolved box name
+    async fn remove_pty(&self, session_name: &str, pty_id: u32) -> Result<String> {
         let mut sessions = self.sessions.write().await;
         let name = Self::resolve_name(&sessions, session_name)?;
         let session = sessions.get_mut(&name).unwrap();
@@ -568,7 +1254,10 @@ impl SessionManager {
             .iter()
             .position(|p| p.id == pty_id)
             .ok_or_else(|| {
-                anyhow::anyhow!("PTY {} not found in session '{}'", pty_id, session_name)
+                CodedError::new(
+                    ERR_PTY_NOT_FOUND,
+                    format!("PTY {} not found in session '{}'", pty_id, session_name),
+                )
             })?;
 
         let pty = &session.ptys[pty_idx];
@@ -591,7 +1280,7 @@ impl SessionManager {
         session.ptys.remove(pty_idx);
 
         tracing::info!(session = %session_name, pty = pty_id, "Killed PTY session");
-        Ok(Response::ok())
+        Ok(name)
     }
 
     /// List PTY sessions within a specific box
@@ -613,6 +1302,7 @@ impl SessionManager {
                 role: p.role.clone(),
                 command: p.command.clone(),
                 pid: p.pid,
+                status: p.status,
             })
             .collect();
 
@@ -633,7 +1323,12 @@ impl SessionManager {
         }))
     }
 
-    pub async fn kill_session(&self, session_name: &str, force: bool) -> Result<Response> {
+    pub async fn kill_session(
+        &self,
+        session_name: &str,
+        force: bool,
+        actor: &Actor,
+    ) -> Result<Response> {
         let mut sessions = self.sessions.write().await;
 
         // Resolve session name (could be workspace path)
@@ -657,6 +1352,16 @@ impl SessionManager {
         };
 
         if let Some(session) = sessions.remove(&name) {
+            session.audit.record(actor, None, AuditAction::Kill);
+
+            // Additional agents and shells are nsenter'd from the daemon rather
+            // than forked from init, so signal them individually
+            for pid in session.ptys.iter().filter_map(|p| p.pid) {
+                if pid != session.namespace_pid {
+                    let _ = namespace::kill_session(pid, force);
+                }
+            }
+
             // Kill the namespace init process
             if session.namespace_pid > 0 {
                 if let Err(e) = namespace::kill_session(session.namespace_pid, force) {
@@ -686,6 +1391,7 @@ impl SessionManager {
             }
 
             tracing::info!(session = %name, "Killed session");
+            self.publish(SessionEvent::SessionKilled { session: name });
             Ok(Response::ok())
         } else {
             Ok(Response::err(
@@ -695,11 +1401,19 @@ impl SessionManager {
         }
     }
 
-    pub async fn kill_all(&self, force: bool) -> Result<Response> {
+    pub async fn kill_all(&self, force: bool, actor: &Actor) -> Result<Response> {
         let mut sessions = self.sessions.write().await;
         let count = sessions.len();
 
         for (name, session) in sessions.drain() {
+            session.audit.record(actor, None, AuditAction::Kill);
+
+            for pid in session.ptys.iter().filter_map(|p| p.pid) {
+                if pid != session.namespace_pid {
+                    let _ = namespace::kill_session(pid, force);
+                }
+            }
+
             if session.namespace_pid > 0 {
                 if let Err(e) = namespace::kill_session(session.namespace_pid, force) {
                     tracing::warn!(
@@ -716,29 +1430,55 @@ impl SessionManager {
                 let _ = std::fs::remove_dir_all(session_dir.join("work"));
                 let _ = std::fs::remove_dir_all(session_dir.join("merged"));
             }
+            self.publish(SessionEvent::SessionKilled { session: name });
         }
 
         tracing::info!(count = count, "Killed all sessions");
         Ok(Response::ok())
     }
 
-    /// Get scrollback logs for a PTY, optionally tail N lines.
+    /// Get scrollback logs for a PTY (or a service addressed by name),
+    /// optionally tail N lines. `redact` masks them as for remote clients.
     pub async fn get_logs(
         &self,
         session_name: &str,
         pty_id: u32,
+        service: Option<&str>,
         tail_lines: Option<usize>,
+        redact: bool,
     ) -> Result<Response> {
         let sessions = self.sessions.read().await;
         let session = self.resolve_session(&sessions, session_name)?;
 
-        let pty = session
-            .ptys
-            .iter()
-            .find(|p| p.id == pty_id)
-            .ok_or_else(|| {
-                anyhow::anyhow!("PTY {} not found in session '{}'", pty_id, session_name)
-            })?;
+        let pty = match service {
+            Some(service) => match session
+                .ptys
+                .iter()
+                .find(|p| matches!(&p.role, PtyRole::Service(n) if n == service))
+            {
+                Some(p) => p,
+                None => {
+                    return Ok(Response::err(
+                        ERR_PTY_NOT_FOUND,
+                        format!(
+                            "Service '{}' not found in session '{}'",
+                            service, session.name
+                        ),
+                    ))
+                }
+            },
+            None => session
+                .ptys
+                .iter()
+                .find(|p| p.id == pty_id)
+                .ok_or_else(|| {
+                    CodedError::new(
+                        ERR_PTY_NOT_FOUND,
+                        format!("PTY {} not found in session '{}'", pty_id, session_name),
+                    )
+                })?,
+        };
+        let pty_id = pty.id;
 
         let scrollback = pty
             .scrollback
@@ -767,10 +1507,16 @@ impl SessionManager {
         } else {
             data.clone()
         };
+        let bytes = if redact {
+            session.redact.redact(&bytes)
+        } else {
+            bytes
+        };
 
         let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
         Ok(Response::ok_with(ResponseData {
             log_data: Some(encoded),
+            pty: Some(pty_id),
             ..Default::default()
         }))
     }
@@ -782,6 +1528,7 @@ impl SessionManager {
         self: &Arc<Self>,
         session_name: &str,
         pty_id: u32,
+        actor: &Actor,
     ) -> Result<Response> {
         let mut sessions = self.sessions.write().await;
         let name = Self::resolve_name(&sessions, session_name)?;
@@ -792,7 +1539,10 @@ impl SessionManager {
             .iter()
             .find(|p| p.id == pty_id)
             .ok_or_else(|| {
-                anyhow::anyhow!("PTY {} not found in session '{}'", pty_id, session_name)
+                CodedError::new(
+                    ERR_PTY_NOT_FOUND,
+                    format!("PTY {} not found in session '{}'", pty_id, session_name),
+                )
             })?;
 
         let old_pid = pty.pid;
@@ -805,65 +1555,76 @@ impl SessionManager {
             .scrollback
             .clone()
             .ok_or_else(|| anyhow::anyhow!("PTY {} has no scrollback buffer", pty_id))?;
+        let stats = pty.stats.clone();
 
-        // Re-read coop.toml to pick up config changes
+        // Re-read coop.toml to pick up config changes. A broken input filter
+        // refuses the restart rather than leaving the PTY unfiltered or
+        // quietly keeping the old rules
         let workspace_path = PathBuf::from(&session.workspace);
         let mut config = Coopfile::resolve(&workspace_path, None).unwrap_or_default();
         config.expand_env();
+        if let Err(e) = config.validate_input_filter() {
+            return Ok(Response::err("INVALID_CONFIG", e.to_string()));
+        }
+        session
+            .audit
+            .record(actor, Some(pty_id), AuditAction::Restart);
 
         // Update session-level settings from fresh config
         session.default_shell = config.sandbox.shell_command().to_string();
-        session.user_env = config
-            .env
-            .iter()
-            .map(|(k, v)| (k.clone(), v.clone()))
-            .collect();
+        let mut env = config.env.clone();
+        session.forwards.apply_env(&mut env);
+        session.user_env = env.into_iter().collect();
         session.restart_delay_ms = config.session.restart_delay_ms;
-
-        // Determine the command: agent (PTY 0) picks up new agent command,
-        // shells keep their original command
-        let command = if pty_id == 0 {
-            let new_cmd = config
-                .sandbox
-                .agent_command()
-                .unwrap_or("claude")
-                .to_string();
-            new_cmd
-        } else {
-            pty.command.clone()
-        };
-
-        // Update PTY-level settings
-        let auto_restart = if pty_id == 0 {
-            config.session.auto_restart
-        } else {
-            pty.auto_restart
+        session.input_filter = config.input_filter.clone();
+        session.send_filters.retain(|(_, pty), _| *pty != pty_id);
+
+        // Agents and services pick up their latest command, env and restart
+        // policy by name (falling back to the current settings if they were
+        // removed from the config); shells keep their original command
+        let mut ready = None;
+        let resolved = match &pty.role {
+            PtyRole::Agent(agent_name) => config
+                .agents()
+                .into_iter()
+                .find(|a| &a.name == agent_name)
+                .map(|a| {
+                    (
+                        a.command,
+                        a.env,
+                        RestartPolicy::from_auto_restart(a.auto_restart),
+                        a.restart_delay_ms,
+                    )
+                }),
+            PtyRole::Service(service_name) => config
+                .services
+                .into_iter()
+                .find(|s| &s.name == service_name)
+                .map(|s| {
+                    ready = s.ready;
+                    (
+                        s.command,
+                        s.env.into_iter().collect(),
+                        s.restart,
+                        s.restart_delay_ms,
+                    )
+                }),
+            PtyRole::Shell => None,
         };
-        let restart_delay_ms = session.restart_delay_ms;
+        let (command, mut extra_env, restart, restart_delay_ms) = resolved.unwrap_or_else(|| {
+            (
+                pty.command.clone(),
+                Vec::new(),
+                pty.restart,
+                session.restart_delay_ms,
+            )
+        });
+        if matches!(pty.role, PtyRole::Agent(_)) {
+            extra_env.extend(session.secrets.agent_env());
+        }
 
         // nsenter new process using pinned namespace fds (works even after init dies)
-        let env_vars = session.user_env.clone();
-        let ns_user_fd = session.ns_user_fd;
-        let ns_mnt_fd = session.ns_mnt_fd;
-        let ns_uts_fd = session.ns_uts_fd;
-        let ns_net_fd = session.ns_net_fd;
-        let ns_root_fd = session.ns_root_fd;
-        let sandbox_user = session.sandbox_user.clone();
-        let sandbox_home = session.sandbox_home.clone();
-        let sandbox_workspace = session.sandbox_workspace.clone();
-
-        let shell_ns = namespace::nsenter_shell(
-            ns_user_fd,
-            ns_mnt_fd,
-            ns_uts_fd,
-            ns_net_fd,
-            ns_root_fd,
-            &command,
-            &env_vars,
-            &sandbox_user,
-            &sandbox_home,
-            &sandbox_workspace,
-        )?;
+        let shell_ns = session.nsenter(&command, &extra_env)?;
 
         // Kill old process
         if let Some(pid) = old_pid {
@@ -882,17 +1643,24 @@ impl SessionManager {
         }
 
         // Start new pty_reader with SAME output_tx and scrollback
-        let exit_rx = spawn_pty_reader(shell_ns.pty_master_fd, output_tx.clone(), scrollback);
+        stats.restarts.fetch_add(1, Ordering::Relaxed);
+        let exit_rx =
+            spawn_pty_reader(shell_ns.pty_master_fd, output_tx.clone(), scrollback, stats);
 
         // Update PtyState in-place
         let pty = session.ptys.iter_mut().find(|p| p.id == pty_id).unwrap();
         pty.pid = Some(shell_ns.shell_pid);
         pty.command = command;
-        pty.auto_restart = auto_restart;
+        pty.restart = restart;
         let fast_failures = pty.fast_failures.clone();
+        let role = pty.role.clone();
+        let is_service = matches!(role, PtyRole::Service(_));
+        if is_service {
+            pty.status = Some(ServiceStatus::Starting);
+        }
 
-        // If this was the agent (PTY 0), update namespace_pid
-        if pty_id == 0 {
+        // If this was the namespace init process (PTY 0), track its replacement
+        if old_pid == Some(session.namespace_pid) {
             session.namespace_pid = shell_ns.shell_pid;
         }
 
@@ -901,15 +1669,18 @@ impl SessionManager {
         // Spawn watcher for the new process (only auto-restart if the PTY had it before)
         self.spawn_exit_watcher(
             exit_rx,
-            name,
+            name.clone(),
             pty_id,
             shell_ns.shell_pid,
             output_tx,
-            auto_restart,
+            restart,
             restart_delay_ms,
             fast_failures,
             Instant::now(),
         );
+        if is_service {
+            self.spawn_readiness_check(name.clone(), pty_id, shell_ns.shell_pid, ready);
+        }
 
         tracing::info!(
             session = %session_name,
@@ -918,6 +1689,12 @@ impl SessionManager {
             new_pid = shell_ns.shell_pid,
             "Restarted PTY"
         );
+        self.publish(SessionEvent::PtySpawned {
+            session: name,
+            pty: pty_id,
+            role,
+            pid: shell_ns.shell_pid,
+        });
 
         Ok(Response::ok_with(ResponseData {
             pid: Some(shell_ns.shell_pid),
@@ -926,19 +1703,17 @@ impl SessionManager {
         }))
     }
 
-    /// Get the broadcast sender and master fd for a PTY in a session.
-    /// Used by stream mode to bridge client connections to the PTY.
-    /// The master_fd is an `Arc<AtomicI32>` so stream handlers always
-    /// read the current fd even after a PTY restart.
+    /// Connect to a PTY's output and input. Used by stream mode, the web
+    /// socket and tunnel channels to bridge clients to the PTY. What is
+    /// written through the handle is audited as `actor`'s, and should pass
+    /// the handle's filter for `client`.
     pub async fn get_pty_handle(
         &self,
         session_name: &str,
         pty_id: u32,
-    ) -> Result<(
-        Arc<AtomicI32>,
-        broadcast::Sender<Bytes>,
-        Option<Arc<Mutex<Vec<u8>>>>,
-    )> {
+        actor: Actor,
+        client: InputClient,
+    ) -> Result<PtyHandle> {
         let sessions = self.sessions.read().await;
         let session = self.resolve_session(&sessions, session_name)?;
 
@@ -947,7 +1722,10 @@ impl SessionManager {
             .iter()
             .find(|p| p.id == pty_id)
             .ok_or_else(|| {
-                anyhow::anyhow!("PTY {} not found in session '{}'", pty_id, session_name)
+                CodedError::new(
+                    ERR_PTY_NOT_FOUND,
+                    format!("PTY {} not found in session '{}'", pty_id, session_name),
+                )
             })?;
 
         let output_tx = pty
@@ -955,7 +1733,283 @@ impl SessionManager {
             .clone()
             .ok_or_else(|| anyhow::anyhow!("PTY {} has no output channel", pty_id))?;
 
-        Ok((pty.master_fd.clone(), output_tx, pty.scrollback.clone()))
+        let policy = session.input_filter.policy(client);
+        let approval = (policy.require_approval && (pty.role.is_agent() || policy.shells))
+            .then(|| ApprovalGate::new(session.approvals.clone(), pty_id, actor.to_string()));
+
+        Ok(PtyHandle {
+            input: PtyInput {
+                master_fd: pty.master_fd.clone(),
+                stats: pty.stats.clone(),
+                pty: pty_id,
+                actor,
+                audit: session.audit.clone(),
+            },
+            output: output_tx.subscribe(),
+            scrollback: pty.scrollback.clone(),
+            redact: session.redact.clone(),
+            filter: InputFilter::for_pty(&policy, &pty.role)?,
+            approval,
+        })
+    }
+
+    /// Write one-shot input (`coop send`, the input API): `text` with its
+    /// escapes decoded, then the named `keys`. It goes in as `client` would
+    /// type it, through its input filter and, if its policy asks, local
+    /// approval, which this waits for.
+    pub async fn send_input(
+        &self,
+        session_name: &str,
+        pty_id: u32,
+        actor: Actor,
+        client: InputClient,
+        text: &str,
+        keys: &[String],
+    ) -> Result<Response> {
+        let data = match input_bytes(text, keys) {
+            Ok(data) if data.is_empty() => {
+                return Ok(Response::err(ERR_INVALID_COMMAND, "Nothing to send"))
+            }
+            Ok(data) => data,
+            Err(e) => return Ok(Response::err(ERR_INVALID_COMMAND, e.to_string())),
+        };
+        let PtyHandle {
+            input,
+            filter,
+            approval: mut gate,
+            ..
+        } = self
+            .get_pty_handle(session_name, pty_id, actor.clone(), client)
+            .await?;
+        if !input.is_open() {
+            return Ok(Response::err(
+                ERR_PTY_NOT_FOUND,
+                format!("PTY {} has exited", pty_id),
+            ));
+        }
+
+        // The client's earlier sends to this PTY shape what this one may do
+        // (a line typed in one send and submitted in the next)
+        let filter = match filter {
+            Some(filter) => Some(
+                self.send_filter(session_name, pty_id, actor, filter)
+                    .await?,
+            ),
+            None => None,
+        };
+        let mut filter = match &filter {
+            Some(filter) => Some(filter.lock().await),
+            None => None,
+        };
+
+        let mut warning = input.write_filtered(filter.as_deref_mut(), gate.as_mut(), &data);
+        // Bytes held as a possible start of a blocked sequence can't be
+        // completed by later input
+        if let Some(filter) = &mut filter {
+            let (held, held_warning) = filter.flush_pending();
+            if held_warning.is_some() {
+                if let Some(sequence) = filter.take_blocked() {
+                    input.blocked(&sequence);
+                }
+                warning = warning.or(held_warning);
+            }
+            match &mut gate {
+                Some(gate) => gate.push(&held),
+                None => input.write(&held),
+            }
+        }
+        if let Some(gate) = &mut gate {
+            loop {
+                match gate.next().await {
+                    Approval::Waiting => continue,
+                    approval => {
+                        warning = input.settle(approval).or(warning);
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(match warning {
+            Some(warning) => Response::err(ERR_INPUT_BLOCKED, plain_text(warning)),
+            None => Response::ok(),
+        })
+    }
+
+    /// The filter kept for `actor`'s one-shot input to a PTY, or `fresh` as
+    /// its first
+    async fn send_filter(
+        &self,
+        session_name: &str,
+        pty_id: u32,
+        actor: Actor,
+        fresh: InputFilter,
+    ) -> Result<Arc<Mutex<InputFilter>>> {
+        let mut sessions = self.sessions.write().await;
+        let name = Self::resolve_name(&sessions, session_name)?;
+        let session = sessions.get_mut(&name).unwrap();
+        Ok(session
+            .send_filters
+            .entry((actor, pty_id))
+            .or_insert_with(|| Arc::new(Mutex::new(fresh)))
+            .clone())
+    }
+
+    /// Wait for a PTY's output to match `pattern`. The response carries the
+    /// matched text, `timed_out`, or neither if the PTY went away first
+    /// (with its exit code, if known). With `redact`, the masked output web
+    /// and tunnel clients see is matched, so a pattern can't probe secrets.
+    pub async fn wait_output(
+        &self,
+        session_name: &str,
+        pty_id: u32,
+        pattern: &str,
+        timeout: Option<Duration>,
+        scrollback: bool,
+        redact: bool,
+    ) -> Result<Response> {
+        let mut matcher = match OutputMatcher::new(pattern) {
+            Ok(matcher) => matcher,
+            Err(e) => return Ok(Response::err(ERR_INVALID_COMMAND, format!("{:#}", e))),
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // The exit code of a PTY that goes away arrives on the event bus
+        let mut events = self.subscribe();
+        let (name, mut output_rx, history, rules) = {
+            let sessions = self.sessions.read().await;
+            let session = self.resolve_session(&sessions, session_name)?;
+            let pty = session
+                .ptys
+                .iter()
+                .find(|p| p.id == pty_id)
+                .ok_or_else(|| {
+                    CodedError::new(
+                        ERR_PTY_NOT_FOUND,
+                        format!("PTY {} not found in session '{}'", pty_id, session_name),
+                    )
+                })?;
+            let output_tx = pty
+                .output_tx
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("PTY {} has no output channel", pty_id))?;
+            (
+                session.name.clone(),
+                output_tx.subscribe(),
+                pty.scrollback.clone(),
+                session.redact.clone(),
+            )
+        };
+        let mut redactor = if redact { rules } else { Arc::default() }.stream();
+
+        let mut matched = None;
+        if let (true, Some(history)) = (scrollback, &history) {
+            matched = matcher.push(&redactor.push(&history.lock().await));
+        }
+
+        let mut resp = Response::ok();
+        while matched.is_none() {
+            let flush_at = redactor.deadline();
+            tokio::select! {
+                data = output_rx.recv() => match data {
+                    Ok(bytes) => matched = matcher.push(&redactor.push(&bytes)),
+                    Err(broadcast::error::RecvError::Lagged(n)) => self.record_lag(n),
+                    Err(broadcast::error::RecvError::Closed) => {
+                        matched = matcher.push(&redactor.finish());
+                        if matched.is_none() {
+                            resp.data.exit_code = reported_exit_code(&mut events, &name, pty_id).await;
+                            return Ok(resp);
+                        }
+                    }
+                },
+                _ = crate::pty::redact::sleep_until(flush_at) => {
+                    matched = matcher.push(&redactor.flush());
+                }
+                _ = crate::pty::redact::sleep_until(deadline) => {
+                    resp.data.timed_out = Some(true);
+                    return Ok(resp);
+                }
+            }
+        }
+        resp.data.matched = matched;
+        Ok(resp)
+    }
+
+    /// Requests for a box's local terminals to approve remote input
+    pub async fn approvals(&self, session_name: &str) -> Result<Arc<Approvals>> {
+        let sessions = self.sessions.read().await;
+        let session = self.resolve_session(&sessions, session_name)?;
+        Ok(session.approvals.clone())
+    }
+
+    /// Messages of a box's `stream-json` agents after `since`. With
+    /// `redact`, masked like the box's output for web and tunnel clients.
+    pub async fn messages(&self, session_name: &str, since: u64, redact: bool) -> Result<Response> {
+        let sessions = self.sessions.read().await;
+        let session = self.resolve_session(&sessions, session_name)?;
+        let mut messages = session.transcript.since(since);
+        if redact && !session.redact.is_empty() {
+            for message in &mut messages {
+                adapter::redact(message, &session.redact);
+            }
+        }
+        Ok(Response::ok_with(ResponseData {
+            messages: Some(messages),
+            ..Default::default()
+        }))
+    }
+
+    /// A box's agent messages, to follow
+    pub async fn transcript(&self, session_name: &str) -> Result<Arc<Transcript>> {
+        let sessions = self.sessions.read().await;
+        let session = self.resolve_session(&sessions, session_name)?;
+        Ok(session.transcript.clone())
+    }
+
+    /// Open a TCP connection to a port inside a box.
+    /// Boxes with their own network namespace are entered via the pinned net fd;
+    /// `host` mode boxes share the host network, so loopback is targeted directly.
+    pub async fn connect_box_port(
+        &self,
+        session_name: &str,
+        port: u16,
+    ) -> Result<tokio::net::TcpStream> {
+        // Dup the pinned fd under the lock so a concurrent kill can't close it
+        // out from under the connecting thread
+        let net_fd = {
+            let sessions = self.sessions.read().await;
+            let session = self.resolve_session(&sessions, session_name)?;
+            match session.ns_net_fd {
+                Some(fd) if fd >= 0 => Some(nix::unistd::dup(fd)?),
+                _ => None,
+            }
+        };
+
+        let Some(fd) = net_fd else {
+            return Ok(tokio::net::TcpStream::connect(("127.0.0.1", port)).await?);
+        };
+
+        let stream =
+            tokio::task::spawn_blocking(move || namespace::connect_in_netns(fd, port)).await??;
+        stream.set_nonblocking(true)?;
+        Ok(tokio::net::TcpStream::from_std(stream)?)
+    }
+
+    /// Resolve a box name or workspace path to the box name
+    pub async fn session_name(&self, name_or_path: &str) -> Option<String> {
+        let sessions = self.sessions.read().await;
+        Self::resolve_name(&sessions, name_or_path).ok()
+    }
+
+    /// Get the role of a PTY in a session
+    pub async fn pty_role(&self, session_name: &str, pty_id: u32) -> Option<PtyRole> {
+        let sessions = self.sessions.read().await;
+        let session = self.resolve_session(&sessions, session_name).ok()?;
+        session
+            .ptys
+            .iter()
+            .find(|p| p.id == pty_id)
+            .map(|p| p.role.clone())
     }
 
     /// Increment the local client count for a session
@@ -963,6 +2017,10 @@ impl SessionManager {
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.get_mut(session_name) {
             s.local_clients += 1;
+            self.publish(SessionEvent::ClientAttached {
+                session: session_name.to_string(),
+                client: ClientKind::Local,
+            });
         }
     }
 
@@ -971,6 +2029,10 @@ impl SessionManager {
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.get_mut(session_name) {
             s.local_clients = s.local_clients.saturating_sub(1);
+            self.publish(SessionEvent::ClientDetached {
+                session: session_name.to_string(),
+                client: ClientKind::Local,
+            });
         }
     }
 
@@ -979,6 +2041,10 @@ impl SessionManager {
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.get_mut(session_name) {
             s.web_clients += 1;
+            self.publish(SessionEvent::ClientAttached {
+                session: session_name.to_string(),
+                client: ClientKind::Web,
+            });
         }
     }
 
@@ -987,6 +2053,10 @@ impl SessionManager {
         let mut sessions = self.sessions.write().await;
         if let Some(s) = sessions.get_mut(session_name) {
             s.web_clients = s.web_clients.saturating_sub(1);
+            self.publish(SessionEvent::ClientDetached {
+                session: session_name.to_string(),
+                client: ClientKind::Web,
+            });
         }
     }
 
@@ -1005,7 +2075,7 @@ impl SessionManager {
     const FAST_FAILURE_THRESHOLD: std::time::Duration = std::time::Duration::from_secs(5);
 
     /// Spawn a background task that watches for a PTY to exit.
-    /// If auto_restart is true, restarts the process after a delay.
+    /// Restarts the process after a delay if its restart policy asks for it.
     /// Detects crash loops: if the process exits within 5s three times in a row,
     /// auto-restart is disabled with a clear error message.
     #[allow(clippy::too_many_arguments)]
@@ -1016,7 +2086,7 @@ impl SessionManager {
         pty_id: u32,
         expected_pid: u32,
         output_tx: broadcast::Sender<Bytes>,
-        auto_restart: bool,
+        restart: RestartPolicy,
         restart_delay_ms: u64,
         fast_failures: Arc<AtomicU32>,
         start_time: Instant,
@@ -1030,6 +2100,22 @@ impl SessionManager {
                 return;
             }
 
+            let is_service = matches!(
+                sm.pty_role(&session_name, pty_id).await,
+                Some(PtyRole::Service(_))
+            );
+            let code = exit_code(expected_pid).await;
+            sm.publish(SessionEvent::PtyExited {
+                session: session_name.clone(),
+                pty: pty_id,
+                code,
+            });
+            let auto_restart = match restart {
+                RestartPolicy::Always => true,
+                RestartPolicy::Never => false,
+                RestartPolicy::OnFailure => code != Some(0),
+            };
+
             if auto_restart {
                 // Crash loop detection: if the process died very quickly, count it
                 let uptime = start_time.elapsed();
@@ -1048,6 +2134,16 @@ impl SessionManager {
                             "Crash loop detected ({} fast failures), disabling auto-restart",
                             failures
                         );
+                        if is_service {
+                            sm.stop_service(&session_name, pty_id, ServiceStatus::Failed)
+                                .await;
+                        }
+                        sm.metrics.crash_loops.fetch_add(1, Ordering::Relaxed);
+                        sm.publish(SessionEvent::CrashLoop {
+                            session: session_name,
+                            pty: pty_id,
+                            failures,
+                        });
                         return;
                     }
                 } else {
@@ -1060,6 +2156,11 @@ impl SessionManager {
                     restart_delay_ms
                 );
                 let _ = output_tx.send(Bytes::from(msg));
+                sm.publish(SessionEvent::PtyRestarting {
+                    session: session_name.clone(),
+                    pty: pty_id,
+                    delay_ms: restart_delay_ms,
+                });
 
                 tokio::time::sleep(std::time::Duration::from_millis(restart_delay_ms)).await;
 
@@ -1067,17 +2168,30 @@ impl SessionManager {
                     return;
                 }
 
-                match sm.restart_pty(&session_name, pty_id).await {
-                    Ok(_) => {
+                match sm
+                    .restart_pty(&session_name, pty_id, &Actor::default())
+                    .await
+                {
+                    Ok(resp) if resp.ok => {
                         tracing::info!(session = %session_name, pty = pty_id, "Auto-restarted PTY")
                     }
+                    Ok(resp) => {
+                        tracing::error!(session = %session_name, pty = pty_id, error = resp.message.unwrap_or_default(), "Failed to auto-restart PTY")
+                    }
                     Err(e) => {
                         tracing::error!(session = %session_name, pty = pty_id, error = %e, "Failed to auto-restart PTY")
                     }
                 }
+            } else if is_service {
+                tracing::info!(session = %session_name, pty = pty_id, "Service exited");
+                let _ = output_tx.send(Bytes::from_static(
+                    b"\r\n\x1b[2m[service exited]\x1b[0m\r\n",
+                ));
+                sm.stop_service(&session_name, pty_id, ServiceStatus::Stopped)
+                    .await;
             } else {
                 tracing::info!(session = %session_name, pty = pty_id, "PTY exited, cleaning up");
-                let _ = sm.kill_pty(&session_name, pty_id).await;
+                let _ = sm.remove_pty(&session_name, pty_id).await;
             }
         });
     }
@@ -1092,7 +2206,11 @@ impl SessionManager {
                 return Ok(s.name.clone());
             }
         }
-        bail!("Session '{}' not found", name_or_path);
+        Err(CodedError::new(
+            ERR_SESSION_NOT_FOUND,
+            format!("Session '{}' not found", name_or_path),
+        )
+        .into())
     }
 
     fn resolve_session<'a>(
@@ -1104,3 +2222,51 @@ impl SessionManager {
         Ok(&sessions[&key])
     }
 }
+
+/// The exit code a PTY's `PtyExited` event reports. The event may trail
+/// its output closing, so this waits for it briefly.
+async fn reported_exit_code(
+    events: &mut broadcast::Receiver<SessionEvent>,
+    session_name: &str,
+    pty_id: u32,
+) -> Option<i32> {
+    let exited = async {
+        loop {
+            match events.recv().await {
+                Ok(SessionEvent::PtyExited { session, pty, code })
+                    if session == session_name && pty == pty_id =>
+                {
+                    return code;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+                _ => {}
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_millis(500), exited)
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_pty() {
+        // Ends once the slave is closed
+        let pty = nix::pty::openpty(None, None).unwrap();
+        nix::unistd::write(&pty.slave, b"ready\r\n").unwrap();
+        drop(pty.slave);
+        tokio::time::timeout(Duration::from_secs(5), drain_pty(pty.master))
+            .await
+            .unwrap();
+
+        // A slave held open, as by a child the check left running, keeps it
+        // going without blocking a thread, so a timeout can cancel it
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let drain = tokio::time::timeout(Duration::from_millis(50), drain_pty(pty.master));
+        assert!(drain.await.is_err());
+    }
+}
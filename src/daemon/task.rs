@@ -0,0 +1,358 @@
+//! Headless task runs (`coop run`): create a box, hand the agent a task, wait
+//! for it to exit and collect the results into an output directory.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::audit::Actor;
+use super::session::{exit_code, try_exit_code, PtyHandle, SessionManager};
+use crate::config::InputClient;
+use crate::ipc::{Response, ResponseData};
+
+/// Transcript of everything the agent wrote to its terminal
+pub const TRANSCRIPT_FILE: &str = "transcript.log";
+/// Workspace changes made during the run, as a git patch
+pub const DIFF_FILE: &str = "diff.patch";
+/// Summary of the run (exit code, timing)
+pub const RESULT_FILE: &str = "result.json";
+
+/// How often to check whether the agent exited while its output is quiet
+const EXIT_POLL: Duration = Duration::from_millis(500);
+/// Idle time after the agent exited before the transcript is considered complete
+const EXIT_DRAIN: Duration = Duration::from_millis(200);
+
+/// Longer than kill_session's SIGTERM grace period
+const REAP_DELAY: Duration = Duration::from_secs(6);
+
+/// Git's well-known empty tree, used as the diff base in repos without commits
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+pub struct TaskRun {
+    pub name: Option<String>,
+    pub workspace: String,
+    pub task: String,
+    /// Extra agent arguments, placed before the task
+    pub args: Vec<String>,
+    /// Write the task to the agent's stdin instead of passing it as an argument
+    pub stdin: bool,
+    pub timeout: Option<Duration>,
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+struct TaskResult<'a> {
+    #[serde(rename = "box")]
+    box_name: &'a str,
+    task: &'a str,
+    /// Agent exit code (128+N if killed by signal N); null on timeout
+    exit_code: Option<i32>,
+    timed_out: bool,
+    started: u64,
+    duration_secs: f64,
+    /// Whether `diff.patch` was produced (the workspace is a git repo)
+    diff: bool,
+}
+
+/// Run a task to completion. The box is always torn down afterwards, also
+/// when this future is dropped early; the response carries the agent's exit
+/// code.
+pub async fn run_task(session_manager: &Arc<SessionManager>, run: TaskRun) -> Result<Response> {
+    std::fs::create_dir_all(&run.output)
+        .with_context(|| format!("Failed to create {}", run.output.display()))?;
+
+    let workspace = PathBuf::from(&run.workspace);
+    let baseline = GitBaseline::capture(&workspace).await;
+
+    let task = run.task.clone();
+    let args = run.args.clone();
+    let via_stdin = run.stdin;
+    let resp = session_manager
+        .create_session_with(run.name.clone(), run.workspace.clone(), move |config| {
+            // The run ends when the agent exits, so it must not be restarted
+            config.session.auto_restart = false;
+            let mut task_args = args;
+            if !via_stdin {
+                task_args.push(task);
+            }
+            match config.agents.first_mut() {
+                Some(agent) => {
+                    agent.auto_restart = Some(false);
+                    for arg in &task_args {
+                        agent.command.push(' ');
+                        agent.command.push_str(&shell_quote(arg));
+                    }
+                }
+                None => config.sandbox.args.extend(task_args),
+            }
+        })
+        .await?;
+    if !resp.ok {
+        return Ok(resp);
+    }
+    let session = resp.data.session.clone().unwrap_or_default();
+    let pid = resp.data.pid.unwrap_or(0);
+    let mut guard = Teardown {
+        session_manager: session_manager.clone(),
+        session: session.clone(),
+        pid,
+        armed: true,
+    };
+
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let start = Instant::now();
+
+    let transcript = collect_transcript(session_manager, &session, pid, &run).await;
+    let timed_out = matches!(transcript, Ok(false));
+    if let Err(e) = &transcript {
+        tracing::error!(session = %session, error = %e, "Task transcript failed");
+    }
+
+    let code = if timed_out {
+        tracing::warn!(session = %session, "Task timed out");
+        None
+    } else {
+        exit_code(pid).await
+    };
+    let duration = start.elapsed();
+
+    guard.armed = false;
+    teardown(session_manager, &session, pid, timed_out).await;
+
+    let diff = match &baseline {
+        Some(baseline) => {
+            let patch = baseline.diff(&workspace).await;
+            std::fs::write(run.output.join(DIFF_FILE), patch)?;
+            true
+        }
+        None => false,
+    };
+
+    let result = TaskResult {
+        box_name: &session,
+        task: &run.task,
+        exit_code: code,
+        timed_out,
+        started,
+        duration_secs: duration.as_secs_f64(),
+        diff,
+    };
+    std::fs::write(
+        run.output.join(RESULT_FILE),
+        serde_json::to_vec_pretty(&result)?,
+    )?;
+
+    tracing::info!(session = %session, exit_code = ?code, timed_out, "Task finished");
+
+    // Propagate transcript errors only after the box is gone
+    transcript?;
+
+    Ok(Response::ok_with(ResponseData {
+        session: Some(session),
+        exit_code: code,
+        timed_out: Some(timed_out),
+        ..Default::default()
+    }))
+}
+
+/// Kill the box and reap the agent
+async fn teardown(session_manager: &SessionManager, session: &str, pid: u32, force: bool) {
+    let _ = session_manager
+        .kill_session(session, force, &Actor::default())
+        .await;
+    // The agent was our child; reap it once kill_session's grace-period
+    // SIGKILL can no longer hit a recycled pid
+    if pid > 0 {
+        tokio::spawn(async move {
+            tokio::time::sleep(REAP_DELAY).await;
+            let _ = nix::sys::wait::waitpid(
+                nix::unistd::Pid::from_raw(pid as i32),
+                Some(nix::sys::wait::WaitPidFlag::WNOHANG),
+            );
+        });
+    }
+}
+
+/// Tears the box down if the run is dropped before it finishes, e.g. when
+/// the `coop run` client disconnects
+struct Teardown {
+    session_manager: Arc<SessionManager>,
+    session: String,
+    pid: u32,
+    armed: bool,
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        tracing::info!(session = %self.session, "Task abandoned, removing its box");
+        let session_manager = self.session_manager.clone();
+        let session = std::mem::take(&mut self.session);
+        let pid = self.pid;
+        tokio::spawn(async move { teardown(&session_manager, &session, pid, true).await });
+    }
+}
+
+/// Stream the agent's output into the transcript file until it exits.
+/// Returns `Ok(false)` if the timeout hit first.
+async fn collect_transcript(
+    session_manager: &Arc<SessionManager>,
+    session: &str,
+    pid: u32,
+    run: &TaskRun,
+) -> Result<bool> {
+    let mut file = tokio::fs::File::create(run.output.join(TRANSCRIPT_FILE)).await?;
+    let PtyHandle {
+        input,
+        output: mut rx,
+        scrollback,
+        ..
+    } = session_manager
+        .get_pty_handle(session, 0, Actor::default(), InputClient::Local)
+        .await?;
+    if let Some(sb) = &scrollback {
+        file.write_all(&sb.lock().await).await?;
+    }
+
+    if run.stdin {
+        // End the line, then Ctrl+D for EOF on the (canonical mode) terminal
+        input.write(format!("{}\n\x04", run.task).as_bytes());
+    }
+
+    // The channel closes once the PTY is cleaned up, but attached clients
+    // keep it open, so also watch for the agent's exit and then drain
+    let copy = async {
+        let mut exited = false;
+        loop {
+            let wait = if exited { EXIT_DRAIN } else { EXIT_POLL };
+            match tokio::time::timeout(wait, rx.recv()).await {
+                Ok(Ok(data)) => file.write_all(&data).await?,
+                Ok(Err(RecvError::Lagged(n))) => {
+                    tracing::warn!(session = %session, skipped = n, "Transcript lagged behind agent output");
+                }
+                Ok(Err(RecvError::Closed)) => break,
+                Err(_) if exited => break,
+                Err(_) => exited = try_exit_code(pid).is_some(),
+            }
+        }
+        file.flush().await?;
+        anyhow::Ok(())
+    };
+
+    match run.timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, copy).await {
+            Ok(result) => result.map(|_| true),
+            Err(_) => Ok(false),
+        },
+        None => copy.await.map(|_| true),
+    }
+}
+
+/// Workspace state before the run, so the diff only shows the agent's changes
+struct GitBaseline {
+    /// Commit (or tree) capturing tracked files, including uncommitted edits
+    base: String,
+    /// Untracked files that already existed
+    untracked: Vec<String>,
+}
+
+impl GitBaseline {
+    /// Snapshot the workspace. Returns None if it isn't a git work tree.
+    async fn capture(workspace: &Path) -> Option<Self> {
+        git(workspace, &["rev-parse", "--is-inside-work-tree"]).await?;
+
+        // `git stash create` records uncommitted changes without touching the
+        // work tree; it prints nothing when the tree is clean
+        let base = match git(workspace, &["stash", "create"]).await {
+            Some(stash) if !stash.trim().is_empty() => stash.trim().to_string(),
+            _ => git(workspace, &["rev-parse", "HEAD"])
+                .await
+                .map(|h| h.trim().to_string())
+                .unwrap_or_else(|| EMPTY_TREE.to_string()),
+        };
+
+        Some(Self {
+            base,
+            untracked: untracked_files(workspace).await,
+        })
+    }
+
+    /// Patch of tracked changes since the baseline plus newly created files
+    async fn diff(&self, workspace: &Path) -> String {
+        let mut patch = git(workspace, &["diff", "--binary", &self.base])
+            .await
+            .unwrap_or_default();
+
+        for file in untracked_files(workspace).await {
+            if self.untracked.contains(&file) {
+                continue;
+            }
+            // --no-index exits 1 when the files differ, so take stdout regardless
+            if let Ok(out) = tokio::process::Command::new("git")
+                .args(["diff", "--binary", "--no-index", "/dev/null", &file])
+                .current_dir(workspace)
+                .output()
+                .await
+            {
+                patch.push_str(&String::from_utf8_lossy(&out.stdout));
+            }
+        }
+
+        patch
+    }
+}
+
+async fn untracked_files(workspace: &Path) -> Vec<String> {
+    git(
+        workspace,
+        &["ls-files", "--others", "--exclude-standard", "-z"],
+    )
+    .await
+    .map(|out| {
+        out.split('\0')
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Run a git command in the workspace, returning stdout on success
+async fn git(workspace: &Path, args: &[&str]) -> Option<String> {
+    let out = tokio::process::Command::new("git")
+        .args(args)
+        .current_dir(workspace)
+        .output()
+        .await
+        .ok()?;
+    out.status
+        .success()
+        .then(|| String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Quote an argument for `sh -c` (agent commands from `[[agents]]` are
+/// command lines rather than argument vectors)
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("fix tests"), "'fix tests'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
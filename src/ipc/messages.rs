@@ -41,6 +41,9 @@ pub enum Command {
         session: String,
         #[serde(default)]
         pty: u32,
+        /// Attach to the named agent instead of `pty`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        agent: Option<String>,
         #[serde(default = "default_cols")]
         cols: u16,
         #[serde(default = "default_rows")]
@@ -76,9 +79,84 @@ pub enum Command {
         host: String,
         #[serde(skip_serializing_if = "Option::is_none")]
         token: Option<String>,
+        /// Serve HTTPS, with `cert`/`key` or a self-signed certificate
+        #[serde(default)]
+        tls: bool,
+        /// Absolute path to a PEM certificate chain
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cert: Option<String>,
+        /// Absolute path to the PEM private key for `cert`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+        /// Also serve `/metrics`, unauthenticated, on this port of `host`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metrics_port: Option<u16>,
+        /// Also serve box ports on this port of `host`, an origin apart
+        /// from the web UI
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        proxy_port: Option<u16>,
     },
+    /// Open a WebRTC tunnel to the daemon. Replies with the offer; the
+    /// connection then waits for a `tunnelanswer` and stays open for the
+    /// tunnel's lifetime.
     Tunnel {
+        /// Custom STUN server (default: public Google servers)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stun: Option<String>,
+        #[serde(default)]
+        no_stun: bool,
+        /// Only offer a 127.0.0.1 candidate
+        #[serde(default)]
+        loopback: bool,
+        /// Rendezvous server URL. When set, the offer is posted there under a
+        /// short code and the daemon picks up the answer itself, replying
+        /// with a second response once the tunnel connects.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signal: Option<String>,
+        /// What the peer, and any device it pairs, may access
+        #[serde(default)]
+        scope: DeviceScope,
+    },
+    /// SDP answer from the remote browser, on the connection that sent `tunnel`
+    TunnelAnswer {
+        answer: String,
+    },
+    /// Remember the connected browser as a paired device. Only valid on a
+    /// tunnel control channel whose tunnel was opened with `signal`.
+    Pair {
+        name: String,
+    },
+    /// List paired tunnel devices
+    DeviceLs,
+    /// Forget a paired device and drop its connection
+    DeviceRevoke {
+        id: String,
+    },
+    /// Create a named web API token; the secret is only returned here
+    TokenCreate {
+        name: String,
+        scope: TokenScope,
+        /// Boxes the token can reach; empty means all of them
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sessions: Vec<String>,
+        /// Lifetime in seconds; `None` never expires
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_secs: Option<u64>,
+    },
+    /// Mint a read-only token for one box (and optionally one PTY) and
+    /// return a web UI link for it
+    Share {
         session: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pty: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_secs: Option<u64>,
+    },
+    /// List web API tokens (names and scopes, never secrets)
+    TokenLs,
+    /// Delete a web API token
+    TokenRevoke {
+        name: String,
     },
     /// List PTY sessions within a box
     SessionLs {
@@ -93,6 +171,9 @@ pub enum Command {
     Logs {
         session: String,
         pty: u32,
+        /// Show the named service's logs instead of `pty`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        service: Option<String>,
         #[serde(default)]
         follow: bool,
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -103,8 +184,87 @@ pub enum Command {
         session: String,
         pty: u32,
     },
+    /// Reply once a PTY's output (escape sequences stripped) matches
+    /// `pattern`, the PTY goes away, or `timeout_ms` passes
+    Wait {
+        session: String,
+        #[serde(default)]
+        pty: u32,
+        pattern: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+        /// Also match output already in the scrollback
+        #[serde(default)]
+        scrollback: bool,
+    },
+    /// Write to a PTY without attaching: `text` (with `\n`, `\x03`-style
+    /// escapes), then the tmux-named `keys`
+    SendInput {
+        session: String,
+        #[serde(default)]
+        pty: u32,
+        #[serde(default)]
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        keys: Vec<String>,
+    },
+    /// Run a headless task: create a box, wait for the agent to exit, collect
+    /// results into `output` and tear the box down. Replies when finished.
+    Run {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        workspace: String,
+        task: String,
+        /// Extra agent arguments, placed before the task
+        #[serde(default)]
+        args: Vec<String>,
+        /// Pass the task on stdin instead of as the last argument
+        #[serde(default)]
+        stdin: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+        output: String,
+    },
+    /// Add headless tasks to the queue
+    QueueAdd {
+        tasks: Vec<TaskSpec>,
+        /// Max concurrent boxes for the whole queue
+        #[serde(skip_serializing_if = "Option::is_none")]
+        concurrency: Option<usize>,
+    },
+    /// List queued tasks
+    QueueLs,
+    /// Cancel a pending or running task
+    QueueCancel {
+        id: u64,
+    },
+    /// Re-queue a finished, failed or cancelled task
+    QueueRetry {
+        id: u64,
+    },
+    /// Messages parsed from a box's `stream-json` agents, after `since`
+    /// (a `seq`). With `follow`, new messages are then streamed, one
+    /// message each, until the client disconnects.
+    Messages {
+        session: String,
+        #[serde(default)]
+        since: u64,
+        #[serde(default)]
+        follow: bool,
+    },
+    /// Stream `SessionEvent`s, one message each, until the client
+    /// disconnects. Limited to one box if `session` is given.
+    Subscribe {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+    },
     Shutdown,
     Detach,
+    /// Answer an `InputApproval` event (stream mode, writable attach only)
+    ApproveInput {
+        id: u64,
+        approve: bool,
+    },
 }
 
 fn default_cols() -> u16 {
@@ -151,14 +311,218 @@ pub struct ResponseData {
     pub host: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub token: Option<String>,
+    /// SHA-256 fingerprint of the web server's TLS certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Whether that certificate was generated by coop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_signed: Option<bool>,
+    /// Web UI link for a `share` token, if the web server is running
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Encoded tunnel connection envelope (SDP offer + machine identity)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub offer_sdp: Option<String>,
+    /// Rendezvous short code, when the tunnel uses a signal server
     #[serde(skip_serializing_if = "Option::is_none")]
     pub short_code: Option<String>,
+    /// Tunnel connect URL, shown as a QR code
     #[serde(skip_serializing_if = "Option::is_none")]
     pub qr_data: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub log_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timed_out: Option<bool>,
+    /// The output a `wait` pattern matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub messages: Option<Vec<AgentMessage>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tasks: Option<Vec<QueuedTask>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devices: Option<Vec<DeviceInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pairing: Option<Pairing>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<TokenInfo>>,
+}
+
+/// What a tunnel peer may access
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DeviceScope {
+    /// Boxes the peer can see; empty means all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<String>,
+    /// Watch only: no input, resizing, shells, kills or restarts
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl DeviceScope {
+    pub fn allows(&self, session: &str) -> bool {
+        self.sessions.is_empty() || self.sessions.iter().any(|s| s == session)
+    }
+}
+
+/// A browser paired over the tunnel (see `coop tunnel devices`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 fingerprint of the browser's persistent DTLS certificate
+    pub fingerprint: String,
+    pub scope: DeviceScope,
+    /// Rendezvous server the device reconnects through
+    pub signal: String,
+    pub paired: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    #[serde(default)]
+    pub connected: bool,
+}
+
+/// Handed to a browser when it pairs: everything it needs to reconnect
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pairing {
+    pub device_id: String,
+    pub machine_id: String,
+    pub signal: String,
+    /// Rendezvous code the daemon keeps a fresh offer under for this device
+    pub code: String,
+    /// base64url key sealing that offer and the device's answers
+    pub key: String,
+}
+
+/// What a web API token may do. Each scope includes the ones below it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// List boxes and watch PTY output
+    Read,
+    /// Type into and resize PTYs, open proxied ports
+    Attach,
+    /// Spawn shells
+    Write,
+    /// Create and kill boxes
+    Admin,
+}
+
+impl std::fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            TokenScope::Read => "read",
+            TokenScope::Attach => "attach",
+            TokenScope::Write => "write",
+            TokenScope::Admin => "admin",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::str::FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(TokenScope::Read),
+            "attach" => Ok(TokenScope::Attach),
+            "write" => Ok(TokenScope::Write),
+            "admin" => Ok(TokenScope::Admin),
+            _ => Err(format!(
+                "invalid scope '{}' (use read, attach, write or admin)",
+                s
+            )),
+        }
+    }
+}
+
+/// A web API token as listed by `coop token ls`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub name: String,
+    pub scope: TokenScope,
+    /// Boxes the token can reach; empty means all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<String>,
+    /// The only PTY the token can watch (share links)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pty: Option<u32>,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+}
+
+/// A headless task to run (see `coop run`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TaskSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub task: String,
+    /// Source workspace; queued tasks run on a copy of it
+    pub workspace: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub stdin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Pending,
+    Running,
+    /// Agent exited with status 0
+    Done,
+    /// Non-zero exit, timeout or setup error
+    Failed,
+    Cancelled,
+}
+
+impl std::fmt::Display for TaskState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            TaskState::Pending => "pending",
+            TaskState::Running => "running",
+            TaskState::Done => "done",
+            TaskState::Failed => "failed",
+            TaskState::Cancelled => "cancelled",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A task in the daemon's queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedTask {
+    pub id: u64,
+    #[serde(flatten)]
+    pub spec: TaskSpec,
+    pub state: TaskState,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Box the task runs in while running
+    #[serde(rename = "box")]
+    pub box_name: String,
+    /// Results directory (transcript, diff, result.json)
+    pub output: String,
+    /// How many times the task was started; a run reports back under its
+    /// attempt, so a run that outlived a retry is ignored
+    #[serde(default)]
+    pub attempt: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -175,27 +539,321 @@ pub struct SessionInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PtyInfo {
     pub id: u32,
+    #[serde(flatten)]
     pub role: PtyRole,
     pub command: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pid: Option<u32>,
+    /// Supervision state (services only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ServiceStatus>,
 }
 
+/// On the wire a role is two fields, flattened into the PTY or event:
+/// `"role"` (`agent`, `service` or `shell`) and the agent or service `"name"`
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+#[serde(into = "RoleFields", from = "RoleFields")]
 pub enum PtyRole {
+    /// An agent process, carrying its configured name
+    Agent(String),
+    /// A background service from `[[services]]`, carrying its name
+    Service(String),
+    Shell,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RoleKind {
     Agent,
+    Service,
     Shell,
 }
 
+#[derive(Serialize, Deserialize)]
+struct RoleFields {
+    role: RoleKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl From<PtyRole> for RoleFields {
+    fn from(role: PtyRole) -> Self {
+        let (role, name) = match role {
+            PtyRole::Agent(name) => (RoleKind::Agent, Some(name)),
+            PtyRole::Service(name) => (RoleKind::Service, Some(name)),
+            PtyRole::Shell => (RoleKind::Shell, None),
+        };
+        RoleFields { role, name }
+    }
+}
+
+impl From<RoleFields> for PtyRole {
+    fn from(fields: RoleFields) -> Self {
+        let name = fields.name.unwrap_or_default();
+        match fields.role {
+            RoleKind::Agent => PtyRole::Agent(name),
+            RoleKind::Service => PtyRole::Service(name),
+            RoleKind::Shell => PtyRole::Shell,
+        }
+    }
+}
+
+impl PtyRole {
+    pub fn is_agent(&self) -> bool {
+        matches!(self, PtyRole::Agent(_))
+    }
+}
+
+impl std::fmt::Display for PtyRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PtyRole::Agent(name) => write!(f, "agent:{}", name),
+            PtyRole::Service(name) => write!(f, "service:{}", name),
+            PtyRole::Shell => write!(f, "shell"),
+        }
+    }
+}
+
+/// Supervision state of a service PTY
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceStatus {
+    /// Running, readiness check still pending
+    Starting,
+    Ready,
+    /// Running, but the readiness check timed out
+    NotReady,
+    /// Exited and not restarted (per its restart policy)
+    Stopped,
+    /// Crash loop detected, restarts stopped
+    Failed,
+}
+
+impl std::fmt::Display for ServiceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ServiceStatus::Starting => "starting",
+            ServiceStatus::Ready => "ready",
+            ServiceStatus::NotReady => "not-ready",
+            ServiceStatus::Stopped => "stopped",
+            ServiceStatus::Failed => "failed",
+        };
+        f.write_str(s)
+    }
+}
+
+/// A conversation turn or tool call parsed from a `stream-json` agent's
+/// output
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentMessage {
+    /// Position in the box's transcript, from 1
+    pub seq: u64,
+    /// The agent's PTY
+    pub pty: u32,
+    pub time: u64,
+    pub kind: MessageKind,
+    /// The turn's text, a tool's output, or the run's final result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Name of the tool called (`tool_use`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    /// Pairs a `tool_result` with its `tool_use`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_use_id: Option<String>,
+    /// The tool's arguments (`tool_use`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+    /// A failed tool call or run
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_error: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// Session start and other notices from the agent itself
+    System,
+    User,
+    Assistant,
+    ToolUse,
+    ToolResult,
+    /// End of a run, with its final answer
+    Result,
+}
+
+impl std::fmt::Display for MessageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            MessageKind::System => "system",
+            MessageKind::User => "user",
+            MessageKind::Assistant => "assistant",
+            MessageKind::ToolUse => "tool_use",
+            MessageKind::ToolResult => "tool_result",
+            MessageKind::Result => "result",
+        };
+        f.write_str(s)
+    }
+}
+
 // ── Events (Daemon → Client, in stream mode) ────────────────
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "event", rename_all = "snake_case")]
 pub enum DaemonEvent {
-    PtyExited { code: i32 },
-    PtyRestarting { delay_ms: u64 },
+    PtyExited {
+        code: i32,
+    },
+    PtyRestarting {
+        delay_ms: u64,
+    },
     Detached,
+    /// Remote input held for approval (`require_approval`). `client` names
+    /// the sender (e.g. `web:alice`); `input` is the held input, escaped.
+    InputApproval {
+        id: u64,
+        pty: u32,
+        client: String,
+        input: String,
+    },
+    /// An approval request was answered, expired or withdrawn
+    InputApprovalDone {
+        id: u64,
+    },
+}
+
+/// A change in daemon state, published on the session manager's event bus
+/// and streamed by `subscribe` and `GET /api/events`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    SessionCreated {
+        session: String,
+    },
+    SessionKilled {
+        session: String,
+    },
+    /// A PTY process started, or was replaced by a restart
+    PtySpawned {
+        session: String,
+        pty: u32,
+        #[serde(flatten)]
+        role: PtyRole,
+        pid: u32,
+    },
+    /// `code` is unknown for PTYs killed on request
+    PtyExited {
+        session: String,
+        pty: u32,
+        code: Option<i32>,
+    },
+    PtyRestarting {
+        session: String,
+        pty: u32,
+        delay_ms: u64,
+    },
+    /// Auto-restart was given up after repeated fast failures
+    CrashLoop {
+        session: String,
+        pty: u32,
+        failures: u32,
+    },
+    ClientAttached {
+        session: String,
+        client: ClientKind,
+    },
+    ClientDetached {
+        session: String,
+        client: ClientKind,
+    },
+    /// The subscriber fell behind and `missed` events were dropped
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl SessionEvent {
+    /// The box the event is about
+    pub fn session(&self) -> Option<&str> {
+        match self {
+            Self::SessionCreated { session }
+            | Self::SessionKilled { session }
+            | Self::PtySpawned { session, .. }
+            | Self::PtyExited { session, .. }
+            | Self::PtyRestarting { session, .. }
+            | Self::CrashLoop { session, .. }
+            | Self::ClientAttached { session, .. }
+            | Self::ClientDetached { session, .. } => Some(session),
+            Self::Lagged { .. } => None,
+        }
+    }
+
+    /// Whether a subscriber limited to `session` should see the event
+    pub fn concerns(&self, session: &str) -> bool {
+        self.session().map_or(true, |s| s == session)
+    }
+
+    /// The PTY the event is about
+    pub fn pty(&self) -> Option<u32> {
+        match self {
+            Self::PtySpawned { pty, .. }
+            | Self::PtyExited { pty, .. }
+            | Self::PtyRestarting { pty, .. }
+            | Self::CrashLoop { pty, .. } => Some(*pty),
+            _ => None,
+        }
+    }
+
+    /// The `event` tag, e.g. `pty_exited`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SessionCreated { .. } => "session_created",
+            Self::SessionKilled { .. } => "session_killed",
+            Self::PtySpawned { .. } => "pty_spawned",
+            Self::PtyExited { .. } => "pty_exited",
+            Self::PtyRestarting { .. } => "pty_restarting",
+            Self::CrashLoop { .. } => "crash_loop",
+            Self::ClientAttached { .. } => "client_attached",
+            Self::ClientDetached { .. } => "client_detached",
+            Self::Lagged { .. } => "lagged",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientKind {
+    /// `coop attach` over the daemon socket
+    Local,
+    /// The web UI or a tunnel peer
+    Web,
+    /// A `coop tunnel` peer. Only the audit log tells these apart; they are
+    /// counted and announced as web clients.
+    Tunnel,
+}
+
+impl std::fmt::Display for ClientKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ClientKind::Local => "local",
+            ClientKind::Web => "web",
+            ClientKind::Tunnel => "tunnel",
+        };
+        f.write_str(s)
+    }
+}
+
+impl std::str::FromStr for ClientKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(ClientKind::Local),
+            "web" => Ok(ClientKind::Web),
+            "tunnel" => Ok(ClientKind::Tunnel),
+            _ => Err(format!("invalid client '{}' (use local, web or tunnel)", s)),
+        }
+    }
 }
 
 // ── Stream Frame Types ───────────────────────────────────────
@@ -208,10 +866,17 @@ pub const FRAME_CONTROL: u8 = 0x01;
 
 pub const ERR_SESSION_NOT_FOUND: &str = "SESSION_NOT_FOUND";
 pub const ERR_SESSION_EXISTS: &str = "SESSION_EXISTS";
-#[allow(dead_code)]
 pub const ERR_PTY_NOT_FOUND: &str = "PTY_NOT_FOUND";
-#[allow(dead_code)]
+pub const ERR_TASK_NOT_FOUND: &str = "TASK_NOT_FOUND";
+pub const ERR_TUNNEL_FAILED: &str = "TUNNEL_FAILED";
+pub const ERR_DEVICE_NOT_FOUND: &str = "DEVICE_NOT_FOUND";
+pub const ERR_FORBIDDEN: &str = "FORBIDDEN";
+pub const ERR_TOKEN_NOT_FOUND: &str = "TOKEN_NOT_FOUND";
+pub const ERR_TOKEN_EXISTS: &str = "TOKEN_EXISTS";
+pub const ERR_UNAUTHORIZED: &str = "UNAUTHORIZED";
+pub const ERR_INTERNAL_ERROR: &str = "INTERNAL_ERROR";
 pub const ERR_INVALID_COMMAND: &str = "INVALID_COMMAND";
+pub const ERR_INPUT_BLOCKED: &str = "INPUT_BLOCKED";
 #[allow(dead_code)]
 pub const ERR_VERSION_MISMATCH: &str = "VERSION_MISMATCH";
 #[allow(dead_code)]
@@ -253,4 +918,67 @@ impl Response {
             data,
         }
     }
+
+    /// Turn a handler result into a response. Errors become `INTERNAL_ERROR`
+    /// unless they are a `CodedError`.
+    pub fn from_result(result: anyhow::Result<Response>) -> Self {
+        result.unwrap_or_else(|e| match e.downcast_ref::<CodedError>() {
+            Some(coded) => Response::err(coded.code, coded.message.clone()),
+            None => Response::err(ERR_INTERNAL_ERROR, e.to_string()),
+        })
+    }
+}
+
+/// An error that carries a protocol error code, for failures raised deep
+/// inside a handler (e.g. an unknown session) that should not be reported
+/// as `INTERNAL_ERROR`
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct CodedError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl CodedError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pty_role_wire_format() {
+        let info = PtyInfo {
+            id: 0,
+            role: PtyRole::Agent("reviewer".into()),
+            command: "claude".into(),
+            pid: None,
+            status: None,
+        };
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["role"], "agent");
+        assert_eq!(json["name"], "reviewer");
+        let back: PtyInfo = serde_json::from_value(json).unwrap();
+        assert_eq!(back.role, PtyRole::Agent("reviewer".into()));
+
+        let shell: PtyInfo =
+            serde_json::from_str(r#"{"id":1,"role":"shell","command":"/bin/sh"}"#).unwrap();
+        assert_eq!(shell.role, PtyRole::Shell);
+
+        let event = SessionEvent::PtySpawned {
+            session: "box".into(),
+            pty: 2,
+            role: PtyRole::Service("web".into()),
+            pid: 42,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["role"], "service");
+        assert_eq!(json["name"], "web");
+    }
 }
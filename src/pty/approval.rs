@@ -0,0 +1,276 @@
+//! Local approval of remote input (`[input_filter] require_approval`). A
+//! remote client's input is held in batches, one line (or one pause in
+//! typing) at a time; each batch goes to the `coop attach` clients of the
+//! box, and is written to the PTY once one of them approves it.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{broadcast, oneshot};
+
+use crate::ipc::DaemonEvent;
+
+/// Notice for input sent off for approval
+pub const WAITING_NOTICE: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Input held for approval by a local terminal.\x1b[0m\r\n";
+
+/// Warning for input a local terminal rejected
+pub const REJECTED_WARNING: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Input rejected by the local terminal.\x1b[0m\r\n";
+
+/// Warning for input nobody answered in time
+pub const EXPIRED_WARNING: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Input not approved in time and dropped.\x1b[0m\r\n";
+
+/// Warning for input with no local terminal attached to approve it
+pub const NO_APPROVER_WARNING: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Input dropped: no local terminal attached to approve it.\x1b[0m\r\n";
+
+/// Requests waiting on a box's local terminals
+#[derive(Debug)]
+pub struct Approvals {
+    next_id: AtomicU64,
+    /// Open requests, oldest first, as last announced
+    pending: Mutex<VecDeque<(DaemonEvent, oneshot::Sender<bool>)>>,
+    /// `InputApproval` and `InputApprovalDone` events for local clients
+    events: broadcast::Sender<DaemonEvent>,
+}
+
+impl Default for Approvals {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(VecDeque::new()),
+            events: broadcast::channel(64).0,
+        }
+    }
+}
+
+impl Approvals {
+    /// Follow requests as an approver: the open ones, then a stream of new
+    /// and finished ones
+    pub fn subscribe(&self) -> (Vec<DaemonEvent>, broadcast::Receiver<DaemonEvent>) {
+        let pending = self.pending.lock().unwrap();
+        let open = pending.iter().map(|(event, _)| event.clone()).collect();
+        (open, self.events.subscribe())
+    }
+
+    /// Answer request `id`. False if it is no longer open.
+    pub fn answer(&self, id: u64, approve: bool) -> bool {
+        match self.take(id) {
+            Some(reply) => {
+                let _ = reply.send(approve);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Open a request, unless no approver is listening
+    fn request(
+        &self,
+        pty: u32,
+        client: &str,
+        input: &[u8],
+    ) -> Option<(u64, oneshot::Receiver<bool>)> {
+        let mut pending = self.pending.lock().unwrap();
+        if self.events.receiver_count() == 0 {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let event = DaemonEvent::InputApproval {
+            id,
+            pty,
+            client: client.to_string(),
+            input: String::from_utf8_lossy(input).escape_debug().to_string(),
+        };
+        let (tx, rx) = oneshot::channel();
+        pending.push_back((event.clone(), tx));
+        let _ = self.events.send(event);
+        Some((id, rx))
+    }
+
+    /// Close request `id`, announcing it
+    fn take(&self, id: u64) -> Option<oneshot::Sender<bool>> {
+        let mut pending = self.pending.lock().unwrap();
+        let pos = pending.iter().position(
+            |(event, _)| matches!(event, DaemonEvent::InputApproval { id: i, .. } if *i == id),
+        )?;
+        let (_, reply) = pending.remove(pos)?;
+        let _ = self.events.send(DaemonEvent::InputApprovalDone { id });
+        Some(reply)
+    }
+}
+
+/// What became of a remote client's held input
+#[derive(Debug, PartialEq)]
+pub enum Approval {
+    /// A batch was sent to the local terminals
+    Waiting,
+    /// Write this
+    Approved(Vec<u8>),
+    Rejected(Vec<u8>),
+    Expired(Vec<u8>),
+    /// Nobody was attached to ask
+    NoApprover(Vec<u8>),
+}
+
+impl Approval {
+    /// The notice to show the remote client
+    pub fn notice(&self) -> Option<&'static [u8]> {
+        match self {
+            Approval::Waiting => Some(WAITING_NOTICE),
+            Approval::Approved(_) => None,
+            Approval::Rejected(_) => Some(REJECTED_WARNING),
+            Approval::Expired(_) => Some(EXPIRED_WARNING),
+            Approval::NoApprover(_) => Some(NO_APPROVER_WARNING),
+        }
+    }
+}
+
+/// Holds one remote client's input for approval. At most one batch is out
+/// at a time; input typed meanwhile waits for the next one.
+pub struct ApprovalGate {
+    approvals: Arc<Approvals>,
+    pty: u32,
+    client: String,
+    /// Input not yet sent for approval
+    batch: Vec<u8>,
+    last_input: Option<Instant>,
+    /// The batch sent for approval: (id, input, expiry, reply)
+    waiting: Option<(u64, Vec<u8>, Instant, oneshot::Receiver<bool>)>,
+}
+
+impl ApprovalGate {
+    /// A batch without Enter goes out after this long without input
+    pub const IDLE: Duration = Duration::from_secs(1);
+    /// How long a batch waits for an answer
+    pub const TIMEOUT: Duration = Duration::from_secs(120);
+
+    pub fn new(approvals: Arc<Approvals>, pty: u32, client: String) -> Self {
+        Self {
+            approvals,
+            pty,
+            client,
+            batch: Vec::new(),
+            last_input: None,
+            waiting: None,
+        }
+    }
+
+    /// Hold input until [`next`](Self::next) sends it for approval
+    pub fn push(&mut self, data: &[u8]) {
+        if !data.is_empty() {
+            self.batch.extend_from_slice(data);
+            self.last_input = Some(Instant::now());
+        }
+    }
+
+    /// Wait for the next thing to tell the client. Cancel-safe, for
+    /// `select!` loops.
+    pub async fn next(&mut self) -> Approval {
+        if let Some((_, _, expires, reply)) = &mut self.waiting {
+            let answer = tokio::time::timeout_at((*expires).into(), reply).await;
+            let (id, input, _, _) = self.waiting.take().expect("waiting batch");
+            return match answer {
+                Ok(Ok(true)) => Approval::Approved(input),
+                Ok(_) => Approval::Rejected(input),
+                Err(_) => {
+                    self.approvals.take(id);
+                    Approval::Expired(input)
+                }
+            };
+        }
+
+        let Some(last_input) = self.last_input else {
+            return std::future::pending().await;
+        };
+        if !self.batch.iter().any(|&b| b == b'\r' || b == b'\n') {
+            tokio::time::sleep_until((last_input + Self::IDLE).into()).await;
+        }
+        self.last_input = None;
+        let input = std::mem::take(&mut self.batch);
+        match self.approvals.request(self.pty, &self.client, &input) {
+            Some((id, reply)) => {
+                self.waiting = Some((id, input, Instant::now() + Self::TIMEOUT, reply));
+                Approval::Waiting
+            }
+            None => Approval::NoApprover(input),
+        }
+    }
+}
+
+impl Drop for ApprovalGate {
+    /// Withdraw the open request when the client goes away
+    fn drop(&mut self) {
+        if let Some((id, ..)) = self.waiting.take() {
+            self.approvals.take(id);
+        }
+    }
+}
+
+/// [`ApprovalGate::next`] for an optional gate; never resolves without one
+pub async fn next(gate: Option<&mut ApprovalGate>) -> Approval {
+    match gate {
+        Some(gate) => gate.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_approval_flow() {
+        let approvals = Arc::new(Approvals::default());
+        let mut gate = ApprovalGate::new(approvals.clone(), 0, "web:alice".into());
+
+        // Nobody to ask
+        gate.push(b"ls\r");
+        assert_eq!(gate.next().await, Approval::NoApprover(b"ls\r".to_vec()));
+
+        let (open, mut events) = approvals.subscribe();
+        assert!(open.is_empty());
+
+        gate.push(b"rm -rf /\r");
+        assert_eq!(gate.next().await, Approval::Waiting);
+        let Ok(DaemonEvent::InputApproval {
+            id, input, client, ..
+        }) = events.recv().await
+        else {
+            panic!("expected a request");
+        };
+        assert_eq!(input, "rm -rf /\\r");
+        assert_eq!(client, "web:alice");
+        assert_eq!(approvals.subscribe().0.len(), 1);
+
+        // Typed while waiting: held for the next batch
+        gate.push(b"ls\r");
+        assert!(approvals.answer(id, false));
+        assert!(!approvals.answer(id, true));
+        assert_eq!(
+            gate.next().await,
+            Approval::Rejected(b"rm -rf /\r".to_vec())
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            DaemonEvent::InputApprovalDone { id }
+        );
+
+        assert_eq!(gate.next().await, Approval::Waiting);
+        let Ok(DaemonEvent::InputApproval { id, .. }) = events.recv().await else {
+            panic!("expected a request");
+        };
+        approvals.answer(id, true);
+        assert_eq!(gate.next().await, Approval::Approved(b"ls\r".to_vec()));
+
+        // Dropping the gate withdraws its request
+        gate.push(b"pwd\r");
+        assert_eq!(gate.next().await, Approval::Waiting);
+        drop(gate);
+        assert!(approvals.subscribe().0.is_empty());
+    }
+}
@@ -0,0 +1,115 @@
+//! Matching PTY output against a pattern, for `coop wait`. Output is
+//! scanned as text: escape sequences and control characters other than
+//! `\n` and `\t` are stripped first, across chunk boundaries.
+
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+
+/// Stripped output kept for matches that span chunks
+const MAX_TEXT: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Text,
+    /// After ESC
+    Escape,
+    /// ESC followed by a charset or line-size selector, which takes one
+    /// more byte
+    EscapeArg,
+    /// CSI parameters, up to the final byte
+    Csi,
+    /// OSC, DCS and other strings, up to BEL or ST
+    Str,
+    /// ESC within a string (ST is ESC \)
+    StrEscape,
+}
+
+/// Scans one PTY's output for a pattern
+pub struct OutputMatcher {
+    pattern: Regex,
+    state: State,
+    /// Stripped output not yet ruled out
+    text: Vec<u8>,
+}
+
+impl OutputMatcher {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Ok(Self {
+            pattern: Regex::new(pattern)
+                .with_context(|| format!("Invalid pattern '{}'", pattern))?,
+            state: State::Text,
+            text: Vec::new(),
+        })
+    }
+
+    /// Feed output. Returns the matched text once the pattern matches.
+    pub fn push(&mut self, data: &[u8]) -> Option<String> {
+        let start = self.text.len();
+        for &b in data {
+            self.state = match (self.state, b) {
+                (State::Text, 0x1b) => State::Escape,
+                (State::Text, b'\n' | b'\t') => {
+                    self.text.push(b);
+                    State::Text
+                }
+                (State::Text, b) if b < 0x20 || b == 0x7f => State::Text,
+                (State::Text, b) => {
+                    self.text.push(b);
+                    State::Text
+                }
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']' | b'P' | b'X' | b'^' | b'_') => State::Str,
+                (State::Escape, b'(' | b')' | b'*' | b'+' | b'#' | b'%') => State::EscapeArg,
+                (State::Escape | State::EscapeArg, _) => State::Text,
+                (State::Csi, 0x40..=0x7e) => State::Text,
+                (State::Csi, _) => State::Csi,
+                (State::Str, 0x07) => State::Text,
+                (State::Str | State::StrEscape, 0x1b) => State::StrEscape,
+                (State::StrEscape, b'\\') => State::Text,
+                (State::Str | State::StrEscape, _) => State::Str,
+            };
+        }
+        if self.text.len() == start {
+            return None;
+        }
+
+        if let Some(m) = self.pattern.find(&self.text) {
+            let matched = String::from_utf8_lossy(m.as_bytes()).into_owned();
+            self.text.clear();
+            return Some(matched);
+        }
+        if self.text.len() > MAX_TEXT {
+            self.text.drain(..self.text.len() - MAX_TEXT / 2);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_across_chunks() {
+        let mut matcher = OutputMatcher::new(r"Tests passed|FAILED").unwrap();
+        assert_eq!(matcher.push(b"running 3 tests\r\n"), None);
+        assert_eq!(matcher.push(b"\x1b[32mTests pa"), None);
+        assert_eq!(
+            matcher.push(b"ssed\x1b[0m\r\n").as_deref(),
+            Some("Tests passed")
+        );
+    }
+
+    #[test]
+    fn test_strips_escapes() {
+        let mut matcher = OutputMatcher::new(r"(?m)^\$ done$").unwrap();
+        // A title (OSC), colours split mid-sequence, a charset switch
+        assert_eq!(matcher.push(b"\x1b]0;title\x07\x1b(B$ \x1b["), None);
+        assert_eq!(matcher.push(b"1;3"), None);
+        assert_eq!(
+            matcher.push(b"1mdone\x1b[0m\r\n").as_deref(),
+            Some("$ done")
+        );
+        assert!(OutputMatcher::new("(").is_err());
+    }
+}
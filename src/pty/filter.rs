@@ -1,14 +1,28 @@
 use std::time::Instant;
 
 use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::Result;
+use regex::Regex;
 
-/// Input filter for web-connected PTYs.
-/// Blocks dangerous sequences and debounces Ctrl+C.
+use crate::config::InputPolicy;
+use crate::ipc::PtyRole;
+
+/// Input filter for a client's connection to a PTY, built from an
+/// `[input_filter]` policy. Drops keys outside `allow_keys`, blocks
+/// dangerous sequences, debounces Ctrl+C and checks lines against
+/// `block_patterns` when Enter is pressed.
+#[derive(Debug)]
 pub struct InputFilter {
+    /// Only these keys pass, when set
+    allow_keys: Option<Vec<Vec<u8>>>,
     /// Aho-Corasick automaton for blocked sequences
     automaton: AhoCorasick,
     /// The blocked patterns (for reference)
     patterns: Vec<Vec<u8>>,
+    /// Regexes a line may not match when submitted
+    block_patterns: Vec<Regex>,
+    /// The line typed so far, as far as it can be followed
+    line: Vec<u8>,
     /// Partial match buffer
     pending: Vec<u8>,
     /// Last time Ctrl+C was forwarded
@@ -19,6 +33,8 @@ pub struct InputFilter {
     partial_timeout_ms: u64,
     /// Time of last input
     last_input: Option<Instant>,
+    /// The sequence behind the last warning, for the audit log
+    blocked: Option<Vec<u8>>,
 }
 
 /// Warning message sent when input is blocked
@@ -29,8 +45,24 @@ pub const BLOCKED_WARNING: &[u8] =
 pub const CTRL_C_DEBOUNCED_WARNING: &[u8] =
     b"\r\n\x1b[1;33m\xe2\x9a\xa0  Ctrl+C debounced. Wait before pressing again.\x1b[0m\r\n";
 
+/// Warning message for a key outside `allow_keys`
+pub const KEY_BLOCKED_WARNING: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Key not allowed from this client.\x1b[0m\r\n";
+
+/// Warning message for a line matching `block_patterns`
+pub const LINE_BLOCKED_WARNING: &[u8] =
+    b"\r\n\x1b[1;33m\xe2\x9a\xa0  Line blocked by input filter. Edit it before pressing Enter.\x1b[0m\r\n";
+
 impl InputFilter {
-    pub fn new(ctrl_c_debounce_ms: u64, custom_block_sequences: &[String]) -> Self {
+    /// The filter `policy` puts on a PTY with `role`, if any
+    pub fn for_pty(policy: &InputPolicy, role: &PtyRole) -> Result<Option<Self>> {
+        if !policy.enabled || !(role.is_agent() || policy.shells) {
+            return Ok(None);
+        }
+        Self::new(policy).map(Some)
+    }
+
+    pub fn new(policy: &InputPolicy) -> Result<Self> {
         let mut patterns: Vec<Vec<u8>> = vec![
             // Built-in blocked sequences
             vec![0x04],          // Ctrl+D
@@ -44,7 +76,7 @@ impl InputFilter {
         ];
 
         // Add custom block sequences
-        for seq in custom_block_sequences {
+        for seq in &policy.block_sequences {
             patterns.push(parse_escape_sequence(seq));
         }
 
@@ -53,19 +85,104 @@ impl InputFilter {
             .build(&patterns)
             .expect("Failed to build Aho-Corasick automaton");
 
-        Self {
+        let allow_keys = if policy.allow_keys.is_empty() {
+            None
+        } else {
+            Some(
+                policy
+                    .allow_keys
+                    .iter()
+                    .map(|key| parse_escape_sequence(key))
+                    .collect(),
+            )
+        };
+
+        Ok(Self {
+            allow_keys,
             automaton,
             patterns,
+            block_patterns: policy
+                .block_patterns
+                .iter()
+                .map(|p| Regex::new(p))
+                .collect::<Result<_, _>>()?,
+            line: Vec::new(),
             pending: Vec::new(),
             last_ctrl_c: None,
-            ctrl_c_debounce_ms,
+            ctrl_c_debounce_ms: policy.ctrl_c_debounce_ms,
             partial_timeout_ms: 500,
             last_input: None,
-        }
+            blocked: None,
+        })
     }
 
     /// Filter input bytes. Returns (bytes_to_forward, optional_warning).
     pub fn filter(&mut self, input: &[u8]) -> (Vec<u8>, Option<&'static [u8]>) {
+        let (input, key_warning) = self.filter_keys(input);
+        if input.is_empty() {
+            return (input, key_warning);
+        }
+        let (output, warning) = self.filter_sequences(&input);
+        let (output, line_warning) = self.filter_lines(output);
+        (output, key_warning.or(warning).or(line_warning))
+    }
+
+    /// Drop keys outside `allow_keys`
+    fn filter_keys(&mut self, input: &[u8]) -> (Vec<u8>, Option<&'static [u8]>) {
+        let Some(allowed) = &self.allow_keys else {
+            return (input.to_vec(), None);
+        };
+        let mut output = Vec::new();
+        let mut warning = None;
+        for key in split_keys(input) {
+            if allowed.iter().any(|k| k == key) {
+                output.extend_from_slice(key);
+            } else {
+                self.blocked.get_or_insert_with(|| key.to_vec());
+                warning = Some(KEY_BLOCKED_WARNING);
+            }
+        }
+        (output, warning)
+    }
+
+    /// Follow the line being typed and hold back the Enter that would submit
+    /// one matching `block_patterns`, along with anything after it
+    fn filter_lines(&mut self, output: Vec<u8>) -> (Vec<u8>, Option<&'static [u8]>) {
+        if self.block_patterns.is_empty() {
+            return (output, None);
+        }
+        let mut pos = 0;
+        for key in split_keys(&output) {
+            match key {
+                b"\r" | b"\n" => {
+                    let line = String::from_utf8_lossy(&self.line);
+                    if self.block_patterns.iter().any(|re| re.is_match(&line)) {
+                        // The line stays in the agent's prompt, so keep
+                        // following it
+                        self.blocked.get_or_insert_with(|| self.line.clone());
+                        return (output[..pos].to_vec(), Some(LINE_BLOCKED_WARNING));
+                    }
+                    self.line.clear();
+                }
+                [0x7f] | [0x08] => {
+                    while let Some(b) = self.line.pop() {
+                        if b & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                // Ctrl+C and Ctrl+U discard the line
+                [0x03] | [0x15] => self.line.clear(),
+                [c, ..] if *c >= 0x20 => self.line.extend_from_slice(key),
+                _ => {}
+            }
+            pos += key.len();
+        }
+        (output, None)
+    }
+
+    /// Debounce Ctrl+C and block `block_sequences`
+    fn filter_sequences(&mut self, input: &[u8]) -> (Vec<u8>, Option<&'static [u8]>) {
         let now = Instant::now();
         self.last_input = Some(now);
 
@@ -106,6 +223,8 @@ impl InputFilter {
         }
 
         // Found a blocked sequence - suppress it
+        let pattern = &self.patterns[matches[0].pattern()];
+        self.blocked.get_or_insert_with(|| pattern.clone());
         self.pending.clear();
         (Vec::new(), Some(BLOCKED_WARNING))
     }
@@ -114,6 +233,7 @@ impl InputFilter {
         if let Some(last) = self.last_ctrl_c {
             if now.duration_since(last).as_millis() as u64 <= self.ctrl_c_debounce_ms {
                 // Within debounce window, suppress
+                self.blocked.get_or_insert_with(|| vec![0x03]);
                 return (Vec::new(), Some(CTRL_C_DEBOUNCED_WARNING));
             }
         }
@@ -133,10 +253,17 @@ impl InputFilter {
         false
     }
 
-    /// Flush any pending bytes (call on timeout)
-    #[allow(dead_code)]
-    pub fn flush_pending(&mut self) -> Vec<u8> {
-        std::mem::take(&mut self.pending)
+    /// The sequence suppressed by the last `filter` call that returned a
+    /// warning
+    pub fn take_blocked(&mut self) -> Option<Vec<u8>> {
+        self.blocked.take()
+    }
+
+    /// Flush any pending bytes (call on timeout, or at the end of one-shot
+    /// input), checking them against `block_patterns` like the rest
+    pub fn flush_pending(&mut self) -> (Vec<u8>, Option<&'static [u8]>) {
+        let pending = std::mem::take(&mut self.pending);
+        self.filter_lines(pending)
     }
 }
 
@@ -164,21 +291,59 @@ fn parse_escape_sequence(s: &str) -> Vec<u8> {
     result
 }
 
+/// Split terminal input into keys: escape sequences, control bytes and
+/// UTF-8 characters
+fn split_keys(data: &[u8]) -> Vec<&[u8]> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let len = match data[i..] {
+            // CSI: parameters and intermediates, then a final byte
+            [0x1b, b'[', ref rest @ ..] => {
+                2 + rest
+                    .iter()
+                    .position(|b| (0x40..=0x7e).contains(b))
+                    .map_or(rest.len(), |p| p + 1)
+            }
+            // SS3 (application cursor keys, F1-F4) and Alt+key
+            [0x1b, b'O', _, ..] => 3,
+            [0x1b, next, ..] if next != 0x1b => 1 + utf8_len(&data[i + 1..]),
+            _ => utf8_len(&data[i..]),
+        };
+        keys.push(&data[i..i + len]);
+        i += len;
+    }
+    keys
+}
+
+/// Length of the UTF-8 character at the start of `data` (1 for invalid)
+fn utf8_len(data: &[u8]) -> usize {
+    let len = match data[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    len.min(data.len())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_blocks_ctrl_d() {
-        let mut filter = InputFilter::new(500, &[]);
+        let mut filter = InputFilter::new(&InputPolicy::default()).unwrap();
         let (out, warning) = filter.filter(&[0x04]);
         assert!(out.is_empty());
         assert!(warning.is_some());
+        assert_eq!(filter.take_blocked(), Some(vec![0x04]));
+        assert_eq!(filter.take_blocked(), None);
     }
 
     #[test]
     fn test_passes_normal_input() {
-        let mut filter = InputFilter::new(500, &[]);
+        let mut filter = InputFilter::new(&InputPolicy::default()).unwrap();
         let (out, warning) = filter.filter(b"hello");
         assert_eq!(out, b"hello");
         assert!(warning.is_none());
@@ -186,7 +351,7 @@ mod tests {
 
     #[test]
     fn test_ctrl_c_debounce() {
-        let mut filter = InputFilter::new(500, &[]);
+        let mut filter = InputFilter::new(&InputPolicy::default()).unwrap();
 
         // First Ctrl+C passes through
         let (out, warning) = filter.filter(&[0x03]);
@@ -198,4 +363,110 @@ mod tests {
         assert!(out.is_empty());
         assert!(warning.is_some());
     }
+
+    #[test]
+    fn test_allow_keys() {
+        let policy = InputPolicy {
+            allow_keys: vec![
+                "\\x1b[A".into(),
+                "\\x1bOA".into(),
+                "\\x0d".into(),
+                "y".into(),
+            ],
+            ..Default::default()
+        };
+        let mut filter = InputFilter::new(&policy).unwrap();
+        let (out, warning) = filter.filter(b"\x1bOAy\r");
+        assert_eq!(out, b"\x1bOAy\r");
+        assert!(warning.is_none());
+
+        let (out, warning) = filter.filter(b"yes\r");
+        assert_eq!(out, b"y\r");
+        assert_eq!(warning, Some(KEY_BLOCKED_WARNING));
+        assert_eq!(filter.take_blocked(), Some(b"e".to_vec()));
+    }
+
+    #[test]
+    fn test_block_patterns() {
+        let policy = InputPolicy {
+            block_patterns: vec![r"rm\s+-rf".into()],
+            ..Default::default()
+        };
+        let mut filter = InputFilter::new(&policy).unwrap();
+        assert_eq!(filter.filter(b"ls\r").0, b"ls\r");
+
+        // Typed a key at a time, with a correction
+        for key in [b"r".as_slice(), b"n", b"\x7f", b"m -rf /"] {
+            assert_eq!(filter.filter(key).0, key);
+        }
+        let (out, warning) = filter.filter(b"\r");
+        assert!(out.is_empty());
+        assert_eq!(warning, Some(LINE_BLOCKED_WARNING));
+        assert_eq!(filter.take_blocked(), Some(b"rm -rf /".to_vec()));
+
+        // Still blocked until the line is discarded
+        assert!(filter.filter(b"\r").0.is_empty());
+        filter.filter(&[0x15]);
+        assert_eq!(filter.filter(b"echo ok\r").0, b"echo ok\r");
+    }
+
+    #[test]
+    fn test_block_patterns_across_sends() {
+        let policy = InputPolicy {
+            block_patterns: vec![r"rm\s+-rf".into(), "^/ex$".into()],
+            ..Default::default()
+        };
+        // As `coop send` uses the filter it keeps for a client: each send's
+        // held bytes are flushed at its end
+        let mut filter = InputFilter::new(&policy).unwrap();
+        let mut send = |data: &[u8]| {
+            let (mut out, warning) = filter.filter(data);
+            let (held, held_warning) = filter.flush_pending();
+            out.extend(held);
+            (out, warning.or(held_warning))
+        };
+
+        // coop send box "rm -rf /" && coop send box --keys Enter
+        assert_eq!(send(b"rm -rf /"), (b"rm -rf /".to_vec(), None));
+        assert_eq!(send(b"\r"), (Vec::new(), Some(LINE_BLOCKED_WARNING)));
+        assert_eq!(send(&[0x03]), (vec![0x03], None));
+        assert_eq!(send(&[0x03]), (Vec::new(), Some(CTRL_C_DEBOUNCED_WARNING)));
+
+        // Held as the start of `/exit`, then flushed into the line
+        assert_eq!(send(b"/ex"), (b"/ex".to_vec(), None));
+        assert_eq!(send(b"\r"), (Vec::new(), Some(LINE_BLOCKED_WARNING)));
+    }
+
+    #[test]
+    fn test_split_keys() {
+        let input = "y\x1b[A\x1bOB\x1b[5~é\r\x1bb".as_bytes();
+        assert_eq!(
+            split_keys(input),
+            vec![
+                b"y".as_slice(),
+                b"\x1b[A",
+                b"\x1bOB",
+                b"\x1b[5~",
+                "é".as_bytes(),
+                b"\r",
+                b"\x1bb",
+            ]
+        );
+        assert_eq!(split_keys(b"\x1b"), vec![b"\x1b".as_slice()]);
+    }
+
+    #[test]
+    fn test_for_pty() {
+        let agent = PtyRole::Agent("agent".into());
+        let policy = InputPolicy::default();
+        assert!(InputFilter::for_pty(&policy, &agent).unwrap().is_some());
+        assert!(InputFilter::for_pty(&policy, &PtyRole::Shell)
+            .unwrap()
+            .is_none());
+        let off = InputPolicy {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(InputFilter::for_pty(&off, &agent).unwrap().is_none());
+    }
 }
@@ -0,0 +1,133 @@
+//! Key names (tmux style: `Enter`, `Up`, `C-c`, `M-x`, `y`) and the bytes a
+//! terminal sends for them.
+
+use anyhow::{bail, Result};
+
+/// Every encoding of a named key. Cursor keys have two: normal and
+/// application mode.
+pub fn parse_key(name: &str) -> Result<Vec<Vec<u8>>> {
+    let csi = |s: &str| [b"\x1b[".as_slice(), s.as_bytes()].concat();
+    let ss3 = |s: &str| [b"\x1bO".as_slice(), s.as_bytes()].concat();
+
+    let encodings = match name.to_ascii_lowercase().as_str() {
+        "enter" => vec![b"\r".to_vec()],
+        "tab" => vec![b"\t".to_vec()],
+        "btab" => vec![csi("Z")],
+        "escape" | "esc" => vec![b"\x1b".to_vec()],
+        "bspace" => vec![b"\x7f".to_vec(), b"\x08".to_vec()],
+        "space" => vec![b" ".to_vec()],
+        "up" => vec![csi("A"), ss3("A")],
+        "down" => vec![csi("B"), ss3("B")],
+        "right" => vec![csi("C"), ss3("C")],
+        "left" => vec![csi("D"), ss3("D")],
+        "home" => vec![csi("H"), ss3("H"), csi("1~")],
+        "end" => vec![csi("F"), ss3("F"), csi("4~")],
+        "ic" | "insert" => vec![csi("2~")],
+        "dc" | "delete" => vec![csi("3~")],
+        "ppage" | "pageup" | "pgup" => vec![csi("5~")],
+        "npage" | "pagedown" | "pgdn" => vec![csi("6~")],
+        _ => {
+            if let Some(key) = name.strip_prefix("C-") {
+                match key.as_bytes() {
+                    [c] if c.is_ascii_alphabetic() => vec![vec![c.to_ascii_lowercase() & 0x1f]],
+                    [b'@'] | [b' '] => vec![vec![0]],
+                    [c @ b'['..=b'_'] => vec![vec![c & 0x1f]],
+                    _ => bail!("Unknown key '{}'", name),
+                }
+            } else if let Some(key) = name.strip_prefix("M-") {
+                parse_key(key)?
+                    .into_iter()
+                    .map(|k| [b"\x1b".as_slice(), &k].concat())
+                    .collect()
+            } else if name.chars().count() == 1 {
+                vec![name.as_bytes().to_vec()]
+            } else {
+                bail!("Unknown key '{}'", name)
+            }
+        }
+    };
+    Ok(encodings)
+}
+
+/// The bytes for `text` (with `\n`, `\x03`-style escapes) followed by the
+/// named `keys`, as `coop send` and the input API write them
+pub fn input_bytes(text: &str, keys: &[String]) -> Result<Vec<u8>> {
+    let mut data = parse_text(text);
+    for key in keys {
+        data.extend(parse_key(key)?.swap_remove(0));
+    }
+    Ok(data)
+}
+
+/// Decode `coop send` text: `\n`, `\r`, `\t`, `\e`, `\\` and `\xNN`.
+/// Any other backslash is taken literally.
+fn parse_text(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut data = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 2) {
+            Some(b"\\n") => Some((b'\n', 2)),
+            Some(b"\\r") => Some((b'\r', 2)),
+            Some(b"\\t") => Some((b'\t', 2)),
+            Some(b"\\e") => Some((0x1b, 2)),
+            Some(b"\\\\") => Some((b'\\', 2)),
+            Some(b"\\x") => bytes
+                .get(i + 2..i + 4)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .map(|byte| (byte, 4)),
+            _ => None,
+        };
+        match escaped {
+            Some((byte, len)) => {
+                data.push(byte);
+                i += len;
+            }
+            None => {
+                data.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("Enter").unwrap(), vec![b"\r".to_vec()]);
+        assert_eq!(
+            parse_key("up").unwrap(),
+            vec![b"\x1b[A".to_vec(), b"\x1bOA".to_vec()]
+        );
+        assert_eq!(parse_key("C-c").unwrap(), vec![vec![0x03]]);
+        assert_eq!(parse_key("M-b").unwrap(), vec![b"\x1bb".to_vec()]);
+        assert_eq!(parse_key("y").unwrap(), vec![b"y".to_vec()]);
+        assert_eq!(parse_key("é").unwrap(), vec!["é".as_bytes().to_vec()]);
+        assert!(parse_key("Hyper").is_err());
+        assert!(parse_key("C-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_text() {
+        assert_eq!(parse_text("\\x1b:q!\\r"), b"\x1b:q!\r");
+        assert_eq!(parse_text("a\\tb\\e"), b"a\tb\x1b");
+        assert_eq!(parse_text("C:\\\\new"), b"C:\\new");
+        assert_eq!(parse_text("\\q \\x+1 \\x4"), b"\\q \\x+1 \\x4");
+    }
+
+    #[test]
+    fn test_input_bytes() {
+        let keys = vec!["C-c".to_string(), "Up".to_string(), "Enter".to_string()];
+        assert_eq!(
+            input_bytes("run the tests\\n", &keys).unwrap(),
+            b"run the tests\n\x03\x1b[A\r"
+        );
+        assert!(input_bytes("", &["Hyper".to_string()]).is_err());
+    }
+}
@@ -1,4 +1,10 @@
+pub mod approval;
+pub mod expect;
 pub mod filter;
+pub mod keys;
 pub mod manager;
+pub mod redact;
 
+pub use approval::{ApprovalGate, Approvals};
 pub use filter::InputFilter;
+pub use redact::RedactRules;
@@ -0,0 +1,151 @@
+//! `/etc/resolv.conf` and `/etc/hosts` for a box, built on the host before
+//! the fork from the host's effective resolver configuration and `[network]`.
+
+use std::net::IpAddr;
+
+use anyhow::Result;
+
+use crate::config::{NetworkConfig, NetworkMode};
+
+/// The host's resolver configuration
+const HOST_RESOLV_CONF: &str = "/etc/resolv.conf";
+
+/// The upstream servers behind systemd-resolved's 127.0.0.53 stub
+const RESOLVED_UPSTREAM: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Used when neither the Coopfile nor the host name a usable nameserver
+const FALLBACK_NAMESERVERS: [&str; 2] = ["8.8.8.8", "8.8.4.4"];
+
+/// resolv.conf for a box with the given `[network]` config.
+pub fn resolv_conf(network: &NetworkConfig) -> String {
+    let host = std::fs::read_to_string(HOST_RESOLV_CONF).unwrap_or_default();
+    // A box with its own network stack cannot reach a resolver listening on
+    // the host's loopback, so look behind systemd-resolved's stub
+    let isolated = network.mode != NetworkMode::Host;
+    let host = if isolated && has_loopback_nameserver(&host) {
+        std::fs::read_to_string(RESOLVED_UPSTREAM).unwrap_or(host)
+    } else {
+        host
+    };
+    build_resolv_conf(&host, &network.dns, &network.dns_search, isolated)
+}
+
+/// /etc/hosts for a box: localhost, the box hostname and `extra_hosts`.
+pub fn hosts(hostname: &str, network: &NetworkConfig) -> Result<String> {
+    let mut out = format!("127.0.0.1 localhost\n::1 localhost\n127.0.1.1 {hostname}\n");
+    for (name, addr) in network.extra_hosts()? {
+        out.push_str(&format!("{addr} {name}\n"));
+    }
+    Ok(out)
+}
+
+fn has_loopback_nameserver(resolv_conf: &str) -> bool {
+    nameservers(resolv_conf).any(|addr| addr.is_loopback())
+}
+
+fn nameservers(resolv_conf: &str) -> impl Iterator<Item = IpAddr> + '_ {
+    directives(resolv_conf, "nameserver").filter_map(|value| value.parse().ok())
+}
+
+/// Values of `keyword` lines, comments skipped
+fn directives<'a>(resolv_conf: &'a str, keyword: &'a str) -> impl Iterator<Item = &'a str> {
+    resolv_conf.lines().filter_map(move |line| {
+        let (key, value) = line.trim().split_once(char::is_whitespace)?;
+        (key == keyword).then(|| value.trim())
+    })
+}
+
+/// Take the host's resolv.conf, replacing nameservers with `dns` and search
+/// domains with `dns_search` when given. With `isolated`, the host's loopback
+/// nameservers are dropped.
+fn build_resolv_conf(host: &str, dns: &[String], dns_search: &[String], isolated: bool) -> String {
+    let mut servers: Vec<String> = if dns.is_empty() {
+        nameservers(host)
+            .filter(|addr| !(isolated && addr.is_loopback()))
+            .map(|addr| addr.to_string())
+            .collect()
+    } else {
+        dns.to_vec()
+    };
+    if servers.is_empty() {
+        servers = FALLBACK_NAMESERVERS.iter().map(|s| s.to_string()).collect();
+    }
+
+    // `domain` and `search` override each other; the last one wins
+    let search = if dns_search.is_empty() {
+        host.lines().rev().find_map(|line| {
+            let (key, value) = line.trim().split_once(char::is_whitespace)?;
+            matches!(key, "search" | "domain").then(|| value.trim().to_string())
+        })
+    } else {
+        Some(dns_search.join(" "))
+    };
+
+    let mut out = String::new();
+    for server in servers {
+        out.push_str(&format!("nameserver {server}\n"));
+    }
+    if let Some(search) = search {
+        out.push_str(&format!("search {search}\n"));
+    }
+    for options in directives(host, "options") {
+        out.push_str(&format!("options {options}\n"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STUB: &str = "# This is /run/systemd/resolve/stub-resolv.conf\n\
+                        nameserver 127.0.0.53\n\
+                        options edns0 trust-ad\n\
+                        search corp.example.com\n";
+
+    #[test]
+    fn test_build_resolv_conf() {
+        // Shared network: the host's stub works as is
+        assert_eq!(
+            build_resolv_conf(STUB, &[], &[], false),
+            "nameserver 127.0.0.53\nsearch corp.example.com\noptions edns0 trust-ad\n"
+        );
+        // Own network: loopback resolvers are unreachable
+        assert_eq!(
+            build_resolv_conf(STUB, &[], &[], true),
+            "nameserver 8.8.8.8\nnameserver 8.8.4.4\nsearch corp.example.com\noptions edns0 trust-ad\n"
+        );
+        // Coopfile settings replace the host's
+        assert_eq!(
+            build_resolv_conf(
+                STUB,
+                &["10.0.0.2".to_string()],
+                &["a.example".to_string(), "b.example".to_string()],
+                false,
+            ),
+            "nameserver 10.0.0.2\nsearch a.example b.example\noptions edns0 trust-ad\n"
+        );
+        assert_eq!(
+            build_resolv_conf("domain lan\nnameserver 192.168.1.1\n", &[], &[], true),
+            "nameserver 192.168.1.1\nsearch lan\n"
+        );
+    }
+
+    #[test]
+    fn test_hosts() {
+        let network = NetworkConfig {
+            extra_hosts: vec!["db.local:10.0.0.5".to_string(), "v6:fd00::5".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            hosts("mybox", &network).unwrap(),
+            "127.0.0.1 localhost\n::1 localhost\n127.0.1.1 mybox\n10.0.0.5 db.local\nfd00::5 v6\n"
+        );
+
+        let bad = NetworkConfig {
+            extra_hosts: vec!["10.0.0.5".to_string()],
+            ..Default::default()
+        };
+        assert!(hosts("mybox", &bad).is_err());
+    }
+}
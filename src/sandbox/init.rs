@@ -6,7 +6,7 @@ use anyhow::{bail, Context, Result};
 use nix::sched::CloneFlags;
 use nix::unistd::ForkResult;
 
-use crate::config::{self, Coopfile};
+use crate::config::{self, Coopfile, NetworkConfig, NetworkMode};
 
 /// Compute a hash of the config fields that affect the rootfs (image + setup).
 fn config_hash(config: &Coopfile) -> String {
@@ -113,11 +113,15 @@ async fn do_build_rootfs(config: &Coopfile) -> Result<()> {
         create_minimal_rootfs(&base_path)?;
     }
 
-    // Step 2: Run setup commands
+    // Step 2: Run setup commands (on the host network)
+    let resolv_conf = super::dns::resolv_conf(&NetworkConfig {
+        mode: NetworkMode::Host,
+        ..config.network.clone()
+    });
     let total = config.sandbox.setup.len();
     for (i, cmd) in config.sandbox.setup.iter().enumerate() {
         eprint!("  [{}/{}] Running: {} ... ", i + 1, total, cmd);
-        match run_in_rootfs(&base_path, cmd) {
+        match run_in_rootfs(&base_path, cmd, &resolv_conf) {
             Ok(()) => eprintln!("ok"),
             Err(e) => {
                 eprintln!("FAILED");
@@ -264,7 +268,7 @@ fn pull_oci_image(image: &str, target: &Path) -> Result<()> {
 /// Run a command inside the rootfs using a temporary user+mount namespace.
 /// This is used during `coop init` to install packages and run setup commands.
 /// Output is captured and only displayed on failure.
-fn run_in_rootfs(rootfs: &Path, cmd: &str) -> Result<()> {
+fn run_in_rootfs(rootfs: &Path, cmd: &str, resolv_conf: &str) -> Result<()> {
     let rootfs_owned = rootfs.to_path_buf();
     let cmd_owned = cmd.to_string();
 
@@ -303,7 +307,7 @@ fn run_in_rootfs(rootfs: &Path, cmd: &str) -> Result<()> {
             }
 
             // Write UID/GID maps
-            super::namespace::setup_uid_map(child)?;
+            super::namespace::setup_uid_map(child, 0)?;
 
             // Signal child that maps are ready
             let wr_fd = unsafe { std::os::unix::io::OwnedFd::from_raw_fd(pipe2_wr) };
@@ -452,10 +456,7 @@ fn run_in_rootfs(rootfs: &Path, cmd: &str) -> Result<()> {
 
             // Ensure DNS and hostname resolution work
             let _ = std::fs::create_dir_all("/etc");
-            let _ = std::fs::write(
-                "/etc/resolv.conf",
-                "nameserver 8.8.8.8\nnameserver 8.8.4.4\n",
-            );
+            let _ = std::fs::write("/etc/resolv.conf", resolv_conf);
             let _ = std::fs::write("/etc/hosts", "127.0.0.1 localhost\n::1 localhost\n");
 
             // Disable apt privilege dropping
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::ffi::CString;
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -8,7 +9,7 @@ use anyhow::{bail, Context, Result};
 use nix::sched::CloneFlags;
 use nix::unistd::{ForkResult, Pid};
 
-use crate::config::{self, Coopfile, NetworkMode};
+use crate::config::{self, Coopfile, NetworkMode, Secrets};
 
 /// Result of creating a sandboxed session
 pub struct SessionNamespace {
@@ -64,6 +65,7 @@ pub fn create_session(
     name: &str,
     config: &Coopfile,
     workspace_host: &Path,
+    secrets: &Secrets,
 ) -> Result<SessionNamespace> {
     let base_path = config::rootfs_base_path()?;
     if !base_path.exists() {
@@ -102,9 +104,16 @@ pub fn create_session(
     let pipe3_rd = pipe3_rd_owned.into_raw_fd(); // parent reads
     let pipe3_wr = pipe3_wr_owned.into_raw_fd(); // child writes
 
-    // Resolve the agent command before forking
-    let agent_cmd = config.sandbox.agent_command().unwrap_or("claude");
-    let agent_args = &config.sandbox.args;
+    // Resolve the primary agent (PTY 0) before forking. `sandbox.args` only
+    // applies to the agent derived from `sandbox.agent`.
+    let agents = config.agents();
+    let primary = &agents[0];
+    let agent_cmd = primary.command.as_str();
+    let agent_args = if config.agents.is_empty() {
+        config.sandbox.args.clone()
+    } else {
+        Vec::new()
+    };
     let workspace_path = &config.workspace.path;
     let persist_dirs = &config.session.persist;
     let user_env = &config.env;
@@ -132,8 +141,14 @@ pub fn create_session(
     let persist_dirs_owned = persist_dirs.clone();
     // Resolve sandbox user and home path
     let sandbox_user = &config.sandbox.user;
+    let sandbox_uid = config.sandbox.uid;
     let sandbox_home = format!("/home/{}", sandbox_user);
 
+    // Resolver files are built from the host's, which are out of reach after
+    // pivot_root
+    let resolv_conf = super::dns::resolv_conf(&config.network);
+    let hosts = super::dns::hosts(name, &config.network)?;
+
     // Resolve mounts before fork. Path-based mounts are direct bind mounts;
     // named mounts (left side is a name, not a path) use managed storage.
     let mut extra_mounts: Vec<(PathBuf, String)> = config
@@ -150,14 +165,26 @@ pub fn create_session(
         })
         .collect();
 
-    // Auto-mount the agent command binary into the sandbox if it exists on the host
-    if let Some(cmd_name) = config.sandbox.agent_command() {
-        if let Ok(host_path) = resolve_host_binary(cmd_name) {
-            let container_path = format!("/usr/local/bin/{}", cmd_name);
+    // Auto-mount agent command binaries into the sandbox if they exist on the host
+    for agent in &agents {
+        if let Ok(host_path) = resolve_host_binary(&agent.command) {
+            let container_path = format!("/usr/local/bin/{}", agent.command);
             extra_mounts.push((host_path, container_path));
         }
     }
 
+    // Sockets the daemon forwards into the box, and the `coop` binary that
+    // git runs as its credential helper
+    if config.sandbox.forwards() {
+        extra_mounts.push((
+            config::session_run_dir(name)?,
+            config::BOX_RUN_DIR.to_string(),
+        ));
+        if let Ok(exe) = std::env::current_exe() {
+            extra_mounts.push((exe, format!("{}/coop", config::BOX_RUN_DIR)));
+        }
+    }
+
     // Resolve named mounts (managed persistent storage)
     let session_volumes_dir = session_dir.join("volumes");
     std::fs::create_dir_all(&session_volumes_dir)?;
@@ -211,7 +238,7 @@ pub fn create_session(
     let user_env_owned = user_env.clone();
     let name_owned = name.to_string();
     let agent_cmd_owned = agent_cmd.to_string();
-    let agent_args_owned = agent_args.clone();
+    let agent_args_owned = agent_args;
 
     // Fork: child becomes the namespace init process
     match unsafe { nix::unistd::fork() }.context("fork() failed")? {
@@ -228,7 +255,7 @@ pub fn create_session(
             unsafe { nix::libc::close(pipe1_rd) };
 
             // Write UID/GID mappings for the child's user namespace
-            setup_uid_map(child)?;
+            setup_uid_map(child, sandbox_uid)?;
 
             // Signal child that maps are ready
             let wr_fd = unsafe { OwnedFd::from_raw_fd(pipe2_wr) };
@@ -305,6 +332,15 @@ pub fn create_session(
                 nix::libc::close(pipe2_rd);
             }
 
+            // A fresh network namespace starts with loopback down, which would
+            // leave services in the box unreachable (even from inside it)
+            if network_mode != NetworkMode::Host {
+                if let Err(e) = bring_up_loopback() {
+                    eprintln!("coop: failed to bring up loopback: {}", e);
+                    // Non-fatal
+                }
+            }
+
             // Redirect child stderr to a log file for debugging
             // (daemon stderr goes to /dev/null so child errors are otherwise lost)
             if let Ok(log_path) = crate::config::coop_dir() {
@@ -327,7 +363,8 @@ pub fn create_session(
                 }
             }
 
-            // Now we are "root" inside the user namespace.
+            // As creator of the user namespace we hold every capability in
+            // it until exec, whatever uid the host user is mapped to.
             // Set up the filesystem.
             // Combine regular mounts and volume mounts
             let mut all_mounts = extra_mounts.clone();
@@ -344,7 +381,11 @@ pub fn create_session(
                 &persist_path_owned,
                 &all_mounts,
                 &sandbox_user_owned,
+                sandbox_uid,
                 &sandbox_home_owned,
+                &resolv_conf,
+                &hosts,
+                secrets,
             ) {
                 eprintln!("coop: filesystem setup failed: {:?}", e);
                 std::process::exit(1);
@@ -368,10 +409,12 @@ pub fn create_session(
             std::env::set_var("COOP_CREATED", now.to_string());
 
             // Build env vars vec for entrypoint
-            let env_vars: Vec<(String, String)> = user_env_owned
+            let mut env_vars: Vec<(String, String)> = user_env_owned
                 .iter()
                 .map(|(k, v)| (k.clone(), v.clone()))
                 .collect();
+            env_vars.extend(primary.env.iter().cloned());
+            env_vars.extend(secrets.agent_env());
 
             child_entrypoint(
                 slave_fd,
@@ -386,44 +429,27 @@ pub fn create_session(
     }
 }
 
-/// Set up UID/GID mappings for the user namespace.
+/// Set up UID/GID mappings for the user namespace, mapping the host user to
+/// `inner_id` (both uid and gid).
 ///
 /// Tries newuidmap/newgidmap first (maps full subordinate range so all uids/gids
 /// exist inside the namespace — needed for Debian/Ubuntu package managers).
-/// Falls back to writing /proc/<pid>/uid_map directly (single uid 0 only).
-pub fn setup_uid_map(child_pid: Pid) -> Result<()> {
-    let uid = nix::unistd::getuid();
-    let gid = nix::unistd::getgid();
+/// Falls back to writing /proc/<pid>/uid_map directly (single id only).
+pub fn setup_uid_map(child_pid: Pid, inner_id: u32) -> Result<()> {
+    let uid = nix::unistd::getuid().as_raw() as u64;
+    let gid = nix::unistd::getgid().as_raw() as u64;
+    let inner = inner_id as u64;
     let pid = child_pid.as_raw();
 
     // Try newuidmap/newgidmap for full subordinate range
     if let (Ok(sub_uid), Ok(sub_gid)) = (get_subid("/etc/subuid"), get_subid("/etc/subgid")) {
-        // newuidmap <pid> 0 <real_uid> 1 1 <sub_start> <sub_count>
-        let uid_status = std::process::Command::new("newuidmap")
-            .args([
-                &pid.to_string(),
-                "0",
-                &uid.to_string(),
-                "1",
-                "1",
-                &sub_uid.0.to_string(),
-                &sub_uid.1.to_string(),
-            ])
-            .status();
-
-        let gid_status = std::process::Command::new("newgidmap")
-            .args([
-                &pid.to_string(),
-                "0",
-                &gid.to_string(),
-                "1",
-                "1",
-                &sub_gid.0.to_string(),
-                &sub_gid.1.to_string(),
-            ])
-            .status();
-
-        if matches!(uid_status, Ok(s) if s.success()) && matches!(gid_status, Ok(s) if s.success())
+        let uid_map = id_map(inner, uid, Some(sub_uid));
+        let gid_map = id_map(inner, gid, Some(sub_gid));
+
+        if uid_map.len() > 1
+            && gid_map.len() > 1
+            && run_id_map("newuidmap", pid, &uid_map)
+            && run_id_map("newgidmap", pid, &gid_map)
         {
             return Ok(());
         }
@@ -431,21 +457,62 @@ pub fn setup_uid_map(child_pid: Pid) -> Result<()> {
         eprintln!("coop: newuidmap/newgidmap failed, falling back to single-uid mapping");
     }
 
-    // Fallback: single uid/gid mapping (only uid 0 exists inside)
+    // Fallback: single uid/gid mapping (only the sandbox user exists inside)
     std::fs::write(format!("/proc/{}/setgroups", pid), "deny")
         .context("Failed to write setgroups")?;
 
-    let uid_map = format!("0 {} 1\n", uid);
+    let uid_map = format!("{} {} 1\n", inner, uid);
     std::fs::write(format!("/proc/{}/uid_map", pid), &uid_map)
         .context("Failed to write uid_map")?;
 
-    let gid_map = format!("0 {} 1\n", gid);
+    let gid_map = format!("{} {} 1\n", inner, gid);
     std::fs::write(format!("/proc/{}/gid_map", pid), &gid_map)
         .context("Failed to write gid_map")?;
 
     Ok(())
 }
 
+/// `(inside, outside, count)` lines mapping `host` to `inner` plus, when
+/// available, the subordinate range `sub` for every other id.
+///
+/// `coop init` unpacks the rootfs with 0 → host and n → sub_start + n - 1, so
+/// for a non-zero `inner` the host id and `sub_start + inner - 1` swap places:
+/// every other id keeps the owner it has on disk, and files the image owns
+/// as root belong to the sandbox user. Returns the single host line if the
+/// range is too small to hold `inner`.
+fn id_map(inner: u64, host: u64, sub: Option<(u64, u64)>) -> Vec<(u64, u64, u64)> {
+    let Some((start, count)) = sub else {
+        return vec![(inner, host, 1)];
+    };
+    if inner == 0 {
+        return vec![(0, host, 1), (1, start, count)];
+    }
+    if count < inner {
+        return vec![(inner, host, 1)];
+    }
+    let mut map = vec![(0, start + inner - 1, 1)];
+    if inner > 1 {
+        map.push((1, start, inner - 1));
+    }
+    map.push((inner, host, 1));
+    if count > inner {
+        map.push((inner + 1, start + inner, count - inner));
+    }
+    map
+}
+
+/// Run newuidmap or newgidmap with the given mapping lines
+fn run_id_map(program: &str, pid: i32, map: &[(u64, u64, u64)]) -> bool {
+    let mut args = vec![pid.to_string()];
+    for (inside, outside, count) in map {
+        args.extend([inside.to_string(), outside.to_string(), count.to_string()]);
+    }
+    matches!(
+        std::process::Command::new(program).args(&args).status(),
+        Ok(s) if s.success()
+    )
+}
+
 /// Parse /etc/subuid or /etc/subgid for the current user.
 /// Returns (start, count) of the subordinate range.
 fn get_subid(path: &str) -> Result<(u64, u64)> {
@@ -481,7 +548,11 @@ fn child_setup_fs(
     persist_path: &Path,
     extra_mounts: &[(PathBuf, String)],
     sandbox_user: &str,
+    sandbox_uid: u32,
     sandbox_home: &str,
+    resolv_conf: &str,
+    hosts: &str,
+    secrets: &Secrets,
 ) -> Result<()> {
     // Make our mount namespace fully private so mounts don't propagate to the host
     nix::mount::mount(
@@ -525,8 +596,14 @@ fn child_setup_fs(
         sandbox_home,
     )?;
 
-    // Set up the sandbox user (uid 0 mapped to host user, named per config)
-    setup_sandbox_user(&root, sandbox_user, sandbox_home)?;
+    // Set up the sandbox user (the in-box uid the host user is mapped to)
+    setup_sandbox_user(&root, sandbox_user, sandbox_uid, sandbox_home)?;
+
+    // Name resolution (needed for OAuth callbacks, localhost binding, etc.)
+    std::fs::write(root.join("etc/resolv.conf"), resolv_conf)?;
+    std::fs::write(root.join("etc/hosts"), hosts)?;
+
+    setup_secrets(&root, secrets, sandbox_uid)?;
 
     // Create /dev/null, /dev/zero, /dev/random, /dev/urandom symlinks/nodes
     setup_dev_nodes(&root)?;
@@ -739,41 +816,121 @@ pub fn setup_bind_mounts(
 }
 
 /// Set up the sandbox user inside the namespace.
-/// Since we're in a user namespace with uid 0 mapped to the host user,
-/// we write /etc/passwd and /etc/group to name uid 0 as the configured user.
-fn setup_sandbox_user(root: &Path, user: &str, home: &str) -> Result<()> {
+/// The host user is mapped to `uid`, so we add the configured user with that
+/// uid (and a group with the same gid) to the image's /etc/passwd, /etc/group
+/// and /etc/shadow, replacing any entry with the same name or id.
+fn setup_sandbox_user(root: &Path, user: &str, uid: u32, home: &str) -> Result<()> {
     let etc = root.join("etc");
     std::fs::create_dir_all(&etc)?;
 
     let home_dir = root.join(home.trim_start_matches('/'));
     std::fs::create_dir_all(&home_dir)?;
+    // The image may already ship the home directory under another owner
+    if std::fs::metadata(&home_dir)?.uid() != uid {
+        chown_tree(&home_dir, uid)?;
+    }
 
+    let read = |file: &str| std::fs::read_to_string(etc.join(file)).unwrap_or_default();
     std::fs::write(
         etc.join("passwd"),
-        format!(
-            "{user}:x:0:0:{user}:{home}:/bin/sh\nnobody:x:65534:65534:nobody:/:/sbin/nologin\n"
+        merge_db_entry(
+            &read("passwd"),
+            user,
+            Some(uid),
+            &format!("{user}:x:{uid}:{uid}:{user}:{home}:/bin/sh"),
         ),
     )?;
-
     std::fs::write(
         etc.join("group"),
-        format!("{user}:x:0:\nnogroup:x:65534:\n"),
+        merge_db_entry(&read("group"), user, Some(uid), &format!("{user}:x:{uid}:")),
+    )?;
+    std::fs::write(
+        etc.join("shadow"),
+        merge_db_entry(&read("shadow"), user, None, &format!("{user}:!:0::::::")),
     )?;
 
-    std::fs::write(etc.join("shadow"), format!("{user}:!:0::::::\n"))?;
+    Ok(())
+}
 
-    // Hostname resolution (needed for OAuth callbacks, localhost binding, etc.)
-    std::fs::write(etc.join("hosts"), "127.0.0.1 localhost\n::1 localhost\n")?;
+/// Write file-delivered secrets to a tmpfs at /run/secrets that only the
+/// sandbox user can read, so they never touch the overlay upper dir on disk
+fn setup_secrets(root: &Path, secrets: &Secrets, uid: u32) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
 
-    // DNS resolution
-    std::fs::write(
-        etc.join("resolv.conf"),
-        "nameserver 8.8.8.8\nnameserver 8.8.4.4\n",
-    )?;
+    if secrets.files().next().is_none() {
+        return Ok(());
+    }
+    let dir = root.join(config::BOX_SECRETS_DIR.trim_start_matches('/'));
+    std::fs::create_dir_all(&dir)?;
+    nix::mount::mount(
+        Some("tmpfs"),
+        &dir,
+        Some("tmpfs"),
+        nix::mount::MsFlags::MS_NOSUID | nix::mount::MsFlags::MS_NODEV,
+        Some(format!("mode=0700,uid={uid},gid={uid},size=1m").as_str()),
+    )
+    .context("Failed to mount secrets tmpfs")?;
+
+    for secret in secrets.files() {
+        let path = dir.join(&secret.name);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o400)
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.write_all(secret.value.as_bytes())?;
+        chown_nofollow(&path, uid)?;
+    }
+    Ok(())
+}
 
+/// Append `entry` to a passwd-style file, dropping lines for the same `name`
+/// or (third field) `id`
+fn merge_db_entry(existing: &str, name: &str, id: Option<u32>, entry: &str) -> String {
+    let id = id.map(|id| id.to_string());
+    let mut out: String = existing
+        .lines()
+        .filter(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            fields[0] != name && (id.is_none() || fields.get(2).copied() != id.as_deref())
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect();
+    out.push_str(entry);
+    out.push('\n');
+    out
+}
+
+/// Chown a directory tree to `id:id`, without following symlinks or
+/// descending into mounts (persist dirs and volumes bound into the home)
+fn chown_tree(path: &Path, id: u32) -> Result<()> {
+    let dev = std::fs::symlink_metadata(path)?.dev();
+
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let meta = entry.metadata()?;
+            if meta.dev() != dev {
+                continue;
+            }
+            if meta.is_dir() {
+                stack.push(entry.path());
+            } else {
+                chown_nofollow(&entry.path(), id)?;
+            }
+        }
+        chown_nofollow(&dir, id)?;
+    }
     Ok(())
 }
 
+fn chown_nofollow(path: &Path, id: u32) -> Result<()> {
+    std::os::unix::fs::lchown(path, Some(id), Some(id))
+        .with_context(|| format!("Failed to chown {}", path.display()))
+}
+
 /// Create basic device nodes in /dev
 fn setup_dev_nodes(root: &Path) -> Result<()> {
     let dev = root.join("dev");
@@ -1013,6 +1170,53 @@ fn child_entrypoint(
     std::process::exit(1);
 }
 
+/// Bring up the loopback interface in the current network namespace.
+fn bring_up_loopback() -> Result<()> {
+    let sock = unsafe { nix::libc::socket(nix::libc::AF_INET, nix::libc::SOCK_DGRAM, 0) };
+    if sock < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create socket");
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(sock) };
+
+    let mut ifr: nix::libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(b"lo\0") {
+        *dst = *src as nix::libc::c_char;
+    }
+
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        if nix::libc::ioctl(sock.as_raw_fd(), nix::libc::SIOCGIFFLAGS, &mut ifr) < 0 {
+            return Err(std::io::Error::last_os_error()).context("SIOCGIFFLAGS failed");
+        }
+        ifr.ifr_ifru.ifru_flags |= (nix::libc::IFF_UP | nix::libc::IFF_RUNNING) as i16;
+        if nix::libc::ioctl(sock.as_raw_fd(), nix::libc::SIOCSIFFLAGS, &ifr) < 0 {
+            return Err(std::io::Error::last_os_error()).context("SIOCSIFFLAGS failed");
+        }
+    }
+
+    Ok(())
+}
+
+/// Open a TCP connection to `127.0.0.1:<port>` inside a pinned network namespace.
+///
+/// A socket belongs to the namespace it was created in, so we create it on a
+/// dedicated thread that has setns()'d into the box's network namespace. The
+/// thread exits afterwards, leaving the daemon's own threads untouched.
+/// Takes ownership of `ns_net_fd` (pass a dup of the session's pinned fd).
+pub fn connect_in_netns(ns_net_fd: RawFd, port: u16) -> Result<std::net::TcpStream> {
+    let net_ns = unsafe { std::fs::File::from_raw_fd(ns_net_fd) };
+
+    std::thread::spawn(move || -> Result<std::net::TcpStream> {
+        nix::sched::setns(&net_ns, CloneFlags::CLONE_NEWNET)
+            .context("setns(net) failed for port connection")?;
+        drop(net_ns);
+        std::net::TcpStream::connect(("127.0.0.1", port))
+            .with_context(|| format!("Failed to connect to port {} in box", port))
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("Port connection thread panicked"))?
+}
+
 /// Kill a session by sending SIGTERM to its namespace init process,
 /// then SIGKILL after a timeout.
 pub fn kill_session(pid: u32, force: bool) -> Result<()> {
@@ -1136,3 +1340,60 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_map() {
+        // Root inside: host id is 0, the range covers 1..
+        assert_eq!(
+            id_map(0, 1000, Some((100000, 65536))),
+            vec![(0, 1000, 1), (1, 100000, 65536)]
+        );
+        // Non-root: host id and the range id of `inner` trade places
+        assert_eq!(
+            id_map(1000, 501, Some((100000, 65536))),
+            vec![
+                (0, 100999, 1),
+                (1, 100000, 999),
+                (1000, 501, 1),
+                (1001, 101000, 64536),
+            ]
+        );
+        assert_eq!(
+            id_map(1, 501, Some((100000, 1))),
+            vec![(0, 100000, 1), (1, 501, 1)]
+        );
+        // Range too small, or none at all
+        assert_eq!(id_map(1000, 501, Some((100000, 10))), vec![(1000, 501, 1)]);
+        assert_eq!(id_map(1000, 501, None), vec![(1000, 501, 1)]);
+    }
+
+    #[test]
+    fn test_merge_db_entry() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      ubuntu:x:1000:1000::/home/ubuntu:/bin/bash\n\
+                      nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin\n";
+        let merged = merge_db_entry(
+            passwd,
+            "coop",
+            Some(1000),
+            "coop:x:1000:1000:coop:/home/coop:/bin/sh",
+        );
+        assert_eq!(
+            merged,
+            "root:x:0:0:root:/root:/bin/bash\n\
+             nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin\n\
+             coop:x:1000:1000:coop:/home/coop:/bin/sh\n"
+        );
+
+        let shadow = "root:*:19000:0:99999:7:::\ncoop:!:0::::::\n";
+        assert_eq!(
+            merge_db_entry(shadow, "coop", None, "coop:!:0::::::"),
+            "root:*:19000:0:99999:7:::\ncoop:!:0::::::\n"
+        );
+        assert_eq!(merge_db_entry("", "coop", None, "coop:x:0:"), "coop:x:0:\n");
+    }
+}
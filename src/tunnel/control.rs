@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use webrtc_data::data_channel::DataChannel;
+
+use super::devices::DeviceRegistry;
+use super::peer::ChannelOpener;
+use super::pty_channel::{self, PtyTarget};
+use crate::config::InputClient;
+use crate::daemon::audit::Actor;
+use crate::daemon::session::SessionManager;
+use crate::ipc::{
+    Command, DeviceScope, Response, ResponseData, ERR_FORBIDDEN, ERR_INVALID_COMMAND,
+    MAX_MESSAGE_SIZE,
+};
+
+/// What the peer on a tunnel may do
+#[derive(Clone)]
+pub struct Access {
+    pub scope: DeviceScope,
+    pub devices: Arc<DeviceRegistry>,
+    /// Paired device on the other end; `None` for a `coop tunnel` session
+    pub device: Option<String>,
+    /// Rendezvous server the tunnel was signaled through, needed to pair
+    pub signal: Option<String>,
+}
+
+/// Handle IPC-style JSON commands arriving on the control channel until it
+/// closes. Each command gets one JSON response on the same channel; `attach`
+/// and `shell` additionally open a PTY channel. `fingerprint` identifies the
+/// peer's DTLS certificate, which is what pairing remembers.
+pub async fn serve(
+    channel: Arc<DataChannel>,
+    channels: Arc<ChannelOpener>,
+    session_manager: Arc<SessionManager>,
+    access: Access,
+    fingerprint: String,
+) {
+    let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+    loop {
+        let n = match channel.read_data_channel(&mut buf).await {
+            Ok((n, _)) => n,
+            Err(e) => {
+                tracing::debug!(error = %e, "Tunnel control channel closed");
+                break;
+            }
+        };
+
+        let resp = match serde_json::from_slice::<Command>(&buf[..n]) {
+            Ok(cmd) => match denied(&cmd, &access.scope) {
+                Some(resp) => resp,
+                None => {
+                    handle_command(cmd, &channels, &session_manager, &access, &fingerprint).await
+                }
+            },
+            Err(e) => Response::err(ERR_INVALID_COMMAND, format!("Parse error: {}", e)),
+        };
+        let json = match serde_json::to_vec(&resp) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to encode tunnel response");
+                continue;
+            }
+        };
+        if let Err(e) = channel.write_data_channel(&Bytes::from(json), true).await {
+            tracing::debug!(error = %e, "Failed to send tunnel control response");
+            break;
+        }
+    }
+}
+
+/// Enforce the tunnel's scope: the error response for a command the peer
+/// may not run
+fn denied(cmd: &Command, scope: &DeviceScope) -> Option<Response> {
+    let (session, writes) = match cmd {
+        Command::SessionLs { session }
+        | Command::Attach { session, .. }
+        | Command::Logs { session, .. }
+        | Command::Messages { session, .. } => (session, false),
+        Command::Shell { session, .. }
+        | Command::SessionKill { session, .. }
+        | Command::Restart { session, .. }
+        | Command::SendInput { session, .. } => (session, true),
+        Command::Kill { session, all, .. } => {
+            if *all && !scope.sessions.is_empty() {
+                return Some(Response::err(
+                    ERR_FORBIDDEN,
+                    "Killing all boxes needs access to all boxes",
+                ));
+            }
+            (session, true)
+        }
+        _ => return None,
+    };
+    if writes && scope.read_only {
+        return Some(Response::err(ERR_FORBIDDEN, "This tunnel is read-only"));
+    }
+    if !scope.allows(session) {
+        return Some(Response::err(
+            ERR_FORBIDDEN,
+            format!("No access to box '{}'", session),
+        ));
+    }
+    None
+}
+
+async fn handle_command(
+    cmd: Command,
+    channels: &ChannelOpener,
+    session_manager: &Arc<SessionManager>,
+    access: &Access,
+    fingerprint: &str,
+) -> Response {
+    let actor = Actor::tunnel(access.device.as_deref());
+    // PTY channel to open once the command succeeds
+    let mut open = None;
+
+    let resp = match cmd {
+        Command::Ls => session_manager.list_sessions().await.map(|mut resp| {
+            if let Some(sessions) = resp.data.sessions.as_mut() {
+                sessions.retain(|s| access.scope.allows(&s.name));
+            }
+            resp
+        }),
+        Command::SessionLs { session } => session_manager.session_ls(&session).await,
+        Command::Attach {
+            session,
+            pty,
+            agent,
+            cols,
+            rows,
+        } => {
+            open = Some((session.clone(), cols, rows));
+            session_manager
+                .attach(&session, pty, agent.as_deref(), cols, rows)
+                .await
+        }
+        Command::Shell {
+            session,
+            command,
+            force_new,
+            cols,
+            rows,
+        } => {
+            open = Some((session.clone(), cols, rows));
+            session_manager
+                .spawn_shell(&session, command, force_new, cols, rows)
+                .await
+        }
+        Command::Kill {
+            session,
+            all,
+            force,
+        } => {
+            if all {
+                session_manager.kill_all(force, &actor).await
+            } else {
+                session_manager.kill_session(&session, force, &actor).await
+            }
+        }
+        Command::SessionKill { session, pty } => {
+            session_manager.kill_pty(&session, pty, &actor).await
+        }
+        Command::Logs {
+            session,
+            pty,
+            service,
+            tail_lines,
+            follow: false,
+        } => {
+            session_manager
+                .get_logs(&session, pty, service.as_deref(), tail_lines, true)
+                .await
+        }
+        Command::Messages {
+            session,
+            since,
+            follow: false,
+        } => session_manager.messages(&session, since, true).await,
+        Command::Restart { session, pty } => {
+            session_manager.restart_pty(&session, pty, &actor).await
+        }
+        Command::SendInput {
+            session,
+            pty,
+            text,
+            keys,
+        } => {
+            session_manager
+                .send_input(
+                    &session,
+                    pty,
+                    actor.clone(),
+                    InputClient::Tunnel,
+                    &text,
+                    &keys,
+                )
+                .await
+        }
+        Command::Pair { name } => pair(&name, access, fingerprint).await,
+        _ => Ok(Response::err(
+            ERR_INVALID_COMMAND,
+            "Command not available over the tunnel",
+        )),
+    };
+    let resp = Response::from_result(resp);
+
+    if let (true, Some((session, cols, rows)), Some(pty)) = (resp.ok, open, resp.data.pty) {
+        let label = format!("coop-pty-{}-{}", session, pty);
+        match channels.open(&label).await {
+            Ok(channel) => {
+                let target = PtyTarget {
+                    session,
+                    pty,
+                    cols,
+                    rows,
+                    read_only: access.scope.read_only,
+                    actor: actor.clone(),
+                };
+                pty_channel::bridge(channel, target, session_manager.clone());
+            }
+            Err(e) => return Response::err("INTERNAL_ERROR", format!("{:#}", e)),
+        }
+    }
+
+    resp
+}
+
+/// Remember the peer as a paired device with this tunnel's scope
+async fn pair(name: &str, access: &Access, fingerprint: &str) -> anyhow::Result<Response> {
+    if access.device.is_some() {
+        return Ok(Response::err(
+            ERR_INVALID_COMMAND,
+            "This device is already paired",
+        ));
+    }
+    let Some(signal) = &access.signal else {
+        return Ok(Response::err(
+            ERR_INVALID_COMMAND,
+            "Pairing needs a tunnel opened with --signal",
+        ));
+    };
+    let pairing = access
+        .devices
+        .pair(name, fingerprint, access.scope.clone(), signal)
+        .await?;
+    Ok(Response::ok_with(ResponseData {
+        pairing: Some(pairing),
+        ..Default::default()
+    }))
+}
@@ -0,0 +1,445 @@
+//! Paired tunnel devices (see `coop tunnel devices`).
+//!
+//! A device is a browser that asked to be remembered over an established
+//! tunnel. It is identified by the fingerprint of its persistent DTLS
+//! certificate: the connect page keeps that keypair in IndexedDB, and the
+//! DTLS handshake proves it still holds the private key. While the daemon
+//! runs it keeps a fresh offer for each device on the device's rendezvous
+//! server, sealed with a per-device key, so the browser can reconnect
+//! without a new QR scan. Answers from any other certificate are refused.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+use super::control::Access;
+use super::peer::{Tunnel, TunnelConfig, CONNECT_TIMEOUT};
+use super::rendezvous::{self, Rendezvous, DEVICE_CODE_LEN};
+use super::sdp::SessionDescription;
+use super::signal_server::SLOT_TTL;
+use super::signaling::{self, ConnectionEnvelope};
+use crate::config;
+use crate::daemon::session::SessionManager;
+use crate::ipc::{DeviceInfo, DeviceScope, Pairing, Response, ResponseData, ERR_DEVICE_NOT_FOUND};
+
+/// Back-off after a listener error (signal server down, failed connect)
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairedDevice {
+    id: String,
+    name: String,
+    fingerprint: String,
+    scope: DeviceScope,
+    signal: String,
+    code: String,
+    key: String,
+    paired: u64,
+    #[serde(default)]
+    last_seen: Option<u64>,
+}
+
+impl PairedDevice {
+    fn info(&self, connected: bool) -> DeviceInfo {
+        DeviceInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            fingerprint: self.fingerprint.clone(),
+            scope: self.scope.clone(),
+            signal: self.signal.clone(),
+            paired: self.paired,
+            last_seen: self.last_seen,
+            connected,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RegistryState {
+    devices: Vec<PairedDevice>,
+    /// Cancelling stops a device's listener and drops its tunnel
+    listeners: HashMap<String, CancellationToken>,
+    connected: HashSet<String>,
+}
+
+pub struct DeviceRegistry {
+    /// `None` keeps devices in memory only
+    path: Option<PathBuf>,
+    state: Mutex<RegistryState>,
+    session_manager: Arc<SessionManager>,
+    /// Used for every reconnect tunnel
+    tunnel_config: TunnelConfig,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl DeviceRegistry {
+    /// Load the paired devices from ~/.coop/devices.json
+    pub fn load(session_manager: Arc<SessionManager>) -> Self {
+        let path = config::devices_path().ok();
+        let devices = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            state: Mutex::new(RegistryState {
+                devices,
+                ..Default::default()
+            }),
+            session_manager,
+            tunnel_config: TunnelConfig::new(None, false, false),
+        }
+    }
+
+    /// Registry that forgets devices with the daemon and reconnects over
+    /// loopback only
+    #[cfg(test)]
+    pub fn in_memory(session_manager: Arc<SessionManager>) -> Self {
+        Self {
+            path: None,
+            state: Mutex::default(),
+            session_manager,
+            tunnel_config: TunnelConfig::new(None, false, true),
+        }
+    }
+
+    /// Start listening for every paired device
+    pub async fn start(self: &Arc<Self>) {
+        let mut state = self.state.lock().await;
+        let ids: Vec<String> = state.devices.iter().map(|d| d.id.clone()).collect();
+        for id in ids {
+            self.spawn_listener(&mut state, id);
+        }
+    }
+
+    /// Whether a paired device is connected (keeps the daemon alive)
+    pub async fn is_busy(&self) -> bool {
+        !self.state.lock().await.connected.is_empty()
+    }
+
+    /// Remember the peer with `fingerprint`, replacing any earlier pairing
+    /// of the same certificate, and start listening for it
+    pub async fn pair(
+        self: &Arc<Self>,
+        name: &str,
+        fingerprint: &str,
+        scope: DeviceScope,
+        signal: &str,
+    ) -> Result<Pairing> {
+        let key: [u8; 32] = rand::random();
+        let device = PairedDevice {
+            id: uuid::Uuid::new_v4().to_string()[..8].to_string(),
+            name: name.to_string(),
+            fingerprint: fingerprint.to_string(),
+            scope,
+            signal: signal.trim_end_matches('/').to_string(),
+            code: rendezvous::generate_code(DEVICE_CODE_LEN),
+            key: rendezvous::encode_key(&key),
+            paired: now(),
+            last_seen: Some(now()),
+        };
+        let pairing = Pairing {
+            device_id: device.id.clone(),
+            machine_id: signaling::get_machine_id()?,
+            signal: device.signal.clone(),
+            code: device.code.clone(),
+            key: device.key.clone(),
+        };
+
+        let mut state = self.state.lock().await;
+        let replaced: Vec<String> = state
+            .devices
+            .iter()
+            .filter(|d| d.fingerprint == fingerprint)
+            .map(|d| d.id.clone())
+            .collect();
+        for id in &replaced {
+            if let Some(listener) = state.listeners.remove(id) {
+                listener.cancel();
+            }
+        }
+        state.devices.retain(|d| d.fingerprint != fingerprint);
+        state.devices.push(device.clone());
+        self.save(&state)?;
+        self.spawn_listener(&mut state, device.id.clone());
+
+        tracing::info!(device = %device.id, name = %device.name, "Paired tunnel device");
+        Ok(pairing)
+    }
+
+    pub async fn list(&self) -> Result<Response> {
+        let state = self.state.lock().await;
+        let devices = state
+            .devices
+            .iter()
+            .map(|d| d.info(state.connected.contains(&d.id)))
+            .collect();
+        Ok(Response::ok_with(ResponseData {
+            devices: Some(devices),
+            ..Default::default()
+        }))
+    }
+
+    /// Forget a device and drop its connection, if any
+    pub async fn revoke(&self, id: &str) -> Result<Response> {
+        let mut state = self.state.lock().await;
+        let Some(index) = state.devices.iter().position(|d| d.id == id) else {
+            return Ok(Response::err(
+                ERR_DEVICE_NOT_FOUND,
+                format!("No paired device '{}'", id),
+            ));
+        };
+        let device = state.devices.remove(index);
+        self.save(&state)?;
+        if let Some(listener) = state.listeners.remove(id) {
+            listener.cancel();
+        }
+        tracing::info!(device = %id, name = %device.name, "Revoked tunnel device");
+        Ok(Response::ok_with(ResponseData {
+            devices: Some(vec![device.info(false)]),
+            ..Default::default()
+        }))
+    }
+
+    fn save(&self, state: &RegistryState) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Holds rendezvous keys, so owner-only like the socket
+        let tmp = path.with_extension("json.tmp");
+        {
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            file.write_all(&serde_json::to_vec_pretty(&state.devices)?)?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn spawn_listener(self: &Arc<Self>, state: &mut RegistryState, id: String) {
+        let cancel = CancellationToken::new();
+        state.listeners.insert(id.clone(), cancel.clone());
+        let registry = Arc::clone(self);
+        tokio::spawn(async move { registry.listen(id, cancel).await });
+    }
+
+    /// Keep an offer up for the device until it is revoked, serving one
+    /// connection at a time
+    async fn listen(self: Arc<Self>, id: String, cancel: CancellationToken) {
+        while !cancel.is_cancelled() {
+            let device = {
+                let state = self.state.lock().await;
+                state.devices.iter().find(|d| d.id == id).cloned()
+            };
+            let Some(device) = device else {
+                return;
+            };
+
+            if let Err(e) = self.accept(&device, &cancel).await {
+                tracing::warn!(device = %id, error = %format!("{:#}", e), "Paired device listener error");
+                tokio::select! {
+                    _ = cancel.cancelled() => {}
+                    _ = tokio::time::sleep(RETRY_DELAY) => {}
+                }
+            }
+        }
+    }
+
+    async fn accept(
+        self: &Arc<Self>,
+        device: &PairedDevice,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let access = Access {
+            scope: device.scope.clone(),
+            devices: Arc::clone(self),
+            device: Some(device.id.clone()),
+            signal: None,
+        };
+        let mut tunnel =
+            Tunnel::new(self.session_manager.clone(), &self.tunnel_config, access).await?;
+        let result = self.serve(&mut tunnel, device, cancel).await;
+        tunnel.close().await;
+        result
+    }
+
+    /// Publish an offer, wait for the device's answer while the slot lives,
+    /// then hold the connection until it ends
+    async fn serve(
+        &self,
+        tunnel: &mut Tunnel,
+        device: &PairedDevice,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let key = rendezvous::decode_key(&device.key)?;
+        let rendezvous = Rendezvous::with_code(&device.signal, device.code.clone(), key)?;
+        let envelope = ConnectionEnvelope::new(tunnel.offer().await?)?;
+        rendezvous.publish_offer(&envelope).await?;
+
+        let deadline = Instant::now() + SLOT_TTL;
+        let answer = loop {
+            if Instant::now() >= deadline {
+                // The slot expired unanswered; publish a fresh offer
+                return Ok(());
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => return Ok(()),
+                answer = rendezvous.poll_answer() => {
+                    if let Some(answer) = answer? {
+                        break answer;
+                    }
+                }
+            }
+        };
+
+        let fingerprint = SessionDescription::parse(&answer)?.fingerprint_hex();
+        if fingerprint != device.fingerprint {
+            anyhow::bail!(
+                "Answer for device {} came from another certificate",
+                device.id
+            );
+        }
+        tunnel
+            .connect(&answer, CONNECT_TIMEOUT)
+            .await
+            .context("Paired device failed to connect")?;
+
+        tracing::info!(device = %device.id, name = %device.name, "Paired device connected");
+        self.set_connected(&device.id, true).await;
+        tokio::select! {
+            _ = tunnel.closed() => {}
+            _ = cancel.cancelled() => {}
+        }
+        self.set_connected(&device.id, false).await;
+        tracing::info!(device = %device.id, "Paired device disconnected");
+        Ok(())
+    }
+
+    async fn set_connected(&self, id: &str, connected: bool) {
+        let mut state = self.state.lock().await;
+        if connected {
+            state.connected.insert(id.to_string());
+        } else {
+            state.connected.remove(id);
+        }
+        if let Some(device) = state.devices.iter_mut().find(|d| d.id == id) {
+            device.last_seen = Some(now());
+        }
+        if let Err(e) = self.save(&state) {
+            tracing::warn!(error = %e, "Failed to save paired devices");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::ERR_FORBIDDEN;
+    use crate::tunnel::peer::Peer;
+    use crate::tunnel::signal_server;
+    use crate::tunnel::signaling::AnswerEnvelope;
+    use bytes::Bytes;
+    use webrtc_data::data_channel::{Config as ChannelConfig, DataChannel};
+
+    #[tokio::test]
+    async fn test_paired_device_reconnects() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(signal_server::serve(listener));
+
+        let registry = Arc::new(DeviceRegistry::in_memory(Arc::new(SessionManager::new())));
+        let browser = Peer::new(&TunnelConfig::new(None, false, true))
+            .await
+            .unwrap();
+        let answer = browser.describe("active").await.unwrap();
+        let scope = DeviceScope {
+            sessions: vec!["web".to_string()],
+            read_only: true,
+        };
+        let pairing = registry
+            .pair("phone", &answer.fingerprint_hex(), scope, &server)
+            .await
+            .unwrap();
+
+        // Reconnect: pick up the standing offer and answer with the paired certificate
+        let key = rendezvous::decode_key(&pairing.key).unwrap();
+        let http = reqwest::Client::new();
+        let slot = format!("{}/v1/{}", server, pairing.code);
+        let sealed = loop {
+            let resp = http.get(format!("{}/offer", slot)).send().await.unwrap();
+            if resp.status().is_success() {
+                break resp.text().await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        let offer: ConnectionEnvelope = rendezvous::open(&key, &sealed).unwrap();
+        let offer = SessionDescription::parse(&offer.sdp).unwrap();
+        let sealed_answer = rendezvous::seal(
+            &key,
+            &AnswerEnvelope {
+                sdp: answer.to_sdp(),
+            },
+        )
+        .unwrap();
+        http.put(format!("{}/answer", slot))
+            .body(sealed_answer)
+            .send()
+            .await
+            .unwrap();
+
+        let association = browser.connect(&offer, false).await.unwrap();
+        let control = DataChannel::accept(
+            &association,
+            ChannelConfig::default(),
+            &[] as &[DataChannel],
+        )
+        .await
+        .unwrap();
+
+        // The device keeps the scope it was paired with
+        control
+            .write_data_channel(
+                &Bytes::from_static(br#"{"cmd":"shell","session":"web"}"#),
+                true,
+            )
+            .await
+            .unwrap();
+        let mut buf = vec![0; 4096];
+        let (n, _) = control.read_data_channel(&mut buf).await.unwrap();
+        let resp: Response = serde_json::from_slice(&buf[..n]).unwrap();
+        assert_eq!(resp.error.as_deref(), Some(ERR_FORBIDDEN));
+
+        // Revoking drops the connection
+        assert!(registry.revoke(&pairing.device_id).await.unwrap().ok);
+        let closed =
+            tokio::time::timeout(Duration::from_secs(10), control.read_data_channel(&mut buf))
+                .await
+                .unwrap();
+        assert!(closed.is_err() || closed.unwrap().0 == 0);
+
+        let _ = association.close().await;
+        browser.close().await;
+    }
+}
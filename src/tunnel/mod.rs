@@ -1,4 +1,15 @@
-// WebRTC tunnel implementation
-// TODO: implement P2P remote access via WebRTC DataChannels
+//! WebRTC tunnel: P2P remote access over DataChannels (see docs/rfc/10-tunnel.md)
 
+mod control;
+mod devices;
+mod peer;
+mod pty_channel;
+mod rendezvous;
+mod sdp;
+pub mod signal_server;
 pub mod signaling;
+
+pub use control::Access;
+pub use devices::DeviceRegistry;
+pub use peer::{Tunnel, TunnelConfig, CONNECT_TIMEOUT};
+pub use rendezvous::Rendezvous;
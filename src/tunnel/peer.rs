@@ -0,0 +1,390 @@
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio::sync::{mpsc, watch, Notify};
+use webrtc_data::data_channel::{Config as ChannelConfig, DataChannel};
+use webrtc_dtls::config::{ClientAuthType, Config as DtlsConfig, ExtendedMasterSecretType};
+use webrtc_dtls::conn::DTLSConn;
+use webrtc_dtls::crypto::Certificate;
+use webrtc_ice::agent::agent_config::AgentConfig;
+use webrtc_ice::agent::Agent;
+use webrtc_ice::candidate::candidate_base::unmarshal_candidate;
+use webrtc_ice::candidate::{Candidate, CandidateType};
+use webrtc_ice::mdns::MulticastDnsMode;
+use webrtc_ice::network_type::NetworkType;
+use webrtc_ice::state::ConnectionState;
+use webrtc_ice::udp_mux::{UDPMux, UDPMuxDefault, UDPMuxParams};
+use webrtc_ice::udp_network::UDPNetwork;
+use webrtc_ice::url::Url;
+use webrtc_sctp::association::{Association, Config as SctpConfig};
+use webrtc_util::Conn;
+
+use super::control::{self, Access};
+use super::sdp::SessionDescription;
+use crate::daemon::session::SessionManager;
+
+/// Default STUN servers (see RFC 10.4.1)
+pub const DEFAULT_STUN_SERVERS: &[&str] = &[
+    "stun:stun.l.google.com:19302",
+    "stun:stun1.l.google.com:19302",
+];
+
+/// Label of the control DataChannel
+pub const CONTROL_CHANNEL: &str = "coop-control";
+
+/// How long ICE, DTLS and SCTP get to connect after the answer is applied
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default)]
+pub struct TunnelConfig {
+    pub stun_servers: Vec<String>,
+    /// Only offer a 127.0.0.1 candidate (same-machine peers, tests)
+    pub loopback: bool,
+}
+
+impl TunnelConfig {
+    pub fn new(stun: Option<String>, no_stun: bool, loopback: bool) -> Self {
+        let stun_servers = match stun {
+            _ if no_stun || loopback => Vec::new(),
+            Some(server) => vec![server],
+            None => DEFAULT_STUN_SERVERS.iter().map(|s| s.to_string()).collect(),
+        };
+        Self {
+            stun_servers,
+            loopback,
+        }
+    }
+}
+
+/// A WebRTC connection exposing the daemon to one remote browser. The
+/// daemon is always the offerer: ICE-controlling, with the browser picking
+/// the DTLS role in its answer.
+pub struct Tunnel {
+    peer: Peer,
+    session_manager: Arc<SessionManager>,
+    access: Access,
+    association: Option<Arc<Association>>,
+    /// Notified when the control channel ends
+    control_closed: Arc<Notify>,
+}
+
+impl Tunnel {
+    pub async fn new(
+        session_manager: Arc<SessionManager>,
+        config: &TunnelConfig,
+        access: Access,
+    ) -> Result<Self> {
+        Ok(Self {
+            peer: Peer::new(config).await?,
+            session_manager,
+            access,
+            association: None,
+            control_closed: Arc::new(Notify::new()),
+        })
+    }
+
+    /// The SDP offer, carrying every gathered candidate (there is no trickle
+    /// channel to send more later)
+    pub async fn offer(&self) -> Result<String> {
+        Ok(self.peer.describe("actpass").await?.to_sdp())
+    }
+
+    /// Apply the browser's SDP answer, connect and open the control channel
+    pub async fn connect(&mut self, answer: &str, timeout: Duration) -> Result<()> {
+        let remote = SessionDescription::parse(answer)?;
+        let association = tokio::time::timeout(timeout, self.peer.connect(&remote, true))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for the peer to connect"))??;
+
+        // Per RFC 8832 the DTLS client uses even stream ids, the server odd
+        let first_id = if remote.setup == "active" { 1 } else { 0 };
+        let channels = Arc::new(ChannelOpener {
+            association: association.clone(),
+            next_id: AtomicU16::new(first_id),
+        });
+        let control = channels.open(CONTROL_CHANNEL).await?;
+        self.association = Some(association);
+
+        let session_manager = self.session_manager.clone();
+        let access = self.access.clone();
+        let fingerprint = remote.fingerprint_hex();
+        let control_closed = self.control_closed.clone();
+        tokio::spawn(async move {
+            control::serve(control, channels, session_manager, access, fingerprint).await;
+            control_closed.notify_one();
+        });
+        Ok(())
+    }
+
+    /// Resolve once the peer disconnects or the connection fails
+    pub async fn closed(&mut self) {
+        tokio::select! {
+            _ = self.peer.ice_state.wait_for(|state| {
+                matches!(
+                    state,
+                    ConnectionState::Disconnected | ConnectionState::Failed | ConnectionState::Closed
+                )
+            }) => {}
+            _ = self.control_closed.notified() => {}
+        }
+    }
+
+    pub async fn close(&self) {
+        if let Some(association) = &self.association {
+            let _ = association.close().await;
+        }
+        self.peer.close().await;
+    }
+}
+
+/// Opens daemon-initiated DataChannels on the tunnel's SCTP association
+pub(super) struct ChannelOpener {
+    association: Arc<Association>,
+    next_id: AtomicU16,
+}
+
+impl ChannelOpener {
+    /// Open a reliable, ordered channel
+    pub(super) async fn open(&self, label: &str) -> Result<Arc<DataChannel>> {
+        let id = self.next_id.fetch_add(2, Ordering::SeqCst);
+        let config = ChannelConfig {
+            label: label.to_string(),
+            ..Default::default()
+        };
+        let channel = DataChannel::dial(&self.association, id, config)
+            .await
+            .with_context(|| format!("Failed to open channel {}", label))?;
+        Ok(Arc::new(channel))
+    }
+}
+
+/// One side of a data-only WebRTC connection: ICE, then DTLS, then SCTP
+pub(crate) struct Peer {
+    agent: Arc<Agent>,
+    certificate: Certificate,
+    /// Loopback socket shared by all ICE candidates, if any
+    udp_mux: Option<Arc<UDPMuxDefault>>,
+    ice_state: watch::Receiver<ConnectionState>,
+}
+
+impl Peer {
+    /// In loopback mode every candidate is rewritten to 127.0.0.1 and served
+    /// from one socket bound there, so two peers on the same machine connect
+    /// without touching the network
+    pub(crate) async fn new(config: &TunnelConfig) -> Result<Self> {
+        let urls = config
+            .stun_servers
+            .iter()
+            .map(|s| Url::parse_url(s).with_context(|| format!("Invalid STUN server '{}'", s)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut agent_config = AgentConfig {
+            urls,
+            network_types: vec![NetworkType::Udp4, NetworkType::Udp6],
+            ..Default::default()
+        };
+        let udp_mux = if config.loopback {
+            let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await?;
+            let mux = UDPMuxDefault::new(UDPMuxParams::new(socket));
+            agent_config.udp_network = UDPNetwork::Muxed(mux.clone());
+            agent_config.network_types = vec![NetworkType::Udp4];
+            agent_config.nat_1to1_ips = vec!["127.0.0.1".to_string()];
+            agent_config.nat_1to1_ip_candidate_type = CandidateType::Host;
+            agent_config.multicast_dns_mode = MulticastDnsMode::Disabled;
+            Some(mux)
+        } else {
+            // Browsers hide host addresses behind mDNS names
+            agent_config.multicast_dns_mode = MulticastDnsMode::QueryOnly;
+            None
+        };
+
+        let agent = Arc::new(Agent::new(agent_config).await?);
+        let (state_tx, ice_state) = watch::channel(ConnectionState::New);
+        agent.on_connection_state_change(Box::new(move |state| {
+            tracing::debug!(state = %state, "Tunnel ICE state");
+            let _ = state_tx.send(state);
+            Box::pin(async {})
+        }));
+
+        Ok(Self {
+            agent,
+            certificate: Certificate::generate_self_signed(vec!["coop".to_string()])?,
+            udp_mux,
+            ice_state,
+        })
+    }
+
+    /// Gather candidates and describe this peer
+    pub(crate) async fn describe(&self, setup: &str) -> Result<SessionDescription> {
+        let (candidate_tx, mut candidate_rx) = mpsc::unbounded_channel();
+        self.agent.on_candidate(Box::new(move |candidate| {
+            let _ = candidate_tx.send(candidate);
+            Box::pin(async {})
+        }));
+        self.agent.gather_candidates()?;
+
+        // `None` marks the end of gathering
+        let mut candidates = Vec::new();
+        while let Some(Some(candidate)) = candidate_rx.recv().await {
+            candidates.push(candidate.marshal());
+        }
+        if candidates.is_empty() {
+            bail!("No ICE candidates found (is there a network interface?)");
+        }
+
+        let (ice_ufrag, ice_pwd) = self.agent.get_local_user_credentials().await;
+        Ok(SessionDescription {
+            ice_ufrag,
+            ice_pwd,
+            fingerprint: format!("sha-256 {}", fingerprint(&self.certificate)),
+            setup: setup.to_string(),
+            candidates,
+        })
+    }
+
+    /// Connect to the remote peer. The DTLS certificate must match the
+    /// fingerprint in its description, which is what authenticates it.
+    pub(crate) async fn connect(
+        &self,
+        remote: &SessionDescription,
+        controlling: bool,
+    ) -> Result<Arc<Association>> {
+        for raw in &remote.candidates {
+            match unmarshal_candidate(raw) {
+                Ok(candidate) => {
+                    let candidate: Arc<dyn Candidate + Send + Sync> = Arc::new(candidate);
+                    self.agent.add_remote_candidate(&candidate)?;
+                }
+                Err(e) => {
+                    tracing::debug!(candidate = %raw, error = %e, "Skipping remote candidate")
+                }
+            }
+        }
+
+        // Dropping the sender would cancel connectivity checks
+        let (_cancel_tx, cancel_rx) = mpsc::channel(1);
+        let (ufrag, pwd) = (remote.ice_ufrag.clone(), remote.ice_pwd.clone());
+        let conn: Arc<dyn Conn + Send + Sync> = if controlling {
+            self.agent.dial(cancel_rx, ufrag, pwd).await?
+        } else {
+            self.agent.accept(cancel_rx, ufrag, pwd).await?
+        };
+
+        let expected = remote.fingerprint_hex();
+        let dtls_config = DtlsConfig {
+            certificates: vec![self.certificate.clone()],
+            extended_master_secret: ExtendedMasterSecretType::Require,
+            client_auth: ClientAuthType::RequireAnyClientCert,
+            // Certificates are self-signed; the fingerprint check replaces CA verification
+            insecure_skip_verify: true,
+            verify_peer_certificate: Some(Arc::new(move |certs: &[Vec<u8>], _| {
+                match certs.first() {
+                    Some(cert) if hex_fingerprint(cert) == expected => Ok(()),
+                    _ => Err(webrtc_dtls::Error::Other(
+                        "peer certificate does not match the SDP fingerprint".to_string(),
+                    )),
+                }
+            })),
+            ..Default::default()
+        };
+        // The offerer's `actpass` lets the answerer choose; `active` is the client
+        let is_client = remote.setup != "active";
+        let dtls = DTLSConn::new(conn, dtls_config, is_client, None)
+            .await
+            .context("DTLS handshake failed")?;
+
+        // WebRTC peers both start SCTP as clients; the INIT collision is resolved by SCTP
+        let association = Association::client(SctpConfig {
+            net_conn: Arc::new(dtls),
+            max_receive_buffer_size: 0,
+            max_message_size: 0,
+            name: "coop-tunnel".to_string(),
+        })
+        .await
+        .context("SCTP association failed")?;
+        Ok(Arc::new(association))
+    }
+
+    pub(crate) async fn close(&self) {
+        let _ = self.agent.close().await;
+        if let Some(mux) = &self.udp_mux {
+            let _ = mux.close().await;
+        }
+    }
+}
+
+fn fingerprint(certificate: &Certificate) -> String {
+    certificate
+        .certificate
+        .first()
+        .map(|cert| hex_fingerprint(&cert.0))
+        .unwrap_or_default()
+}
+
+/// SHA-256 in SDP fingerprint form: `AB:CD:...`
+fn hex_fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::Response;
+    use crate::tunnel::DeviceRegistry;
+    use bytes::Bytes;
+
+    #[tokio::test]
+    async fn test_loopback_tunnel() {
+        let config = TunnelConfig::new(None, false, true);
+        let session_manager = Arc::new(SessionManager::new());
+        let access = Access {
+            scope: Default::default(),
+            devices: Arc::new(DeviceRegistry::in_memory(session_manager.clone())),
+            device: None,
+            signal: None,
+        };
+        let mut tunnel = Tunnel::new(session_manager, &config, access).await.unwrap();
+        let offer = SessionDescription::parse(&tunnel.offer().await.unwrap()).unwrap();
+        assert!(offer.candidates.iter().all(|c| c.contains("127.0.0.1")));
+
+        // Play the browser: answer as the DTLS client
+        let browser = Peer::new(&config).await.unwrap();
+        let answer = browser.describe("active").await.unwrap().to_sdp();
+        let (connected, association) = tokio::join!(
+            tunnel.connect(&answer, Duration::from_secs(10)),
+            browser.connect(&offer, false)
+        );
+        connected.unwrap();
+        let association = association.unwrap();
+
+        let control = DataChannel::accept(
+            &association,
+            ChannelConfig::default(),
+            &[] as &[DataChannel],
+        )
+        .await
+        .unwrap();
+        assert_eq!(control.config.label, CONTROL_CHANNEL);
+
+        control
+            .write_data_channel(&Bytes::from_static(br#"{"cmd":"ls"}"#), true)
+            .await
+            .unwrap();
+        let mut buf = vec![0; 4096];
+        let (n, _) = control.read_data_channel(&mut buf).await.unwrap();
+        let resp: Response = serde_json::from_slice(&buf[..n]).unwrap();
+        assert!(resp.ok);
+        assert_eq!(resp.data.sessions.map(|s| s.len()), Some(0));
+
+        tunnel.close().await;
+        let _ = association.close().await;
+        browser.close().await;
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::codec::{Decoder, Encoder};
+use webrtc_data::data_channel::DataChannel;
+
+use crate::config::InputClient;
+use crate::daemon::audit::Actor;
+use crate::daemon::session::{PtyHandle, SessionManager};
+use crate::ipc::{
+    Command, DaemonEvent, StreamCodec, StreamFrame, FRAME_CONTROL, FRAME_PTY_DATA, MAX_MESSAGE_SIZE,
+};
+use crate::pty::{approval, redact};
+
+/// Largest PTY payload per DataChannel message. Browsers only guarantee
+/// 16KB messages, so scrollback replay and output bursts are split.
+const MAX_CHUNK: usize = 16 * 1024;
+
+pub struct PtyTarget {
+    pub session: String,
+    pub pty: u32,
+    pub cols: u16,
+    pub rows: u16,
+    /// Drop input and resizes (read-only scope)
+    pub read_only: bool,
+    pub actor: Actor,
+}
+
+/// Bridge a PTY DataChannel to a PTY, using the same broadcast/scrollback
+/// path as stream mode and the WebSocket handler
+pub fn bridge(channel: Arc<DataChannel>, target: PtyTarget, session_manager: Arc<SessionManager>) {
+    tokio::spawn(async move {
+        tracing::info!(session = %target.session, pty = target.pty, "Tunnel PTY channel opened");
+        session_manager.add_web_client(&target.session).await;
+
+        if let Err(e) = run(&channel, &target, &session_manager).await {
+            tracing::error!(session = %target.session, pty = target.pty, error = %e, "Tunnel PTY channel error");
+        }
+
+        session_manager.remove_web_client(&target.session).await;
+        let _ = channel.close().await;
+        tracing::info!(session = %target.session, pty = target.pty, "Tunnel PTY channel closed");
+    });
+}
+
+async fn run(
+    channel: &Arc<DataChannel>,
+    target: &PtyTarget,
+    session_manager: &SessionManager,
+) -> Result<()> {
+    // Output is subscribed before replaying scrollback so nothing is missed
+    let PtyHandle {
+        input,
+        output: mut output_rx,
+        scrollback,
+        redact,
+        filter: mut input_filter,
+        approval: mut gate,
+    } = session_manager
+        .get_pty_handle(
+            &target.session,
+            target.pty,
+            target.actor.clone(),
+            InputClient::Tunnel,
+        )
+        .await?;
+
+    // Read the channel on its own task; the receiver closes with the channel
+    let (input_tx, mut input_rx) = mpsc::channel::<Bytes>(64);
+    let reader = channel.clone();
+    let reader_task = tokio::spawn(async move {
+        let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
+        while let Ok((n, _)) = reader.read_data_channel(&mut buf).await {
+            if input_tx
+                .send(Bytes::copy_from_slice(&buf[..n]))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    let _reader_guard = AbortOnDrop(reader_task);
+
+    if !target.read_only {
+        input.resize(target.cols, target.rows);
+    }
+
+    // Output is masked as for web clients
+    let mut redactor = redact.stream();
+
+    if let Some(sb) = &scrollback {
+        let data = redactor.push(&sb.lock().await);
+        send_pty_data(channel, &data).await?;
+    }
+
+    loop {
+        let flush_at = redactor.deadline();
+        tokio::select! {
+            // Browser -> daemon: one tagged frame per message
+            data = input_rx.recv() => {
+                let Some(data) = data else {
+                    break;
+                };
+                let Some(frame) = StreamCodec.decode(&mut BytesMut::from(&data[..]))? else {
+                    tracing::debug!("Ignoring truncated tunnel frame");
+                    continue;
+                };
+                match frame.frame_type {
+                    FRAME_PTY_DATA if target.read_only => {}
+                    FRAME_PTY_DATA => {
+                        if let Some(warning) = input.write_filtered(input_filter.as_mut(), gate.as_mut(), &frame.payload) {
+                            send_pty_data(channel, warning).await?;
+                        }
+                    }
+                    FRAME_CONTROL => match serde_json::from_slice::<Command>(&frame.payload) {
+                        Ok(Command::Resize { cols, rows }) if !target.read_only => {
+                            input.resize(cols, rows);
+                        }
+                        Ok(Command::Detach) => {
+                            send_event(channel, &DaemonEvent::Detached).await?;
+                            break;
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+
+            // PTY output -> browser
+            data = output_rx.recv() => {
+                match data {
+                    Ok(bytes) => send_pty_data(channel, &redactor.push(&bytes)).await?,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!(dropped = n, "Tunnel client lagging, dropped frames");
+                        session_manager.record_lag(n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        send_pty_data(channel, &redactor.finish()).await?;
+                        send_event(channel, &DaemonEvent::PtyExited { code: 0 }).await?;
+                        break;
+                    }
+                }
+            }
+
+            _ = redact::sleep_until(flush_at) => {
+                send_pty_data(channel, &redactor.flush()).await?;
+            }
+
+            approval = approval::next(gate.as_mut()) => {
+                if let Some(notice) = input.settle(approval) {
+                    send_pty_data(channel, notice).await?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_frame(channel: &DataChannel, frame: StreamFrame) -> Result<()> {
+    let mut buf = BytesMut::new();
+    StreamCodec.encode(frame, &mut buf)?;
+    channel.write_data_channel(&buf.freeze(), false).await?;
+    Ok(())
+}
+
+async fn send_pty_data(channel: &DataChannel, data: &[u8]) -> Result<()> {
+    for chunk in data.chunks(MAX_CHUNK) {
+        send_frame(
+            channel,
+            StreamFrame::pty_data(Bytes::copy_from_slice(chunk)),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+async fn send_event(channel: &DataChannel, event: &DaemonEvent) -> Result<()> {
+    let json = serde_json::to_vec(event)?;
+    send_frame(channel, StreamFrame::control(Bytes::from(json))).await
+}
+
+/// Stops the channel reader when the bridge ends
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,254 @@
+//! Rendezvous signaling: the daemon posts its offer to a signal server under
+//! a short code and polls for the browser's answer, so nothing has to be
+//! pasted. Both blobs are sealed with AES-256-GCM under a random key that
+//! is only ever carried in the connect URL fragment.
+
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::signal_server::{ANSWER_POLL, SLOT_TTL};
+use super::signaling::{self, AnswerEnvelope, ConnectionEnvelope, CONNECT_URL};
+
+/// Short code alphabet: lowercase without look-alikes (0/o, 1/l/i)
+const CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const CODE_LEN: usize = 6;
+
+/// Paired devices' codes are long-lived, so make them unguessable too
+pub(super) const DEVICE_CODE_LEN: usize = 16;
+
+const NONCE_LEN: usize = 12;
+
+pub struct Rendezvous {
+    server: String,
+    code: String,
+    key: [u8; 32],
+    http: reqwest::Client,
+}
+
+impl Rendezvous {
+    /// Pick a fresh short code and key for `server` (e.g. `https://signal.example.com`)
+    pub fn new(server: &str) -> Result<Self> {
+        Self::with_code(server, generate_code(CODE_LEN), rand::random())
+    }
+
+    /// Use a known code and key, as paired devices do
+    pub(super) fn with_code(server: &str, code: String, key: [u8; 32]) -> Result<Self> {
+        let server = server.trim_end_matches('/');
+        if !server.starts_with("http://") && !server.starts_with("https://") {
+            bail!(
+                "Signal server must be an http:// or https:// URL: {}",
+                server
+            );
+        }
+        let http = reqwest::Client::builder()
+            .timeout(ANSWER_POLL + Duration::from_secs(10))
+            .build()?;
+        Ok(Self {
+            server: server.to_string(),
+            code,
+            key,
+            http,
+        })
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Connect page URL: the fragment names the server, the code and the key
+    pub fn url(&self) -> String {
+        format!(
+            "{}#c={}&k={}&s={}",
+            CONNECT_URL,
+            self.code,
+            encode_key(&self.key),
+            encode_component(&self.server)
+        )
+    }
+
+    fn endpoint(&self, blob: &str) -> String {
+        format!("{}/v1/{}/{}", self.server, self.code, blob)
+    }
+
+    pub async fn publish_offer(&self, envelope: &ConnectionEnvelope) -> Result<()> {
+        let resp = self
+            .http
+            .put(self.endpoint("offer"))
+            .body(seal(&self.key, envelope)?)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach signal server {}", self.server))?;
+        if !resp.status().is_success() {
+            bail!("Signal server rejected the offer: {}", resp.status());
+        }
+        Ok(())
+    }
+
+    /// Poll until the browser posts its answer, returning the answer SDP
+    pub async fn wait_answer(&self) -> Result<String> {
+        let deadline = Instant::now() + SLOT_TTL;
+        while Instant::now() < deadline {
+            if let Some(sdp) = self.poll_answer().await? {
+                return Ok(sdp);
+            }
+        }
+        bail!("Timed out waiting for the browser's answer")
+    }
+
+    /// One long poll for the answer SDP; `None` if none arrived in time
+    pub(super) async fn poll_answer(&self) -> Result<Option<String>> {
+        let resp = self
+            .http
+            .get(self.endpoint("answer"))
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach signal server {}", self.server))?;
+        match resp.status() {
+            StatusCode::OK => {
+                let answer: AnswerEnvelope = open(&self.key, &resp.text().await?)?;
+                Ok(Some(answer.sdp))
+            }
+            StatusCode::NO_CONTENT => Ok(None),
+            status => bail!(
+                "Signal server error while waiting for the answer: {}",
+                status
+            ),
+        }
+    }
+}
+
+pub(super) fn generate_code(len: usize) -> String {
+    use rand::Rng;
+    let mut rng = rand::rng();
+    let suffix: String = (0..len)
+        .map(|_| CODE_ALPHABET[rng.random_range(0..CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("coop-{}", suffix)
+}
+
+pub(super) fn encode_key(key: &[u8; 32]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(key)
+}
+
+pub(super) fn decode_key(encoded: &str) -> Result<[u8; 32]> {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(encoded)
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .context("Invalid rendezvous key")
+}
+
+/// Compress and encrypt: base64url(nonce || AES-256-GCM(deflate(json)))
+pub(super) fn seal<T: Serialize>(key: &[u8; 32], value: &T) -> Result<String> {
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            signaling::compress(value)?.as_slice(),
+        )
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    let mut blob = nonce.to_vec();
+    blob.extend(ciphertext);
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(blob))
+}
+
+/// Inverse of `seal`; fails if the blob was tampered with or sealed under
+/// another key
+pub(super) fn open<T: DeserializeOwned>(key: &[u8; 32], blob: &str) -> Result<T> {
+    let blob = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(blob.trim().trim_end_matches('='))
+        .context("Invalid base64")?;
+    if blob.len() < NONCE_LEN {
+        bail!("Sealed envelope too short");
+    }
+    let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+    let compressed = Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Could not decrypt envelope (wrong key or corrupted)"))?;
+    signaling::decompress(&compressed)
+}
+
+/// Percent-encode a URL for use as a fragment parameter value
+fn encode_component(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~:/".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rendezvous_roundtrip() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(super::super::signal_server::serve(listener));
+
+        let rendezvous = Rendezvous::new(&server).unwrap();
+        let envelope = ConnectionEnvelope {
+            sdp: "v=0\r\noffer\r\n".to_string(),
+            machine_id: "a1b2c3d4".to_string(),
+            hostname: "desktop".to_string(),
+            version: signaling::ENVELOPE_VERSION,
+        };
+        rendezvous.publish_offer(&envelope).await.unwrap();
+
+        // Play the connect page: everything it needs is in the fragment
+        let url = rendezvous.url();
+        let fragment = url.split_once('#').unwrap().1;
+        let params: Vec<(&str, &str)> = fragment
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .collect();
+        let param = |name| params.iter().find(|(k, _)| *k == name).unwrap().1;
+        assert_eq!(param("c"), rendezvous.code());
+        assert_eq!(param("s"), server);
+        let key = decode_key(param("k")).unwrap();
+
+        let http = reqwest::Client::new();
+        let offer_url = format!("{}/v1/{}/offer", server, param("c"));
+        let sealed = http
+            .get(&offer_url)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(!sealed.contains("desktop"));
+        let offer: ConnectionEnvelope = open(&key, &sealed).unwrap();
+        assert_eq!(offer.sdp, envelope.sdp);
+        assert!(open::<ConnectionEnvelope>(&[0; 32], &sealed).is_err());
+
+        // The offer is handed out once
+        let again = http.get(&offer_url).send().await.unwrap();
+        assert_eq!(again.status(), StatusCode::GONE);
+
+        let waiter = tokio::spawn(async move { rendezvous.wait_answer().await });
+        let answer = AnswerEnvelope {
+            sdp: "v=0\r\nanswer\r\n".to_string(),
+        };
+        let resp = http
+            .put(format!("{}/v1/{}/answer", server, param("c")))
+            .body(seal(&key, &answer).unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(waiter.await.unwrap().unwrap(), answer.sdp);
+    }
+}
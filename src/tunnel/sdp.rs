@@ -0,0 +1,129 @@
+use anyhow::{bail, Context, Result};
+
+/// The parts of an SDP offer/answer a data-only peer connection needs: one
+/// `application` media section carrying ICE credentials and candidates, the
+/// DTLS certificate fingerprint and the DTLS role
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionDescription {
+    pub ice_ufrag: String,
+    pub ice_pwd: String,
+    /// `sha-256 AB:CD:...`
+    pub fingerprint: String,
+    /// DTLS role: `actpass` (offers), `active` or `passive` (answers)
+    pub setup: String,
+    /// Candidate attribute values, without the `candidate:` prefix
+    pub candidates: Vec<String>,
+}
+
+/// SCTP port both peers use (the browser default)
+const SCTP_PORT: u16 = 5000;
+
+/// Largest DataChannel message we accept
+const MAX_MESSAGE_SIZE: usize = 262_144;
+
+impl SessionDescription {
+    /// The fingerprint's hex part, uppercased: `AB:CD:...`
+    pub fn fingerprint_hex(&self) -> String {
+        self.fingerprint
+            .split_once(' ')
+            .map(|(_, fp)| fp.to_ascii_uppercase())
+            .unwrap_or_default()
+    }
+
+    pub fn to_sdp(&self) -> String {
+        let session_id: u64 = rand::random::<u64>() >> 1;
+        let mut lines = vec![
+            "v=0".to_string(),
+            format!("o=- {} 2 IN IP4 127.0.0.1", session_id),
+            "s=-".to_string(),
+            "t=0 0".to_string(),
+            "a=group:BUNDLE 0".to_string(),
+            "m=application 9 UDP/DTLS/SCTP webrtc-datachannel".to_string(),
+            "c=IN IP4 0.0.0.0".to_string(),
+            format!("a=ice-ufrag:{}", self.ice_ufrag),
+            format!("a=ice-pwd:{}", self.ice_pwd),
+            format!("a=fingerprint:{}", self.fingerprint),
+            format!("a=setup:{}", self.setup),
+            "a=mid:0".to_string(),
+            format!("a=sctp-port:{}", SCTP_PORT),
+            format!("a=max-message-size:{}", MAX_MESSAGE_SIZE),
+        ];
+        for candidate in &self.candidates {
+            lines.push(format!("a=candidate:{}", candidate));
+        }
+        lines.push("a=end-of-candidates".to_string());
+
+        let mut sdp = lines.join("\r\n");
+        sdp.push_str("\r\n");
+        sdp
+    }
+
+    /// Parse an SDP offer or answer. Attributes may appear at session or
+    /// media level; only the first media section is considered.
+    pub fn parse(sdp: &str) -> Result<Self> {
+        let mut ice_ufrag = None;
+        let mut ice_pwd = None;
+        let mut fingerprint = None;
+        let mut setup = None;
+        let mut candidates = Vec::new();
+        let mut media_sections = 0;
+
+        for line in sdp.lines().map(str::trim) {
+            if line.starts_with("m=") {
+                media_sections += 1;
+                if media_sections > 1 {
+                    break;
+                }
+                if !line.starts_with("m=application") {
+                    bail!("Expected a data channel (application) media section");
+                }
+                continue;
+            }
+            let Some(attr) = line.strip_prefix("a=") else {
+                continue;
+            };
+            let (key, value) = attr.split_once(':').unwrap_or((attr, ""));
+            match key {
+                "ice-ufrag" => ice_ufrag = Some(value.to_string()),
+                "ice-pwd" => ice_pwd = Some(value.to_string()),
+                "fingerprint" => fingerprint = Some(value.to_string()),
+                "setup" => setup = Some(value.to_string()),
+                "candidate" => candidates.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        let fingerprint = fingerprint.context("SDP has no DTLS fingerprint")?;
+        if !fingerprint.to_ascii_lowercase().starts_with("sha-256 ") {
+            bail!("Unsupported fingerprint algorithm: {}", fingerprint);
+        }
+
+        Ok(Self {
+            ice_ufrag: ice_ufrag.context("SDP has no ice-ufrag")?,
+            ice_pwd: ice_pwd.context("SDP has no ice-pwd")?,
+            fingerprint,
+            setup: setup.unwrap_or_else(|| "active".to_string()),
+            candidates,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_browser_answer() {
+        let answer = "v=0\r\no=- 4611731400430051336 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n\
+            a=group:BUNDLE 0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+            c=IN IP4 0.0.0.0\r\na=candidate:1 1 udp 2113937151 192.168.1.2 53011 typ host generation 0\r\n\
+            a=ice-ufrag:Zm9v\r\na=ice-pwd:YmFyYmF6cXV4cXV1eHF1dXg\r\n\
+            a=fingerprint:sha-256 01:02:03\r\na=setup:active\r\na=mid:0\r\na=sctp-port:5000\r\n";
+        let desc = SessionDescription::parse(answer).unwrap();
+        assert_eq!(desc.ice_ufrag, "Zm9v");
+        assert_eq!(desc.setup, "active");
+        assert_eq!(desc.candidates.len(), 1);
+
+        assert!(SessionDescription::parse("v=0\r\nm=audio 9 RTP/AVP 0\r\n").is_err());
+    }
+}
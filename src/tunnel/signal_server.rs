@@ -0,0 +1,174 @@
+//! Rendezvous server for tunnel signaling (`coop signal-server`).
+//!
+//! Holds one sealed offer and one sealed answer per short code. Blobs are
+//! encrypted end to end with a key that only travels in the connect URL
+//! fragment, so the server never sees SDP, candidates or host names.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::put;
+use axum::Router;
+use tokio::sync::{Mutex, Notify};
+use tower_http::cors::CorsLayer;
+
+/// Slots are dropped this long after the offer is posted
+pub const SLOT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long `GET /v1/{code}/answer` holds the request open
+pub const ANSWER_POLL: Duration = Duration::from_secs(25);
+
+/// Sealed envelopes are a few KB; anything much larger is not an SDP
+const MAX_BLOB_SIZE: usize = 64 * 1024;
+
+/// Cap on concurrently pending tunnels
+const MAX_SLOTS: usize = 1024;
+
+struct Slot {
+    offer: Option<String>,
+    answer: Option<String>,
+    created: Instant,
+    answered: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct SignalState {
+    slots: Mutex<HashMap<String, Slot>>,
+}
+
+impl SignalState {
+    /// Lock the slot table, dropping expired slots
+    async fn slots(&self) -> tokio::sync::MutexGuard<'_, HashMap<String, Slot>> {
+        let mut slots = self.slots.lock().await;
+        slots.retain(|_, slot| slot.created.elapsed() < SLOT_TTL);
+        slots
+    }
+}
+
+/// Create the rendezvous router
+pub fn create_router() -> Router {
+    Router::new()
+        .route("/v1/{code}/offer", put(put_offer).get(get_offer))
+        .route("/v1/{code}/answer", put(put_answer).get(get_answer))
+        .layer(DefaultBodyLimit::max(MAX_BLOB_SIZE))
+        // The connect page is served from another origin
+        .layer(CorsLayer::permissive())
+        .with_state(Arc::new(SignalState::default()))
+}
+
+/// Serve the rendezvous API on an already-bound listener
+pub async fn serve(listener: tokio::net::TcpListener) -> Result<()> {
+    axum::serve(listener, create_router()).await?;
+    Ok(())
+}
+
+/// Run the rendezvous server in the foreground
+pub async fn run(host: &str, port: u16) -> Result<()> {
+    let addr = format!("{}:{}", host, port);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    tracing::info!(addr = %addr, "Signal server listening");
+    println!("Signal server listening on http://{}", addr);
+    serve(listener).await
+}
+
+/// Short codes are generated by the daemon; reject anything that could not
+/// have come from one
+fn valid_code(code: &str) -> bool {
+    (4..=64).contains(&code.len()) && code.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, message.to_string()).into_response()
+}
+
+async fn put_offer(
+    State(state): State<Arc<SignalState>>,
+    Path(code): Path<String>,
+    body: String,
+) -> Response {
+    if !valid_code(&code) {
+        return error(StatusCode::BAD_REQUEST, "Invalid code");
+    }
+    let mut slots = state.slots().await;
+    if slots.contains_key(&code) {
+        return error(StatusCode::CONFLICT, "Code already in use");
+    }
+    if slots.len() >= MAX_SLOTS {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "Too many pending tunnels");
+    }
+    slots.insert(
+        code,
+        Slot {
+            offer: Some(body),
+            answer: None,
+            created: Instant::now(),
+            answered: Arc::new(Notify::new()),
+        },
+    );
+    StatusCode::CREATED.into_response()
+}
+
+/// The offer can be fetched once; the slot stays until the answer is taken
+async fn get_offer(State(state): State<Arc<SignalState>>, Path(code): Path<String>) -> Response {
+    let mut slots = state.slots().await;
+    match slots.get_mut(&code).map(|slot| slot.offer.take()) {
+        Some(Some(offer)) => offer.into_response(),
+        Some(None) => error(StatusCode::GONE, "Offer already retrieved"),
+        None => error(StatusCode::NOT_FOUND, "Unknown or expired code"),
+    }
+}
+
+async fn put_answer(
+    State(state): State<Arc<SignalState>>,
+    Path(code): Path<String>,
+    body: String,
+) -> Response {
+    let mut slots = state.slots().await;
+    let Some(slot) = slots.get_mut(&code) else {
+        return error(StatusCode::NOT_FOUND, "Unknown or expired code");
+    };
+    if slot.answer.is_some() {
+        return error(StatusCode::CONFLICT, "Already answered");
+    }
+    slot.answer = Some(body);
+    // notify_one stores a permit, so a poller between checks still wakes
+    slot.answered.notify_one();
+    StatusCode::CREATED.into_response()
+}
+
+/// Long-poll for the answer. Returns 204 if none arrived within
+/// `ANSWER_POLL`; taking the answer removes the slot.
+async fn get_answer(State(state): State<Arc<SignalState>>, Path(code): Path<String>) -> Response {
+    let answered = {
+        let mut slots = state.slots().await;
+        let Some(slot) = slots.get(&code) else {
+            return error(StatusCode::NOT_FOUND, "Unknown or expired code");
+        };
+        if slot.answer.is_some() {
+            return take_answer(&mut slots, &code);
+        }
+        slot.answered.clone()
+    };
+
+    if tokio::time::timeout(ANSWER_POLL, answered.notified())
+        .await
+        .is_err()
+    {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let mut slots = state.slots().await;
+    take_answer(&mut slots, &code)
+}
+
+fn take_answer(slots: &mut HashMap<String, Slot>, code: &str) -> Response {
+    match slots.remove(code).and_then(|slot| slot.answer) {
+        Some(answer) => answer.into_response(),
+        None => error(StatusCode::NOT_FOUND, "Unknown or expired code"),
+    }
+}
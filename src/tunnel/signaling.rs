@@ -1,8 +1,19 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+/// Static connect page; the envelope travels in the URL fragment, which
+/// browsers never send to the server
+pub const CONNECT_URL: &str = "https://opencoop.sh/connect";
+
+/// Envelope format version
+pub const ENVELOPE_VERSION: u32 = 1;
+
 /// Connection envelope exchanged during signaling
 #[derive(Debug, Serialize, Deserialize)]
-#[allow(dead_code)]
 pub struct ConnectionEnvelope {
     pub sdp: String,
     pub machine_id: String,
@@ -10,8 +21,75 @@ pub struct ConnectionEnvelope {
     pub version: u32,
 }
 
+/// What the connect page hands back, pasted or through a rendezvous server
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnswerEnvelope {
+    pub sdp: String,
+}
+
+impl ConnectionEnvelope {
+    /// Wrap an SDP offer with this machine's identity
+    pub fn new(sdp: String) -> Result<Self> {
+        Ok(Self {
+            sdp,
+            machine_id: get_machine_id()?,
+            hostname: nix::unistd::gethostname()?.to_string_lossy().into_owned(),
+            version: ENVELOPE_VERSION,
+        })
+    }
+
+    /// Connect page URL carrying the encoded envelope
+    pub fn url(&self) -> Result<String> {
+        Ok(format!("{}#{}", CONNECT_URL, encode(self)?))
+    }
+}
+
+/// Serialize to JSON, deflate (raw, for the browser's `deflate-raw`
+/// DecompressionStream) and base64url-encode
+pub fn encode<T: Serialize>(value: &T) -> Result<String> {
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(compress(value)?))
+}
+
+/// Inverse of `encode`
+pub fn decode<T: DeserializeOwned>(encoded: &str) -> Result<T> {
+    let compressed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(encoded.trim().trim_end_matches('='))
+        .context("Invalid base64")?;
+    decompress(&compressed)
+}
+
+/// JSON, raw-deflated
+pub(super) fn compress<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let json = serde_json::to_vec(value)?;
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&json)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inverse of `compress`
+pub(super) fn decompress<T: DeserializeOwned>(compressed: &[u8]) -> Result<T> {
+    let mut json = Vec::new();
+    flate2::read::DeflateDecoder::new(compressed)
+        .read_to_end(&mut json)
+        .context("Invalid compressed data")?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Parse a pasted SDP answer: either raw SDP or an encoded envelope,
+/// optionally as a full URL with the envelope in the fragment
+pub fn parse_answer(input: &str) -> Result<String> {
+    let input = input.trim();
+    if input.starts_with("v=0") {
+        return Ok(input.to_string());
+    }
+    let encoded = input
+        .rsplit_once('#')
+        .map_or(input, |(_, fragment)| fragment);
+    let answer: AnswerEnvelope = decode(encoded).context("Not a valid tunnel answer")?;
+    Ok(answer.sdp)
+}
+
 /// Get or create the machine ID
-#[allow(dead_code)]
 pub fn get_machine_id() -> anyhow::Result<String> {
     let path = crate::config::machine_id_path()?;
 
@@ -28,7 +106,6 @@ pub fn get_machine_id() -> anyhow::Result<String> {
 }
 
 /// Generate a QR code string for terminal display
-#[allow(dead_code)]
 pub fn generate_qr_terminal(data: &str) -> String {
     use qrcode::QrCode;
 
@@ -44,3 +121,29 @@ pub fn generate_qr_terminal(data: &str) -> String {
         Err(e) => format!("(QR generation failed: {})", e),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let envelope = ConnectionEnvelope {
+            sdp: "v=0\r\no=- 1 1 IN IP4 0.0.0.0\r\n".to_string(),
+            machine_id: "a1b2c3d4".to_string(),
+            hostname: "desktop".to_string(),
+            version: ENVELOPE_VERSION,
+        };
+        let url = envelope.url().unwrap();
+        let fragment = url.strip_prefix("https://opencoop.sh/connect#").unwrap();
+
+        let decoded: ConnectionEnvelope = decode(fragment).unwrap();
+        assert_eq!(decoded.sdp, envelope.sdp);
+        assert_eq!(decoded.machine_id, "a1b2c3d4");
+
+        // The connect page's answer reuses the encoding; raw SDP is accepted too
+        assert_eq!(parse_answer(&url).unwrap(), envelope.sdp);
+        assert_eq!(parse_answer("  v=0\r\n").unwrap(), "v=0");
+        assert!(parse_answer("garbage").is_err());
+    }
+}
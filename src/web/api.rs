@@ -1,54 +1,148 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
-use axum::response::Json;
+use axum::response::{IntoResponse, Json};
 use axum::routing::{delete, get, post};
 use axum::Router;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use super::auth::Auth;
 use super::server::WebState;
-use crate::ipc::SessionInfo;
+use crate::daemon::audit::Actor;
+use crate::ipc::{
+    AgentMessage, PtyInfo, QueuedTask, Response, SessionInfo, TokenScope, ERR_DEVICE_NOT_FOUND,
+    ERR_FORBIDDEN, ERR_INPUT_BLOCKED, ERR_INVALID_COMMAND, ERR_PTY_NOT_FOUND, ERR_SESSION_EXISTS,
+    ERR_SESSION_NOT_FOUND, ERR_TASK_NOT_FOUND, ERR_TOKEN_EXISTS, ERR_TOKEN_NOT_FOUND,
+    ERR_UNAUTHORIZED,
+};
 
 /// API routes
 pub fn api_routes() -> Router<Arc<WebState>> {
     Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .route("/api/status", get(status))
         .route("/api/sessions", get(list_sessions))
         .route("/api/sessions", post(create_session))
         .route("/api/sessions/{name}", delete(kill_session))
         .route("/api/sessions/{name}/shell", post(spawn_shell))
+        .route("/api/sessions/{name}/logs", get(logs))
+        .route("/api/sessions/{name}/messages", get(messages))
+        .route("/api/sessions/{name}/ptys", get(list_ptys))
+        .route("/api/sessions/{name}/ptys/{pty}", delete(kill_pty))
+        .route("/api/sessions/{name}/ptys/{pty}/restart", post(restart_pty))
+        .route("/api/sessions/{name}/ptys/{pty}/resize", post(resize_pty))
+        .route("/api/sessions/{name}/ptys/{pty}/input", post(send_input))
+        .route("/api/sessions/{name}/ptys/{pty}/wait", get(wait_output))
+        .route("/api/queue", get(list_queue))
 }
 
-#[derive(Deserialize)]
-pub struct TokenQuery {
-    pub token: Option<String>,
+/// An IPC response sent as the JSON body, with the HTTP status taken from
+/// its error code. Used for failures of every endpoint, and as the success
+/// body of endpoints that mirror an IPC command.
+pub struct ApiResponse(Box<Response>);
+
+pub type ApiResult<T = ApiResponse> = Result<T, ApiResponse>;
+
+impl ApiResponse {
+    pub fn err(code: &str, message: impl Into<String>) -> Self {
+        Self(Box::new(Response::err(code, message)))
+    }
 }
 
-/// Verify the auth token from query param or Authorization header
-fn verify_token(state: &WebState, token: Option<&str>) -> bool {
-    token.is_some_and(|t| t == state.token)
+impl From<Response> for ApiResponse {
+    fn from(resp: Response) -> Self {
+        Self(Box::new(resp))
+    }
 }
 
-async fn list_sessions(
-    State(state): State<Arc<WebState>>,
-    Query(query): Query<TokenQuery>,
-) -> Result<Json<Vec<SessionInfo>>, StatusCode> {
-    if !verify_token(&state, query.token.as_deref()) {
-        return Err(StatusCode::FORBIDDEN);
+impl From<anyhow::Result<Response>> for ApiResponse {
+    fn from(result: anyhow::Result<Response>) -> Self {
+        Response::from_result(result).into()
+    }
+}
+
+impl IntoResponse for ApiResponse {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self.0.error.as_deref() {
+            None => StatusCode::OK,
+            Some(code) => error_status(code),
+        };
+        (status, Json(self.0)).into_response()
+    }
+}
+
+fn error_status(code: &str) -> StatusCode {
+    match code {
+        ERR_UNAUTHORIZED => StatusCode::UNAUTHORIZED,
+        ERR_FORBIDDEN | ERR_INPUT_BLOCKED => StatusCode::FORBIDDEN,
+        ERR_SESSION_NOT_FOUND
+        | ERR_PTY_NOT_FOUND
+        | ERR_TASK_NOT_FOUND
+        | ERR_DEVICE_NOT_FOUND
+        | ERR_TOKEN_NOT_FOUND => StatusCode::NOT_FOUND,
+        ERR_SESSION_EXISTS | ERR_TOKEN_EXISTS => StatusCode::CONFLICT,
+        ERR_INVALID_COMMAND => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
+}
 
-    match state.session_manager.list_sessions().await {
-        Ok(resp) => {
-            if let Some(sessions) = resp.data.sessions {
-                Ok(Json(sessions))
-            } else {
-                Ok(Json(vec![]))
-            }
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// Unwrap a successful IPC response, or turn it into an error
+fn ok(result: anyhow::Result<Response>) -> ApiResult<Response> {
+    match Response::from_result(result) {
+        resp if resp.ok => Ok(resp),
+        resp => Err(resp.into()),
     }
 }
 
+async fn openapi() -> Json<serde_json::Value> {
+    Json(super::openapi::document())
+}
+
+#[derive(Serialize)]
+struct DaemonStatus {
+    ok: bool,
+    version: &'static str,
+    pid: u32,
+    /// Boxes visible to the token
+    sessions: usize,
+}
+
+async fn status(State(state): State<Arc<WebState>>, auth: Auth) -> ApiResult<Json<DaemonStatus>> {
+    let sessions = visible_sessions(&state, &auth).await?.len();
+    Ok(Json(DaemonStatus {
+        ok: true,
+        version: env!("CARGO_PKG_VERSION"),
+        pid: std::process::id(),
+        sessions,
+    }))
+}
+
+async fn list_sessions(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+) -> ApiResult<Json<Vec<SessionInfo>>> {
+    Ok(Json(visible_sessions(&state, &auth).await?))
+}
+
+/// Every token can read; tokens limited to some boxes or a PTY (share
+/// links) only see those
+async fn visible_sessions(state: &WebState, auth: &Auth) -> ApiResult<Vec<SessionInfo>> {
+    let resp = ok(state.session_manager.list_sessions().await)?;
+    Ok(resp
+        .data
+        .sessions
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|s| auth.grant.allows_session(&s.name))
+        .map(|mut s| {
+            s.ptys.retain(|p| auth.grant.allows_pty(p.id));
+            s
+        })
+        .collect())
+}
+
 #[derive(Deserialize)]
 pub struct CreateSessionRequest {
     pub name: Option<String>,
@@ -57,36 +151,30 @@ pub struct CreateSessionRequest {
 
 async fn create_session(
     State(state): State<Arc<WebState>>,
-    Query(query): Query<TokenQuery>,
+    auth: Auth,
     Json(body): Json<CreateSessionRequest>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    if !verify_token(&state, query.token.as_deref()) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+) -> ApiResult {
+    auth.require(TokenScope::Admin, body.name.as_deref())?;
 
-    match state
+    Ok(state
         .session_manager
         .create_session(body.name, body.workspace, None, true)
         .await
-    {
-        Ok(resp) => Ok(Json(serde_json::to_value(resp).unwrap())),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+        .into())
 }
 
 async fn kill_session(
     State(state): State<Arc<WebState>>,
-    Query(query): Query<TokenQuery>,
+    auth: Auth,
     Path(name): Path<String>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    if !verify_token(&state, query.token.as_deref()) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+) -> ApiResult {
+    auth.require(TokenScope::Admin, Some(&name))?;
 
-    match state.session_manager.kill_session(&name, false).await {
-        Ok(resp) => Ok(Json(serde_json::to_value(resp).unwrap())),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    Ok(state
+        .session_manager
+        .kill_session(&name, false, &Actor::web(&auth.grant.name))
+        .await
+        .into())
 }
 
 #[derive(Deserialize)]
@@ -98,15 +186,13 @@ pub struct SpawnShellRequest {
 
 async fn spawn_shell(
     State(state): State<Arc<WebState>>,
-    Query(query): Query<TokenQuery>,
+    auth: Auth,
     Path(name): Path<String>,
     Json(body): Json<SpawnShellRequest>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
-    if !verify_token(&state, query.token.as_deref()) {
-        return Err(StatusCode::FORBIDDEN);
-    }
+) -> ApiResult {
+    auth.require(TokenScope::Write, Some(&name))?;
 
-    match state
+    Ok(state
         .session_manager
         .spawn_shell(
             &name,
@@ -116,8 +202,236 @@ async fn spawn_shell(
             body.rows.unwrap_or(40),
         )
         .await
-    {
-        Ok(resp) => Ok(Json(serde_json::to_value(resp).unwrap())),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct LogsQuery {
+    #[serde(default)]
+    pub pty: u32,
+    /// A `[[services]]` entry's logs instead of `pty`
+    pub service: Option<String>,
+    /// Last N lines only
+    pub tail: Option<usize>,
+}
+
+async fn logs(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path(name): Path<String>,
+    Query(query): Query<LogsQuery>,
+) -> ApiResult {
+    auth.require(TokenScope::Read, Some(&name))?;
+    // A token limited to one PTY cannot resolve services to other PTYs
+    if query.service.is_some() && auth.grant.pty.is_some() {
+        return Err(ApiResponse::err(
+            ERR_FORBIDDEN,
+            "Token is limited to one PTY",
+        ));
+    }
+    auth.require_pty(query.pty)?;
+
+    Ok(state
+        .session_manager
+        .get_logs(&name, query.pty, query.service.as_deref(), query.tail, true)
+        .await
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct MessagesQuery {
+    /// Only messages after this `seq`
+    #[serde(default)]
+    pub since: u64,
+}
+
+/// Conversation turns and tool calls of the box's `stream-json` agents,
+/// masked like its output
+async fn messages(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path(name): Path<String>,
+    Query(query): Query<MessagesQuery>,
+) -> ApiResult<Json<Vec<AgentMessage>>> {
+    auth.require(TokenScope::Read, Some(&name))?;
+
+    let resp = ok(state
+        .session_manager
+        .messages(&name, query.since, true)
+        .await)?;
+    Ok(Json(
+        resp.data
+            .messages
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|m| auth.grant.allows_pty(m.pty))
+            .collect(),
+    ))
+}
+
+async fn list_ptys(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path(name): Path<String>,
+) -> ApiResult<Json<Vec<PtyInfo>>> {
+    auth.require(TokenScope::Read, Some(&name))?;
+
+    let resp = ok(state.session_manager.session_ls(&name).await)?;
+    Ok(Json(
+        resp.data
+            .ptys
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|p| auth.grant.allows_pty(p.id))
+            .collect(),
+    ))
+}
+
+async fn kill_pty(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, pty)): Path<(String, u32)>,
+) -> ApiResult {
+    auth.require(TokenScope::Write, Some(&name))?;
+    auth.require_pty(pty)?;
+
+    Ok(state
+        .session_manager
+        .kill_pty(&name, pty, &Actor::web(&auth.grant.name))
+        .await
+        .into())
+}
+
+async fn restart_pty(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, pty)): Path<(String, u32)>,
+) -> ApiResult {
+    auth.require(TokenScope::Write, Some(&name))?;
+    auth.require_pty(pty)?;
+
+    Ok(state
+        .session_manager
+        .restart_pty(&name, pty, &Actor::web(&auth.grant.name))
+        .await
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct ResizeRequest {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+async fn resize_pty(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, pty)): Path<(String, u32)>,
+    Json(body): Json<ResizeRequest>,
+) -> ApiResult {
+    auth.require(TokenScope::Attach, Some(&name))?;
+    auth.require_pty(pty)?;
+
+    let result = async {
+        let actor = Actor::web(&auth.grant.name);
+        let handle = state
+            .session_manager
+            .get_pty_handle(&name, pty, actor, auth.input_client(&name))
+            .await?;
+        handle.input.resize(body.cols, body.rows);
+        Ok(Response::ok())
     }
+    .await;
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct InputRequest {
+    /// Typed as is, with `\n`, `\x03`-style escapes
+    #[serde(default)]
+    pub text: String,
+    /// tmux key names sent after `text`
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// Type into a PTY as this token's client class would: through its input
+/// filter and approval policy
+async fn send_input(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, pty)): Path<(String, u32)>,
+    Json(body): Json<InputRequest>,
+) -> ApiResult {
+    auth.require(TokenScope::Attach, Some(&name))?;
+    auth.require_pty(pty)?;
+
+    Ok(state
+        .session_manager
+        .send_input(
+            &name,
+            pty,
+            Actor::web(&auth.grant.name),
+            auth.input_client(&name),
+            &body.text,
+            &body.keys,
+        )
+        .await
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct WaitQuery {
+    /// Regex matched against the output, with escape sequences stripped
+    pub pattern: String,
+    /// How long to hold the request, e.g. `90s` or `10m` (default 30s)
+    pub timeout: Option<String>,
+    /// Match the scrollback first, not just new output
+    #[serde(default)]
+    pub scrollback: bool,
+}
+
+/// Long-poll until a PTY's output matches a pattern. Matches the masked
+/// output, like every other web view of the box.
+async fn wait_output(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, pty)): Path<(String, u32)>,
+    Query(query): Query<WaitQuery>,
+) -> ApiResult {
+    auth.require(TokenScope::Read, Some(&name))?;
+    auth.require_pty(pty)?;
+    let timeout = match query.timeout.as_deref() {
+        Some(timeout) => crate::config::parse_duration(timeout)
+            .map_err(|e| ApiResponse::err(ERR_INVALID_COMMAND, e))?,
+        None => Duration::from_secs(30),
+    };
+
+    Ok(state
+        .session_manager
+        .wait_output(
+            &name,
+            pty,
+            &query.pattern,
+            Some(timeout),
+            query.scrollback,
+            true,
+        )
+        .await
+        .into())
+}
+
+async fn list_queue(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+) -> ApiResult<Json<Vec<QueuedTask>>> {
+    let resp = ok(state.task_queue.list().await)?;
+    Ok(Json(
+        resp.data
+            .tasks
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| auth.grant.allows_session(&t.box_name))
+            .collect(),
+    ))
 }
@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap};
+
+use super::api::ApiResponse;
+use super::server::WebState;
+use super::tokens::Grant;
+use crate::config::InputClient;
+use crate::ipc::{TokenScope, ERR_FORBIDDEN, ERR_UNAUTHORIZED};
+
+/// Cookie that carries the token for requests that cannot set a header:
+/// WebSocket upgrades and pages loaded through the port proxy
+pub const TOKEN_COOKIE: &str = "coop_token";
+
+/// Where the request presented its token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    Header,
+    Cookie,
+    Query,
+}
+
+/// A request authenticated by an API token. Tries `Authorization: Bearer`,
+/// then the `coop_token` cookie, then `?token=`, so a stale cookie does not
+/// shadow a fresh token; rejects with 401 `UNAUTHORIZED` if none of them is
+/// valid.
+pub struct Auth {
+    pub grant: Grant,
+    pub source: TokenSource,
+}
+
+impl Auth {
+    /// 403 `FORBIDDEN` unless the token has `scope` and reaches `session`
+    pub fn require(&self, scope: TokenScope, session: Option<&str>) -> Result<(), ApiResponse> {
+        if self.grant.allows(scope, session) {
+            Ok(())
+        } else {
+            Err(ApiResponse::err(
+                ERR_FORBIDDEN,
+                format!("Token '{}' lacks {} access", self.grant.name, scope),
+            ))
+        }
+    }
+
+    /// 403 `FORBIDDEN` if the token is limited to another PTY
+    pub fn require_pty(&self, pty: u32) -> Result<(), ApiResponse> {
+        if self.grant.allows_pty(pty) {
+            Ok(())
+        } else {
+            Err(ApiResponse::err(
+                ERR_FORBIDDEN,
+                format!("Token '{}' cannot reach PTY {}", self.grant.name, pty),
+            ))
+        }
+    }
+
+    /// The `[input_filter]` policy for this token's input to `session`:
+    /// tokens that can spawn shells are `web`, the rest `spectator`
+    pub fn input_client(&self, session: &str) -> InputClient {
+        if self.grant.allows(TokenScope::Write, Some(session)) {
+            InputClient::Web
+        } else {
+            InputClient::Spectator
+        }
+    }
+}
+
+impl FromRequestParts<Arc<WebState>> for Auth {
+    type Rejection = ApiResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<WebState>,
+    ) -> Result<Self, Self::Rejection> {
+        for (secret, source) in request_tokens(parts) {
+            if let Some(grant) = state.tokens.verify(&secret).await {
+                tracing::debug!(token = %grant.name, path = %parts.uri.path(), "Authenticated web request");
+                return Ok(Auth { grant, source });
+            }
+        }
+        Err(ApiResponse::err(
+            ERR_UNAUTHORIZED,
+            "Missing or invalid API token",
+        ))
+    }
+}
+
+/// Candidate tokens in the order they are tried
+fn request_tokens(parts: &Parts) -> Vec<(String, TokenSource)> {
+    let bearer = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|t| (t.trim().to_string(), TokenSource::Header));
+    let cookie = cookie_value(&parts.headers, TOKEN_COOKIE).map(|t| (t, TokenSource::Cookie));
+    let query = query_param(parts.uri.query(), "token").map(|t| (t, TokenSource::Query));
+    [bearer, cookie, query].into_iter().flatten().collect()
+}
+
+/// Look up a single query parameter value
+pub(super) fn query_param(query: Option<&str>, key: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v.to_string())
+}
+
+/// Read a cookie value from request headers
+pub(super) fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(
+            query_param(Some("a=1&token=xyz"), "token").as_deref(),
+            Some("xyz")
+        );
+        assert_eq!(query_param(Some("a=1"), "token"), None);
+    }
+
+    #[test]
+    fn test_token_sources() {
+        let req = axum::http::Request::builder()
+            .uri("/api/sessions?token=q")
+            .header(header::AUTHORIZATION, "Bearer h")
+            .header(header::COOKIE, "theme=dark; coop_token=c")
+            .body(())
+            .unwrap();
+        let sources: Vec<(String, TokenSource)> = request_tokens(&req.into_parts().0);
+        assert_eq!(
+            sources,
+            vec![
+                ("h".to_string(), TokenSource::Header),
+                ("c".to_string(), TokenSource::Cookie),
+                ("q".to_string(), TokenSource::Query),
+            ]
+        );
+
+        let req = axum::http::Request::builder()
+            .uri("/api/sessions")
+            .body(())
+            .unwrap();
+        assert!(request_tokens(&req.into_parts().0).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! `GET /api/events`: the session manager's event bus as server-sent events,
+//! limited to the boxes (and PTY) the token can see.
+
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use futures_util::Stream;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use super::api::ApiResult;
+use super::auth::Auth;
+use super::server::WebState;
+use super::tokens::Grant;
+use crate::ipc::{SessionEvent, TokenScope};
+
+pub fn event_routes() -> Router<Arc<WebState>> {
+    Router::new().route("/api/events", get(events))
+}
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    /// Only events about this box
+    pub session: Option<String>,
+}
+
+async fn events(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Query(query): Query<EventsQuery>,
+) -> ApiResult<Sse<impl Stream<Item = Result<Event, axum::Error>>>> {
+    if let Some(session) = &query.session {
+        auth.require(TokenScope::Read, Some(session))?;
+    }
+
+    let rx = state.session_manager.subscribe();
+    let stream = futures_util::stream::unfold(
+        (rx, auth.grant, query.session),
+        |(mut rx, grant, session)| async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        SessionEvent::Lagged { missed }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+                if visible(&grant, session.as_deref(), &event) {
+                    let sse = Event::default().event(event.name()).json_data(&event);
+                    return Some((sse, (rx, grant, session)));
+                }
+            }
+        },
+    );
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Whether a subscriber with `grant`, optionally limited to `session`,
+/// should receive `event`
+fn visible(grant: &Grant, session: Option<&str>, event: &SessionEvent) -> bool {
+    let box_ok = match event.session() {
+        Some(name) => grant.allows_session(name) && session.map_or(true, |s| s == name),
+        None => true,
+    };
+    box_ok && event.pty().map_or(true, |pty| grant.allows_pty(pty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::ClientKind;
+
+    #[test]
+    fn test_events_filtered_by_grant() {
+        let grant = Grant {
+            name: "share-web-abc123".to_string(),
+            scope: TokenScope::Read,
+            sessions: vec!["web".to_string()],
+            pty: Some(1),
+        };
+        let exited = |session: &str, pty| SessionEvent::PtyExited {
+            session: session.to_string(),
+            pty,
+            code: Some(0),
+        };
+        assert!(visible(&grant, None, &exited("web", 1)));
+        assert!(!visible(&grant, None, &exited("web", 0)));
+        assert!(!visible(&grant, None, &exited("api", 1)));
+        assert!(!visible(&grant, Some("other"), &exited("web", 1)));
+
+        let attached = SessionEvent::ClientAttached {
+            session: "web".to_string(),
+            client: ClientKind::Web,
+        };
+        assert!(visible(&grant, Some("web"), &attached));
+        assert!(visible(&grant, None, &SessionEvent::Lagged { missed: 3 }));
+    }
+}
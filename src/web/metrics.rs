@@ -0,0 +1,56 @@
+//! `GET /metrics` in the Prometheus text format, on the web server (needing
+//! a read token for all boxes) and optionally on a separate, unauthenticated
+//! port for scrapers (`coop serve --metrics-port`).
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+use super::api::{ApiResponse, ApiResult};
+use super::auth::Auth;
+use super::server::WebState;
+use crate::daemon::session::SessionManager;
+use crate::ipc::{TokenScope, ERR_INTERNAL_ERROR};
+
+const EXPOSITION_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub fn metrics_routes() -> Router<Arc<WebState>> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+async fn metrics(State(state): State<Arc<WebState>>, auth: Auth) -> ApiResult<impl IntoResponse> {
+    // Metrics cover every box, so tokens limited to some boxes are refused
+    auth.require(TokenScope::Read, None)?;
+    scrape(&state.session_manager).await
+}
+
+async fn scrape(session_manager: &SessionManager) -> ApiResult<impl IntoResponse> {
+    let text = session_manager
+        .render_metrics()
+        .await
+        .map_err(|e| ApiResponse::err(ERR_INTERNAL_ERROR, e.to_string()))?;
+    Ok(([(CONTENT_TYPE, EXPOSITION_TYPE)], text))
+}
+
+/// Serve only `/metrics`, without authentication. Bind it somewhere only
+/// the scraper can reach.
+pub async fn start_metrics_server(
+    listener: tokio::net::TcpListener,
+    session_manager: Arc<SessionManager>,
+) -> Result<()> {
+    let app = Router::new()
+        .route(
+            "/metrics",
+            get(|State(sm): State<Arc<SessionManager>>| async move { scrape(&sm).await }),
+        )
+        .with_state(session_manager);
+
+    tracing::info!(addr = %listener.local_addr()?, "Metrics server listening");
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -1,4 +1,11 @@
 pub mod api;
 pub mod assets;
+pub mod auth;
+pub mod events;
+pub mod metrics;
+pub mod openapi;
+pub mod proxy;
 pub mod server;
+pub mod tls;
+pub mod tokens;
 pub mod websocket;
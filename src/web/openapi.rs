@@ -0,0 +1,293 @@
+//! OpenAPI 3.0 description of the REST API, served at `/api/openapi.json`.
+//!
+//! Written by hand next to the routes in `api.rs`; keep the two in step.
+
+use serde_json::{json, Value};
+
+/// The OpenAPI document for this build
+pub fn document() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "coop web API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Manage coop boxes over HTTP. Failures carry the same error \
+                codes as the IPC protocol (RFC 07), e.g. SESSION_NOT_FOUND as a 404.",
+        },
+        "security": [{ "bearer": [] }, { "cookie": [] }, { "query": [] }],
+        "paths": {
+            "/api/status": {
+                "get": op("Daemon version and number of visible boxes", "read", None,
+                    ok_schema("#/components/schemas/Status")),
+            },
+            "/api/sessions": {
+                "get": op("List boxes", "read", None,
+                    ok_array("#/components/schemas/SessionInfo")),
+                "post": op("Create a box", "admin",
+                    Some(body(json!({
+                        "type": "object",
+                        "required": ["workspace"],
+                        "properties": {
+                            "name": { "type": "string" },
+                            "workspace": { "type": "string" },
+                        },
+                    }))),
+                    ok_response()),
+            },
+            "/api/sessions/{name}": {
+                "parameters": [name_param()],
+                "delete": op("Kill a box", "admin", None, ok_response()),
+            },
+            "/api/sessions/{name}/shell": {
+                "parameters": [name_param()],
+                "post": op("Spawn a shell PTY", "write",
+                    Some(body(json!({
+                        "type": "object",
+                        "properties": {
+                            "command": { "type": "string" },
+                            "cols": { "type": "integer" },
+                            "rows": { "type": "integer" },
+                        },
+                    }))),
+                    ok_response()),
+            },
+            "/api/sessions/{name}/logs": {
+                "parameters": [
+                    name_param(),
+                    query_param("pty", "integer", "PTY id (default 0)"),
+                    query_param("service", "string", "Service name, instead of pty"),
+                    query_param("tail", "integer", "Last N lines only"),
+                ],
+                "get": op("Scrollback of a PTY as base64 log_data", "read", None, ok_response()),
+            },
+            "/api/sessions/{name}/messages": {
+                "parameters": [
+                    name_param(),
+                    query_param("since", "integer", "Only messages after this seq"),
+                ],
+                "get": op("Conversation turns and tool calls of the box's stream-json agents", "read", None,
+                    ok_array("#/components/schemas/AgentMessage")),
+            },
+            "/api/sessions/{name}/ptys": {
+                "parameters": [name_param()],
+                "get": op("List the PTYs of a box", "read", None,
+                    ok_array("#/components/schemas/PtyInfo")),
+            },
+            "/api/sessions/{name}/ptys/{pty}": {
+                "parameters": [name_param(), pty_param()],
+                "delete": op("Kill one PTY", "write", None, ok_response()),
+            },
+            "/api/sessions/{name}/ptys/{pty}/restart": {
+                "parameters": [name_param(), pty_param()],
+                "post": op("Restart an agent or service PTY", "write", None, ok_response()),
+            },
+            "/api/sessions/{name}/ptys/{pty}/resize": {
+                "parameters": [name_param(), pty_param()],
+                "post": op("Resize a PTY", "attach",
+                    Some(body(json!({
+                        "type": "object",
+                        "required": ["cols", "rows"],
+                        "properties": {
+                            "cols": { "type": "integer" },
+                            "rows": { "type": "integer" },
+                        },
+                    }))),
+                    ok_response()),
+            },
+            "/api/sessions/{name}/ptys/{pty}/input": {
+                "parameters": [name_param(), pty_param()],
+                "post": op("Type into a PTY, through the token's input filter and approval policy", "attach",
+                    Some(body(json!({
+                        "type": "object",
+                        "properties": {
+                            "text": { "type": "string", "description": "Sent first; `\\n`, `\\r`, `\\t`, `\\e` and `\\xNN` escapes are decoded" },
+                            "keys": { "type": "array", "items": { "type": "string" }, "description": "tmux key names (`Enter`, `C-c`, `Up`), sent after `text`" },
+                        },
+                    }))),
+                    ok_response()),
+            },
+            "/api/sessions/{name}/ptys/{pty}/wait": {
+                "parameters": [
+                    name_param(),
+                    pty_param(),
+                    json!({
+                        "name": "pattern",
+                        "in": "query",
+                        "required": true,
+                        "description": "Regex matched against the output with escape sequences stripped",
+                        "schema": { "type": "string" },
+                    }),
+                    query_param("timeout", "string", "How long to wait, e.g. 90s or 10m (default 30s)"),
+                    query_param("scrollback", "boolean", "Match the scrollback too, not just new output"),
+                ],
+                "get": op("Long-poll until the PTY's output matches: `matched` holds the match, `timed_out` is set on expiry, and neither if the PTY exits first", "read", None, ok_response()),
+            },
+            "/api/events": {
+                "parameters": [query_param("session", "string", "Only events about this box")],
+                "get": op("Server-sent stream of session events", "read", None, json!({
+                    "description": "One SSE message per event, named after its `event` tag",
+                    "content": { "text/event-stream": { "schema": { "type": "string" } } },
+                })),
+            },
+            "/metrics": {
+                "get": op("Prometheus metrics for every box", "read", None, json!({
+                    "description": "Text exposition format 0.0.4; needs a token not limited to some boxes",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                })),
+            },
+            "/api/queue": {
+                "get": op("List queued tasks", "read", None, ok_array("#/components/schemas/Task")),
+            },
+        },
+        "components": {
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "cookie": { "type": "apiKey", "in": "cookie", "name": super::auth::TOKEN_COOKIE },
+                "query": { "type": "apiKey", "in": "query", "name": "token" },
+            },
+            "schemas": {
+                "Response": {
+                    "type": "object",
+                    "required": ["ok"],
+                    "properties": {
+                        "ok": { "type": "boolean" },
+                        "error": { "type": "string", "description": "IPC error code" },
+                        "message": { "type": "string" },
+                        "data": { "type": "object" },
+                    },
+                },
+                "Status": {
+                    "type": "object",
+                    "properties": {
+                        "ok": { "type": "boolean" },
+                        "version": { "type": "string" },
+                        "pid": { "type": "integer" },
+                        "sessions": { "type": "integer" },
+                    },
+                },
+                "SessionInfo": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "workspace": { "type": "string" },
+                        "pid": { "type": "integer" },
+                        "created": { "type": "integer" },
+                        "ptys": { "type": "array", "items": { "$ref": "#/components/schemas/PtyInfo" } },
+                        "web_clients": { "type": "integer" },
+                        "local_clients": { "type": "integer" },
+                    },
+                },
+                "PtyInfo": {
+                    "type": "object",
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "role": { "type": "string", "enum": ["agent", "service", "shell"] },
+                        "name": { "type": "string" },
+                        "command": { "type": "string" },
+                        "pid": { "type": "integer" },
+                        "status": { "type": "string" },
+                    },
+                },
+                "AgentMessage": {
+                    "type": "object",
+                    "required": ["seq", "pty", "time", "kind"],
+                    "properties": {
+                        "seq": { "type": "integer" },
+                        "pty": { "type": "integer" },
+                        "time": { "type": "integer" },
+                        "kind": { "type": "string", "enum": ["system", "user", "assistant", "tool_use", "tool_result", "result"] },
+                        "text": { "type": "string" },
+                        "tool": { "type": "string" },
+                        "tool_use_id": { "type": "string" },
+                        "input": {},
+                        "is_error": { "type": "boolean" },
+                    },
+                },
+                "Task": { "type": "object" },
+            },
+            "responses": {
+                "Error": {
+                    "description": "401 UNAUTHORIZED, 403 FORBIDDEN, 404 *_NOT_FOUND, \
+                        409 *_EXISTS, 500 INTERNAL_ERROR",
+                    "content": { "application/json": {
+                        "schema": { "$ref": "#/components/schemas/Response" },
+                    } },
+                },
+            },
+        },
+    })
+}
+
+/// An operation needing `scope`
+fn op(summary: &str, scope: &str, request_body: Option<Value>, ok: Value) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "description": format!("Requires a token with `{}` scope.", scope),
+        "responses": {
+            "200": ok,
+            "default": { "$ref": "#/components/responses/Error" },
+        },
+    });
+    if let Some(request_body) = request_body {
+        op["requestBody"] = request_body;
+    }
+    op
+}
+
+fn body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn ok_schema(reference: &str) -> Value {
+    json!({
+        "description": "OK",
+        "content": { "application/json": { "schema": { "$ref": reference } } },
+    })
+}
+
+fn ok_array(reference: &str) -> Value {
+    json!({
+        "description": "OK",
+        "content": { "application/json": {
+            "schema": { "type": "array", "items": { "$ref": reference } },
+        } },
+    })
+}
+
+fn ok_response() -> Value {
+    ok_schema("#/components/schemas/Response")
+}
+
+fn name_param() -> Value {
+    json!({ "name": "name", "in": "path", "required": true, "schema": { "type": "string" } })
+}
+
+fn pty_param() -> Value {
+    json!({ "name": "pty", "in": "path", "required": true, "schema": { "type": "integer" } })
+}
+
+fn query_param(name: &str, kind: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": kind },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document_refs_resolve() {
+        let doc = document();
+        let text = doc.to_string();
+        for reference in text.split("\"$ref\":\"#/").skip(1) {
+            let pointer = format!("/{}", &reference[..reference.find('"').unwrap()]);
+            assert!(doc.pointer(&pointer).is_some(), "dangling {}", pointer);
+        }
+        assert!(doc["paths"]["/api/sessions/{name}/ptys/{pty}/restart"]["post"].is_object());
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::any;
+use axum::Router;
+use hyper_util::rt::TokioIo;
+
+use super::auth::{Auth, TokenSource, TOKEN_COOKIE};
+use super::server::WebState;
+use crate::ipc::TokenScope;
+
+/// Reverse proxy routes into box services: /box/{name}/port/{port}/...
+pub fn proxy_routes() -> Router<Arc<WebState>> {
+    Router::new()
+        .route("/box/{name}/port/{port}", any(proxy_bare))
+        .route("/box/{name}/port/{port}/", any(proxy_root))
+        .route("/box/{name}/port/{port}/{*path}", any(proxy_path))
+}
+
+/// Sandbox directives for proxied pages served on the web UI's origin: no
+/// `allow-same-origin`, so they run in an opaque origin and cannot read the
+/// UI's storage (where it keeps its token) or script its pages
+const SANDBOX_POLICY: &str =
+    "sandbox allow-scripts allow-forms allow-popups allow-modals allow-downloads";
+
+/// Response middleware for proxy routes on the web UI's port
+pub async fn sandbox(mut resp: Response) -> Response {
+    if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+        // Appended, so a policy the app sends still applies too
+        resp.headers_mut().append(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(SANDBOX_POLICY),
+        );
+    }
+    resp
+}
+
+/// Redirect `/box/x/port/N` to `/box/x/port/N/` so relative URLs in the
+/// proxied app resolve under the prefix.
+async fn proxy_bare(Path((name, port)): Path<(String, u16)>, req: Request) -> Response {
+    let query = req
+        .uri()
+        .query()
+        .map(|q| format!("?{}", q))
+        .unwrap_or_default();
+    Redirect::temporary(&format!("/box/{}/port/{}/{}", name, port, query)).into_response()
+}
+
+async fn proxy_root(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, port)): Path<(String, u16)>,
+    req: Request,
+) -> Response {
+    proxy(&state, &auth, &name, port, "", req).await
+}
+
+async fn proxy_path(
+    State(state): State<Arc<WebState>>,
+    auth: Auth,
+    Path((name, port, path)): Path<(String, u16, String)>,
+    req: Request,
+) -> Response {
+    proxy(&state, &auth, &name, port, &path, req).await
+}
+
+async fn proxy(
+    state: &WebState,
+    auth: &Auth,
+    name: &str,
+    port: u16,
+    path: &str,
+    req: Request,
+) -> Response {
+    if let Err(rejection) = auth.require(TokenScope::Attach, Some(name)) {
+        return rejection.into_response();
+    }
+    let query_token = super::auth::query_param(req.uri().query(), "token");
+
+    let prefix = format!("/box/{}/port/{}", name, port);
+    let strip_authorization = auth.source == TokenSource::Header;
+    let mut resp = match forward(state, name, port, &prefix, path, req, strip_authorization).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracing::debug!(session = %name, port = port, error = %e, "Proxy request failed");
+            return (
+                StatusCode::BAD_GATEWAY,
+                format!(
+                    "coop: cannot reach port {} in box '{}': {:#}",
+                    port, name, e
+                ),
+            )
+                .into_response();
+        }
+    };
+
+    // Pages served through the proxy load their own assets without
+    // `?token=`, so remember a token passed in the URL in a cookie scoped
+    // to the box/port prefix
+    if let (Some(token), TokenSource::Query) = (query_token, auth.source) {
+        let mut cookie = format!(
+            "{}={}; Path={}/; HttpOnly; SameSite=Strict",
+            TOKEN_COOKIE, token, prefix
+        );
+        if state.tls {
+            cookie.push_str("; Secure");
+        }
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            resp.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+
+    resp
+}
+
+/// Forward a request to the box service and stream the response back.
+/// Upgrade requests (WebSocket, etc.) are spliced into a raw byte pipe
+/// once the upstream answers with 101 Switching Protocols.
+async fn forward(
+    state: &WebState,
+    name: &str,
+    port: u16,
+    prefix: &str,
+    path: &str,
+    mut req: Request,
+    strip_authorization: bool,
+) -> anyhow::Result<Response> {
+    let stream = state.session_manager.connect_box_port(name, port).await?;
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .context("HTTP handshake with box service failed")?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.with_upgrades().await {
+            tracing::debug!(error = %e, "Proxy upstream connection closed");
+        }
+    });
+
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    let (mut parts, body) = req.into_parts();
+
+    // Rewrite the URI to the path inside the box, dropping our own token
+    let mut upstream_uri = format!("/{}", path);
+    if let Some(query) = strip_query_param(parts.uri.query(), "token") {
+        upstream_uri.push('?');
+        upstream_uri.push_str(&query);
+    }
+    parts.uri = upstream_uri.parse()?;
+
+    // Dev servers commonly reject unknown Host headers, so present as localhost
+    if let Some(host) = parts.headers.remove(header::HOST) {
+        parts.headers.insert("x-forwarded-host", host);
+    }
+    parts.headers.insert(
+        header::HOST,
+        HeaderValue::from_str(&format!("localhost:{}", port))?,
+    );
+    parts
+        .headers
+        .insert("x-forwarded-prefix", HeaderValue::from_str(prefix)?);
+
+    // Never leak the coop token to the proxied app
+    if strip_authorization {
+        parts.headers.remove(header::AUTHORIZATION);
+    }
+    if let Some(cookies) = parts.headers.remove(header::COOKIE) {
+        let kept: Vec<&str> = cookies
+            .to_str()
+            .unwrap_or("")
+            .split(';')
+            .map(str::trim)
+            .filter(|c| !c.is_empty() && !c.starts_with(&format!("{}=", TOKEN_COOKIE)))
+            .collect();
+        if !kept.is_empty() {
+            parts
+                .headers
+                .insert(header::COOKIE, HeaderValue::from_str(&kept.join("; "))?);
+        }
+    }
+
+    let mut resp = sender
+        .send_request(hyper::Request::from_parts(parts, body))
+        .await
+        .context("Box service closed the connection")?;
+
+    if resp.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let upstream_upgrade = hyper::upgrade::on(&mut resp);
+        tokio::spawn(async move {
+            match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                Ok((client, upstream)) => {
+                    let mut client = TokioIo::new(client);
+                    let mut upstream = TokioIo::new(upstream);
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                }
+                Err(e) => tracing::debug!(error = %e, "Proxy upgrade failed"),
+            }
+        });
+    }
+
+    Ok(resp.map(Body::new))
+}
+
+/// Remove a query parameter, returning the remaining query (None if empty)
+fn strip_query_param(query: Option<&str>, key: &str) -> Option<String> {
+    let kept: Vec<&str> = query?
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(key))
+        .collect();
+    if kept.is_empty() {
+        None
+    } else {
+        Some(kept.join("&"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_token_from_query() {
+        assert_eq!(
+            strip_query_param(Some("token=abc&page=2"), "token").as_deref(),
+            Some("page=2")
+        );
+        assert_eq!(strip_query_param(Some("token=abc"), "token"), None);
+        assert_eq!(strip_query_param(None, "token"), None);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_proxied_pages() {
+        let page = sandbox(StatusCode::OK.into_response()).await;
+        assert_eq!(
+            page.headers()[header::CONTENT_SECURITY_POLICY],
+            SANDBOX_POLICY
+        );
+        assert!(!SANDBOX_POLICY.contains("allow-same-origin"));
+
+        let upgrade = sandbox(StatusCode::SWITCHING_PROTOCOLS.into_response()).await;
+        assert!(!upgrade
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+}
@@ -1,45 +1,98 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use axum::middleware::map_response;
 use axum::Router;
+use tokio_rustls::TlsAcceptor;
 
+use super::tls::TlsListener;
+use super::tokens::TokenStore;
+use crate::daemon::queue::TaskQueue;
 use crate::daemon::session::SessionManager;
 
 /// State shared across all web request handlers
 pub struct WebState {
     pub session_manager: Arc<SessionManager>,
-    pub token: String,
+    pub task_queue: Arc<TaskQueue>,
+    pub tokens: Arc<TokenStore>,
+    /// Served over HTTPS, so cookies get the `Secure` attribute
+    pub tls: bool,
 }
 
-/// Create the axum router for the web UI
+/// Create the axum router for the web UI. Box ports proxied here share the
+/// UI's origin, so their pages are sandboxed.
 pub fn create_router(state: Arc<WebState>) -> Router {
     Router::new()
         .merge(super::api::api_routes())
+        .merge(super::events::event_routes())
+        .merge(super::metrics::metrics_routes())
         .merge(super::websocket::ws_routes())
+        .merge(super::proxy::proxy_routes().layer(map_response(super::proxy::sandbox)))
         .merge(super::assets::asset_routes())
         .with_state(state)
 }
 
-/// Start the web server
+/// Create the router for `--proxy-port`: box ports only, on an origin the
+/// web UI never runs on
+pub fn create_proxy_router(state: Arc<WebState>) -> Router {
+    super::proxy::proxy_routes().with_state(state)
+}
+
+/// The address other machines on the LAN reach us at: the source address
+/// of the default route (connecting a UDP socket sends nothing)
+pub fn lan_address() -> Option<std::net::IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback()).then_some(ip)
+}
+
+/// Start the web server, and the proxy server on `proxy_listener` if given
 pub async fn start_web_server(
     host: &str,
     port: u16,
     session_manager: Arc<SessionManager>,
-    token: String,
+    task_queue: Arc<TaskQueue>,
+    tokens: Arc<TokenStore>,
+    tls: Option<TlsAcceptor>,
+    proxy_listener: Option<tokio::net::TcpListener>,
 ) -> Result<()> {
     let state = Arc::new(WebState {
         session_manager,
-        token,
+        task_queue,
+        tokens,
+        tls: tls.is_some(),
     });
 
-    let app = create_router(state);
+    let app = create_router(state.clone());
 
     let addr = format!("{}:{}", host, port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
 
-    tracing::info!(addr = %addr, "Web server listening");
+    tracing::info!(addr = %addr, tls = tls.is_some(), "Web server listening");
 
-    axum::serve(listener, app).await?;
+    if let Some(proxy_listener) = proxy_listener {
+        tracing::info!(addr = %proxy_listener.local_addr()?, "Proxy server listening");
+        let proxy_app = create_proxy_router(state);
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(proxy_listener, tls, proxy_app).await {
+                tracing::error!(error = %e, "Proxy server error");
+            }
+        });
+    }
 
+    serve(listener, tls, app).await
+}
+
+async fn serve(
+    listener: tokio::net::TcpListener,
+    tls: Option<TlsAcceptor>,
+    app: Router,
+) -> Result<()> {
+    match tls {
+        Some(acceptor) => axum::serve(TlsListener::new(listener, acceptor)?, app).await?,
+        None => axum::serve(listener, app).await?,
+    }
     Ok(())
 }
@@ -0,0 +1,229 @@
+//! TLS for `coop serve --tls`.
+//!
+//! Uses the given certificate and key, or a self-signed certificate kept in
+//! `~/.coop/tls/` so its fingerprint stays the same across restarts and can
+//! be pinned once. Handshakes run on their own tasks, so a stalled client
+//! cannot hold up other connections.
+
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config;
+
+/// Give up on clients that connect but never finish the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct TlsSetup {
+    pub acceptor: TlsAcceptor,
+    /// SHA-256 of the certificate, `AB:CD:...`
+    pub fingerprint: String,
+    /// Whether the certificate was generated by coop
+    pub self_signed: bool,
+}
+
+/// Load `cert` and `key` (PEM), or the self-signed certificate if neither is
+/// given, generating it on first use
+pub fn load(cert: Option<&Path>, key: Option<&Path>) -> Result<TlsSetup> {
+    let (cert_pem, key_pem, self_signed) = match (cert, key) {
+        (Some(cert), Some(key)) => (
+            std::fs::read(cert).with_context(|| format!("Cannot read {}", cert.display()))?,
+            std::fs::read(key).with_context(|| format!("Cannot read {}", key.display()))?,
+            false,
+        ),
+        (None, None) => {
+            let (cert, key) = self_signed()?;
+            (cert, key, true)
+        }
+        _ => bail!("--cert and --key must be given together"),
+    };
+    setup(&cert_pem, &key_pem, self_signed)
+}
+
+fn setup(cert_pem: &[u8], key_pem: &[u8], self_signed: bool) -> Result<TlsSetup> {
+    let certs = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid certificate PEM")?;
+    let Some(leaf) = certs.first() else {
+        bail!("No certificate found in PEM");
+    };
+    let fingerprint = fingerprint(leaf);
+    let key = PrivateKeyDer::from_pem_slice(key_pem).context("Invalid private key PEM")?;
+
+    let mut server_config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .context("Certificate and key do not match")?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsSetup {
+        acceptor: TlsAcceptor::from(Arc::new(server_config)),
+        fingerprint,
+        self_signed,
+    })
+}
+
+/// The stored self-signed certificate and key, created if missing
+fn self_signed() -> Result<(Vec<u8>, Vec<u8>)> {
+    let dir = config::tls_dir()?;
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    if let (Ok(cert), Ok(key)) = (std::fs::read(&cert_path), std::fs::read(&key_path)) {
+        return Ok((cert, key));
+    }
+
+    let (cert, key) = generate()?;
+    std::fs::create_dir_all(&dir)?;
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&key_path)?;
+        file.write_all(key.as_bytes())?;
+    }
+    std::fs::write(&cert_path, &cert)?;
+    tracing::info!(path = %cert_path.display(), "Generated self-signed TLS certificate");
+    Ok((cert.into_bytes(), key.into_bytes()))
+}
+
+/// New self-signed certificate and key as PEM
+fn generate() -> Result<(String, String)> {
+    let mut names = vec!["localhost".to_string()];
+    if let Ok(hostname) = nix::unistd::gethostname() {
+        names.push(hostname.to_string_lossy().into_owned());
+    }
+    let certified = rcgen::generate_simple_self_signed(names)?;
+    Ok((certified.cert.pem(), certified.key_pair.serialize_pem()))
+}
+
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// TCP listener that yields connections once their TLS handshake is done
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, incoming) = mpsc::channel(32);
+        tokio::spawn(accept_loop(listener, acceptor, tx));
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    while !tx.is_closed() {
+        let (stream, addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::debug!(error = %e, "TLS listener accept error");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = tx.send((stream, addr)).await;
+                }
+                Ok(Err(e)) => tracing::debug!(peer = %addr, error = %e, "TLS handshake failed"),
+                Err(_) => tracing::debug!(peer = %addr, "TLS handshake timed out"),
+            }
+        });
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // The accept loop only stops once we are dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tls_listener_serves_pinned_cert() {
+        let (cert, key) = generate().unwrap();
+        let tls = setup(cert.as_bytes(), key.as_bytes(), true).unwrap();
+        assert_eq!(tls.fingerprint.len(), 32 * 3 - 1);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "hello" }));
+        let listener = TlsListener::new(listener, tls.acceptor).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // Plain HTTP on the TLS port fails the handshake without taking the
+        // listener down
+        let http = reqwest::Client::new();
+        assert!(http.get(format!("http://{}/", addr)).send().await.is_err());
+
+        // A client pinning by fingerprint accepts the self-signed cert
+        let https = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .tls_info(true)
+            .build()
+            .unwrap();
+        let resp = https
+            .get(format!("https://{}/", addr))
+            .send()
+            .await
+            .unwrap();
+        let peer = resp
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(fingerprint)
+            .unwrap();
+        assert_eq!(peer, tls.fingerprint);
+        assert_eq!(resp.text().await.unwrap(), "hello");
+    }
+}
@@ -0,0 +1,393 @@
+//! Named web API tokens (see `coop token`).
+//!
+//! Only a SHA-256 hash of each secret is kept, so `~/.coop/tokens.json`
+//! cannot be replayed if it leaks. The token printed by `coop serve` is
+//! registered as an in-memory admin token named `serve` and is never saved.
+//! `coop share` links are read tokens limited to one box, and optionally to
+//! one PTY.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::config;
+use crate::ipc::{
+    Response, ResponseData, TokenInfo, TokenScope, ERR_TOKEN_EXISTS, ERR_TOKEN_NOT_FOUND,
+};
+
+/// Name of the token handed out by `coop serve`
+const SERVE_TOKEN: &str = "serve";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    name: String,
+    /// Hex SHA-256 of the secret
+    hash: String,
+    scope: TokenScope,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sessions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pty: Option<u32>,
+    created: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<u64>,
+    /// The `coop serve` token lives only as long as the daemon
+    #[serde(skip)]
+    ephemeral: bool,
+}
+
+impl StoredToken {
+    fn info(&self) -> TokenInfo {
+        TokenInfo {
+            name: self.name.clone(),
+            scope: self.scope,
+            sessions: self.sessions.clone(),
+            pty: self.pty,
+            created: self.created,
+            expires: self.expires,
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.expires.is_some_and(|e| e <= now())
+    }
+}
+
+/// What a verified token may do
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub name: String,
+    pub scope: TokenScope,
+    /// Boxes the token can reach; empty means all of them
+    pub sessions: Vec<String>,
+    /// The only PTY the token can watch
+    pub pty: Option<u32>,
+}
+
+impl Grant {
+    /// Whether the token may access `session` at all
+    pub fn allows_session(&self, session: &str) -> bool {
+        self.sessions.is_empty() || self.sessions.iter().any(|s| s == session)
+    }
+
+    /// Whether the token may watch `pty`
+    pub fn allows_pty(&self, pty: u32) -> bool {
+        self.pty.map_or(true, |p| p == pty)
+    }
+
+    /// Whether the token has `scope`, and reaches `session` if one is given.
+    /// Restricted tokens cannot act on no box in particular.
+    pub fn allows(&self, scope: TokenScope, session: Option<&str>) -> bool {
+        self.scope >= scope
+            && match session {
+                Some(session) => self.allows_session(session),
+                None => self.sessions.is_empty(),
+            }
+    }
+}
+
+pub struct TokenStore {
+    /// `None` keeps tokens in memory only
+    path: Option<PathBuf>,
+    tokens: Mutex<Vec<StoredToken>>,
+    /// Where the web server listens (`https://host:port`), for share links
+    origin: Mutex<Option<String>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Random URL-safe token secret
+pub fn generate_token() -> String {
+    use base64::Engine;
+    use rand::Rng;
+    let mut rng = rand::rng();
+    let bytes: [u8; 16] = rng.random();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl TokenStore {
+    pub fn load() -> Self {
+        let path = config::tokens_path().ok();
+        let tokens = path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            tokens: Mutex::new(tokens),
+            origin: Mutex::default(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            tokens: Mutex::default(),
+            origin: Mutex::default(),
+        }
+    }
+
+    /// Register the `coop serve` token, replacing any earlier one, and
+    /// remember the server's `origin` for share links
+    pub async fn set_serve_token(&self, secret: &str, origin: String) {
+        *self.origin.lock().await = Some(origin);
+        let mut tokens = self.tokens.lock().await;
+        tokens.retain(|t| t.name != SERVE_TOKEN);
+        tokens.push(StoredToken {
+            name: SERVE_TOKEN.to_string(),
+            hash: hash(secret),
+            scope: TokenScope::Admin,
+            sessions: Vec::new(),
+            pty: None,
+            created: now(),
+            expires: None,
+            ephemeral: true,
+        });
+    }
+
+    /// Look up a secret, returning its grant unless unknown or expired
+    pub async fn verify(&self, secret: &str) -> Option<Grant> {
+        let hash = hash(secret);
+        let tokens = self.tokens.lock().await;
+        tokens
+            .iter()
+            .find(|t| t.hash == hash && !t.expired())
+            .map(|t| Grant {
+                name: t.name.clone(),
+                scope: t.scope,
+                sessions: t.sessions.clone(),
+                pty: t.pty,
+            })
+    }
+
+    pub async fn create(
+        &self,
+        name: &str,
+        scope: TokenScope,
+        sessions: Vec<String>,
+        expires_secs: Option<u64>,
+    ) -> Result<Response> {
+        let mut tokens = self.tokens.lock().await;
+        if name == SERVE_TOKEN || tokens.iter().any(|t| t.name == name) {
+            return Ok(Response::err(
+                ERR_TOKEN_EXISTS,
+                format!("Token '{}' already exists", name),
+            ));
+        }
+        let (secret, info) = self.add(&mut tokens, name, scope, sessions, None, expires_secs)?;
+        Ok(Response::ok_with(ResponseData {
+            token: Some(secret),
+            tokens: Some(vec![info]),
+            ..Default::default()
+        }))
+    }
+
+    /// Mint a read token for `session` (already resolved to a box name),
+    /// limited to `pty` if given, with a web UI link when the server is up
+    pub async fn share(
+        &self,
+        session: &str,
+        pty: Option<u32>,
+        expires_secs: Option<u64>,
+    ) -> Result<Response> {
+        let mut tokens = self.tokens.lock().await;
+        let name = loop {
+            let suffix: String = generate_token().chars().take(6).collect();
+            let name = format!("share-{}-{}", session, suffix);
+            if !tokens.iter().any(|t| t.name == name) {
+                break name;
+            }
+        };
+        let (secret, info) = self.add(
+            &mut tokens,
+            &name,
+            TokenScope::Read,
+            vec![session.to_string()],
+            pty,
+            expires_secs,
+        )?;
+        let url = self.origin.lock().await.as_ref().map(|origin| {
+            let pty = pty.map(|p| format!("&pty={}", p)).unwrap_or_default();
+            format!("{}/?token={}&session={}{}", origin, secret, session, pty)
+        });
+        Ok(Response::ok_with(ResponseData {
+            session: Some(session.to_string()),
+            token: Some(secret),
+            tokens: Some(vec![info]),
+            url,
+            ..Default::default()
+        }))
+    }
+
+    fn add(
+        &self,
+        tokens: &mut Vec<StoredToken>,
+        name: &str,
+        scope: TokenScope,
+        sessions: Vec<String>,
+        pty: Option<u32>,
+        expires_secs: Option<u64>,
+    ) -> Result<(String, TokenInfo)> {
+        let secret = generate_token();
+        let created = now();
+        let token = StoredToken {
+            name: name.to_string(),
+            hash: hash(&secret),
+            scope,
+            sessions,
+            pty,
+            created,
+            expires: expires_secs.map(|secs| created + secs),
+            ephemeral: false,
+        };
+        let info = token.info();
+        tokens.push(token);
+        self.save(tokens)?;
+        tracing::info!(name = %name, scope = %scope, "Created API token");
+        Ok((secret, info))
+    }
+
+    pub async fn list(&self) -> Result<Response> {
+        let tokens = self.tokens.lock().await;
+        Ok(Response::ok_with(ResponseData {
+            tokens: Some(tokens.iter().map(StoredToken::info).collect()),
+            ..Default::default()
+        }))
+    }
+
+    pub async fn revoke(&self, name: &str) -> Result<Response> {
+        let mut tokens = self.tokens.lock().await;
+        let Some(index) = tokens.iter().position(|t| t.name == name) else {
+            return Ok(Response::err(
+                ERR_TOKEN_NOT_FOUND,
+                format!("No token named '{}'", name),
+            ));
+        };
+        let token = tokens.remove(index);
+        self.save(&tokens)?;
+        tracing::info!(name = %name, "Revoked API token");
+        Ok(Response::ok_with(ResponseData {
+            tokens: Some(vec![token.info()]),
+            ..Default::default()
+        }))
+    }
+
+    fn save(&self, tokens: &[StoredToken]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let saved: Vec<&StoredToken> = tokens.iter().filter(|t| !t.ephemeral).collect();
+        let tmp = path.with_extension("json.tmp");
+        {
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            file.write_all(&serde_json::to_vec_pretty(&saved)?)?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_scopes_and_revoke() {
+        let store = TokenStore::in_memory();
+        let resp = store
+            .create("ci", TokenScope::Attach, vec!["web".to_string()], None)
+            .await
+            .unwrap();
+        let secret = resp.data.token.unwrap();
+
+        let grant = store.verify(&secret).await.unwrap();
+        assert_eq!(grant.name, "ci");
+        assert!(grant.allows(TokenScope::Read, Some("web")));
+        assert!(grant.allows(TokenScope::Attach, Some("web")));
+        assert!(!grant.allows(TokenScope::Write, Some("web")));
+        assert!(!grant.allows(TokenScope::Read, Some("api")));
+        assert!(!grant.allows(TokenScope::Read, None));
+        assert!(store.verify("wrong").await.is_none());
+
+        let dup = store
+            .create("ci", TokenScope::Read, vec![], None)
+            .await
+            .unwrap();
+        assert_eq!(dup.error.as_deref(), Some(ERR_TOKEN_EXISTS));
+
+        store.revoke("ci").await.unwrap();
+        assert!(store.verify(&secret).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_rejected() {
+        let store = TokenStore::in_memory();
+        let resp = store
+            .create("old", TokenScope::Admin, vec![], Some(0))
+            .await
+            .unwrap();
+        assert!(store.verify(&resp.data.token.unwrap()).await.is_none());
+
+        store
+            .set_serve_token("s3cret", "http://127.0.0.1:8888".to_string())
+            .await;
+        let grant = store.verify("s3cret").await.unwrap();
+        assert!(grant.allows(TokenScope::Admin, None));
+        let list = store.list().await.unwrap().data.tokens.unwrap();
+        assert!(list.iter().any(|t| t.name == "serve"));
+    }
+
+    #[tokio::test]
+    async fn test_share_link_watches_one_pty() {
+        let store = TokenStore::in_memory();
+        let resp = store.share("web", Some(2), Some(3600)).await.unwrap();
+        assert!(resp.data.url.is_none());
+        let grant = store.verify(&resp.data.token.unwrap()).await.unwrap();
+        assert!(grant.name.starts_with("share-web-"));
+        assert!(grant.allows(TokenScope::Read, Some("web")));
+        assert!(!grant.allows(TokenScope::Attach, Some("web")));
+        assert!(grant.allows_pty(2));
+        assert!(!grant.allows_pty(0));
+
+        store
+            .set_serve_token("s3cret", "https://10.0.0.5:8888".to_string())
+            .await;
+        let resp = store.share("web", None, None).await.unwrap();
+        let url = resp.data.url.unwrap();
+        assert_eq!(
+            url,
+            format!(
+                "https://10.0.0.5:8888/?token={}&session=web",
+                resp.data.token.unwrap()
+            )
+        );
+    }
+}
@@ -8,8 +8,13 @@ use axum::Router;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 
+use super::auth::Auth;
 use super::server::WebState;
-use crate::pty::InputFilter;
+use crate::config::InputClient;
+use crate::daemon::audit::Actor;
+use crate::daemon::session::PtyHandle;
+use crate::ipc::TokenScope;
+use crate::pty::{approval, redact};
 
 pub fn ws_routes() -> Router<Arc<WebState>> {
     Router::new().route("/ws", get(ws_handler))
@@ -19,27 +24,46 @@ pub fn ws_routes() -> Router<Arc<WebState>> {
 pub struct WsQuery {
     pub session: String,
     pub pty: Option<u32>,
-    pub token: Option<String>,
 }
 
 async fn ws_handler(
     State(state): State<Arc<WebState>>,
+    auth: Auth,
     Query(query): Query<WsQuery>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    // Verify token
-    if query.token.as_deref() != Some(state.token.as_str()) {
-        return axum::http::StatusCode::FORBIDDEN.into_response();
+    let pty = query.pty.unwrap_or(0);
+    if let Err(rejection) = auth
+        .require(TokenScope::Read, Some(&query.session))
+        .and_then(|()| auth.require_pty(pty))
+    {
+        return rejection.into_response();
     }
-
-    ws.on_upgrade(move |socket| handle_socket(socket, state, query.session, query.pty.unwrap_or(0)))
-        .into_response()
+    // Read-only tokens watch; input and resizes need attach
+    let read_only = !auth.grant.allows(TokenScope::Attach, Some(&query.session));
+    let actor = Actor::web(&auth.grant.name);
+    let client = auth.input_client(&query.session);
+
+    ws.on_upgrade(move |socket| {
+        handle_socket(socket, state, query.session, pty, read_only, actor, client)
+    })
+    .into_response()
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<WebState>, session: String, pty: u32) {
-    tracing::info!(session = %session, pty = pty, "WebSocket connected");
-
-    if let Err(e) = handle_socket_inner(socket, &state, &session, pty).await {
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<WebState>,
+    session: String,
+    pty: u32,
+    read_only: bool,
+    actor: Actor,
+    client: InputClient,
+) {
+    tracing::info!(session = %session, pty = pty, read_only = read_only, "WebSocket connected");
+
+    if let Err(e) =
+        handle_socket_inner(socket, &state, &session, pty, read_only, actor, client).await
+    {
         tracing::error!(session = %session, pty = pty, error = %e, "WebSocket handler error");
     }
 
@@ -51,10 +75,22 @@ async fn handle_socket_inner(
     state: &WebState,
     session: &str,
     pty: u32,
+    read_only: bool,
+    actor: Actor,
+    client: InputClient,
 ) -> anyhow::Result<()> {
-    // Look up session and PTY handles
-    let (master_fd, output_tx, scrollback) =
-        state.session_manager.get_pty_handle(session, pty).await?;
+    // Look up session and PTY handles; output is subscribed here
+    let PtyHandle {
+        input,
+        output: mut output_rx,
+        scrollback,
+        redact,
+        filter: mut input_filter,
+        approval: mut gate,
+    } = state
+        .session_manager
+        .get_pty_handle(session, pty, actor, client)
+        .await?;
 
     // Track web client
     state.session_manager.add_web_client(session).await;
@@ -63,57 +99,32 @@ async fn handle_socket_inner(
         session: session.to_string(),
     };
 
-    // Subscribe to PTY output
-    let mut output_rx = output_tx.subscribe();
-
-    // Create input filter for agent PTYs (pty 0) on web connections
-    let mut input_filter = if pty == 0 {
-        Some(InputFilter::new(500, &[]))
-    } else {
-        None
-    };
-
     let (mut ws_sink, mut ws_stream) = socket.split();
 
+    // Output is masked on its way to the browser; the redactor may hold back
+    // the end of a chunk until the next one arrives
+    let mut redactor = redact.stream();
+
     // Replay scrollback buffer so the client sees previous terminal state
     if let Some(sb) = &scrollback {
-        let data = sb.lock().await;
+        let data = redactor.push(&sb.lock().await);
         if !data.is_empty() {
-            let _ = ws_sink.send(Message::Binary(data.clone().into())).await;
+            let _ = ws_sink.send(Message::Binary(data.into())).await;
         }
     }
 
     loop {
+        let flush_at = redactor.deadline();
         tokio::select! {
             // WebSocket -> PTY (input)
             msg = ws_stream.next() => {
                 match msg {
+                    Some(Ok(Message::Binary(_) | Message::Text(_))) if read_only => {}
                     Some(Ok(Message::Binary(data))) => {
-                        // Apply input filter for agent PTYs
-                        let (to_forward, warning) = if let Some(ref mut filter) = input_filter {
-                            filter.filter(&data)
-                        } else {
-                            (data.to_vec(), None)
-                        };
-
-                        // Send warning back to client if blocked
-                        if let Some(warning) = warning {
+                        // Send warning back to client if the filter blocked anything
+                        if let Some(warning) = input.write_filtered(input_filter.as_mut(), gate.as_mut(), &data) {
                             let _ = ws_sink.send(Message::Binary(warning.to_vec().into())).await;
                         }
-
-                        // Write filtered input to PTY master
-                        if !to_forward.is_empty() {
-                            let fd = master_fd.load(std::sync::atomic::Ordering::SeqCst);
-                            if fd >= 0 {
-                                unsafe {
-                                    nix::libc::write(
-                                        fd,
-                                        to_forward.as_ptr() as *const _,
-                                        to_forward.len(),
-                                    );
-                                }
-                            }
-                        }
                     }
                     Some(Ok(Message::Text(text))) => {
                         // JSON control message (e.g., resize)
@@ -121,18 +132,7 @@ async fn handle_socket_inner(
                             if control.get("type").and_then(|t| t.as_str()) == Some("resize") {
                                 let cols = control.get("cols").and_then(|c| c.as_u64()).unwrap_or(120) as u16;
                                 let rows = control.get("rows").and_then(|r| r.as_u64()).unwrap_or(40) as u16;
-                                let fd = master_fd.load(std::sync::atomic::Ordering::SeqCst);
-                                if fd >= 0 {
-                                    let ws = nix::libc::winsize {
-                                        ws_row: rows,
-                                        ws_col: cols,
-                                        ws_xpixel: 0,
-                                        ws_ypixel: 0,
-                                    };
-                                    unsafe {
-                                        nix::libc::ioctl(fd, nix::libc::TIOCSWINSZ, &ws);
-                                    }
-                                }
+                                input.resize(cols, rows);
                             }
                         }
                     }
@@ -149,18 +149,37 @@ async fn handle_socket_inner(
             data = output_rx.recv() => {
                 match data {
                     Ok(bytes) => {
-                        if ws_sink.send(Message::Binary(bytes.to_vec().into())).await.is_err() {
+                        let data = redactor.push(&bytes);
+                        if !data.is_empty() && ws_sink.send(Message::Binary(data.into())).await.is_err() {
                             break;
                         }
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
                         tracing::warn!(dropped = n, "WebSocket client lagging");
+                        state.session_manager.record_lag(n);
                     }
                     Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                         // PTY exited
-                        let msg =
-                            "\r\n\x1b[2m[process exited]\x1b[0m\r\n".to_string();
-                        let _ = ws_sink.send(Message::Binary(msg.into_bytes().into())).await;
+                        let mut msg = redactor.finish();
+                        msg.extend_from_slice(b"\r\n\x1b[2m[process exited]\x1b[0m\r\n");
+                        let _ = ws_sink.send(Message::Binary(msg.into())).await;
+                        break;
+                    }
+                }
+            }
+
+            // Held-back output once the PTY goes quiet
+            _ = redact::sleep_until(flush_at) => {
+                let data = redactor.flush();
+                if !data.is_empty() && ws_sink.send(Message::Binary(data.into())).await.is_err() {
+                    break;
+                }
+            }
+
+            // Held input sent for approval, or answered
+            approval = approval::next(gate.as_mut()) => {
+                if let Some(notice) = input.settle(approval) {
+                    if ws_sink.send(Message::Binary(notice.to_vec().into())).await.is_err() {
                         break;
                     }
                 }
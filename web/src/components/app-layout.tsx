@@ -1,4 +1,4 @@
-import { useState, useCallback, useEffect, useRef } from 'react'
+import { useState, useCallback, useEffect, useMemo, useRef } from 'react'
 import { Allotment } from 'allotment'
 import 'allotment/dist/style.css'
 import { SessionSidebar } from '@/components/session-sidebar'
@@ -19,6 +19,15 @@ interface PtyState {
 export function AppLayout({ transport }: AppLayoutProps) {
   const { layout, setLayout } = useLayout()
 
+  // Share links (`coop share`) name the box and PTY to open
+  const linked = useMemo(() => {
+    const params = new URLSearchParams(window.location.search)
+    return {
+      session: params.get('session'),
+      pty: Number(params.get('pty') ?? 0),
+    }
+  }, [])
+
   // Sessions
   const [sessions, setSessions] = useState<Session[]>([])
   const [activeSessionId, setActiveSessionId] = useState<string | null>(null)
@@ -35,22 +44,28 @@ export function AppLayout({ transport }: AppLayoutProps) {
     try {
       const list = await transport.listSessions()
       setSessions(list)
-      // Auto-select first session if none selected
+      // Auto-select the linked session, else the first one
       if (list.length > 0) {
+        const initial = list.find((s) => s.id === linked.session) ?? list[0]
         setActiveSessionId((prev) =>
-          prev && list.some((s) => s.id === prev) ? prev : list[0].id,
+          prev && list.some((s) => s.id === prev) ? prev : initial.id,
         )
       }
     } catch {
       // Transport not ready yet
     }
-  }, [transport])
+  }, [transport, linked])
 
   useEffect(() => {
     fetchSessions()
-    const interval = setInterval(fetchSessions, 5000)
-    return () => clearInterval(interval)
-  }, [fetchSessions])
+    // Refetch on daemon events; poll slowly as well in case the stream drops
+    const unsubscribe = transport.subscribe?.(() => fetchSessions())
+    const interval = setInterval(fetchSessions, unsubscribe ? 30000 : 5000)
+    return () => {
+      clearInterval(interval)
+      unsubscribe?.()
+    }
+  }, [fetchSessions, transport])
 
   // When active session changes, set up agent PTY tab
   useEffect(() => {
@@ -65,13 +80,17 @@ export function AppLayout({ transport }: AppLayoutProps) {
     }
     connectionsRef.current = []
 
-    // Create agent tab (PTY 0)
-    const agentTab: PtyTab = { id: 0, label: 'Agent', isAgent: true }
+    // Create agent tab (PTY 0), or the linked PTY's
+    const ptyId = activeSessionId === linked.session ? linked.pty : 0
+    const agentTab: PtyTab =
+      ptyId === 0
+        ? { id: 0, label: 'Agent', isAgent: true }
+        : { id: ptyId, label: `PTY ${ptyId}`, isAgent: false }
     setPtyStates([{ tab: agentTab, connection: null }])
-    setActiveTab(0)
+    setActiveTab(ptyId)
 
     // Attach to agent PTY
-    const conn = transport.attachPty(activeSessionId, 0, 80, 24)
+    const conn = transport.attachPty(activeSessionId, ptyId, 80, 24)
     connectionsRef.current.push(conn)
     setPtyStates([{ tab: agentTab, connection: conn }])
 
@@ -81,7 +100,7 @@ export function AppLayout({ transport }: AppLayoutProps) {
       }
       connectionsRef.current = []
     }
-  }, [activeSessionId, transport])
+  }, [activeSessionId, transport, linked])
 
   const handleNewSession = useCallback(async () => {
     try {
@@ -1,4 +1,4 @@
-import type { Transport, Session, PtyConnection } from './transport'
+import type { Transport, Session, PtyConnection, SessionEvent } from './transport'
 
 export class HttpTransport implements Transport {
   private baseUrl: string
@@ -63,15 +63,18 @@ export class HttpTransport implements Transport {
 
   attachPty(sessionId: string, ptyId: number, cols: number, rows: number): PtyConnection {
     const params = new URLSearchParams({
+      session: sessionId,
       pty: String(ptyId),
-      cols: String(cols),
-      rows: String(rows),
     })
     if (this.token) params.set('token', this.token)
 
-    const url = `${this.wsBaseUrl}/api/sessions/${sessionId}/pty?${params}`
+    const url = `${this.wsBaseUrl}/ws?${params}`
     const ws = new WebSocket(url)
     ws.binaryType = 'arraybuffer'
+    // The initial size goes as a control message, like later resizes
+    ws.onopen = () => {
+      ws.send(JSON.stringify({ type: 'resize', cols, rows }))
+    }
 
     const dataCbs: Array<(data: string | ArrayBuffer) => void> = []
     const closeCbs: Array<() => void> = []
@@ -106,7 +109,7 @@ export class HttpTransport implements Transport {
     rows: number,
   ): Promise<void> {
     const res = await fetch(
-      `${this.baseUrl}/api/sessions/${sessionId}/pty/${ptyId}/resize`,
+      `${this.baseUrl}/api/sessions/${sessionId}/ptys/${ptyId}/resize`,
       {
         method: 'POST',
         headers: this.headers(),
@@ -116,6 +119,28 @@ export class HttpTransport implements Transport {
     if (!res.ok) throw new Error(`Failed to resize PTY: ${res.status}`)
   }
 
+  subscribe(cb: (event: SessionEvent) => void): () => void {
+    // EventSource cannot set headers, so the token goes in the query
+    const params = new URLSearchParams()
+    if (this.token) params.set('token', this.token)
+    const source = new EventSource(`${this.baseUrl}/api/events?${params}`)
+    // Every event is named (`event: pty_exited`), so onmessage never fires
+    for (const name of [
+      'session_created',
+      'session_killed',
+      'pty_spawned',
+      'pty_exited',
+      'pty_restarting',
+      'crash_loop',
+      'client_attached',
+      'client_detached',
+      'lagged',
+    ]) {
+      source.addEventListener(name, (e) => cb(JSON.parse((e as MessageEvent).data)))
+    }
+    return () => source.close()
+  }
+
   dispose(): void {
     // Individual PtyConnections should be closed by the caller.
   }
@@ -1,3 +1,3 @@
-export type { Transport, Session, PtyInfo, PtyConnection } from './transport'
+export type { Transport, Session, PtyInfo, PtyConnection, SessionEvent } from './transport'
 export { HttpTransport } from './http-transport'
 export { WebRtcTransport } from './webrtc-transport'
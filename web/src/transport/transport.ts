@@ -11,6 +11,13 @@ export interface PtyInfo {
   label: string
 }
 
+/** A daemon event from `GET /api/events`, tagged by `event` */
+export interface SessionEvent {
+  event: string
+  session?: string
+  pty?: number
+}
+
 export interface Transport {
   /** List all active sessions */
   listSessions(): Promise<Session[]>
@@ -35,6 +42,13 @@ export interface Transport {
   /** Resize an existing PTY */
   resizePty(sessionId: string, ptyId: number, cols: number, rows: number): Promise<void>
 
+  /**
+   * Call `cb` whenever the daemon reports a session change. Returns a
+   * function that stops listening. Transports without an event stream
+   * leave this out and callers poll instead.
+   */
+  subscribe?(cb: (event: SessionEvent) => void): () => void
+
   /** Dispose of the transport and any open connections */
   dispose(): void
 }